@@ -62,6 +62,8 @@ function evaluate() {
         host: $("#host").value,
         headers: collectHeaders(),
     };
+    const body = $("#body").value;
+    if (body) request.body = body;
 
     const raw = playground_eval(JSON.stringify({ expression, request }));
     const result = JSON.parse(raw);
@@ -74,7 +76,7 @@ function evaluate() {
         resultPanel.textContent = "ALLOW (true)";
         resultPanel.classList.add("allow");
     } else {
-        resultPanel.textContent = "DENY (false)";
+        resultPanel.textContent = result.reason ? "DENY (false): " + result.reason : "DENY (false)";
         resultPanel.classList.add("deny");
     }
 }
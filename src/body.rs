@@ -0,0 +1,469 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Request body parsing
+//
+// The body is only buffered when the compiled policy references a body
+// function, and only parsed when it fits within `maxBodySize` and its
+// Content-Type matches. A body that is too large, of another type or
+// malformed is simply absent, like a missing header. Forms are the
+// exception: a form body over the limit, or with more fields than are
+// parsed, fails closed.
+
+use crate::url;
+use serde_json::Value as JsonValue;
+
+/// Default maximum number of body bytes the plugin buffers and parses.
+/// Configurable with `maxBodySize`.
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Maximum number of fields parsed from a form body, for the same reason
+/// as `url::MAX_QUERY_PARAMS`. For multipart bodies this also bounds the
+/// number of parts examined, including skipped file parts.
+pub const MAX_FORM_FIELDS: usize = url::MAX_QUERY_PARAMS;
+
+/// Maximum number of header lines parsed per multipart part
+const MAX_PART_HEADERS: usize = 16;
+
+/// Size of the chunks in which the body is read from the host
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Read a body of unknown size in chunks, buffering at most `max_size + 1`
+/// bytes. `read` fills the given buffer and returns whether the end of the
+/// body was reached and how many bytes it wrote. Returns the body if it
+/// fits within `max_size`, and the number of bytes read, which is
+/// `max_size + 1` for a body that is too large.
+pub fn read_limited(
+    mut read: impl FnMut(&mut [u8]) -> (bool, usize),
+    max_size: usize,
+) -> (Option<Vec<u8>>, u64) {
+    let limit = max_size.saturating_add(1);
+    let mut body = Vec::new();
+    let mut chunk = vec![0; READ_CHUNK_SIZE.min(limit)];
+
+    while body.len() < limit {
+        let want = chunk.len().min(limit - body.len());
+        let (eof, size) = read(&mut chunk[..want]);
+        body.extend_from_slice(&chunk[..size.min(want)]);
+        if eof || size == 0 {
+            break;
+        }
+    }
+
+    let size = body.len() as u64;
+    if body.len() > max_size {
+        (None, size)
+    } else {
+        (Some(body), size)
+    }
+}
+
+/// Returns the media type of a Content-Type value, lowercased and without
+/// parameters (`Application/JSON; charset=utf-8` -> `application/json`)
+pub fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Returns true for `application/json` and structured syntax suffix types
+/// such as `application/merge-patch+json` (RFC 6839)
+pub fn is_json(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    match media_type.split_once('/') {
+        Some(("application", subtype)) => subtype == "json" || subtype.ends_with("+json"),
+        _ => false,
+    }
+}
+
+/// Parse a JSON request body. Returns None if the body is not valid JSON.
+pub fn parse_json(body: &[u8]) -> Option<JsonValue> {
+    serde_json::from_slice(body).ok()
+}
+
+/// Parse a form body into decoded name/value pairs, in order. Supports
+/// `application/x-www-form-urlencoded` and `multipart/form-data`; file
+/// parts (those with a `filename`) are skipped without being decoded.
+/// Returns an empty list for other content types or malformed bodies, and
+/// None if the body has more than `MAX_FORM_FIELDS` fields or parts: the
+/// ones past the limit would be invisible to the policy, so such a form
+/// must not be treated as if it ended at the limit.
+pub fn parse_form(content_type: &str, body: &[u8]) -> Option<Vec<(String, String)>> {
+    match media_type(content_type).as_str() {
+        "application/x-www-form-urlencoded" => {
+            let body = String::from_utf8_lossy(body);
+            let body = body.trim_end_matches(['\r', '\n']);
+            if url::count_query_params(body) > MAX_FORM_FIELDS {
+                return None;
+            }
+            Some(url::parse_query(body))
+        }
+        "multipart/form-data" => match boundary(content_type) {
+            Some(boundary) => parse_multipart(&boundary, body),
+            None => Some(Vec::new()),
+        },
+        _ => Some(Vec::new()),
+    }
+}
+
+/// Get the `boundary` parameter of a multipart Content-Type (RFC 2046
+/// section 5.1.1: 1 to 70 characters, optionally quoted)
+fn boundary(content_type: &str) -> Option<String> {
+    let boundary = split_params(content_type)
+        .into_iter()
+        .skip(1)
+        .find_map(|(name, value)| name.eq_ignore_ascii_case("boundary").then_some(value))?;
+    (1..=70).contains(&boundary.len()).then_some(boundary)
+}
+
+/// Parse a `multipart/form-data` body (RFC 7578). Each part must have a
+/// `Content-Disposition: form-data; name="..."` header; parts without a
+/// name and file parts are skipped. A body without a closing delimiter is
+/// malformed and yields no fields; one with more than `MAX_FORM_FIELDS`
+/// parts yields None.
+fn parse_multipart(boundary: &str, body: &[u8]) -> Option<Vec<(String, String)>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    // A part ends at CRLF followed by the next delimiter
+    let separator = [b"\r\n".as_slice(), delimiter].concat();
+    let mut fields = Vec::new();
+
+    // Skip the preamble up to the first delimiter
+    let Some(start) = find(body, delimiter, 0) else {
+        return Some(Vec::new());
+    };
+    let mut pos = start + delimiter.len();
+
+    for parts in 0.. {
+        // The close delimiter ends the body
+        if body[pos..].starts_with(b"--") {
+            break;
+        }
+        // Too many parts: the rest of the form is not examined
+        if parts == MAX_FORM_FIELDS {
+            return None;
+        }
+        // The rest of the delimiter line (transport padding) is ignored
+        let Some(line_end) = find(body, b"\r\n", pos) else {
+            return Some(Vec::new());
+        };
+        let part_start = line_end + 2;
+
+        let Some(part_end) = find(body, &separator, part_start) else {
+            return Some(Vec::new());
+        };
+
+        if let Some(field) = parse_part(&body[part_start..part_end]) {
+            fields.push(field);
+        }
+        pos = part_end + separator.len();
+    }
+
+    Some(fields)
+}
+
+/// Parse one multipart part into a field, or None for a file part or a
+/// part without a form-data name
+fn parse_part(part: &[u8]) -> Option<(String, String)> {
+    // An empty header section leaves the blank line at the very start
+    let (headers, content) = if part.starts_with(b"\r\n") {
+        (&part[..0], &part[2..])
+    } else {
+        let end = find(part, b"\r\n\r\n", 0)?;
+        (&part[..end], &part[end + 4..])
+    };
+
+    let headers = String::from_utf8_lossy(headers);
+    let disposition = headers
+        .split("\r\n")
+        .take(MAX_PART_HEADERS)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+        .map(|(_, value)| value)?;
+
+    let params = split_params(disposition);
+    let (kind, _) = params.first()?;
+    if !kind.eq_ignore_ascii_case("form-data") {
+        return None;
+    }
+    let param = |key: &str| {
+        params
+            .iter()
+            .skip(1)
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.clone())
+    };
+    if param("filename").is_some() || param("filename*").is_some() {
+        return None;
+    }
+    let name = param("name").filter(|name| !name.is_empty())?;
+
+    Some((name, String::from_utf8_lossy(content).into_owned()))
+}
+
+/// Split a header value of the form `value; name=value; name="quoted"`
+/// into pairs. The first pair is the leading value with an empty value.
+/// Semicolons inside quoted strings are ignored and quoted values are
+/// unquoted.
+fn split_params(value: &str) -> Vec<(String, String)> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            ';' if !in_quotes => segments.push(std::mem::take(&mut current)),
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(ch);
+            }
+            '\\' if in_quotes => {
+                current.push(ch);
+                current.extend(chars.next());
+            }
+            _ => current.push(ch),
+        }
+    }
+    segments.push(current);
+
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let segment = segment.trim();
+            match segment.split_once('=') {
+                Some((name, value)) if i > 0 => (name.trim().to_string(), unquote(value.trim())),
+                _ => (segment.to_string(), String::new()),
+            }
+        })
+        .collect()
+}
+
+/// Remove the quotes and backslash escapes from a quoted string
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|_| value.len() >= 2)
+    else {
+        return value.to_string();
+    };
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Find the first occurrence of `needle` in `haystack` at or after `from`
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A reader over `data` that returns at most `step` bytes per call
+    fn chunked(data: &[u8], step: usize) -> impl FnMut(&mut [u8]) -> (bool, usize) + '_ {
+        let mut pos = 0;
+        move |buf: &mut [u8]| {
+            let n = step.min(buf.len()).min(data.len() - pos);
+            buf[..n].copy_from_slice(&data[pos..pos + n]);
+            pos += n;
+            (pos == data.len(), n)
+        }
+    }
+
+    #[test]
+    fn test_read_limited() {
+        let data = vec![b'x'; 10_000];
+
+        let (body, size) = read_limited(chunked(&data, 1000), 10_000);
+        assert_eq!(body.as_deref(), Some(data.as_slice()));
+        assert_eq!(size, 10_000);
+
+        let (body, size) = read_limited(chunked(b"", 1000), 16);
+        assert_eq!(body, Some(Vec::new()));
+        assert_eq!(size, 0);
+    }
+
+    #[test]
+    fn test_read_limited_stops_past_limit() {
+        // A body without Content-Length is never buffered beyond the limit
+        let data = vec![b'x'; 1_000_000];
+        let mut requested = 0;
+        let mut reader = chunked(&data, usize::MAX);
+        let (body, size) = read_limited(
+            |buf: &mut [u8]| {
+                requested += buf.len();
+                reader(buf)
+            },
+            100,
+        );
+        assert!(body.is_none());
+        assert_eq!(size, 101);
+        assert_eq!(requested, 101);
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(
+            media_type("Application/JSON; charset=utf-8"),
+            "application/json"
+        );
+        assert_eq!(media_type(" text/plain "), "text/plain");
+        assert_eq!(media_type(""), "");
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json("application/json"));
+        assert!(is_json("application/json; charset=utf-8"));
+        assert!(is_json("application/merge-patch+json"));
+        assert!(!is_json("text/json"));
+        assert!(!is_json("application/jsonx"));
+        assert!(!is_json("application/x-www-form-urlencoded"));
+        assert!(!is_json(""));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse_json(br#"{"account":{"id":"42"}}"#),
+            Some(json!({"account": {"id": "42"}}))
+        );
+        assert_eq!(parse_json(b"{broken"), None);
+        assert_eq!(parse_json(b""), None);
+    }
+
+    #[test]
+    fn test_parse_form_urlencoded() {
+        let fields = parse_form(
+            "application/x-www-form-urlencoded; charset=UTF-8",
+            b"action=delete&ids=1&ids=2&note=a+b%21\r\n",
+        );
+        assert_eq!(
+            fields.unwrap(),
+            vec![
+                ("action".to_string(), "delete".to_string()),
+                ("ids".to_string(), "1".to_string()),
+                ("ids".to_string(), "2".to_string()),
+                ("note".to_string(), "a b!".to_string()),
+            ]
+        );
+        assert_eq!(parse_form("text/plain", b"action=delete"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_parse_form_multipart() {
+        let body = concat!(
+            "preamble\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"action\"\r\n",
+            "\r\n",
+            "delete\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"upload\"; filename=\"a;b.txt\"\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "action=ignored\r\n",
+            "--XyZ\r\n",
+            "content-disposition: form-data; name=\"note\"\r\n",
+            "\r\n",
+            "line 1\r\nline 2\r\n",
+            "--XyZ\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "no disposition\r\n",
+            "--XyZ--\r\n",
+            "epilogue",
+        );
+        let fields = parse_form("multipart/form-data; boundary=\"XyZ\"", body.as_bytes());
+        assert_eq!(
+            fields.unwrap(),
+            vec![
+                ("action".to_string(), "delete".to_string()),
+                ("note".to_string(), "line 1\r\nline 2".to_string()),
+            ]
+        );
+
+        // Missing boundary or close delimiter
+        assert_eq!(
+            parse_form("multipart/form-data", body.as_bytes()),
+            Some(Vec::new())
+        );
+        let truncated = &body[..body.find("--XyZ--").unwrap()];
+        assert_eq!(
+            parse_form("multipart/form-data; boundary=XyZ", truncated.as_bytes()),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_parse_form_field_limit() {
+        // A field past the limit cannot be hidden behind padding fields
+        let padding: Vec<String> = (0..MAX_FORM_FIELDS).map(|i| format!("f{}=v", i)).collect();
+        let form = padding.join("&");
+        assert_eq!(
+            parse_form("application/x-www-form-urlencoded", form.as_bytes()).map(|f| f.len()),
+            Some(MAX_FORM_FIELDS)
+        );
+        let form = format!("{}&action=delete", form);
+        assert_eq!(
+            parse_form("application/x-www-form-urlencoded", form.as_bytes()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_form_multipart_part_limit() {
+        let part = |name: &str| {
+            format!(
+                "--b\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\nv\r\n",
+                name
+            )
+        };
+        let mut body: String = (0..MAX_FORM_FIELDS)
+            .map(|i| part(&format!("f{}", i)))
+            .collect();
+        let fields = parse_form(
+            "multipart/form-data; boundary=b",
+            format!("{}--b--\r\n", body).as_bytes(),
+        );
+        assert_eq!(fields.map(|f| f.len()), Some(MAX_FORM_FIELDS));
+
+        // A field past the limit cannot be hidden behind padding parts
+        body.push_str(&part("action"));
+        body.push_str("--b--\r\n");
+        assert_eq!(
+            parse_form("multipart/form-data; boundary=b", body.as_bytes()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_split_params() {
+        assert_eq!(
+            split_params(r#"form-data; name="a\"b"; filename="x;y""#),
+            vec![
+                ("form-data".to_string(), String::new()),
+                ("name".to_string(), "a\"b".to_string()),
+                ("filename".to_string(), "x;y".to_string()),
+            ]
+        );
+    }
+}
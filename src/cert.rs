@@ -0,0 +1,494 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// mTLS client certificate attributes
+//
+// Traefik's passTLSClientCert middleware forwards the client certificate
+// either as URL-escaped PEM (`X-Forwarded-Tls-Client-Cert`) or as an info
+// string (`X-Forwarded-Tls-Client-Cert-Info`). Only the handful of fields
+// that policies need are extracted, using a minimal DER reader rather than
+// a full X.509 crate to keep the WASM binary small. Signatures are not
+// verified; Traefik has already validated the chain during the handshake.
+
+use crate::crypto;
+use crate::url;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Header carrying the URL-escaped PEM certificate chain (lowercase)
+pub const CERT_HEADER: &str = "x-forwarded-tls-client-cert";
+
+/// Header carrying the URL-escaped certificate info string (lowercase)
+pub const CERT_INFO_HEADER: &str = "x-forwarded-tls-client-cert-info";
+
+/// Maximum size of a certificate header that will be parsed, to bound the
+/// work done on attacker-controlled input.
+const MAX_CERT_HEADER_LEN: usize = 16 * 1024;
+
+/// Maximum number of subject alternative names extracted from a certificate
+const MAX_SANS: usize = 128;
+
+/// OID 2.5.4.3 (commonName)
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// OID 2.5.29.17 (subjectAltName)
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Attributes of the leaf client certificate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCert {
+    /// Common name of the subject
+    pub subject_cn: String,
+
+    /// Common name of the issuer
+    pub issuer_cn: String,
+
+    /// Serial number in decimal, as in Traefik's info string
+    pub serial: String,
+
+    /// Subject alternative names: DNS names, email addresses, IP addresses
+    /// and then URIs, the order Traefik uses in its info string
+    pub sans: Vec<String>,
+
+    /// Start of the validity period in Unix seconds
+    pub not_before: i64,
+
+    /// End of the validity period in Unix seconds
+    pub not_after: i64,
+}
+
+impl ClientCert {
+    /// Parse the client certificate from the passTLSClientCert headers.
+    /// The PEM form is preferred because it carries every attribute; the
+    /// info string is used when the PEM header is absent or malformed.
+    pub fn from_headers(pem: Option<&str>, info: Option<&str>) -> Option<ClientCert> {
+        pem.and_then(Self::from_pem_header)
+            .or_else(|| info.and_then(Self::from_info_header))
+    }
+
+    /// Parse the `X-Forwarded-Tls-Client-Cert` value: URL-escaped PEM with
+    /// or without the BEGIN/END lines. For a chain only the leaf (first)
+    /// certificate is used.
+    pub fn from_pem_header(value: &str) -> Option<ClientCert> {
+        if value.len() > MAX_CERT_HEADER_LEN {
+            return None;
+        }
+
+        // Base64 never contains spaces, so `+` is only ever escaped as %2B
+        // and plain percent-decoding is correct here.
+        let decoded = url::percent_decode(value);
+        let leaf = decoded.split(',').next()?;
+        let base64: String = leaf
+            .replace("-----BEGIN CERTIFICATE-----", "")
+            .replace("-----END CERTIFICATE-----", "")
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+
+        let der = crypto::decode_base64(&base64)?;
+        parse_der(&der)
+    }
+
+    /// Parse the `X-Forwarded-Tls-Client-Cert-Info` value, e.g.
+    /// `Subject="O=Example,CN=svc";Issuer="CN=CA";SerialNumber="42";NB="1735689600";NA="1767225600";SAN="svc,10.0.0.7"`.
+    /// For a chain only the leaf (first) certificate is used.
+    pub fn from_info_header(value: &str) -> Option<ClientCert> {
+        if value.len() > MAX_CERT_HEADER_LEN {
+            return None;
+        }
+
+        let decoded = url::decode_form_component(value);
+        let mut cert = ClientCert::default();
+        for (key, value) in parse_info_fields(&decoded)? {
+            match key {
+                "Subject" => cert.subject_cn = dn_common_name(value),
+                "Issuer" => cert.issuer_cn = dn_common_name(value),
+                "SerialNumber" => cert.serial = value.to_string(),
+                "NB" => cert.not_before = value.parse().ok()?,
+                "NA" => cert.not_after = value.parse().ok()?,
+                "SAN" => {
+                    cert.sans = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .take(MAX_SANS)
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        Some(cert)
+    }
+}
+
+/// Split the first certificate of an info string into `key="value"` fields.
+/// Fields are separated by `;` and certificates by `,`. Returns None if the
+/// string is malformed.
+fn parse_info_fields(input: &str) -> Option<Vec<(&str, &str)>> {
+    let mut fields = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (key, after_key) = rest.split_once("=\"")?;
+        let (value, after_value) = after_key.split_once('"')?;
+        fields.push((key.trim(), value));
+
+        match after_value.chars().next() {
+            Some(';') => rest = &after_value[1..],
+            // End of input or the start of the next certificate in the chain
+            Some(',') | None => break,
+            Some(_) => return None,
+        }
+    }
+    Some(fields)
+}
+
+/// Extract the common name from a distinguished name like `O=Example,CN=svc`
+fn dn_common_name(dn: &str) -> String {
+    dn.split(',')
+        .find_map(|rdn| rdn.trim().strip_prefix("CN="))
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Parse a DER-encoded X.509 certificate
+fn parse_der(der: &[u8]) -> Option<ClientCert> {
+    let certificate = DerReader::new(der).expect(0x30)?;
+    let mut tbs = DerReader::new(DerReader::new(certificate).expect(0x30)?);
+
+    // Optional explicit version [0]
+    if tbs.peek_tag() == Some(0xa0) {
+        tbs.read()?;
+    }
+    let serial = tbs.expect(0x02)?;
+    let _signature_algorithm = tbs.expect(0x30)?;
+    let issuer = tbs.expect(0x30)?;
+    let mut validity = DerReader::new(tbs.expect(0x30)?);
+    let subject = tbs.expect(0x30)?;
+    let _subject_public_key_info = tbs.expect(0x30)?;
+
+    let mut cert = ClientCert {
+        subject_cn: name_common_name(subject).unwrap_or_default(),
+        issuer_cn: name_common_name(issuer).unwrap_or_default(),
+        serial: integer_to_decimal(serial),
+        sans: Vec::new(),
+        not_before: parse_time(validity.read()?)?,
+        not_after: parse_time(validity.read()?)?,
+    };
+
+    // Optional issuerUniqueID [1], subjectUniqueID [2] and extensions [3]
+    while let Some((tag, content)) = tbs.read() {
+        if tag == 0xa3 {
+            cert.sans = parse_extensions(content).unwrap_or_default();
+        }
+    }
+
+    Some(cert)
+}
+
+/// Find the first commonName attribute in an X.501 Name
+fn name_common_name(name: &[u8]) -> Option<String> {
+    let mut rdns = DerReader::new(name);
+    while let Some((tag, rdn)) = rdns.read() {
+        if tag != 0x31 {
+            continue;
+        }
+        let mut attributes = DerReader::new(rdn);
+        while let Some(attribute) = attributes.expect(0x30) {
+            let mut attribute = DerReader::new(attribute);
+            if attribute.expect(0x06)? == OID_COMMON_NAME {
+                let (tag, value) = attribute.read()?;
+                return Some(decode_string(tag, value));
+            }
+        }
+    }
+    None
+}
+
+/// Extract the subject alternative names from the extensions block
+fn parse_extensions(extensions: &[u8]) -> Option<Vec<String>> {
+    let mut extensions = DerReader::new(DerReader::new(extensions).expect(0x30)?);
+    while let Some(extension) = extensions.expect(0x30) {
+        let mut extension = DerReader::new(extension);
+        if extension.expect(0x06)? != OID_SUBJECT_ALT_NAME {
+            continue;
+        }
+        // Skip the optional critical flag
+        if extension.peek_tag() == Some(0x01) {
+            extension.read()?;
+        }
+        let value = extension.expect(0x04)?;
+        return parse_general_names(DerReader::new(value).expect(0x30)?);
+    }
+    Some(Vec::new())
+}
+
+/// Parse GeneralNames (RFC 5280 section 4.2.1.6)
+fn parse_general_names(names: &[u8]) -> Option<Vec<String>> {
+    let mut dns = Vec::new();
+    let mut emails = Vec::new();
+    let mut ips = Vec::new();
+    let mut uris = Vec::new();
+
+    let mut reader = DerReader::new(names);
+    while let Some((tag, value)) = reader.read() {
+        match tag {
+            0x81 => emails.push(String::from_utf8_lossy(value).into_owned()),
+            0x82 => dns.push(String::from_utf8_lossy(value).into_owned()),
+            0x86 => uris.push(String::from_utf8_lossy(value).into_owned()),
+            0x87 => match value.len() {
+                4 => ips.push(Ipv4Addr::from(<[u8; 4]>::try_from(value).ok()?).to_string()),
+                16 => ips.push(Ipv6Addr::from(<[u8; 16]>::try_from(value).ok()?).to_string()),
+                _ => return None,
+            },
+            // Other name forms (otherName, directoryName, ...) are ignored
+            _ => {}
+        }
+    }
+
+    let mut sans: Vec<String> = [dns, emails, ips, uris].concat();
+    sans.truncate(MAX_SANS);
+    Some(sans)
+}
+
+/// Decode an ASN.1 string value. BMPString is UTF-16BE; the other string
+/// types are treated as (lossy) UTF-8.
+fn decode_string(tag: u8, value: &[u8]) -> String {
+    if tag == 0x1e {
+        let units = value
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        return char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+    }
+    String::from_utf8_lossy(value).into_owned()
+}
+
+/// Convert an unsigned big-endian INTEGER to a decimal string
+fn integer_to_decimal(bytes: &[u8]) -> String {
+    let mut number: Vec<u8> = bytes.iter().copied().skip_while(|b| *b == 0).collect();
+    let mut digits = Vec::new();
+    while !number.is_empty() {
+        // Long division by 10
+        let mut remainder = 0u16;
+        for byte in number.iter_mut() {
+            let value = (remainder << 8) | *byte as u16;
+            *byte = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+        let leading_zeros = number.iter().take_while(|b| **b == 0).count();
+        number.drain(..leading_zeros);
+    }
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+/// Parse a UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`)
+/// into Unix seconds
+fn parse_time((tag, value): (u8, &[u8])) -> Option<i64> {
+    let text = std::str::from_utf8(value).ok()?;
+    let text = text.strip_suffix('Z')?;
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let (year, rest) = match (tag, text.len()) {
+        (0x17, 12) => {
+            // RFC 5280: two-digit years 50-99 are 19xx, 00-49 are 20xx
+            let yy: i64 = text[..2].parse().ok()?;
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &text[2..])
+        }
+        (0x18, 14) => (text[..4].parse().ok()?, &text[4..]),
+        _ => return None,
+    };
+
+    let field = |i: usize| -> Option<i64> { rest[i..i + 2].parse().ok() };
+    let (month, day) = (field(0)?, field(2)?);
+    let (hour, minute, second) = (field(4)?, field(6)?, field(8)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil` algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Minimal reader over a sequence of DER TLV elements
+struct DerReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        DerReader { data }
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Read the next element, returning its tag and contents
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            // Long form; indefinite lengths (0x80) are not valid DER
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let (len_bytes, after) = rest.split_at(count);
+            rest = after;
+            len_bytes
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize)
+        };
+
+        if rest.len() < len {
+            return None;
+        }
+        let (content, after) = rest.split_at(len);
+        self.data = after;
+        Some((tag, content))
+    }
+
+    /// Read the next element, which must have the given tag
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (t, content) if t == tag => Some(content),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leaf certificate issued by "Example Issuing CA" with serial
+    /// 0x0123456789abcdef01, valid 2025-01-01 to 2026-01-01 and five SANs.
+    const LEAF_PEM: &str = "\
+MIICJzCCAc2gAwIBAgIJASNFZ4mrze8BMAoGCCqGSM49BAMCMC8xEDAOBgNVBAoMB0V4YW1wbGUx\
+GzAZBgNVBAMMEkV4YW1wbGUgSXNzdWluZyBDQTAeFw0yNTAxMDEwMDAwMDBaFw0yNjAxMDEwMDAw\
+MDBaMD8xEDAOBgNVBAoMB0V4YW1wbGUxKzApBgNVBAMMImJpbGxpbmcucGF5bWVudHMuc3ZjLmNs\
+dXN0ZXIubG9jYWwwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAATGKJYPNathFnCKcdeQx5mXqevP\
+kCGXApYgrZqqyTl+jqVz6QlXYGEm2L8Mou1BYzUnBCF69pftU/qQKt/qNZpUo4HBMIG+MHwGA1Ud\
+EQR1MHOCImJpbGxpbmcucGF5bWVudHMuc3ZjLmNsdXN0ZXIubG9jYWyCB2JpbGxpbmeBD29wc0Bl\
+eGFtcGxlLmNvbYcECgAAB4Ytc3BpZmZlOi8vY2x1c3Rlci5sb2NhbC9ucy9wYXltZW50cy9zYS9i\
+aWxsaW5nMB0GA1UdDgQWBBQZdkDVdAQ8t1BWKDHxpAJcSaLOlTAfBgNVHSMEGDAWgBRpUa8OKJmV\
+Sz7cZr80VLTVuaDL/DAKBggqhkjOPQQDAgNIADBFAiEA04WuMzHjoQNt5xUh0qR/WTyqqzI9kKLc\
+Njnpjz9qV0sCICF45GqJzzPRBzysL+eNSBplOJaxRH6OAKA7cbBXgfFl";
+
+    fn expected_leaf() -> ClientCert {
+        ClientCert {
+            subject_cn: "billing.payments.svc.cluster.local".to_string(),
+            issuer_cn: "Example Issuing CA".to_string(),
+            serial: "20988295479420645121".to_string(),
+            sans: vec![
+                "billing.payments.svc.cluster.local".to_string(),
+                "billing".to_string(),
+                "ops@example.com".to_string(),
+                "10.0.0.7".to_string(),
+                "spiffe://cluster.local/ns/payments/sa/billing".to_string(),
+            ],
+            not_before: 1_735_689_600,
+            not_after: 1_767_225_600,
+        }
+    }
+
+    #[test]
+    fn test_pem_header_url_escaped() {
+        // Traefik escapes the PEM with url.QueryEscape and joins chains with ','
+        let escaped = LEAF_PEM.replace('+', "%2B").replace('/', "%2F");
+        let header = format!("{}%2CMIIBogus", escaped);
+        assert_eq!(ClientCert::from_pem_header(&header), Some(expected_leaf()));
+    }
+
+    #[test]
+    fn test_pem_header_with_armor() {
+        let pem = format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            LEAF_PEM
+        );
+        assert_eq!(ClientCert::from_pem_header(&pem), Some(expected_leaf()));
+    }
+
+    #[test]
+    fn test_pem_header_malformed() {
+        assert_eq!(ClientCert::from_pem_header("not a cert"), None);
+        assert_eq!(ClientCert::from_pem_header(&LEAF_PEM[..200]), None);
+        assert_eq!(ClientCert::from_pem_header(""), None);
+    }
+
+    #[test]
+    fn test_info_header() {
+        let info = r#"Subject="O=Example,CN=billing.payments.svc.cluster.local";Issuer="O=Example,CN=Example Issuing CA";SerialNumber="20988295479420645121";NB="1735689600";NA="1767225600";SAN="billing.payments.svc.cluster.local,billing,ops@example.com,10.0.0.7,spiffe://cluster.local/ns/payments/sa/billing""#;
+        // Second certificate of the chain must be ignored
+        let chain = format!("{},Subject=\"CN=Example Issuing CA\"", info);
+        let escaped = url::percent_encode(&chain);
+        assert_eq!(
+            ClientCert::from_info_header(&escaped),
+            Some(expected_leaf())
+        );
+    }
+
+    #[test]
+    fn test_info_header_partial_and_malformed() {
+        let cert = ClientCert::from_info_header(r#"Subject="CN=web""#).unwrap();
+        assert_eq!(cert.subject_cn, "web");
+        assert_eq!(cert.issuer_cn, "");
+        assert!(cert.sans.is_empty());
+
+        assert_eq!(ClientCert::from_info_header(r#"Subject="CN=web"#), None);
+        assert_eq!(ClientCert::from_info_header(r#"NB="soon""#), None);
+    }
+
+    #[test]
+    fn test_from_headers_prefers_pem() {
+        let info = r#"Subject="CN=from-info""#;
+        let cert = ClientCert::from_headers(Some(LEAF_PEM), Some(info)).unwrap();
+        assert_eq!(cert.subject_cn, "billing.payments.svc.cluster.local");
+
+        let cert = ClientCert::from_headers(Some("garbage"), Some(info)).unwrap();
+        assert_eq!(cert.subject_cn, "from-info");
+
+        assert_eq!(ClientCert::from_headers(None, None), None);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time((0x17, b"700101000000Z")), Some(0));
+        assert_eq!(parse_time((0x17, b"491231235959Z")), Some(2_524_607_999));
+        assert_eq!(parse_time((0x18, b"20250101000000Z")), Some(1_735_689_600));
+        assert_eq!(parse_time((0x17, b"2501010000Z")), None);
+        assert_eq!(parse_time((0x17, b"250101000000+0100")), None);
+    }
+
+    #[test]
+    fn test_integer_to_decimal() {
+        assert_eq!(integer_to_decimal(&[0x00]), "0");
+        assert_eq!(integer_to_decimal(&[0x00, 0xff]), "255");
+        assert_eq!(
+            integer_to_decimal(&[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01]),
+            "20988295479420645121"
+        );
+    }
+}
@@ -7,16 +7,25 @@
 // are serialized as strings. Custom deserializers handle both native JSON
 // types (u16, bool, map) and Traefik's string-based representations.
 
+use crate::body::MAX_BODY_SIZE;
+use crate::context::{RequestContext, MAX_HEADER_LIST_ITEMS};
+use crate::expr::compiler::{self, Environment};
+use crate::grpc;
+use crate::openapi;
+use crate::rbac;
+use crate::relations::RelationGraph;
+use crate::strip::{self, Cidr};
 use serde::de::{self, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 /// Main plugin configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
-    /// Authorization expression to evaluate
+    /// Authorization expression to evaluate (unless `rules` is used)
+    #[serde(default)]
     pub expression: String,
 
     /// HTTP status code to return when authorization fails
@@ -26,10 +35,150 @@ pub struct Config {
     )]
     pub deny_status_code: u16,
 
-    /// Response body to return when authorization fails
+    /// Response body to return when authorization fails, a template that
+    /// may use `{{method}}`, `{{path}}`, `{{uri}}`, `{{host}}`,
+    /// `{{requestId}}`, `{{rule}}`, `{{reason}}`, `{{status}}` and
+    /// `{{statusText}}`
     #[serde(default = "default_deny_body")]
     pub deny_body: String,
 
+    /// Content-Type of deny responses; also selects how template values
+    /// in the body are escaped
+    #[serde(default)]
+    pub deny_content_type: Option<String>,
+
+    /// Extra response headers of deny responses; values are templates
+    #[serde(default)]
+    pub deny_headers: HashMap<String, String>,
+
+    /// Response header that carries the reason of a denied request
+    #[serde(default)]
+    pub deny_reason_header: Option<String>,
+
+    /// Expression that is true for authenticated requests. Denied requests
+    /// for which it is false get a 401 with a `WWW-Authenticate` challenge
+    /// instead of the deny response.
+    #[serde(default)]
+    pub authenticated: Option<String>,
+
+    /// Challenge sent with 401 responses; required with `authenticated`
+    #[serde(default)]
+    pub www_authenticate: Option<WwwAuthenticate>,
+
+    /// Response body of 401 responses, a template like `denyBody`
+    #[serde(default = "default_unauthenticated_body")]
+    pub unauthenticated_body: String,
+
+    /// Use the reason of a denied request as the response body instead of
+    /// `denyBody`
+    #[serde(default, deserialize_with = "deserialize_bool_from_any")]
+    pub deny_body_reason: bool,
+
+    /// Content negotiation of deny responses between problem details,
+    /// HTML and plain text (`denyBody`)
+    #[serde(default)]
+    pub deny_formats: Option<DenyFormats>,
+
+    /// Redirect denied requests (e.g. browsers without a session) instead
+    /// of returning the deny response
+    #[serde(default)]
+    pub deny_redirect: Option<DenyRedirect>,
+
+    /// HMAC-SHA256 key used by signedCookie() to verify cookie signatures,
+    /// computed over `<name>=<payload>`
+    #[serde(default)]
+    pub cookie_secret: Option<Secret>,
+
+    /// Verification of HMAC-signed identity headers from the upstream auth service
+    #[serde(default)]
+    pub signed_headers: Option<SignedHeaders>,
+
+    /// Verification of presigned (expiring, HMAC-signed) URLs
+    #[serde(default)]
+    pub presigned_urls: Option<PresignedUrls>,
+
+    /// Maximum number of items returned by headerList() and sfList()
+    #[serde(
+        default = "default_max_header_list_items",
+        deserialize_with = "deserialize_u64_from_any"
+    )]
+    pub max_header_list_items: u64,
+
+    /// Maximum request body size in bytes buffered for bodyJson() and formValue()
+    #[serde(
+        default = "default_max_body_size",
+        deserialize_with = "deserialize_u64_from_any"
+    )]
+    pub max_body_size: u64,
+
+    /// Rejection of requests that repeat headers which must be singular
+    #[serde(default)]
+    pub strict_headers: Option<StrictHeaders>,
+
+    /// gRPC status codes returned when gRPC requests are rejected
+    #[serde(default)]
+    pub grpc_status_codes: GrpcStatusCodes,
+
+    /// Named static data tables used by lookup(), lookupList() and inList()
+    #[serde(default)]
+    pub data: HashMap<String, DataTable>,
+
+    /// Roles and permissions used by hasRole() and hasPermission()
+    #[serde(default)]
+    pub rbac: Option<Rbac>,
+
+    /// Relationship tuples (`object#relation@subject`) used by related()
+    #[serde(default)]
+    pub relations: Vec<String>,
+
+    /// OpenAPI document whose security requirements openapiAllowed() enforces
+    #[serde(default)]
+    pub openapi: Option<OpenApi>,
+
+    /// Ordered rules evaluated first-match, instead of `expression`
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+
+    /// Tree of policy sets and rules, instead of `expression` or `rules`
+    #[serde(default)]
+    pub policy: Option<PolicyNode>,
+
+    /// Request header changes applied when a request is allowed
+    #[serde(default)]
+    pub on_allow: Option<OnAllow>,
+
+    /// Headers removed from allowed requests, and optionally from untrusted
+    /// requests before evaluation
+    #[serde(default)]
+    pub strip_headers: Option<StripHeaders>,
+
+    /// Expression evaluated against the request and the response from
+    /// upstream; the response is replaced with a denial when it is false
+    #[serde(default)]
+    pub response_expression: Option<String>,
+
+    /// HTTP status code of responses denied by `responseExpression`
+    #[serde(
+        default = "default_deny_status_code",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub response_deny_status_code: u16,
+
+    /// Response body of responses denied by `responseExpression`, a
+    /// template like `denyBody`
+    #[serde(default = "default_deny_body")]
+    pub response_deny_body: String,
+
+    /// Response headers removed from allowed responses, each set under a
+    /// condition
+    #[serde(default)]
+    pub response_strip_headers: Vec<ResponseStripHeaders>,
+
+    /// Action taken when no rule matches, or the policy tree is not
+    /// applicable
+    #[serde(default)]
+    pub default_action: Action,
+
     /// Test cases to validate at startup
     #[serde(default)]
     pub tests: Vec<TestCase>,
@@ -43,167 +192,1778 @@ fn default_deny_body() -> String {
     "Forbidden".to_string()
 }
 
-/// Test case for validating expressions at startup
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct TestCase {
-    /// Descriptive name for the test
-    pub name: String,
-
-    /// Mock request to test against
-    pub request: TestRequest,
-
-    /// Expected authorization result (true = allow, false = deny)
-    #[serde(deserialize_with = "deserialize_bool_from_any")]
-    pub expect: bool,
+fn default_unauthenticated_body() -> String {
+    "Unauthorized".to_string()
 }
 
-/// Mock HTTP request for testing
-#[derive(Debug, Clone, Serialize, Default)]
-pub struct TestRequest {
-    /// HTTP method (GET, POST, etc.)
-    pub method: String,
-
-    /// Request path
-    pub path: String,
+/// Returns true if `name` is a valid HTTP field name (an RFC 9110 token)
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
 
-    /// Request host
-    pub host: String,
+fn default_max_header_list_items() -> u64 {
+    MAX_HEADER_LIST_ITEMS as u64
+}
 
-    /// Request headers (case-insensitive keys)
-    pub headers: HashMap<String, String>,
+fn default_max_body_size() -> u64 {
+    MAX_BODY_SIZE as u64
 }
 
-/// Deserialize a u16 from either a number or a string.
-/// Traefik serializes YAML numbers as strings (e.g., "403" instead of 403).
-fn deserialize_u16_from_any<'de, D>(deserializer: D) -> Result<u16, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    struct U16Visitor;
+impl Config {
+    /// Validate the configuration and build the expression compile
+    /// environment from it
+    pub fn environment(&self) -> Result<Environment, ConfigError> {
+        let policies = [
+            !self.expression.is_empty(),
+            !self.rules.is_empty(),
+            self.policy.is_some(),
+        ];
+        match policies.iter().filter(|&&set| set).count() {
+            0 => {
+                return Err(ConfigError {
+                    message: "one of expression, rules or policy is required".to_string(),
+                })
+            }
+            1 => {}
+            _ => {
+                return Err(ConfigError {
+                    message: "only one of expression, rules or policy can be used".to_string(),
+                })
+            }
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            rule.validate(i)?;
+        }
+        if let Some(policy) = &self.policy {
+            policy.validate("1", 1)?;
+        }
+        if let Some(header) = &self.deny_reason_header {
+            if !is_header_name(header) {
+                return Err(ConfigError {
+                    message: format!("denyReasonHeader '{}' is not a valid header name", header),
+                });
+            }
+        }
+        if let Some(content_type) = &self.deny_content_type {
+            if content_type.trim().is_empty() || content_type.chars().any(char::is_control) {
+                return Err(ConfigError {
+                    message:
+                        "denyContentType must be a non-empty media type without control characters"
+                            .to_string(),
+                });
+            }
+        }
+        if self.deny_formats.is_some() && self.deny_content_type.is_some() {
+            return Err(ConfigError {
+                message: "denyContentType cannot be used with denyFormats, which negotiates the content type".to_string(),
+            });
+        }
+        for name in self.deny_headers.keys() {
+            if !is_header_name(name) {
+                return Err(ConfigError {
+                    message: format!("denyHeaders: '{}' is not a valid header name", name),
+                });
+            }
+            if [
+                "content-type",
+                "content-length",
+                "transfer-encoding",
+                "x-content-type-options",
+            ]
+            .iter()
+            .any(|reserved| name.eq_ignore_ascii_case(reserved))
+            {
+                return Err(ConfigError {
+                    message: format!(
+                        "denyHeaders: '{}' cannot be set; use denyContentType for the content type",
+                        name
+                    ),
+                });
+            }
+        }
+        if let Some(redirect) = &self.deny_redirect {
+            redirect.validate()?;
+        }
+        if let Some(on_allow) = &self.on_allow {
+            on_allow.validate()?;
+        }
+        if let Some(strip) = &self.strip_headers {
+            strip.validate()?;
+        }
+        for (i, strip) in self.response_strip_headers.iter().enumerate() {
+            strip.validate(i)?;
+        }
+        match (&self.authenticated, &self.www_authenticate) {
+            (Some(authenticated), Some(challenge)) => {
+                if authenticated.trim().is_empty() {
+                    return Err(ConfigError {
+                        message: "authenticated must not be empty".to_string(),
+                    });
+                }
+                challenge.validate()?;
+            }
+            (Some(_), None) => {
+                return Err(ConfigError {
+                    message:
+                        "authenticated requires wwwAuthenticate, the challenge of 401 responses"
+                            .to_string(),
+                })
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError {
+                    message: "wwwAuthenticate requires authenticated".to_string(),
+                })
+            }
+            (None, None) => {}
+        }
+        for test in &self.tests {
+            let error = |message: &str| {
+                Err(ConfigError {
+                    message: format!("tests: '{}' {}", test.name, message),
+                })
+            };
+            match (&test.response, test.expect_response) {
+                (Some(_), None) => return error("sets response but not expectResponse"),
+                (None, Some(_)) => return error("sets expectResponse but not response"),
+                (Some(_), Some(_)) if !test.expect => {
+                    return error("sets expectResponse but expects the request to be denied")
+                }
+                (Some(_), Some(_)) if self.response_expression.is_none() => {
+                    return error("sets expectResponse but responseExpression is not configured")
+                }
+                _ => {}
+            }
+            for (field, set) in [
+                ("expectReason", test.expect_reason.is_some()),
+                ("expectLocation", test.expect_location.is_some()),
+            ] {
+                if test.expect && set {
+                    return Err(ConfigError {
+                        message: format!(
+                            "tests: '{}' sets {} but expects the request to be allowed",
+                            test.name, field
+                        ),
+                    });
+                }
+            }
+        }
+        if let Some(signed) = &self.signed_headers {
+            signed.validate()?;
+        }
+        if let Some(presigned) = &self.presigned_urls {
+            presigned.validate()?;
+        }
+        if let Some(strict) = &self.strict_headers {
+            strict.validate()?;
+        }
+        self.grpc_status_codes.validate()?;
+        for (name, table) in &self.data {
+            table.validate(name)?;
+        }
 
-    impl<'de> Visitor<'de> for U16Visitor {
-        type Value = u16;
+        let max_header_list_items = match usize::try_from(self.max_header_list_items) {
+            Ok(max) if max > 0 => max,
+            _ => {
+                return Err(ConfigError {
+                    message: format!(
+                        "maxHeaderListItems must be between 1 and {}, got {}",
+                        usize::MAX,
+                        self.max_header_list_items
+                    ),
+                })
+            }
+        };
 
-        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            f.write_str("a u16 integer or a string containing a u16 integer")
-        }
+        let max_body_size = match usize::try_from(self.max_body_size) {
+            Ok(max) if max > 0 => max,
+            _ => {
+                return Err(ConfigError {
+                    message: format!(
+                        "maxBodySize must be between 1 and {}, got {}",
+                        usize::MAX,
+                        self.max_body_size
+                    ),
+                })
+            }
+        };
 
-        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u16, E> {
-            u16::try_from(v).map_err(|_| E::custom(format!("u16 out of range: {}", v)))
+        let mut env = Environment {
+            cookie_secret: self.cookie_secret.clone(),
+            signed_headers: self.signed_headers.clone(),
+            presigned_urls: self.presigned_urls.clone(),
+            max_header_list_items,
+            max_body_size,
+            data: self.data.clone(),
+            rbac: None,
+            relations: None,
+            openapi: None,
+            response_phase: false,
+        };
+        if !self.relations.is_empty() {
+            env.relations = Some(RelationGraph::build(&self.relations)?);
         }
-
-        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u16, E> {
-            u16::try_from(v).map_err(|_| E::custom(format!("u16 out of range: {}", v)))
+        if let Some(rbac) = &self.rbac {
+            env.rbac = Some(rbac.compile(&env)?);
         }
-
-        fn visit_str<E: de::Error>(self, v: &str) -> Result<u16, E> {
-            v.parse::<u16>()
-                .map_err(|_| E::custom(format!("invalid u16 string: {:?}", v)))
+        if let Some(openapi) = &self.openapi {
+            env.openapi = Some(openapi.compile(&env)?);
         }
+        Ok(env)
     }
+}
 
-    deserializer.deserialize_any(U16Visitor)
+/// Configuration validation error
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub message: String,
 }
 
-/// Deserialize a bool from either a boolean or a string.
-/// Traefik serializes YAML booleans as strings (e.g., "true" instead of true).
-fn deserialize_bool_from_any<'de, D>(deserializer: D) -> Result<bool, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    struct BoolVisitor;
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Config error: {}", self.message)
+    }
+}
 
-    impl<'de> Visitor<'de> for BoolVisitor {
-        type Value = bool;
+impl std::error::Error for ConfigError {}
 
-        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            f.write_str("a boolean or a string containing a boolean")
-        }
+/// Settings for verifying HMAC-signed identity headers.
+///
+/// The upstream auth service signs the canonical string
+///
+/// ```text
+/// <timestamp>\n
+/// <lowercase header name>:<header values joined by ",">\n   (for each header, in order)
+/// ```
+///
+/// with HMAC-SHA256 and sends the signature (hex or base64) in
+/// `signatureHeader` and the Unix timestamp in `timestampHeader`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedHeaders {
+    /// Headers covered by the signature, in canonical order
+    pub headers: Vec<String>,
 
-        fn visit_bool<E: de::Error>(self, v: bool) -> Result<bool, E> {
-            Ok(v)
-        }
+    /// Header carrying the signature
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
 
-        fn visit_str<E: de::Error>(self, v: &str) -> Result<bool, E> {
-            match v {
-                "true" => Ok(true),
-                "false" => Ok(false),
-                _ => Err(E::custom(format!("invalid bool string: {:?}", v))),
-            }
-        }
-    }
+    /// Header carrying the signing time in Unix seconds
+    #[serde(default = "default_timestamp_header")]
+    pub timestamp_header: String,
 
-    deserializer.deserialize_any(BoolVisitor)
-}
+    /// Shared HMAC-SHA256 key
+    pub secret: Secret,
 
-/// Custom deserializer for TestRequest that handles Traefik's serialization quirks.
-/// Traefik serializes empty YAML maps `{}` as empty strings `""`.
-impl<'de> Deserialize<'de> for TestRequest {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field {
-            Method,
-            Path,
-            Host,
-            Headers,
-        }
+    /// Maximum accepted age (and clock skew) of the timestamp in seconds
+    #[serde(
+        default = "default_signature_max_age",
+        deserialize_with = "deserialize_u64_from_any"
+    )]
+    pub max_age: u64,
 
-        struct TestRequestVisitor;
+    /// Deny requests with an invalid signature before evaluating the expression
+    #[serde(default, deserialize_with = "deserialize_bool_from_any")]
+    pub enforce: bool,
 
-        impl<'de> Visitor<'de> for TestRequestVisitor {
-            type Value = TestRequest;
+    /// HTTP status code returned when `enforce` is set and the signature is invalid
+    #[serde(
+        default = "default_signature_deny_status_code",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub deny_status_code: u16,
+}
 
-            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str("a TestRequest object")
-            }
+fn default_signature_header() -> String {
+    "X-Auth-Signature".to_string()
+}
 
-            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<TestRequest, M::Error> {
-                let mut method = None;
-                let mut path = None;
-                let mut host = None;
-                let mut headers = None;
+fn default_timestamp_header() -> String {
+    "X-Auth-Timestamp".to_string()
+}
 
-                while let Some(key) = map.next_key::<Field>()? {
-                    match key {
-                        Field::Method => method = Some(map.next_value()?),
-                        Field::Path => path = Some(map.next_value()?),
-                        Field::Host => host = Some(map.next_value()?),
-                        Field::Headers => {
-                            // Traefik serializes empty maps as empty strings.
-                            headers = Some(map.next_value::<HeadersOrString>()?);
-                        }
-                    }
-                }
+fn default_signature_max_age() -> u64 {
+    300
+}
 
-                Ok(TestRequest {
-                    method: method.unwrap_or_default(),
-                    path: path.unwrap_or_default(),
-                    host: host.unwrap_or_default(),
-                    headers: headers.map(|h| h.into_map()).unwrap_or_default(),
-                })
-            }
+fn default_signature_deny_status_code() -> u16 {
+    401
+}
+
+impl SignedHeaders {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: &str| {
+            Err(ConfigError {
+                message: format!("signedHeaders: {}", message),
+            })
+        };
+
+        if self.headers.is_empty() {
+            return error("headers must not be empty");
+        }
+        if self.secret.as_bytes().is_empty() {
+            return error("secret must not be empty");
         }
+        if self.signature_header.is_empty() || self.timestamp_header.is_empty() {
+            return error("signatureHeader and timestampHeader must not be empty");
+        }
+        if self.max_age == 0 {
+            return error("maxAge must be greater than 0");
+        }
+        Ok(())
+    }
+}
 
-        deserializer.deserialize_map(TestRequestVisitor)
+/// Secret key material. The value is redacted from Debug output so that
+/// it cannot leak into logs.
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Raw key bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
     }
 }
 
-/// Helper to deserialize headers as either a map or an empty string.
-enum HeadersOrString {
-    Map(HashMap<String, String>),
-    Empty,
+impl From<&str> for Secret {
+    fn from(s: &str) -> Self {
+        Secret(s.to_string())
+    }
 }
 
-impl HeadersOrString {
-    fn into_map(self) -> HashMap<String, String> {
-        match self {
-            HeadersOrString::Map(m) => m,
-            HeadersOrString::Empty => HashMap::new(),
-        }
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Settings for verifying presigned URLs.
+///
+/// A presigned URL carries an expiry time and an HMAC-SHA256 signature in
+/// its query parameters. The signature covers the canonical string
+///
+/// ```text
+/// <METHOD>\n<path>\n<canonical query>
+/// ```
+///
+/// where the canonical query contains every parameter except the signature,
+/// sorted by name then value, percent-encoded (RFC 3986 unreserved set) and
+/// joined as `name=value` pairs with `&`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignedUrls {
+    /// Shared HMAC-SHA256 key
+    pub secret: Secret,
+
+    /// Query parameter holding the expiry time in Unix seconds
+    #[serde(default = "default_expires_param")]
+    pub expires_param: String,
+
+    /// Query parameter holding the signature (hex or base64)
+    #[serde(default = "default_signature_param")]
+    pub signature_param: String,
+}
+
+fn default_expires_param() -> String {
+    "expires".to_string()
+}
+
+fn default_signature_param() -> String {
+    "signature".to_string()
+}
+
+impl PresignedUrls {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: &str| {
+            Err(ConfigError {
+                message: format!("presignedUrls: {}", message),
+            })
+        };
+
+        if self.secret.as_bytes().is_empty() {
+            return error("secret must not be empty");
+        }
+        if self.expires_param.is_empty() || self.signature_param.is_empty() {
+            return error("expiresParam and signatureParam must not be empty");
+        }
+        if self.expires_param == self.signature_param {
+            return error("expiresParam and signatureParam must differ");
+        }
+        Ok(())
+    }
+}
+
+/// Deny response formats chosen by the request's `Accept` header.
+///
+/// Problem details (RFC 9457) are returned to API clients, the `html` page
+/// to browsers, and `denyBody` (or a rule's `denyBody`) as plain text to
+/// clients that ask for it. All values are templates.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DenyFormats {
+    /// Members of the problem details document
+    #[serde(default)]
+    pub problem: ProblemDetails,
+
+    /// HTML page; template values are escaped as markup
+    #[serde(default = "default_deny_html")]
+    pub html: String,
+}
+
+impl Default for DenyFormats {
+    fn default() -> Self {
+        DenyFormats {
+            problem: ProblemDetails::default(),
+            html: default_deny_html(),
+        }
+    }
+}
+
+fn default_deny_html() -> String {
+    "<!DOCTYPE html>\n<html><head><title>{{status}} {{statusText}}</title></head>\
+     <body><h1>{{statusText}}</h1><p>{{reason}}</p></body></html>\n"
+        .to_string()
+}
+
+/// Members of a problem details document. `status` is the deny status
+/// code; members other than `type` are left out when they render empty.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProblemDetails {
+    /// URI reference identifying the problem type
+    #[serde(rename = "type", default = "default_problem_type")]
+    pub problem_type: String,
+
+    /// Short summary of the problem type
+    #[serde(default = "default_problem_title")]
+    pub title: String,
+
+    /// Explanation specific to this request
+    #[serde(default = "default_problem_detail")]
+    pub detail: String,
+
+    /// URI reference identifying this occurrence
+    #[serde(default = "default_problem_instance")]
+    pub instance: String,
+}
+
+impl Default for ProblemDetails {
+    fn default() -> Self {
+        ProblemDetails {
+            problem_type: default_problem_type(),
+            title: default_problem_title(),
+            detail: default_problem_detail(),
+            instance: default_problem_instance(),
+        }
+    }
+}
+
+fn default_problem_type() -> String {
+    "about:blank".to_string()
+}
+
+fn default_problem_title() -> String {
+    "{{statusText}}".to_string()
+}
+
+fn default_problem_detail() -> String {
+    "{{reason}}".to_string()
+}
+
+fn default_problem_instance() -> String {
+    "{{path}}".to_string()
+}
+
+/// Redirect of denied requests, typically browsers to a login page.
+///
+/// `url` is a template whose values are percent-encoded, so
+/// `?return_to={{uri}}` carries the original path and query safely. The
+/// redirect only applies when `when` is true for the request; otherwise
+/// the deny response is returned.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DenyRedirect {
+    /// Location template
+    pub url: String,
+
+    /// Expression that must be true for the redirect to apply (always, if
+    /// not set)
+    #[serde(default)]
+    pub when: Option<String>,
+
+    /// Redirect status code: 301, 302, 303, 307 or 308
+    #[serde(
+        default = "default_redirect_status_code",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub status_code: u16,
+}
+
+fn default_redirect_status_code() -> u16 {
+    302
+}
+
+impl DenyRedirect {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: &str| {
+            Err(ConfigError {
+                message: format!("denyRedirect: {}", message),
+            })
+        };
+        if self.url.trim().is_empty() {
+            return error("url must not be empty");
+        }
+        if !matches!(self.status_code, 301 | 302 | 303 | 307 | 308) {
+            return error("statusCode must be 301, 302, 303, 307 or 308");
+        }
+        if self
+            .when
+            .as_ref()
+            .is_some_and(|when| when.trim().is_empty())
+        {
+            return error("when must not be empty");
+        }
+        Ok(())
+    }
+}
+
+/// Request header changes for the upstream of allowed requests.
+///
+/// `set` maps header names to expressions of type string (or int) that are
+/// evaluated against the request; an empty value removes the header, so a
+/// value sent by the client is never forwarded in its place.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnAllow {
+    /// Headers to set, with the expression of each value
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+
+    /// Headers to remove
+    #[serde(default)]
+    pub remove: Vec<String>,
+
+    /// Header set to the label of the rule that allowed the request
+    #[serde(default)]
+    pub rule_header: Option<String>,
+}
+
+impl OnAllow {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: String| {
+            Err(ConfigError {
+                message: format!("onAllow: {}", message),
+            })
+        };
+        let names = self.set.keys().chain(&self.remove).chain(&self.rule_header);
+        let mut seen = HashSet::new();
+        for name in names {
+            if !is_header_name(name) {
+                return error(format!("'{}' is not a valid header name", name));
+            }
+            if ["content-length", "transfer-encoding"]
+                .iter()
+                .any(|framing| name.eq_ignore_ascii_case(framing))
+            {
+                return error(format!("'{}' cannot be changed", name));
+            }
+            if !seen.insert(name.to_ascii_lowercase()) {
+                return error(format!("header '{}' is changed more than once", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Authentication scheme of a `WWW-Authenticate` challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuthScheme {
+    /// OAuth 2.0 bearer tokens (RFC 6750)
+    Bearer,
+    /// HTTP Basic authentication (RFC 7617)
+    Basic,
+}
+
+/// `WWW-Authenticate` challenge of 401 responses
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WwwAuthenticate {
+    /// Authentication scheme
+    pub scheme: AuthScheme,
+
+    /// Protection space
+    pub realm: String,
+
+    /// Bearer only: space-separated scopes needed to access the resource
+    #[serde(default)]
+    pub scope: Option<String>,
+
+    /// Bearer only: error code, e.g. `invalid_token`
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl WwwAuthenticate {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: String| {
+            Err(ConfigError {
+                message: format!("wwwAuthenticate: {}", message),
+            })
+        };
+        if self.scheme == AuthScheme::Basic && (self.scope.is_some() || self.error.is_some()) {
+            return error("scope and error are only allowed with the Bearer scheme".to_string());
+        }
+        // Values are sent as quoted strings; RFC 6750 restricts scope and
+        // error to printable ASCII without '"' and '\'
+        let params = [
+            ("realm", Some(&self.realm)),
+            ("scope", self.scope.as_ref()),
+            ("error", self.error.as_ref()),
+        ];
+        for (name, value) in params {
+            if let Some(value) = value {
+                if value.is_empty()
+                    || !value
+                        .bytes()
+                        .all(|b| (b' '..=b'~').contains(&b) && b != b'"' && b != b'\\')
+                {
+                    return error(format!(
+                        "{} must be non-empty printable ASCII without '\"' or '\\'",
+                        name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The header value, e.g. `Bearer realm="api", scope="read"`
+    pub fn header_value(&self) -> String {
+        let scheme = match self.scheme {
+            AuthScheme::Bearer => "Bearer",
+            AuthScheme::Basic => "Basic",
+        };
+        let mut value = format!("{} realm=\"{}\"", scheme, self.realm);
+        if let Some(scope) = &self.scope {
+            value.push_str(&format!(", scope=\"{}\"", scope));
+        }
+        if let Some(error) = &self.error {
+            value.push_str(&format!(", error=\"{}\"", error));
+        }
+        value
+    }
+}
+
+/// Settings for rejecting ambiguous identity headers.
+///
+/// A header like `X-Auth-User` must carry exactly one value. If a request
+/// sends it on several field lines, the plugin and the backend might not
+/// agree on which value counts (header smuggling), so the request is
+/// denied before the expression is evaluated.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StrictHeaders {
+    /// Headers that may appear at most once (case-insensitive)
+    pub singular: Vec<String>,
+
+    /// HTTP status code returned when a singular header is repeated
+    #[serde(
+        default = "default_strict_headers_deny_status_code",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub deny_status_code: u16,
+}
+
+fn default_strict_headers_deny_status_code() -> u16 {
+    400
+}
+
+impl StrictHeaders {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.singular.is_empty() || self.singular.iter().any(|h| h.is_empty()) {
+            return Err(ConfigError {
+                message: "strictHeaders: singular must list non-empty header names".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the first singular header that the request repeats
+    pub fn repeated_header(&self, ctx: &RequestContext) -> Option<&str> {
+        self.singular
+            .iter()
+            .find(|name| ctx.header_values(name).len() > 1)
+            .map(String::as_str)
+    }
+}
+
+/// Headers kept from backends, such as raw identity headers or headers
+/// that only the policy reads.
+///
+/// ```yaml
+/// stripHeaders:
+///   headers: [X-Auth-*, X-Policy-Debug]
+///   beforeEvaluation: true
+///   trustedSources: [10.0.0.0/8]
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StripHeaders {
+    /// Header names or `*` glob patterns (case-insensitive), removed from
+    /// allowed requests after evaluation
+    pub headers: Vec<String>,
+
+    /// Also remove the headers before evaluation, so the policy never sees
+    /// values sent by untrusted clients
+    #[serde(default, deserialize_with = "deserialize_bool_from_any")]
+    pub before_evaluation: bool,
+
+    /// Client addresses or CIDR networks whose headers are kept for
+    /// evaluation, e.g. an authentication proxy that sets them
+    #[serde(default)]
+    pub trusted_sources: Vec<Cidr>,
+}
+
+impl StripHeaders {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |message: String| {
+            Err(ConfigError {
+                message: format!("stripHeaders: {}", message),
+            })
+        };
+        if self.headers.is_empty() {
+            return error("headers must list header names or patterns".to_string());
+        }
+        for pattern in &self.headers {
+            if !is_header_name(pattern) {
+                return error(format!(
+                    "'{}' is not a valid header name or pattern",
+                    pattern
+                ));
+            }
+            if let Some(reserved) = ["host", "content-length", "transfer-encoding"]
+                .iter()
+                .find(|reserved| strip::matches(pattern, reserved))
+            {
+                return error(format!("'{}' must not match '{}'", pattern, reserved));
+            }
+        }
+        if !self.trusted_sources.is_empty() && !self.before_evaluation {
+            return error("trustedSources requires beforeEvaluation".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns true if a header name matches one of the patterns
+    pub fn strips(&self, name: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|pattern| strip::matches(pattern, name))
+    }
+
+    /// Returns true if the headers must be removed before evaluation: when
+    /// `beforeEvaluation` is set and the client is not a trusted source. A
+    /// source address that cannot be parsed is not trusted.
+    pub fn strips_before_evaluation(&self, ctx: &RequestContext) -> bool {
+        self.before_evaluation
+            && !strip::source_ip(&ctx.source_addr)
+                .is_some_and(|ip| self.trusted_sources.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+/// Response headers removed from allowed responses, such as debug headers
+/// that only admins should see.
+///
+/// ```yaml
+/// responseStripHeaders:
+///   - headers: [X-Debug-*, Server-Timing]
+///     when: NOT contains(headerList("X-Auth-User-Teams"), "admins")
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseStripHeaders {
+    /// Header names or `*` glob patterns (case-insensitive)
+    pub headers: Vec<String>,
+
+    /// Response expression that must be true for the headers to be
+    /// removed; they are always removed without one
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+impl ResponseStripHeaders {
+    fn validate(&self, index: usize) -> Result<(), ConfigError> {
+        let error = |message: String| {
+            Err(ConfigError {
+                message: format!("responseStripHeaders #{}: {}", index + 1, message),
+            })
+        };
+        if self.headers.is_empty() {
+            return error("headers must list header names or patterns".to_string());
+        }
+        for pattern in &self.headers {
+            if !is_header_name(pattern) {
+                return error(format!(
+                    "'{}' is not a valid header name or pattern",
+                    pattern
+                ));
+            }
+            if let Some(framing) = ["content-length", "transfer-encoding"]
+                .iter()
+                .find(|framing| strip::matches(pattern, framing))
+            {
+                return error(format!("'{}' must not match '{}'", pattern, framing));
+            }
+        }
+        if self
+            .when
+            .as_ref()
+            .is_some_and(|when| when.trim().is_empty())
+        {
+            return error("when must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Role-based access control settings.
+///
+/// ```yaml
+/// rbac:
+///   userRoles: headerList("X-Auth-User-Roles")
+///   roles:
+///     viewer: {permissions: ["orders:read"]}
+///     editor: {inherits: [viewer], permissions: ["orders:write"]}
+///     admin: {inherits: [editor]}
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rbac {
+    /// Expression of type `[]string` that yields the user's roles
+    pub user_roles: String,
+
+    /// Role definitions by name
+    pub roles: HashMap<String, Role>,
+}
+
+/// A role: the roles it inherits and the permissions it grants
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Role {
+    /// Roles whose roles and permissions this role also has
+    #[serde(default)]
+    pub inherits: Vec<String>,
+
+    /// Permission strings granted by this role, e.g. `orders:write`
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+impl Rbac {
+    /// Validate and flatten the role graph and compile `userRoles`. The
+    /// expression is compiled without RBAC, so it cannot use hasRole().
+    fn compile(&self, env: &Environment) -> Result<rbac::Policy, ConfigError> {
+        let graph = rbac::RoleGraph::build(&self.roles)?;
+        let user_roles =
+            compiler::compile_list(&self.user_roles, env).map_err(|e| ConfigError {
+                message: format!("rbac: userRoles: {}", e.message),
+            })?;
+        Ok(rbac::Policy { user_roles, graph })
+    }
+}
+
+/// A rule of an ordered policy. Rules are tried in order; the first rule
+/// whose matcher and expression both match the request decides it.
+///
+/// ```yaml
+/// rules:
+///   - name: health
+///     match: {paths: ["/health"], methods: [GET]}
+///     action: allow
+///   - name: admin
+///     match: {paths: ["/admin/*"]}
+///     expression: NOT hasRole("admin")
+///     action: deny
+///     denyStatusCode: 404
+///     denyBody: Not Found
+/// defaultAction: allow
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    /// Name used in logs and startup test output
+    #[serde(default)]
+    pub name: String,
+
+    /// Requests the rule applies to; an empty matcher matches all requests
+    #[serde(default, rename = "match")]
+    pub matcher: RuleMatch,
+
+    /// Condition that must also be true for the rule to match; a rule
+    /// without one matches on its matcher alone
+    #[serde(default)]
+    pub expression: Option<String>,
+
+    /// Action taken when the rule matches
+    pub action: Action,
+
+    /// HTTP status code when the rule denies (defaults to `denyStatusCode`)
+    #[serde(default, deserialize_with = "deserialize_option_u16_from_any")]
+    pub deny_status_code: Option<u16>,
+
+    /// Response body when the rule denies (defaults to `denyBody`)
+    #[serde(default)]
+    pub deny_body: Option<String>,
+}
+
+/// Host, path and method matcher of a rule. Each non-empty list must
+/// contain a pattern that matches the request.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleMatch {
+    /// Hosts, case-insensitive, without port; `*.example.com` matches any
+    /// subdomain of example.com
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    /// Paths: exact (`/health`), or prefixes ending in `*` (`/api/*`
+    /// matches `/api` and everything below it)
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    /// HTTP methods, case-insensitive
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl Rule {
+    /// Name for messages: the configured name, or the 1-based position
+    pub fn label(&self, index: usize) -> String {
+        if self.name.is_empty() {
+            format!("rule #{}", index + 1)
+        } else {
+            format!("rule '{}'", self.name)
+        }
+    }
+
+    fn validate(&self, index: usize) -> Result<(), ConfigError> {
+        let error = |message: String| {
+            Err(ConfigError {
+                message: format!("rules: {} {}", self.label(index), message),
+            })
+        };
+        let m = &self.matcher;
+        if let Some(host) = m.hosts.iter().find(|h| {
+            let name = h.strip_prefix("*.").unwrap_or(h);
+            name.is_empty() || name.contains(['*', '/', ':'])
+        }) {
+            return error(format!("has an invalid host pattern '{}'", host));
+        }
+        if let Some(path) = m.paths.iter().find(|p| {
+            let prefix = p.strip_suffix('*').unwrap_or(p);
+            !p.starts_with('/') || prefix.contains('*')
+        }) {
+            return error(format!("has an invalid path pattern '{}'", path));
+        }
+        if m.methods.iter().any(|method| method.is_empty()) {
+            return error("has an empty method".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Maximum nesting depth of a policy tree
+pub const MAX_POLICY_DEPTH: usize = 16;
+
+/// A node of a policy tree: either a policy set, which combines the
+/// decisions of its children, or a rule, which has an effect. Both may
+/// have a target that decides whether they apply to a request.
+///
+/// ```yaml
+/// policy:
+///   combine: deny-overrides
+///   children:
+///     - name: suspended
+///       condition: header("X-Tenant-Status") == "suspended"
+///       effect: deny
+///     - target: path startsWith "/api/"
+///       combine: first-applicable
+///       children:
+///         - condition: hasRole("admin")
+///           effect: permit
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyNode {
+    /// Name used in logs and startup test output
+    #[serde(default)]
+    pub name: String,
+
+    /// Expression deciding whether the node applies; absent applies to
+    /// all requests
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// Combining algorithm of a policy set
+    #[serde(default)]
+    pub combine: Option<CombiningAlgorithm>,
+
+    /// Children of a policy set
+    #[serde(default)]
+    pub children: Vec<PolicyNode>,
+
+    /// Condition of a rule, evaluated when its target applies
+    #[serde(default)]
+    pub condition: Option<String>,
+
+    /// Effect of a rule
+    #[serde(default)]
+    pub effect: Option<Action>,
+
+    /// HTTP status code when the rule denies (defaults to `denyStatusCode`)
+    #[serde(default, deserialize_with = "deserialize_option_u16_from_any")]
+    pub deny_status_code: Option<u16>,
+
+    /// Response body when the rule denies (defaults to `denyBody`)
+    #[serde(default)]
+    pub deny_body: Option<String>,
+}
+
+/// How a policy set combines the decisions of its children (XACML 3.0
+/// appendix C)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CombiningAlgorithm {
+    DenyOverrides,
+    PermitOverrides,
+    FirstApplicable,
+    OnlyOneApplicable,
+}
+
+impl PolicyNode {
+    /// Name for messages: the configured name, or the position in the
+    /// tree (`1.2` is the second child of the root)
+    pub fn label(&self, position: &str) -> String {
+        let kind = if self.effect.is_some() {
+            "rule"
+        } else {
+            "policy set"
+        };
+        if self.name.is_empty() {
+            format!("{} {}", kind, position)
+        } else {
+            format!("{} '{}'", kind, self.name)
+        }
+    }
+
+    fn validate(&self, position: &str, depth: usize) -> Result<(), ConfigError> {
+        let error = |message: &str| {
+            Err(ConfigError {
+                message: format!("policy: {} {}", self.label(position), message),
+            })
+        };
+        if depth > MAX_POLICY_DEPTH {
+            return error(&format!(
+                "is nested deeper than {} levels",
+                MAX_POLICY_DEPTH
+            ));
+        }
+        match (self.combine, self.effect) {
+            (Some(_), None) => {
+                if self.children.is_empty() {
+                    return error("has no children");
+                }
+                if self.condition.is_some()
+                    || self.deny_status_code.is_some()
+                    || self.deny_body.is_some()
+                {
+                    return error("is a policy set and cannot have a condition or deny response");
+                }
+                for (i, child) in self.children.iter().enumerate() {
+                    child.validate(&format!("{}.{}", position, i + 1), depth + 1)?;
+                }
+                Ok(())
+            }
+            (None, Some(_)) if self.children.is_empty() => Ok(()),
+            (None, Some(_)) => error("is a rule and cannot have children"),
+            _ => error("must have either combine and children, or an effect"),
+        }
+    }
+}
+
+/// OpenAPI-driven authorization settings.
+///
+/// ```yaml
+/// openapi:
+///   document: '{"openapi": "3.0.3", "paths": {...}, ...}'
+///   scopes: headerList("X-Auth-Scopes", " ")
+///   unknownOperation: deny
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApi {
+    /// OpenAPI 3 document, as a JSON object or a string containing JSON
+    #[serde(deserialize_with = "deserialize_json_document")]
+    pub document: serde_json::Value,
+
+    /// Expression of type `[]string` that yields the caller's scopes
+    pub scopes: String,
+
+    /// Action for requests that match no operation in the document
+    #[serde(default)]
+    pub unknown_operation: Action,
+}
+
+/// Whether to allow or deny a request. `permit` is accepted for `allow`,
+/// as in XACML policies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[serde(alias = "permit")]
+    Allow,
+    #[default]
+    Deny,
+}
+
+impl OpenApi {
+    /// Build the route table and compile `scopes`
+    fn compile(&self, env: &Environment) -> Result<openapi::Policy, ConfigError> {
+        let routes = openapi::RouteTable::from_document(&self.document)?;
+        let scopes = compiler::compile_list(&self.scopes, env).map_err(|e| ConfigError {
+            message: format!("openapi: scopes: {}", e.message),
+        })?;
+        Ok(openapi::Policy {
+            routes,
+            scopes,
+            allow_unknown: self.unknown_operation == Action::Allow,
+        })
+    }
+}
+
+/// Deserialize a JSON document given either inline or as a JSON string,
+/// since Traefik passes YAML configuration values as strings
+fn deserialize_json_document<'de, D>(deserializer: D) -> Result<serde_json::Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => serde_json::from_str(&s).map_err(de::Error::custom),
+        value => Ok(value),
+    }
+}
+
+/// A named table of static data, so that mappings that change
+/// independently of the policy logic can live in configuration.
+///
+/// ```yaml
+/// data:
+///   adminTeams: [platform-eng, sre]         # string list
+///   costCenters: {payments: CC-100}         # string map
+///   owners: {payments: [alice, bob]}        # map of lists
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DataTable {
+    /// Set of strings, used by inList()
+    List(Vec<String>),
+    /// String to string map, used by lookup()
+    Map(HashMap<String, String>),
+    /// String to string list map, used by lookupList()
+    MapOfLists(HashMap<String, Vec<String>>),
+}
+
+impl DataTable {
+    /// Kind of table, as used in error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DataTable::List(_) => "list",
+            DataTable::Map(_) => "map",
+            DataTable::MapOfLists(_) => "map of lists",
+        }
+    }
+
+    fn validate(&self, name: &str) -> Result<(), ConfigError> {
+        let error = |message: &str| {
+            Err(ConfigError {
+                message: format!("data: table '{}' {}", name, message),
+            })
+        };
+
+        if name.is_empty() {
+            return Err(ConfigError {
+                message: "data: table names must not be empty".to_string(),
+            });
+        }
+        let empty_key = match self {
+            DataTable::List(_) => false,
+            DataTable::Map(map) => map.contains_key(""),
+            DataTable::MapOfLists(map) => map.contains_key(""),
+        };
+        if empty_key {
+            return error("has an empty key");
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for DataTable {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        /// A map value: string or list of strings
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Str(String),
+            List(Vec<String>),
+        }
+
+        struct DataTableVisitor;
+
+        impl<'de> Visitor<'de> for DataTableVisitor {
+            type Value = DataTable;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of strings, a map of strings or a map of string lists")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<DataTable, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(DataTable::List(items))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<DataTable, M::Error> {
+                let mut strings = HashMap::new();
+                let mut lists = HashMap::new();
+                while let Some((key, entry)) = map.next_entry::<String, Entry>()? {
+                    match entry {
+                        Entry::Str(value) => {
+                            strings.insert(key, value);
+                        }
+                        Entry::List(values) => {
+                            lists.insert(key, values);
+                        }
+                    }
+                }
+                match (strings.is_empty(), lists.is_empty()) {
+                    (_, true) => Ok(DataTable::Map(strings)),
+                    (true, false) => Ok(DataTable::MapOfLists(lists)),
+                    (false, false) => Err(de::Error::custom(
+                        "data table values must be all strings or all lists",
+                    )),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(DataTableVisitor)
+    }
+}
+
+/// Why a request is rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyReason {
+    /// The expression evaluated to false
+    Denied,
+    /// The expression evaluated to false for an unauthenticated request
+    Unauthenticated,
+    /// Enforced signed headers did not verify
+    InvalidSignature,
+    /// A singular header was repeated
+    RepeatedHeader,
+    /// The expression failed to evaluate
+    Error,
+}
+
+/// gRPC status codes returned for each deny reason. gRPC requests are
+/// rejected with HTTP 200 and these codes in `grpc-status` instead of the
+/// HTTP deny status, which gRPC clients cannot interpret.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrpcStatusCodes {
+    /// Requests denied by the expression
+    #[serde(
+        default = "default_grpc_denied",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub denied: u16,
+
+    /// Denied requests that are not authenticated
+    #[serde(
+        default = "default_grpc_unauthenticated",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub unauthenticated: u16,
+
+    /// Requests whose enforced signed headers do not verify
+    #[serde(
+        default = "default_grpc_invalid_signature",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub invalid_signature: u16,
+
+    /// Requests that repeat a singular header
+    #[serde(
+        default = "default_grpc_repeated_header",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub repeated_header: u16,
+
+    /// Requests whose expression evaluation failed
+    #[serde(
+        default = "default_grpc_error",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub error: u16,
+}
+
+fn default_grpc_denied() -> u16 {
+    grpc::PERMISSION_DENIED
+}
+
+fn default_grpc_unauthenticated() -> u16 {
+    grpc::UNAUTHENTICATED
+}
+
+fn default_grpc_invalid_signature() -> u16 {
+    grpc::UNAUTHENTICATED
+}
+
+fn default_grpc_repeated_header() -> u16 {
+    grpc::INVALID_ARGUMENT
+}
+
+fn default_grpc_error() -> u16 {
+    grpc::INTERNAL
+}
+
+impl Default for GrpcStatusCodes {
+    fn default() -> Self {
+        GrpcStatusCodes {
+            denied: default_grpc_denied(),
+            unauthenticated: default_grpc_unauthenticated(),
+            invalid_signature: default_grpc_invalid_signature(),
+            repeated_header: default_grpc_repeated_header(),
+            error: default_grpc_error(),
+        }
+    }
+}
+
+impl GrpcStatusCodes {
+    fn validate(&self) -> Result<(), ConfigError> {
+        // Code 0 (OK) would tell the client that the call succeeded
+        let codes = [
+            self.denied,
+            self.unauthenticated,
+            self.invalid_signature,
+            self.repeated_header,
+            self.error,
+        ];
+        if let Some(code) = codes.iter().find(|code| !(1..=16).contains(*code)) {
+            return Err(ConfigError {
+                message: format!(
+                    "grpcStatusCodes: codes must be between 1 and 16, got {}",
+                    code
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// The gRPC status code for a deny reason
+    pub fn code(&self, reason: DenyReason) -> u16 {
+        match reason {
+            DenyReason::Denied => self.denied,
+            DenyReason::Unauthenticated => self.unauthenticated,
+            DenyReason::InvalidSignature => self.invalid_signature,
+            DenyReason::RepeatedHeader => self.repeated_header,
+            DenyReason::Error => self.error,
+        }
+    }
+}
+
+/// Test case for validating expressions at startup
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TestCase {
+    /// Descriptive name for the test
+    pub name: String,
+
+    /// Mock request to test against
+    pub request: TestRequest,
+
+    /// Expected authorization result (true = allow, false = deny)
+    #[serde(deserialize_with = "deserialize_bool_from_any")]
+    pub expect: bool,
+
+    /// Expected deny reason, for a test that expects a denial
+    #[serde(default, rename = "expectReason")]
+    pub expect_reason: Option<String>,
+
+    /// Expected `Location` of a redirected denial
+    #[serde(default, rename = "expectLocation")]
+    pub expect_location: Option<String>,
+
+    /// Mock response from upstream, for a test of `responseExpression`
+    #[serde(default)]
+    pub response: Option<TestResponse>,
+
+    /// Expected result of `responseExpression` for the mock response
+    #[serde(
+        default,
+        rename = "expectResponse",
+        deserialize_with = "deserialize_option_bool_from_any"
+    )]
+    pub expect_response: Option<bool>,
+}
+
+/// Result of running a test case against the policy
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestResult {
+    /// Whether the request was allowed
+    pub allowed: bool,
+
+    /// Rule, policy set or check that decided
+    pub decided_by: String,
+
+    /// Deny reason of the decision, if any
+    pub reason: Option<String>,
+
+    /// `Location` of a redirected denial
+    pub location: Option<String>,
+
+    /// Whether `responseExpression` accepted the mock response, for an
+    /// allowed test case with a response
+    pub response: Option<bool>,
+}
+
+impl TestCase {
+    /// Compare a result with the expectations of the test case. Returns a
+    /// description of the first mismatch, or None if the test passed.
+    pub fn failure(&self, result: &TestResult) -> Option<String> {
+        if result.allowed != self.expect {
+            return Some(format!(
+                "got {}, expected {}{}",
+                result.allowed,
+                self.expect,
+                result
+                    .reason
+                    .as_ref()
+                    .map_or(String::new(), |r| format!(" (reason: {})", r))
+            ));
+        }
+        if let Some(expected) = self
+            .expect_reason
+            .as_ref()
+            .filter(|expected| result.reason.as_ref() != Some(*expected))
+        {
+            return Some(format!(
+                "got reason {}, expected '{}'",
+                result
+                    .reason
+                    .as_ref()
+                    .map_or("none".to_string(), |r| format!("'{}'", r)),
+                expected
+            ));
+        }
+        if let Some(expected) = self
+            .expect_location
+            .as_ref()
+            .filter(|expected| result.location.as_ref() != Some(*expected))
+        {
+            return Some(format!(
+                "got location {}, expected '{}'",
+                result
+                    .location
+                    .as_ref()
+                    .map_or("none".to_string(), |l| format!("'{}'", l)),
+                expected
+            ));
+        }
+        if let Some(expected) = self
+            .expect_response
+            .filter(|expected| result.response != Some(*expected))
+        {
+            return Some(format!(
+                "got response {}, expected {}",
+                result
+                    .response
+                    .map_or("none".to_string(), |r| r.to_string()),
+                expected
+            ));
+        }
+        None
+    }
+}
+
+/// Mock upstream response for testing
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TestResponse {
+    /// HTTP status code
+    #[serde(
+        default = "default_test_response_status",
+        deserialize_with = "deserialize_u16_from_any"
+    )]
+    pub status: u16,
+
+    /// Response headers (case-insensitive keys)
+    #[serde(default, deserialize_with = "deserialize_headers_or_string")]
+    pub headers: HashMap<String, String>,
+}
+
+fn default_test_response_status() -> u16 {
+    200
+}
+
+/// Mock HTTP request for testing
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct TestRequest {
+    /// HTTP method (GET, POST, etc.)
+    pub method: String,
+
+    /// Request path
+    pub path: String,
+
+    /// Request host
+    pub host: String,
+
+    /// Request headers (case-insensitive keys)
+    pub headers: HashMap<String, String>,
+
+    /// Request cookies (case-sensitive names), merged with any `Cookie` header
+    pub cookies: HashMap<String, String>,
+
+    /// Request time in Unix seconds (defaults to the current time)
+    pub time: Option<u64>,
+
+    /// Request body (set a matching `Content-Type` header to use it)
+    pub body: Option<String>,
+
+    /// Client address, `ip:port` or a bare IP, checked against
+    /// `stripHeaders.trustedSources`
+    #[serde(rename = "sourceAddr")]
+    pub source_addr: String,
+}
+
+/// Deserialize a u16 from either a number or a string.
+/// Traefik serializes YAML numbers as strings (e.g., "403" instead of 403).
+fn deserialize_u16_from_any<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct U16Visitor;
+
+    impl<'de> Visitor<'de> for U16Visitor {
+        type Value = u16;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a u16 integer or a string containing a u16 integer")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u16, E> {
+            u16::try_from(v).map_err(|_| E::custom(format!("u16 out of range: {}", v)))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u16, E> {
+            u16::try_from(v).map_err(|_| E::custom(format!("u16 out of range: {}", v)))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<u16, E> {
+            v.parse::<u16>()
+                .map_err(|_| E::custom(format!("invalid u16 string: {:?}", v)))
+        }
+    }
+
+    deserializer.deserialize_any(U16Visitor)
+}
+
+/// Deserialize an optional u16 from either a number or a string
+fn deserialize_option_u16_from_any<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_u16_from_any(deserializer).map(Some)
+}
+
+/// Deserialize a u64 from either a number or a string.
+/// Traefik serializes YAML numbers as strings (e.g., "300" instead of 300).
+fn deserialize_u64_from_any<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct U64Visitor;
+
+    impl<'de> Visitor<'de> for U64Visitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a u64 integer or a string containing a u64 integer")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+            u64::try_from(v).map_err(|_| E::custom(format!("u64 out of range: {}", v)))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+            v.parse::<u64>()
+                .map_err(|_| E::custom(format!("invalid u64 string: {:?}", v)))
+        }
+    }
+
+    deserializer.deserialize_any(U64Visitor)
+}
+
+/// Helper to deserialize a u64 from either a number or a string outside of
+/// a derived struct field.
+#[derive(Deserialize)]
+struct AnyU64(#[serde(deserialize_with = "deserialize_u64_from_any")] u64);
+
+/// Deserialize a bool from either a boolean or a string.
+/// Traefik serializes YAML booleans as strings (e.g., "true" instead of true).
+fn deserialize_bool_from_any<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct BoolVisitor;
+
+    impl<'de> Visitor<'de> for BoolVisitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a boolean or a string containing a boolean")
+        }
+
+        fn visit_bool<E: de::Error>(self, v: bool) -> Result<bool, E> {
+            Ok(v)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<bool, E> {
+            match v {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(E::custom(format!("invalid bool string: {:?}", v))),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(BoolVisitor)
+}
+
+/// Deserialize an optional bool from either a boolean or a string
+fn deserialize_option_bool_from_any<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_bool_from_any(deserializer).map(Some)
+}
+
+/// Custom deserializer for TestRequest that handles Traefik's serialization quirks.
+/// Traefik serializes empty YAML maps `{}` as empty strings `""`.
+impl<'de> Deserialize<'de> for TestRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "lowercase")]
+        enum Field {
+            Method,
+            Path,
+            Host,
+            Headers,
+            Cookies,
+            Time,
+            Body,
+            #[serde(rename = "sourceAddr")]
+            SourceAddr,
+        }
+
+        struct TestRequestVisitor;
+
+        impl<'de> Visitor<'de> for TestRequestVisitor {
+            type Value = TestRequest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a TestRequest object")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<TestRequest, M::Error> {
+                let mut method = None;
+                let mut path = None;
+                let mut host = None;
+                let mut headers = None;
+                let mut cookies = None;
+                let mut time = None;
+                let mut body = None;
+                let mut source_addr = None;
+
+                while let Some(key) = map.next_key::<Field>()? {
+                    match key {
+                        Field::Method => method = Some(map.next_value()?),
+                        Field::Path => path = Some(map.next_value()?),
+                        Field::Host => host = Some(map.next_value()?),
+                        Field::Headers => {
+                            // Traefik serializes empty maps as empty strings.
+                            headers = Some(map.next_value::<HeadersOrString>()?);
+                        }
+                        Field::Cookies => {
+                            cookies = Some(map.next_value::<HeadersOrString>()?);
+                        }
+                        Field::Time => time = Some(map.next_value::<AnyU64>()?.0),
+                        Field::Body => body = Some(map.next_value()?),
+                        Field::SourceAddr => source_addr = Some(map.next_value()?),
+                    }
+                }
+
+                Ok(TestRequest {
+                    method: method.unwrap_or_default(),
+                    path: path.unwrap_or_default(),
+                    host: host.unwrap_or_default(),
+                    headers: headers.map(|h| h.into_map()).unwrap_or_default(),
+                    cookies: cookies.map(|c| c.into_map()).unwrap_or_default(),
+                    time,
+                    body,
+                    source_addr: source_addr.unwrap_or_default(),
+                })
+            }
+        }
+
+        deserializer.deserialize_map(TestRequestVisitor)
+    }
+}
+
+/// Deserialize headers as either a map or an empty string
+fn deserialize_headers_or_string<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HeadersOrString::deserialize(deserializer).map(HeadersOrString::into_map)
+}
+
+/// Helper to deserialize headers (or cookies) as either a map or an empty string.
+enum HeadersOrString {
+    Map(HashMap<String, String>),
+    Empty,
+}
+
+impl HeadersOrString {
+    fn into_map(self) -> HashMap<String, String> {
+        match self {
+            HeadersOrString::Map(m) => m,
+            HeadersOrString::Empty => HashMap::new(),
+        }
     }
 }
 
@@ -214,130 +1974,1012 @@ impl<'de> Deserialize<'de> for HeadersOrString {
     {
         struct HeadersVisitor;
 
-        impl<'de> Visitor<'de> for HeadersVisitor {
-            type Value = HeadersOrString;
+        impl<'de> Visitor<'de> for HeadersVisitor {
+            type Value = HeadersOrString;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of headers or an empty string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<HeadersOrString, E> {
+                if v.is_empty() {
+                    Ok(HeadersOrString::Empty)
+                } else {
+                    Err(E::custom(format!("unexpected string for headers: {:?}", v)))
+                }
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<HeadersOrString, M::Error> {
+                let mut headers = HashMap::new();
+                while let Some((k, v)) = map.next_entry()? {
+                    headers.insert(k, v);
+                }
+                Ok(HeadersOrString::Map(headers))
+            }
+        }
+
+        deserializer.deserialize_any(HeadersVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_deserialization_minimal() {
+        let json = r#"{"expression": "method == \"GET\""}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(config.expression, "method == \"GET\"");
+        assert_eq!(config.deny_status_code, 403);
+        assert_eq!(config.deny_body, "Forbidden");
+        assert_eq!(config.tests.len(), 0);
+    }
+
+    #[test]
+    fn test_config_deserialization_full() {
+        let json = r#"{
+            "expression": "method == \"POST\"",
+            "denyStatusCode": 401,
+            "denyBody": "Unauthorized",
+            "tests": [
+                {
+                    "name": "POST allowed",
+                    "request": {
+                        "method": "POST",
+                        "path": "/api",
+                        "host": "example.com",
+                        "headers": {
+                            "X-Test": "value"
+                        }
+                    },
+                    "expect": true
+                }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(config.expression, "method == \"POST\"");
+        assert_eq!(config.deny_status_code, 401);
+        assert_eq!(config.deny_body, "Unauthorized");
+        assert_eq!(config.tests.len(), 1);
+        assert_eq!(config.tests[0].name, "POST allowed");
+        assert_eq!(config.tests[0].request.method, "POST");
+        assert_eq!(config.tests[0].request.path, "/api");
+        assert_eq!(config.tests[0].request.host, "example.com");
+        assert_eq!(
+            config.tests[0].request.headers.get("X-Test"),
+            Some(&"value".to_string())
+        );
+        assert!(config.tests[0].expect);
+    }
+
+    #[test]
+    fn test_config_deserialization_traefik_strings() {
+        // Traefik serializes all YAML values as strings
+        let json = r#"{
+            "expression": "method == \"GET\"",
+            "denyStatusCode": "403",
+            "denyBody": "Forbidden",
+            "tests": [
+                {
+                    "name": "test1",
+                    "request": {
+                        "method": "GET",
+                        "headers": ""
+                    },
+                    "expect": "true"
+                },
+                {
+                    "name": "test2",
+                    "request": {
+                        "headers": {"X-Team": "eng"}
+                    },
+                    "expect": "false"
+                }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(config.deny_status_code, 403);
+        assert!(config.tests[0].expect);
+        assert_eq!(config.tests[0].request.headers.len(), 0);
+        assert!(!config.tests[1].expect);
+        assert_eq!(
+            config.tests[1].request.headers.get("X-Team"),
+            Some(&"eng".to_string())
+        );
+    }
+
+    #[test]
+    fn test_test_request_default() {
+        let req = TestRequest::default();
+        assert_eq!(req.method, "");
+        assert_eq!(req.path, "");
+        assert_eq!(req.host, "");
+        assert_eq!(req.headers.len(), 0);
+        assert_eq!(req.cookies.len(), 0);
+        assert_eq!(req.time, None);
+    }
+
+    #[test]
+    fn test_config_cookie_secret() {
+        let json = r#"{
+            "expression": "signedCookie(\"session\") != \"\"",
+            "cookieSecret": "s3cret",
+            "tests": [
+                {
+                    "name": "cookies map",
+                    "request": {"cookies": {"session": "abc"}},
+                    "expect": "false"
+                },
+                {
+                    "name": "empty cookies",
+                    "request": {"cookies": ""},
+                    "expect": "false"
+                }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(config.cookie_secret, Some(Secret::from("s3cret")));
+        assert!(!format!("{:?}", config).contains("s3cret"));
+        assert_eq!(
+            config.tests[0].request.cookies.get("session"),
+            Some(&"abc".to_string())
+        );
+        assert_eq!(config.tests[1].request.cookies.len(), 0);
+        assert!(config.environment().unwrap().cookie_secret.is_some());
+    }
+
+    #[test]
+    fn test_config_signed_headers() {
+        let json = r#"{
+            "expression": "signatureValid()",
+            "signedHeaders": {
+                "headers": ["X-Auth-User-Login", "X-Auth-User-Teams"],
+                "secret": "s3cret",
+                "maxAge": "60",
+                "enforce": "true"
+            },
+            "tests": [
+                {
+                    "name": "time as string",
+                    "request": {"time": "1700000000"},
+                    "expect": false
+                }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        let signed = config.signed_headers.as_ref().unwrap();
+        assert_eq!(signed.headers.len(), 2);
+        assert_eq!(signed.signature_header, "X-Auth-Signature");
+        assert_eq!(signed.timestamp_header, "X-Auth-Timestamp");
+        assert_eq!(signed.max_age, 60);
+        assert!(signed.enforce);
+        assert_eq!(signed.deny_status_code, 401);
+        assert_eq!(config.tests[0].request.time, Some(1700000000));
+        assert!(config.environment().is_ok());
+    }
+
+    #[test]
+    fn test_config_presigned_urls() {
+        let json = r#"{
+            "expression": "presignedValid()",
+            "presignedUrls": {"secret": "k", "signatureParam": "sig"}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let presigned = config.presigned_urls.as_ref().unwrap();
+        assert_eq!(presigned.expires_param, "expires");
+        assert_eq!(presigned.signature_param, "sig");
+        assert!(config.environment().is_ok());
+
+        let json = r#"{
+            "expression": "presignedValid()",
+            "presignedUrls": {"secret": "k", "expiresParam": "x", "signatureParam": "x"}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("must differ"));
+    }
+
+    #[test]
+    fn test_config_max_header_list_items() {
+        let config: Config = serde_json::from_str(r#"{"expression": "true"}"#).unwrap();
+        assert_eq!(config.environment().unwrap().max_header_list_items, 128);
+
+        // Traefik passes YAML numbers as strings
+        let json = r#"{"expression": "true", "maxHeaderListItems": "16"}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.environment().unwrap().max_header_list_items, 16);
+
+        let json = r#"{"expression": "true", "maxHeaderListItems": 0}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_err());
+    }
+
+    #[test]
+    fn test_config_max_body_size_and_test_body() {
+        let json = r#"{
+            "expression": "true",
+            "maxBodySize": "1024",
+            "tests": [
+                {
+                    "name": "body",
+                    "request": {"method": "POST", "body": "{\"id\":1}"},
+                    "expect": true
+                }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.environment().unwrap().max_body_size, 1024);
+        assert_eq!(config.tests[0].request.body.as_deref(), Some(r#"{"id":1}"#));
+
+        let config: Config = serde_json::from_str(r#"{"expression": "true"}"#).unwrap();
+        assert_eq!(config.environment().unwrap().max_body_size, 64 * 1024);
 
-            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str("a map of headers or an empty string")
-            }
+        let json = r#"{"expression": "true", "maxBodySize": 0}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_err());
+    }
 
-            fn visit_str<E: de::Error>(self, v: &str) -> Result<HeadersOrString, E> {
-                if v.is_empty() {
-                    Ok(HeadersOrString::Empty)
-                } else {
-                    Err(E::custom(format!("unexpected string for headers: {:?}", v)))
-                }
+    #[test]
+    fn test_config_grpc_status_codes() {
+        let config: Config = serde_json::from_str(r#"{"expression": "true"}"#).unwrap();
+        let codes = &config.grpc_status_codes;
+        assert_eq!(codes.code(DenyReason::Denied), 7);
+        assert_eq!(codes.code(DenyReason::Unauthenticated), 16);
+        assert_eq!(codes.code(DenyReason::InvalidSignature), 16);
+        assert_eq!(codes.code(DenyReason::RepeatedHeader), 3);
+        assert_eq!(codes.code(DenyReason::Error), 13);
+
+        let json = r#"{"expression": "true", "grpcStatusCodes": {"denied": "16"}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(config.grpc_status_codes.code(DenyReason::Denied), 16);
+        assert_eq!(config.grpc_status_codes.code(DenyReason::Error), 13);
+        assert!(config.environment().is_ok());
+
+        let json = r#"{"expression": "true", "grpcStatusCodes": {"denied": 0}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("between 1 and 16"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_config_data_tables() {
+        let json = r#"{
+            "expression": "true",
+            "data": {
+                "adminTeams": ["platform-eng", "sre"],
+                "costCenters": {"payments": "CC-100", "search": "CC-200"},
+                "owners": {"payments": ["alice", "bob"]},
+                "empty": {}
             }
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            config.data["adminTeams"],
+            DataTable::List(vec!["platform-eng".to_string(), "sre".to_string()])
+        );
+        assert_eq!(config.data["costCenters"].kind(), "map");
+        assert_eq!(config.data["owners"].kind(), "map of lists");
+        assert_eq!(config.data["empty"], DataTable::Map(HashMap::new()));
+        assert_eq!(config.environment().unwrap().data.len(), 4);
 
-            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<HeadersOrString, M::Error> {
-                let mut headers = HashMap::new();
-                while let Some((k, v)) = map.next_entry()? {
-                    headers.insert(k, v);
+        let json = r#"{"expression": "true", "data": {"mixed": {"a": "x", "b": ["y"]}}}"#;
+        let err = serde_json::from_str::<Config>(json).unwrap_err();
+        assert!(
+            err.to_string().contains("all strings or all lists"),
+            "{}",
+            err
+        );
+
+        let json = r#"{"expression": "true", "data": {"teams": "sre"}}"#;
+        assert!(serde_json::from_str::<Config>(json).is_err());
+
+        let json = r#"{"expression": "true", "data": {"m": {"": "x"}}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("empty key"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_config_rbac() {
+        let json = r#"{
+            "expression": "hasRole(\"admin\")",
+            "rbac": {
+                "userRoles": "headerList(\"X-Auth-User-Roles\")",
+                "roles": {
+                    "viewer": {"permissions": ["orders:read"]},
+                    "admin": {"inherits": ["viewer"]}
                 }
-                Ok(HeadersOrString::Map(headers))
             }
-        }
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let env = config.environment().unwrap();
+        let policy = env.rbac.as_ref().unwrap();
+        assert!(policy.graph.has_role("admin"));
+        assert!(policy.graph.has_permission("orders:read"));
 
-        deserializer.deserialize_any(HeadersVisitor)
+        let json = r#"{
+            "expression": "true",
+            "rbac": {"userRoles": "header(\"X-Role\")", "roles": {}}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(
+            err.message.starts_with("rbac: userRoles:"),
+            "{}",
+            err.message
+        );
+
+        let json = r#"{
+            "expression": "true",
+            "rbac": {"userRoles": "headerValues(\"X-Role\")", "roles": {"a": {"inherits": ["a"]}}}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("cycle"), "{}", err.message);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_config_relations() {
+        let json = r#"{
+            "expression": "true",
+            "relations": ["project:42#owner@org:acme#member", "org:acme#member@user:alice"]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let env = config.environment().unwrap();
+        let graph = env.relations.as_ref().unwrap();
+        assert!(graph.related("user:alice", "owner", "project:42"));
+
+        let json = r#"{"expression": "true"}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().unwrap().relations.is_none());
+
+        let json = r#"{"expression": "true", "relations": ["project:42#owner"]}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(
+            err.message.starts_with("relations: invalid tuple"),
+            "{}",
+            err.message
+        );
+    }
 
     #[test]
-    fn test_config_deserialization_minimal() {
-        let json = r#"{"expression": "method == \"GET\""}"#;
+    fn test_config_openapi() {
+        let json = r#"{
+            "expression": "openapiAllowed()",
+            "openapi": {
+                "document": "{\"openapi\": \"3.0.3\", \"paths\": {\"/health\": {\"get\": {}}}}",
+                "scopes": "headerList(\"X-Auth-Scopes\", \" \")"
+            }
+        }"#;
         let config: Config = serde_json::from_str(json).unwrap();
+        let openapi = config.openapi.as_ref().unwrap();
+        assert_eq!(openapi.unknown_operation, Action::Deny);
+        let policy = config.environment().unwrap().openapi.unwrap();
+        assert!(policy.routes.find("GET", "/health").is_some());
+        assert!(!policy.allow_unknown);
 
-        assert_eq!(config.expression, "method == \"GET\"");
-        assert_eq!(config.deny_status_code, 403);
-        assert_eq!(config.deny_body, "Forbidden");
-        assert_eq!(config.tests.len(), 0);
+        let json = r#"{
+            "expression": "true",
+            "openapi": {
+                "document": {"openapi": "3.1.0", "paths": {}},
+                "scopes": "header(\"X-Auth-Scopes\")",
+                "unknownOperation": "allow"
+            }
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            config.openapi.as_ref().unwrap().unknown_operation,
+            Action::Allow
+        );
+        let err = config.environment().unwrap_err();
+        assert!(
+            err.message.starts_with("openapi: scopes:"),
+            "{}",
+            err.message
+        );
+
+        let json = r#"{
+            "expression": "true",
+            "openapi": {"document": "{not json", "scopes": "cookieNames()"}
+        }"#;
+        assert!(serde_json::from_str::<Config>(json).is_err());
     }
 
     #[test]
-    fn test_config_deserialization_full() {
+    fn test_config_deny_reasons() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "denyReasonHeader": "X-Authz-Reason",
+                "denyBodyReason": "true",
+                "tests": [{"name": "t", "request": {}, "expect": false, "expectReason": "why"}]
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        assert!(config.deny_body_reason);
+        assert_eq!(config.tests[0].expect_reason.as_deref(), Some("why"));
+
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(
+            error(r#"{"expression": "true", "denyReasonHeader": "X Reason"}"#)
+                .contains("not a valid header name")
+        );
+        assert!(error(
+            r#"{"expression": "true", "tests": [{"name": "t", "request": {}, "expect": true, "expectReason": "why"}]}"#
+        )
+        .contains("expects the request to be allowed"));
+    }
+
+    #[test]
+    fn test_config_deny_response() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "denyBody": "{\"error\": \"{{reason}}\"}",
+                "denyContentType": "application/json",
+                "denyHeaders": {"X-Request-Id": "{{requestId}}", "Cache-Control": "no-store"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        assert_eq!(
+            config.deny_content_type.as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(config.deny_headers.len(), 2);
+
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(
+            error(r#"{"expression": "true", "denyHeaders": {"Bad Name": "x"}}"#)
+                .contains("not a valid header name")
+        );
+        assert!(
+            error(r#"{"expression": "true", "denyHeaders": {"content-type": "text/html"}}"#)
+                .contains("use denyContentType")
+        );
+        assert!(error(
+            r#"{"expression": "true", "denyHeaders": {"X-Content-Type-Options": "sniff"}}"#
+        )
+        .contains("cannot be set"));
+        assert!(
+            error(r#"{"expression": "true", "denyContentType": "text/html\r\nX-A: 1"}"#)
+                .contains("denyContentType")
+        );
+    }
+
+    #[test]
+    fn test_config_deny_formats() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "denyFormats": {"problem": {"type": "https://errors.example.com/forbidden"}}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        let formats = config.deny_formats.unwrap();
+        assert_eq!(
+            formats.problem.problem_type,
+            "https://errors.example.com/forbidden"
+        );
+        assert_eq!(formats.problem.title, "{{statusText}}");
+        assert_eq!(formats.problem.detail, "{{reason}}");
+        assert_eq!(formats.html, default_deny_html());
+
+        let config: Config = serde_json::from_str(
+            r#"{"expression": "true", "denyFormats": {}, "denyContentType": "application/json"}"#,
+        )
+        .unwrap();
+        assert!(config
+            .environment()
+            .unwrap_err()
+            .message
+            .contains("denyContentType cannot be used with denyFormats"));
+    }
+
+    #[test]
+    fn test_config_deny_redirect() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "denyRedirect": {"url": "/login?return_to={{uri}}", "when": "method == \"GET\""},
+                "tests": [{
+                    "name": "redirect",
+                    "request": {"method": "GET", "path": "/a"},
+                    "expect": false,
+                    "expectLocation": "/login?return_to=%2Fa"
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        let redirect = config.deny_redirect.as_ref().unwrap();
+        assert_eq!(redirect.status_code, 302);
+        assert_eq!(redirect.when.as_deref(), Some("method == \"GET\""));
+        assert_eq!(
+            config.tests[0].expect_location.as_deref(),
+            Some("/login?return_to=%2Fa")
+        );
+
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(error(
+            r#"{"expression": "true", "denyRedirect": {"url": "/login", "statusCode": 200}}"#
+        )
+        .contains("denyRedirect: statusCode must be 301, 302, 303, 307 or 308"));
+        assert!(
+            error(r#"{"expression": "true", "denyRedirect": {"url": " "}}"#)
+                .contains("denyRedirect: url must not be empty")
+        );
+        assert!(error(
+            r#"{
+                "expression": "true",
+                "tests": [{"name": "t", "request": {}, "expect": true, "expectLocation": "/login"}]
+            }"#
+        )
+        .contains("'t' sets expectLocation but expects the request to be allowed"));
+    }
+
+    #[test]
+    fn test_config_www_authenticate() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "authenticated": "headerExists(\"X-Auth-User\")",
+                "wwwAuthenticate": {"scheme": "Basic", "realm": "Internal tools"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        assert_eq!(config.unauthenticated_body, "Unauthorized");
+        assert_eq!(
+            config.www_authenticate.unwrap().header_value(),
+            r#"Basic realm="Internal tools""#
+        );
+
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(error(r#"{"expression": "true", "authenticated": "true"}"#)
+            .contains("authenticated requires wwwAuthenticate"));
+        assert!(error(
+            r#"{"expression": "true", "wwwAuthenticate": {"scheme": "Basic", "realm": "a"}}"#
+        )
+        .contains("wwwAuthenticate requires authenticated"));
+        assert!(error(
+            r#"{
+                "expression": "true",
+                "authenticated": "true",
+                "wwwAuthenticate": {"scheme": "Basic", "realm": "a", "error": "invalid_token"}
+            }"#
+        )
+        .contains("only allowed with the Bearer scheme"));
+        assert!(error(
+            r#"{
+                "expression": "true",
+                "authenticated": "true",
+                "wwwAuthenticate": {"scheme": "Bearer", "realm": "a\", error=\"x"}
+            }"#
+        )
+        .contains("realm must be non-empty printable ASCII"));
+    }
+
+    #[test]
+    fn test_config_on_allow() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "onAllow": {"set": {"X-Tenant": "header(\"X-Team\")"}, "remove": ["X-Debug"], "ruleHeader": "X-Authz-Rule"}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        let on_allow = config.on_allow.unwrap();
+        assert_eq!(on_allow.remove, vec!["X-Debug"]);
+        assert_eq!(on_allow.rule_header.as_deref(), Some("X-Authz-Rule"));
+
+        let error = |on_allow: &str| {
+            let config: Config = serde_json::from_str(&format!(
+                r#"{{"expression": "true", "onAllow": {}}}"#,
+                on_allow
+            ))
+            .unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(error(r#"{"set": {"X-A": "path"}, "remove": ["x-a"]}"#)
+            .contains("onAllow: header 'x-a' is changed more than once"));
+        assert!(error(r#"{"remove": ["Content-Length"]}"#).contains("cannot be changed"));
+        assert!(error(r#"{"ruleHeader": "X Rule"}"#).contains("not a valid header name"));
+    }
+
+    #[test]
+    fn test_config_rules() {
         let json = r#"{
-            "expression": "method == \"POST\"",
-            "denyStatusCode": 401,
-            "denyBody": "Unauthorized",
-            "tests": [
+            "rules": [
+                {"name": "health", "match": {"paths": ["/health"]}, "action": "allow"},
                 {
-                    "name": "POST allowed",
-                    "request": {
-                        "method": "POST",
-                        "path": "/api",
-                        "host": "example.com",
-                        "headers": {
-                            "X-Test": "value"
-                        }
-                    },
-                    "expect": true
+                    "match": {"hosts": ["*.internal"], "methods": ["delete"]},
+                    "expression": "header(\"X-Admin\") != \"1\"",
+                    "action": "deny",
+                    "denyStatusCode": "404",
+                    "denyBody": "Not Found"
                 }
-            ]
+            ],
+            "defaultAction": "allow"
         }"#;
         let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_ok());
+        assert_eq!(config.default_action, Action::Allow);
+        assert_eq!(config.rules[0].expression, None);
+        assert_eq!(config.rules[0].deny_status_code, None);
+        assert_eq!(config.rules[1].deny_status_code, Some(404));
+        assert_eq!(config.rules[0].label(0), "rule 'health'");
+        assert_eq!(config.rules[1].label(1), "rule #2");
 
-        assert_eq!(config.expression, "method == \"POST\"");
-        assert_eq!(config.deny_status_code, 401);
-        assert_eq!(config.deny_body, "Unauthorized");
-        assert_eq!(config.tests.len(), 1);
-        assert_eq!(config.tests[0].name, "POST allowed");
-        assert_eq!(config.tests[0].request.method, "POST");
-        assert_eq!(config.tests[0].request.path, "/api");
-        assert_eq!(config.tests[0].request.host, "example.com");
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(
+            error(r#"{"defaultAction": "deny"}"#).contains("one of expression, rules or policy")
+        );
+        assert!(
+            error(r#"{"expression": "true", "rules": [{"action": "allow"}]}"#)
+                .contains("only one of expression, rules or policy")
+        );
         assert_eq!(
-            config.tests[0].request.headers.get("X-Test"),
-            Some(&"value".to_string())
+            error(r#"{"rules": [{"action": "allow", "match": {"paths": ["api/*"]}}]}"#),
+            "rules: rule #1 has an invalid path pattern 'api/*'"
+        );
+        assert!(
+            error(r#"{"rules": [{"action": "allow", "match": {"paths": ["/a/*/b"]}}]}"#)
+                .contains("invalid path pattern")
         );
-        assert_eq!(config.tests[0].expect, true);
+        assert!(
+            error(r#"{"rules": [{"action": "allow", "match": {"hosts": ["a.*.com"]}}]}"#)
+                .contains("invalid host pattern")
+        );
+        assert!(serde_json::from_str::<Config>(r#"{"rules": [{"name": "x"}]}"#).is_err());
     }
 
     #[test]
-    fn test_config_deserialization_traefik_strings() {
-        // Traefik serializes all YAML values as strings
+    fn test_config_policy_tree() {
         let json = r#"{
-            "expression": "method == \"GET\"",
-            "denyStatusCode": "403",
-            "denyBody": "Forbidden",
-            "tests": [
-                {
-                    "name": "test1",
-                    "request": {
-                        "method": "GET",
-                        "headers": ""
-                    },
-                    "expect": "true"
-                },
-                {
-                    "name": "test2",
-                    "request": {
-                        "headers": {"X-Team": "eng"}
-                    },
-                    "expect": "false"
-                }
-            ]
+            "policy": {
+                "name": "root",
+                "combine": "deny-overrides",
+                "children": [
+                    {"name": "suspended", "condition": "header(\"X-Status\") == \"suspended\"", "effect": "deny"},
+                    {"target": "method == \"GET\"", "combine": "first-applicable", "children": [{"effect": "permit"}]}
+                ]
+            }
         }"#;
         let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_ok());
+        let root = config.policy.as_ref().unwrap();
+        assert_eq!(root.combine, Some(CombiningAlgorithm::DenyOverrides));
+        assert_eq!(root.children[1].children[0].effect, Some(Action::Allow));
+        assert_eq!(root.label("1"), "policy set 'root'");
+        assert_eq!(root.children[1].label("1.2"), "policy set 1.2");
+        assert_eq!(root.children[1].children[0].label("1.2.1"), "rule 1.2.1");
 
-        assert_eq!(config.deny_status_code, 403);
-        assert_eq!(config.tests[0].expect, true);
-        assert_eq!(config.tests[0].request.headers.len(), 0);
-        assert_eq!(config.tests[1].expect, false);
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            config.environment().unwrap_err().message
+        };
         assert_eq!(
-            config.tests[1].request.headers.get("X-Team"),
-            Some(&"eng".to_string())
+            error(r#"{"policy": {"combine": "permit-overrides", "children": [{"name": "x"}]}}"#),
+            "policy: policy set 'x' must have either combine and children, or an effect"
         );
+        assert_eq!(
+            error(r#"{"policy": {"combine": "first-applicable"}}"#),
+            "policy: policy set 1 has no children"
+        );
+        assert!(
+            error(r#"{"policy": {"effect": "deny", "children": [{"effect": "deny"}]}}"#)
+                .contains("cannot have children")
+        );
+        assert!(
+            error(r#"{"rules": [{"action": "deny"}], "policy": {"effect": "deny"}}"#)
+                .contains("only one of")
+        );
+
+        let mut deep = r#"{"effect": "deny"}"#.to_string();
+        for _ in 0..MAX_POLICY_DEPTH {
+            deep = format!(
+                r#"{{"combine": "first-applicable", "children": [{}]}}"#,
+                deep
+            );
+        }
+        assert!(error(&format!(r#"{{"policy": {}}}"#, deep)).contains("nested deeper"));
+        assert!(serde_json::from_str::<Config>(r#"{"policy": {"combine": "majority"}}"#).is_err());
     }
 
     #[test]
-    fn test_test_request_default() {
-        let req = TestRequest::default();
-        assert_eq!(req.method, "");
-        assert_eq!(req.path, "");
-        assert_eq!(req.host, "");
-        assert_eq!(req.headers.len(), 0);
+    fn test_config_strict_headers() {
+        let json = r#"{
+            "expression": "true",
+            "strictHeaders": {"singular": ["X-Auth-User"]}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let strict = config.strict_headers.as_ref().unwrap();
+        assert_eq!(strict.deny_status_code, 400);
+        assert!(config.environment().is_ok());
+
+        let json = r#"{"expression": "true", "strictHeaders": {"singular": []}}"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_err());
+    }
+
+    #[test]
+    fn test_config_response_checks() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "responseExpression": "status < 500",
+                "responseDenyStatusCode": "502",
+                "responseStripHeaders": [{"headers": ["X-Debug-*"]}],
+                "tests": [{
+                    "name": "t",
+                    "request": {},
+                    "expect": true,
+                    "response": {"status": "503", "headers": ""},
+                    "expectResponse": "false"
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert!(config.environment().is_ok());
+        assert_eq!(config.response_deny_status_code, 502);
+        assert_eq!(config.response_deny_body, "Forbidden");
+        let response = config.tests[0].response.as_ref().unwrap();
+        assert_eq!((response.status, response.headers.len()), (503, 0));
+        assert_eq!(config.tests[0].expect_response, Some(false));
+
+        let error = |fields: &str| {
+            let config: Config =
+                serde_json::from_str(&format!(r#"{{"expression": "true", {}}}"#, fields)).unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(error(r#""responseStripHeaders": [{"headers": []}]"#)
+            .contains("responseStripHeaders #1: headers must list"));
+        assert!(
+            error(r#""responseStripHeaders": [{"headers": ["Content-*"]}]"#)
+                .contains("must not match 'content-length'")
+        );
+        assert!(
+            error(r#""responseStripHeaders": [{"headers": ["X-A"], "when": " "}]"#)
+                .contains("when must not be empty")
+        );
+
+        let test = |test: &str| {
+            error(&format!(
+                r#""responseExpression": "status < 500", "tests": [{{"name": "t", "request": {{}}, {}}}]"#,
+                test
+            ))
+        };
+        assert!(test(r#""expect": true, "response": {}"#).contains("not expectResponse"));
+        assert!(test(r#""expect": true, "expectResponse": true"#).contains("not response"));
+        assert!(
+            test(r#""expect": false, "response": {}, "expectResponse": true"#)
+                .contains("expects the request to be denied")
+        );
+        assert!(error(
+            r#""tests": [{"name": "t", "request": {}, "expect": true, "response": {}, "expectResponse": true}]"#
+        )
+        .contains("responseExpression is not configured"));
+    }
+
+    #[test]
+    fn test_test_case_failure() {
+        let test_case: TestCase = serde_json::from_str(
+            r#"{
+                "name": "t",
+                "request": {},
+                "expect": false,
+                "expectReason": "needs login",
+                "expectLocation": "/login"
+            }"#,
+        )
+        .unwrap();
+        let result = TestResult {
+            allowed: false,
+            decided_by: "default action".to_string(),
+            reason: Some("needs login".to_string()),
+            location: Some("/login".to_string()),
+            response: None,
+        };
+        assert_eq!(test_case.failure(&result), None);
+
+        assert_eq!(
+            test_case
+                .failure(&TestResult {
+                    allowed: true,
+                    ..result.clone()
+                })
+                .as_deref(),
+            Some("got true, expected false (reason: needs login)")
+        );
+        assert_eq!(
+            test_case
+                .failure(&TestResult {
+                    reason: None,
+                    ..result.clone()
+                })
+                .as_deref(),
+            Some("got reason none, expected 'needs login'")
+        );
+        assert_eq!(
+            test_case
+                .failure(&TestResult {
+                    location: Some("/other".to_string()),
+                    ..result.clone()
+                })
+                .as_deref(),
+            Some("got location '/other', expected '/login'")
+        );
+
+        let test_case = TestCase {
+            expect: true,
+            expect_reason: None,
+            expect_location: None,
+            expect_response: Some(true),
+            ..test_case
+        };
+        assert_eq!(
+            test_case
+                .failure(&TestResult {
+                    allowed: true,
+                    response: Some(false),
+                    ..result
+                })
+                .as_deref(),
+            Some("got response false, expected true")
+        );
+    }
+
+    #[test]
+    fn test_config_strip_headers() {
+        let json = r#"{
+            "expression": "true",
+            "stripHeaders": {"headers": ["X-Auth-*"], "beforeEvaluation": "true", "trustedSources": ["10.0.0.0/8"]}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.environment().is_ok());
+        assert!(config.strip_headers.unwrap().before_evaluation);
+
+        let error = |strip: &str| {
+            let config: Config = serde_json::from_str(&format!(
+                r#"{{"expression": "true", "stripHeaders": {}}}"#,
+                strip
+            ))
+            .unwrap();
+            config.environment().unwrap_err().message
+        };
+        assert!(error(r#"{"headers": []}"#).contains("headers must list"));
+        assert!(error(r#"{"headers": ["X Auth"]}"#).contains("not a valid header name"));
+        assert!(error(r#"{"headers": ["*"]}"#).contains("'*' must not match 'host'"));
+        assert!(error(r#"{"headers": ["Content-*"]}"#).contains("must not match 'content-length'"));
+        assert!(
+            error(r#"{"headers": ["X-A"], "trustedSources": ["10.0.0.1"]}"#)
+                .contains("trustedSources requires beforeEvaluation")
+        );
+        // Networks are parsed with the configuration
+        let err = serde_json::from_str::<Config>(
+            r#"{"expression": "true", "stripHeaders": {"headers": ["X-A"], "beforeEvaluation": true, "trustedSources": ["10.0.0.0/40"]}}"#,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("'10.0.0.0/40' is not an IP address or CIDR network"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_strip_headers() {
+        let strip = StripHeaders {
+            headers: vec!["X-Auth-*".to_string(), "X-Policy-Debug".to_string()],
+            before_evaluation: true,
+            trusted_sources: vec![
+                Cidr::parse("10.0.0.0/8").unwrap(),
+                Cidr::parse("2001:db8::1").unwrap(),
+            ],
+        };
+        let request = |source_addr: &str| TestRequest {
+            headers: HashMap::from([
+                ("X-AUTH-User".to_string(), "mallory".to_string()),
+                ("x-auth-user-teams".to_string(), "admins".to_string()),
+                ("x-policy-DEBUG".to_string(), "1".to_string()),
+                ("X-Authz-Trace".to_string(), "abc".to_string()),
+            ]),
+            source_addr: source_addr.to_string(),
+            ..Default::default()
+        };
+
+        // Patterns match the lowercase names of the context, whatever the
+        // case of the pattern or of the header as sent
+        let mut ctx = RequestContext::from_test(&request("192.0.2.1:51234"));
+        assert!(strip.strips_before_evaluation(&ctx));
+        ctx.remove_headers(|name| strip.strips(name));
+        assert!(!ctx.has_header("X-Auth-User"));
+        assert_eq!(ctx.header("x-auth-user-teams"), "");
+        assert!(ctx.header_values("X-Policy-Debug").is_empty());
+        assert_eq!(ctx.header("X-Authz-Trace"), "abc");
+
+        for trusted in ["10.1.2.3:443", "[2001:db8::1]:8443"] {
+            let ctx = RequestContext::from_test(&request(trusted));
+            assert!(!strip.strips_before_evaluation(&ctx), "{}", trusted);
+        }
+        let ctx = RequestContext::from_test(&request(""));
+        assert!(strip.strips_before_evaluation(&ctx));
+
+        let after_only = StripHeaders {
+            before_evaluation: false,
+            trusted_sources: Vec::new(),
+            ..strip
+        };
+        assert!(!after_only.strips_before_evaluation(&ctx));
+    }
+
+    #[test]
+    fn test_strict_headers_repeated_header() {
+        let strict = StrictHeaders {
+            singular: vec!["X-Auth-User".to_string(), "X-Auth-Email".to_string()],
+            deny_status_code: 400,
+        };
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([
+                ("X-Auth-User".to_string(), "alice".to_string()),
+                // Same header on a second field line
+                ("x-auth-user".to_string(), "admin".to_string()),
+                ("X-Auth-Email".to_string(), "alice@example.com".to_string()),
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(strict.repeated_header(&ctx), Some("X-Auth-User"));
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([("X-Auth-User".to_string(), "alice".to_string())]),
+            ..Default::default()
+        });
+        assert_eq!(strict.repeated_header(&ctx), None);
+    }
+
+    #[test]
+    fn test_config_signed_headers_validation() {
+        let json = r#"{
+            "expression": "signatureValid()",
+            "signedHeaders": {"headers": [], "secret": "s3cret"}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("headers must not be empty"));
+
+        let json = r#"{
+            "expression": "signatureValid()",
+            "signedHeaders": {"headers": ["X-User"], "secret": ""}
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+        let err = config.environment().unwrap_err();
+        assert!(err.message.contains("secret must not be empty"));
     }
 }
@@ -3,12 +3,30 @@
 
 // Request context for expression evaluation
 
+use crate::body;
+use crate::cert::{self, ClientCert};
 use crate::config::TestRequest;
+use crate::crypto;
+use crate::graphql;
+use crate::grpc;
+use crate::json;
+use crate::rbac;
+use crate::sfv;
+use crate::url;
+use serde_json::Value as JsonValue;
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Maximum number of items returned by `header_list()` to prevent
+/// Default maximum number of items returned by `header_list()` to prevent
 /// unbounded memory allocation from attacker-controlled header values.
-const MAX_HEADER_LIST_ITEMS: usize = 128;
+/// Configurable with `maxHeaderListItems`.
+pub const MAX_HEADER_LIST_ITEMS: usize = 128;
+
+/// Maximum number of cookies parsed from `Cookie` headers, for the same
+/// reason as `MAX_HEADER_LIST_ITEMS`.
+const MAX_COOKIES: usize = 128;
 
 /// Context containing HTTP request attributes for expression evaluation
 #[derive(Debug, Clone)]
@@ -19,9 +37,23 @@ pub struct RequestContext {
     /// Request path
     pub path: String,
 
+    /// Request path normalized with `url::normalize_path`, as the backend
+    /// will resolve it
+    /// Used to match rule paths and OpenAPI operations
+    pub normalized_path: String,
+
+    /// Request URI: the path and query string, as received
+    pub uri: String,
+
     /// Request host
     pub host: String,
 
+    /// Client address as reported by the host, e.g. `192.0.2.1:51234`
+    pub source_addr: String,
+
+    /// Time the request was received, in Unix seconds
+    pub now: u64,
+
     /// Headers map (lowercase key -> first value)
     /// Used by header() function
     headers: HashMap<String, String>,
@@ -29,6 +61,53 @@ pub struct RequestContext {
     /// All headers map (lowercase key -> all values)
     /// Used by headerValues() and headerList() functions
     all_headers: HashMap<String, Vec<String>>,
+
+    /// Cookies parsed from all `Cookie` header lines (name -> value), in order
+    /// Used by cookie(), cookieNames() and signedCookie() functions
+    cookies: Vec<(String, String)>,
+
+    /// Decoded query parameters (name -> value), in order
+    /// Used by query() and presignedValid() functions
+    query: Vec<(String, String)>,
+
+    /// Client certificate from the passTLSClientCert headers, parsed on
+    /// first use so that policies not referencing `clientCert.*` skip it
+    client_cert: OnceCell<Option<ClientCert>>,
+
+    /// JSON documents parsed from headers (lowercase name -> document),
+    /// cached so each header is parsed at most once per request
+    json_headers: RefCell<HashMap<String, Option<Rc<JsonValue>>>>,
+
+    /// Request body, buffered only when the policy references it
+    /// Used by bodyJson() and formValue() functions
+    body: Option<Vec<u8>>,
+
+    /// Size of the request body in bytes (the declared Content-Length, or
+    /// `maxBodySize + 1` without one, if the body was too large to buffer)
+    /// Used by the bodySize identifier
+    body_size: u64,
+
+    /// JSON document parsed from the body on first use
+    body_json: OnceCell<Option<JsonValue>>,
+
+    /// Form fields parsed from the body on first use (name -> value), in order
+    /// None if the form has more fields than are parsed
+    form: OnceCell<Option<Vec<(String, String)>>>,
+
+    /// GraphQL operation parsed from the body or query on first use
+    graphql: OnceCell<Option<graphql::Operation>>,
+
+    /// Effective RBAC roles and permissions, resolved on first use
+    rbac_subject: OnceCell<rbac::Subject>,
+
+    /// Response status code, set in the response phase
+    /// Used by the status identifier
+    response_status: u16,
+
+    /// Response headers (lowercase key -> all values), set in the response
+    /// phase
+    /// Used by responseHeader() and responseHeaderExists() functions
+    response_headers: HashMap<String, Vec<String>>,
 }
 
 impl RequestContext {
@@ -43,7 +122,7 @@ impl RequestContext {
 
         // Extract URI (returns Bytes)
         let uri = request.uri().to_str().unwrap_or("").to_string();
-        let path = uri.split('?').next().unwrap_or(&uri).to_string();
+        let (path, query) = split_path_query(&uri);
 
         // Extract all headers (Header trait returns HashMap<Bytes, Vec<Bytes>>)
         let all = request.header().get();
@@ -66,15 +145,84 @@ impl RequestContext {
         // Extract host from headers or default to empty
         let host = headers.get("host").cloned().unwrap_or_default();
 
+        let cookies = parse_cookies(all_headers.get("cookie").map_or(&[], |v| v.as_slice()));
+
         RequestContext {
             method,
+            normalized_path: url::normalize_path(&path),
             path,
+            uri,
             host,
+            source_addr: request.source_addr().to_str().unwrap_or("").to_string(),
+            now: unix_now(),
             headers,
             all_headers,
+            cookies,
+            query,
+            client_cert: OnceCell::new(),
+            json_headers: RefCell::new(HashMap::new()),
+            body: None,
+            body_size: 0,
+            body_json: OnceCell::new(),
+            form: OnceCell::new(),
+            graphql: OnceCell::new(),
+            rbac_subject: OnceCell::new(),
+            response_status: 0,
+            response_headers: HashMap::new(),
         }
     }
 
+    /// Add the status and headers of the response from upstream, for
+    /// response expressions
+    #[cfg(all(target_arch = "wasm32", feature = "traefik-plugin"))]
+    pub fn read_response(&mut self, response: &http_wasm_guest::Response) {
+        let status = u16::try_from(response.status()).unwrap_or(0);
+        let headers = response.header().get();
+        let headers = headers.iter().flat_map(|(name, values)| {
+            let name = name.to_str().unwrap_or("");
+            values
+                .iter()
+                .map(move |value| (name, value.to_str().unwrap_or("")))
+        });
+        self.set_response(status, headers);
+    }
+
+    /// Set the response status and headers, replacing any previous
+    /// response. Header names are normalized to lowercase.
+    pub fn set_response<'a>(
+        &mut self,
+        status: u16,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) {
+        self.response_status = status;
+        self.response_headers.clear();
+        for (name, value) in headers {
+            self.response_headers
+                .entry(name.to_lowercase())
+                .or_default()
+                .push(value.to_string());
+        }
+    }
+
+    /// Buffer the request body, up to `max_size` bytes. A body whose
+    /// declared size exceeds the limit is not read, and one without a
+    /// declared size is read only until it exceeds the limit. Its size is
+    /// still recorded for `bodySize`: the declared length, or `max_size + 1`
+    /// if it was cut off. The BufferRequest feature must be enabled for the
+    /// body to be readable here.
+    #[cfg(all(target_arch = "wasm32", feature = "traefik-plugin"))]
+    pub fn read_body(&mut self, max_size: usize) {
+        let declared = self.header("content-length").trim().parse::<u64>().ok();
+        if let Some(len) = declared.filter(|len| *len > max_size as u64) {
+            self.body_size = len;
+            return;
+        }
+
+        let (body, size) = body::read_limited(host::read_request_body, max_size);
+        self.body = body;
+        self.body_size = size;
+    }
+
     /// Create a RequestContext from a test request
     pub fn from_test(test_req: &TestRequest) -> Self {
         let mut headers = HashMap::new();
@@ -96,12 +244,43 @@ impl RequestContext {
                 .push(value.clone());
         }
 
+        let mut cookies = parse_cookies(all_headers.get("cookie").map_or(&[], |v| v.as_slice()));
+
+        // Add cookies from the cookies map in a deterministic order
+        let mut test_cookies: Vec<_> = test_req.cookies.iter().collect();
+        test_cookies.sort();
+        for (name, value) in test_cookies {
+            if cookies.len() >= MAX_COOKIES {
+                break;
+            }
+            cookies.push((name.clone(), value.clone()));
+        }
+
+        // The test path may include a query string, like a request URI
+        let (path, query) = split_path_query(&test_req.path);
+
         RequestContext {
             method: test_req.method.clone(),
-            path: test_req.path.clone(),
+            normalized_path: url::normalize_path(&path),
+            path,
+            uri: test_req.path.clone(),
             host: test_req.host.clone(),
+            source_addr: test_req.source_addr.clone(),
+            now: test_req.time.unwrap_or_else(unix_now),
             headers,
             all_headers,
+            cookies,
+            query,
+            client_cert: OnceCell::new(),
+            json_headers: RefCell::new(HashMap::new()),
+            body_size: test_req.body.as_ref().map_or(0, |b| b.len() as u64),
+            body: test_req.body.as_ref().map(|b| b.as_bytes().to_vec()),
+            body_json: OnceCell::new(),
+            form: OnceCell::new(),
+            graphql: OnceCell::new(),
+            rbac_subject: OnceCell::new(),
+            response_status: 0,
+            response_headers: HashMap::new(),
         }
     }
 
@@ -114,6 +293,42 @@ impl RequestContext {
             .unwrap_or("")
     }
 
+    /// Returns true if the request has a header (case-insensitive), even
+    /// with an empty value
+    pub fn has_header(&self, name: &str) -> bool {
+        self.all_headers.contains_key(&name.to_lowercase())
+    }
+
+    /// Response status code, or 0 before the response phase
+    pub fn response_status(&self) -> u16 {
+        self.response_status
+    }
+
+    /// Get the first value of a response header (case-insensitive)
+    /// Returns empty string if header not found
+    pub fn response_header(&self, name: &str) -> &str {
+        self.response_headers
+            .get(&name.to_lowercase())
+            .and_then(|values| values.first())
+            .map_or("", String::as_str)
+    }
+
+    /// Returns true if the response has a header (case-insensitive)
+    pub fn has_response_header(&self, name: &str) -> bool {
+        self.response_headers.contains_key(&name.to_lowercase())
+    }
+
+    /// Remove the headers whose lowercase name matches `strip`, along with
+    /// the cookies when the `Cookie` header is removed. Must be called
+    /// before evaluation, as values parsed from headers are cached.
+    pub fn remove_headers(&mut self, strip: impl Fn(&str) -> bool) {
+        self.headers.retain(|name, _| !strip(name));
+        self.all_headers.retain(|name, _| !strip(name));
+        if strip("cookie") {
+            self.cookies.clear();
+        }
+    }
+
     /// Get all values of a header (case-insensitive)
     /// Returns empty slice if header not found
     pub fn header_values(&self, name: &str) -> &[String] {
@@ -123,27 +338,390 @@ impl RequestContext {
             .unwrap_or(&[])
     }
 
-    /// Get header value split by comma into a list
-    /// Trims whitespace from each value
+    /// Get header values split by comma into a list (RFC 9110 section 5.6.1)
+    /// Combines all field lines, ignores commas inside quoted strings and
+    /// trims whitespace from each value
     /// Returns empty vec if header not found
     pub fn header_list(&self, name: &str) -> Vec<String> {
-        let value = self.header(name);
-        if value.is_empty() {
-            return Vec::new();
-        }
+        self.header_list_with(name, ",", MAX_HEADER_LIST_ITEMS)
+    }
 
-        value
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .take(MAX_HEADER_LIST_ITEMS)
+    /// Get header values split by a custom separator, returning at most
+    /// `max_items` values. An empty separator does not split field lines.
+    pub fn header_list_with(&self, name: &str, separator: &str, max_items: usize) -> Vec<String> {
+        self.header_values(name)
+            .iter()
+            .flat_map(|line| split_list(line, separator))
+            .take(max_items)
             .collect()
     }
+
+    /// Get the JSON document carried by a header (case-insensitive), which
+    /// may be plain or base64-encoded JSON. The document is parsed on first
+    /// use and cached for the rest of the request.
+    /// Returns None if the header is missing, too large or not valid JSON
+    pub fn header_json(&self, name: &str) -> Option<Rc<JsonValue>> {
+        let name = name.to_lowercase();
+        if let Some(cached) = self.json_headers.borrow().get(&name) {
+            return cached.clone();
+        }
+
+        let parsed = self
+            .headers
+            .get(&name)
+            .and_then(|value| json::parse_header_value(value))
+            .map(Rc::new);
+        self.json_headers.borrow_mut().insert(name, parsed.clone());
+        parsed
+    }
+
+    /// Parse a header as an RFC 8941 structured field List
+    /// Returns None if the header is missing or malformed
+    pub fn sf_list(&self, name: &str) -> Option<sfv::List> {
+        sfv::parse_list(&self.combined_field(name)?)
+    }
+
+    /// Parse a header as an RFC 8941 structured field Dictionary
+    /// Returns None if the header is missing or malformed
+    pub fn sf_dict(&self, name: &str) -> Option<sfv::Dictionary> {
+        sfv::parse_dictionary(&self.combined_field(name)?)
+    }
+
+    /// Parse a header as an RFC 8941 structured field Item
+    /// Returns None if the header is missing or malformed
+    pub fn sf_item(&self, name: &str) -> Option<sfv::Item> {
+        sfv::parse_item(&self.combined_field(name)?)
+    }
+
+    /// Combine all field lines of a header into one value, as RFC 8941
+    /// requires before parsing
+    fn combined_field(&self, name: &str) -> Option<String> {
+        let values = self.header_values(name);
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.join(", "))
+    }
+
+    /// Get the value of a cookie (case-sensitive name)
+    /// If the cookie appears more than once, the first value wins
+    /// Returns empty string if cookie not found
+    pub fn cookie(&self, name: &str) -> &str {
+        self.cookies
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    }
+
+    /// Get the distinct cookie names in the order they first appear
+    pub fn cookie_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (name, _) in &self.cookies {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Get the first decoded value of a query parameter (case-sensitive name)
+    /// Returns empty string if the parameter is not present
+    pub fn query(&self, name: &str) -> &str {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("")
+    }
+
+    /// Get all decoded query parameters in the order they appear
+    pub fn query_params(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    /// Size of the request body in bytes, 0 if there is none
+    pub fn body_size(&self) -> u64 {
+        self.body_size
+    }
+
+    /// Get the JSON document in the request body. The body is parsed on
+    /// first use if its Content-Type is JSON.
+    /// Returns None if there is no buffered body, the Content-Type is not
+    /// JSON, or the body is not valid JSON
+    pub fn body_json(&self) -> Option<&JsonValue> {
+        self.body_json
+            .get_or_init(|| {
+                let body = self.body.as_deref()?;
+                if !body::is_json(self.header("content-type")) {
+                    return None;
+                }
+                body::parse_json(body)
+            })
+            .as_ref()
+    }
+
+    /// Get the decoded form fields in the request body, in order. The body
+    /// is parsed on first use if it is `application/x-www-form-urlencoded`
+    /// or `multipart/form-data`; file parts are skipped.
+    /// Returns None if the form has more than `MAX_FORM_FIELDS` fields
+    pub fn form_fields(&self) -> Option<&[(String, String)]> {
+        self.form
+            .get_or_init(|| match &self.body {
+                Some(body) => body::parse_form(self.header("content-type"), body),
+                None => Some(Vec::new()),
+            })
+            .as_deref()
+    }
+
+    /// Get the first value of a form field (case-sensitive name)
+    /// Returns empty string if the field is not present, and None if the
+    /// form has more than `MAX_FORM_FIELDS` fields
+    pub fn form_value(&self, name: &str) -> Option<&str> {
+        let fields = self.form_fields()?;
+        Some(
+            fields
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or(""),
+        )
+    }
+
+    /// Get all values of a form field (case-sensitive name), in order
+    /// Returns None if the form has more than `MAX_FORM_FIELDS` fields
+    pub fn form_values(&self, name: &str) -> Option<Vec<String>> {
+        let fields = self.form_fields()?;
+        Some(
+            fields
+                .iter()
+                .filter(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+                .collect(),
+        )
+    }
+
+    /// Get the service and method of a gRPC request, detected from the
+    /// Content-Type, from a path of the form `/<service>/<method>`
+    /// Returns None if the request is not a gRPC call
+    pub fn grpc_call(&self) -> Option<(&str, &str)> {
+        grpc::media_type(self.header("content-type"))?;
+        grpc::parse_path(&self.path)
+    }
+
+    /// Get the effective RBAC roles and permissions of the user, resolving
+    /// them with `init` on first use
+    pub fn rbac_subject<E>(
+        &self,
+        init: impl FnOnce() -> Result<rbac::Subject, E>,
+    ) -> Result<&rbac::Subject, E> {
+        if let Some(subject) = self.rbac_subject.get() {
+            return Ok(subject);
+        }
+        let subject = init()?;
+        Ok(self.rbac_subject.get_or_init(|| subject))
+    }
+
+    /// Get the GraphQL operation of the request. GET requests carry the
+    /// document in the `query` parameter (and `operationName`), other
+    /// requests in a JSON body (`{"query": ..., "operationName": ...}`).
+    /// Returns None if there is no document, it does not parse, or the
+    /// operation to execute is ambiguous
+    pub fn graphql_operation(&self) -> Option<&graphql::Operation> {
+        self.graphql
+            .get_or_init(|| {
+                if self.method.eq_ignore_ascii_case("GET") {
+                    let document = self.query("query");
+                    if document.is_empty() {
+                        return None;
+                    }
+                    return graphql::parse_operation(document, Some(self.query("operationName")));
+                }
+                let body = self.body_json()?;
+                let document = body.get("query")?.as_str()?;
+                let name = body.get("operationName").and_then(JsonValue::as_str);
+                graphql::parse_operation(document, name)
+            })
+            .as_ref()
+    }
+
+    /// Get the client certificate forwarded by Traefik's passTLSClientCert
+    /// middleware. Returns None if neither header is present or parseable.
+    pub fn client_cert(&self) -> Option<&ClientCert> {
+        self.client_cert
+            .get_or_init(|| {
+                ClientCert::from_headers(
+                    self.headers.get(cert::CERT_HEADER).map(String::as_str),
+                    self.headers.get(cert::CERT_INFO_HEADER).map(String::as_str),
+                )
+            })
+            .as_ref()
+    }
+
+    /// Get the payload of a signed cookie if its signature is valid
+    ///
+    /// Signed cookies have the form `<payload>.<signature>` where the
+    /// signature is the HMAC-SHA256 of `<name>=<payload>`, base64url (or
+    /// hex) encoded. Binding the cookie name into the MAC stops a value
+    /// signed for one cookie from being replayed as another. Returns empty
+    /// string if the cookie is missing, malformed, or the signature does
+    /// not verify.
+    pub fn signed_cookie(&self, name: &str, secret: &[u8]) -> &str {
+        let value = self.cookie(name);
+        let Some((payload, signature)) = value.rsplit_once('.') else {
+            return "";
+        };
+        let message = format!("{}={}", name, payload);
+        match crypto::decode_signature(signature) {
+            Some(sig) if crypto::verify_hmac_sha256(secret, message.as_bytes(), &sig) => payload,
+            _ => "",
+        }
+    }
+}
+
+/// Chunked access to the request body. http-wasm-guest only exposes
+/// reading the whole body at once, which would copy an arbitrarily large
+/// body into guest memory before its size could be checked.
+#[cfg(all(target_arch = "wasm32", feature = "traefik-plugin"))]
+mod host {
+    /// `body_kind` of the request body in the http-wasm ABI
+    const BODY_KIND_REQUEST: i32 = 0;
+
+    #[link(wasm_import_module = "http_handler")]
+    unsafe extern "C" {
+        fn read_body(body_kind: i32, buf: *mut u8, buf_limit: i32) -> i64;
+    }
+
+    /// Read the next chunk of the request body into `buf`. Returns whether
+    /// the end of the body was reached and the number of bytes read.
+    pub fn read_request_body(buf: &mut [u8]) -> (bool, usize) {
+        let limit = i32::try_from(buf.len()).unwrap_or(i32::MAX);
+        // SAFETY: the host writes at most `limit` bytes into `buf`
+        let result = unsafe { read_body(BODY_KIND_REQUEST, buf.as_mut_ptr(), limit) };
+        let eof = (result >> 32) & 1 == 1;
+        let size = (result as u32 as usize).min(buf.len());
+        (eof, size)
+    }
+}
+
+/// Split one field line into list elements. Separators inside quoted
+/// strings (with backslash escapes) are ignored, elements are trimmed of
+/// optional whitespace, empty elements are dropped, and an element that is
+/// entirely a quoted string is unquoted.
+fn split_list(line: &str, separator: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut rest = line;
+
+    while let Some(ch) = rest.chars().next() {
+        if !in_quotes && !separator.is_empty() && rest.starts_with(separator) {
+            items.push(std::mem::take(&mut current));
+            rest = &rest[separator.len()..];
+            continue;
+        }
+        rest = &rest[ch.len_utf8()..];
+        current.push(ch);
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => {
+                // quoted-pair: the next character is literal
+                if let Some(escaped) = rest.chars().next() {
+                    current.push(escaped);
+                    rest = &rest[escaped.len_utf8()..];
+                }
+            }
+            _ => {}
+        }
+    }
+    items.push(current);
+
+    items
+        .iter()
+        .map(|item| item.trim_matches([' ', '\t']))
+        .filter(|item| !item.is_empty())
+        .map(unquote)
+        .collect()
+}
+
+/// Remove the quotes and escapes from an element that is a quoted string
+fn unquote(item: &str) -> String {
+    let Some(inner) = item
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .filter(|_| item.len() >= 2)
+    else {
+        return item.to_string();
+    };
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => result.extend(chars.next()),
+            // An unescaped quote means the element is not a single quoted string
+            '"' => return item.to_string(),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Split a request URI into its path and decoded query parameters
+fn split_path_query(uri: &str) -> (String, Vec<(String, String)>) {
+    match uri.split_once('?') {
+        Some((path, query)) => (path.to_string(), url::parse_query(query)),
+        None => (uri.to_string(), Vec::new()),
+    }
+}
+
+/// Current wall-clock time in Unix seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parse `Cookie` header lines into name/value pairs (RFC 6265 section 5.4).
+///
+/// Each line is a `; `-separated list of `name=value` pairs. HTTP/2 allows
+/// the cookie header to be split across several field lines, so all lines
+/// are parsed in order. Pairs without `=` or with an empty name are
+/// ignored, and optional double quotes around a value are removed.
+fn parse_cookies(lines: &[String]) -> Vec<(String, String)> {
+    let mut cookies = Vec::new();
+
+    for line in lines {
+        for pair in line.split(';') {
+            if cookies.len() >= MAX_COOKIES {
+                return cookies;
+            }
+
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+
+            cookies.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    cookies
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::StrictHeaders;
 
     #[test]
     fn test_from_test_basic() {
@@ -152,12 +730,211 @@ mod tests {
             path: "/api/users".to_string(),
             host: "example.com".to_string(),
             headers: HashMap::new(),
+            ..Default::default()
         };
 
         let ctx = RequestContext::from_test(&test_req);
         assert_eq!(ctx.method, "GET");
         assert_eq!(ctx.path, "/api/users");
         assert_eq!(ctx.host, "example.com");
+        assert!(ctx.now > 0, "time defaults to the current time");
+    }
+
+    #[test]
+    fn test_from_test_query() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            path: "/download/report.pdf?expires=1700000000&name=q%203&name=x".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(ctx.path, "/download/report.pdf");
+        assert_eq!(ctx.query("expires"), "1700000000");
+        assert_eq!(ctx.query("name"), "q 3");
+        assert_eq!(ctx.query("missing"), "");
+        assert_eq!(ctx.query_params().len(), 3);
+    }
+
+    #[test]
+    fn test_from_test_normalized_path() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            path: "/public/..//%61dmin/?x=1".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(ctx.path, "/public/..//%61dmin/");
+        assert_eq!(ctx.normalized_path, "/admin/");
+    }
+
+    #[test]
+    fn test_client_cert_lazy() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([(
+                "X-Forwarded-Tls-Client-Cert-Info".to_string(),
+                "Subject%3D%22CN%3Dweb%22".to_string(),
+            )]),
+            ..Default::default()
+        });
+        assert!(ctx.client_cert.get().is_none());
+        assert_eq!(ctx.client_cert().unwrap().subject_cn, "web");
+        assert!(ctx.client_cert.get().is_some());
+
+        let ctx = RequestContext::from_test(&TestRequest::default());
+        assert_eq!(ctx.client_cert(), None);
+    }
+
+    #[test]
+    fn test_header_json_cached() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([(
+                "X-Userinfo".to_string(),
+                r#"{"sub":"jdoe","groups":["sre"]}"#.to_string(),
+            )]),
+            ..Default::default()
+        });
+
+        let first = ctx.header_json("x-userinfo").unwrap();
+        assert_eq!(first["sub"], "jdoe");
+        let second = ctx.header_json("X-USERINFO").unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+
+        assert!(ctx.header_json("X-Missing").is_none());
+        assert!(ctx.json_headers.borrow().contains_key("x-missing"));
+    }
+
+    #[test]
+    fn test_body_json_cached() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "POST".to_string(),
+            headers: HashMap::from([("Content-Type".to_string(), "application/json".to_string())]),
+            body: Some(r#"{"account":{"id":"42"}}"#.to_string()),
+            ..Default::default()
+        });
+        assert_eq!(ctx.body_size(), 23);
+        assert!(ctx.body_json.get().is_none());
+        assert_eq!(ctx.body_json().unwrap()["account"]["id"], "42");
+        assert!(ctx.body_json.get().is_some());
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            body: Some(r#"{"id":"42"}"#.to_string()),
+            ..Default::default()
+        });
+        assert!(ctx.body_json().is_none(), "missing Content-Type");
+
+        let ctx = RequestContext::from_test(&TestRequest::default());
+        assert_eq!(ctx.body_size(), 0);
+        assert!(ctx.body_json().is_none());
+    }
+
+    #[test]
+    fn test_form_fields() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "POST".to_string(),
+            headers: HashMap::from([(
+                "Content-Type".to_string(),
+                "application/x-www-form-urlencoded".to_string(),
+            )]),
+            body: Some("action=delete&id=1&id=2".to_string()),
+            ..Default::default()
+        });
+        assert!(ctx.form.get().is_none());
+        assert_eq!(ctx.form_value("action"), Some("delete"));
+        assert_eq!(
+            ctx.form_values("id"),
+            Some(vec!["1".to_string(), "2".to_string()])
+        );
+        assert_eq!(ctx.form_value("Action"), Some(""));
+        assert_eq!(ctx.form_values("missing"), Some(Vec::new()));
+
+        let ctx = RequestContext::from_test(&TestRequest::default());
+        assert_eq!(ctx.form_fields(), Some([].as_slice()));
+    }
+
+    #[test]
+    fn test_graphql_operation() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "POST".to_string(),
+            path: "/graphql".to_string(),
+            headers: HashMap::from([("Content-Type".to_string(), "application/json".to_string())]),
+            body: Some(
+                r#"{"query":"query A { a } mutation B { b c }","operationName":"B"}"#.to_string(),
+            ),
+            ..Default::default()
+        });
+        let op = ctx.graphql_operation().unwrap();
+        assert_eq!(op.operation_type, "mutation");
+        assert_eq!(op.name, "B");
+        assert_eq!(op.root_fields, vec!["b", "c"]);
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "GET".to_string(),
+            path: "/graphql?query=%7B%20me%20%7B%20id%20%7D%20%7D".to_string(),
+            ..Default::default()
+        });
+        let op = ctx.graphql_operation().unwrap();
+        assert_eq!(op.operation_type, "query");
+        assert_eq!(op.root_fields, vec!["me"]);
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "GET".to_string(),
+            path: "/graphql".to_string(),
+            ..Default::default()
+        });
+        assert!(ctx.graphql_operation().is_none());
+    }
+
+    #[test]
+    fn test_grpc_call() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "POST".to_string(),
+            path: "/billing.v1.Invoices/GetInvoice".to_string(),
+            headers: HashMap::from([(
+                "Content-Type".to_string(),
+                "application/grpc+proto".to_string(),
+            )]),
+            ..Default::default()
+        });
+        assert_eq!(ctx.grpc_call(), Some(("billing.v1.Invoices", "GetInvoice")));
+
+        // Same path without a gRPC content type
+        let ctx = RequestContext::from_test(&TestRequest {
+            method: "POST".to_string(),
+            path: "/billing.v1.Invoices/GetInvoice".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(ctx.grpc_call(), None);
+    }
+
+    #[test]
+    fn test_structured_fields() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([
+                (
+                    "Example-List".to_string(),
+                    r#"sugar, "tea";q=0.5"#.to_string(),
+                ),
+                ("Example-Dict".to_string(), "u=1, i".to_string()),
+                ("Example-Item".to_string(), "token;a=?0".to_string()),
+                ("Example-Bad".to_string(), "a,,b".to_string()),
+            ]),
+            ..Default::default()
+        });
+
+        assert_eq!(ctx.sf_list("example-list").unwrap().len(), 2);
+        let dict = ctx.sf_dict("Example-Dict").unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(ctx.sf_item("Example-Item").unwrap().params.len(), 1);
+
+        assert!(ctx.sf_list("Example-Bad").is_none());
+        assert!(ctx.sf_dict("Example-Missing").is_none());
+        // A List is not a valid Item
+        assert!(ctx.sf_item("Example-List").is_none());
+    }
+
+    #[test]
+    fn test_from_test_time() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            time: Some(1_700_000_000),
+            ..Default::default()
+        });
+        assert_eq!(ctx.now, 1_700_000_000);
     }
 
     #[test]
@@ -171,6 +948,7 @@ mod tests {
             path: "/api".to_string(),
             host: "example.com".to_string(),
             headers,
+            ..Default::default()
         };
 
         let ctx = RequestContext::from_test(&test_req);
@@ -249,6 +1027,60 @@ mod tests {
         assert_eq!(list[2], "sre");
     }
 
+    #[test]
+    fn test_header_list_multiple_lines_and_quotes() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers: HashMap::from([
+                ("X-Teams".to_string(), r#"sre, "ops, east""#.to_string()),
+                // A second field line for the same header
+                (
+                    "x-teams".to_string(),
+                    r#"dev , "say \"hi\"", ,"#.to_string(),
+                ),
+            ]),
+            ..Default::default()
+        });
+
+        let mut list = ctx.header_list("X-Teams");
+        list.sort();
+        assert_eq!(list, vec!["dev", "ops, east", "say \"hi\"", "sre"]);
+    }
+
+    #[test]
+    fn test_header_list_with_separator() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "X-Scopes".to_string(),
+            r#"read:orders  write:orders "a b""#.to_string(),
+        );
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            ctx.header_list_with("X-Scopes", " ", 10),
+            vec!["read:orders", "write:orders", "a b"]
+        );
+        assert_eq!(
+            ctx.header_list_with("X-Scopes", " ", 1),
+            vec!["read:orders"]
+        );
+        assert_eq!(
+            ctx.header_list_with("X-Scopes", "", 10),
+            vec![r#"read:orders  write:orders "a b""#]
+        );
+    }
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote(r#""a,b""#), "a,b");
+        assert_eq!(unquote(r#""a\"b""#), r#"a"b"#);
+        assert_eq!(unquote(r#"a="b""#), r#"a="b""#);
+        assert_eq!(unquote(r#""a" "b""#), r#""a" "b""#);
+        assert_eq!(unquote(r#"""#), r#"""#);
+    }
+
     #[test]
     fn test_header_list_empty() {
         let test_req = TestRequest::default();
@@ -281,4 +1113,138 @@ mod tests {
             format!("item{}", MAX_HEADER_LIST_ITEMS - 1)
         );
     }
+
+    #[test]
+    fn test_cookie_parsing() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Cookie".to_string(),
+            r#"session=abc123; tenant="acme"; flag; =bad;  theme = dark "#.to_string(),
+        );
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers,
+            ..Default::default()
+        });
+
+        assert_eq!(ctx.cookie("session"), "abc123");
+        assert_eq!(ctx.cookie("tenant"), "acme");
+        assert_eq!(ctx.cookie("theme"), "dark");
+        assert_eq!(ctx.cookie("flag"), "");
+        assert_eq!(ctx.cookie("Session"), "", "cookie names are case-sensitive");
+        assert_eq!(ctx.cookie_names(), vec!["session", "tenant", "theme"]);
+    }
+
+    #[test]
+    fn test_cookie_multiple_lines_first_wins() {
+        let cookies = parse_cookies(&["a=1; b=2".to_string(), "a=3; c=4".to_string()]);
+        assert_eq!(cookies.len(), 4);
+
+        let ctx = RequestContext {
+            cookies,
+            ..RequestContext::from_test(&TestRequest::default())
+        };
+        assert_eq!(ctx.cookie("a"), "1");
+        assert_eq!(ctx.cookie("c"), "4");
+        assert_eq!(ctx.cookie_names(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_strict_headers_repeated_values() {
+        // Built directly: a test request cannot repeat a header name
+        let ctx = RequestContext {
+            headers: HashMap::from([("x-auth-user".to_string(), "alice".to_string())]),
+            all_headers: HashMap::from([(
+                "x-auth-user".to_string(),
+                vec!["alice".to_string(), "admin".to_string()],
+            )]),
+            ..RequestContext::from_test(&TestRequest::default())
+        };
+        let strict = StrictHeaders {
+            singular: vec!["X-Auth-Email".to_string(), "X-Auth-User".to_string()],
+            deny_status_code: 400,
+        };
+        assert_eq!(strict.repeated_header(&ctx), Some("X-Auth-User"));
+
+        let strict = StrictHeaders {
+            singular: vec!["X-Auth-Email".to_string()],
+            deny_status_code: 400,
+        };
+        assert_eq!(strict.repeated_header(&ctx), None);
+    }
+
+    #[test]
+    fn test_cookie_from_cookies_map() {
+        let mut headers = HashMap::new();
+        headers.insert("Cookie".to_string(), "a=header".to_string());
+        let mut cookies = HashMap::new();
+        cookies.insert("a".to_string(), "map".to_string());
+        cookies.insert("b".to_string(), "map".to_string());
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            headers,
+            cookies,
+            ..Default::default()
+        });
+
+        assert_eq!(ctx.cookie("a"), "header");
+        assert_eq!(ctx.cookie("b"), "map");
+    }
+
+    #[test]
+    fn test_cookies_capped_at_max() {
+        let line: Vec<String> = (0..200).map(|i| format!("c{}=v", i)).collect();
+        let cookies = parse_cookies(&[line.join("; ")]);
+        assert_eq!(cookies.len(), MAX_COOKIES);
+    }
+
+    #[test]
+    fn test_remove_headers() {
+        let mut headers = HashMap::new();
+        headers.insert("X-Auth-User".to_string(), "alice".to_string());
+        headers.insert("Cookie".to_string(), "session=abc".to_string());
+        headers.insert("Accept".to_string(), "*/*".to_string());
+        let mut ctx = RequestContext::from_test(&TestRequest {
+            headers,
+            cookies: HashMap::from([("theme".to_string(), "dark".to_string())]),
+            ..Default::default()
+        });
+
+        // Names are passed lowercase, as stored
+        ctx.remove_headers(|name| name == "x-auth-user" || name == "cookie");
+        assert!(!ctx.has_header("X-Auth-User"));
+        assert!(ctx.header_values("x-auth-user").is_empty());
+        assert_eq!(ctx.cookie("session"), "");
+        assert_eq!(ctx.cookie("theme"), "");
+        assert_eq!(ctx.header("Accept"), "*/*");
+    }
+
+    #[test]
+    fn test_signed_cookie() {
+        let secret = b"s3cret";
+        let signature = crypto::encode_base64url(&crypto::hmac_sha256(secret, b"good=alice"));
+        let prefs_signature =
+            crypto::encode_base64url(&crypto::hmac_sha256(secret, b"prefs=alice"));
+
+        let mut cookies = HashMap::new();
+        cookies.insert("good".to_string(), format!("alice.{}", signature));
+        cookies.insert("tampered".to_string(), format!("mallory.{}", signature));
+        cookies.insert("unsigned".to_string(), "alice".to_string());
+        cookies.insert("prefs".to_string(), format!("alice.{}", prefs_signature));
+        // A value signed for "prefs" replayed under another cookie name
+        cookies.insert("session".to_string(), format!("alice.{}", prefs_signature));
+
+        let ctx = RequestContext::from_test(&TestRequest {
+            cookies,
+            ..Default::default()
+        });
+
+        assert_eq!(ctx.signed_cookie("good", secret), "alice");
+        assert_eq!(ctx.signed_cookie("good", b"wrong"), "");
+        assert_eq!(ctx.signed_cookie("tampered", secret), "");
+        assert_eq!(ctx.signed_cookie("unsigned", secret), "");
+        assert_eq!(ctx.signed_cookie("missing", secret), "");
+        assert_eq!(ctx.signed_cookie("prefs", secret), "alice");
+        assert_eq!(ctx.signed_cookie("session", secret), "");
+    }
 }
@@ -0,0 +1,101 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Cryptographic helpers for signature verification
+//
+// All signatures are HMAC-SHA256. Verification uses the constant-time
+// comparison provided by the `hmac` crate so that response timing does
+// not leak how many signature bytes matched.
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Compute HMAC-SHA256 of `message` using `key`
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this cannot fail.
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Verify an HMAC-SHA256 `signature` over `message` in constant time
+pub fn verify_hmac_sha256(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Decode base64 in any of the common alphabets (standard or URL-safe,
+/// with or without padding). Returns None if the input is not valid base64.
+pub fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim();
+    [URL_SAFE_NO_PAD, URL_SAFE, STANDARD_NO_PAD, STANDARD]
+        .iter()
+        .find_map(|engine| engine.decode(input).ok())
+}
+
+/// Decode a signature that is either hex or base64 encoded
+pub fn decode_signature(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim();
+    // A SHA-256 MAC is 32 bytes, i.e. exactly 64 hex digits.
+    if input.len() == 64 && input.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return decode_hex(input);
+    }
+    decode_base64(input)
+}
+
+/// Encode bytes as standard base64 with padding
+pub fn encode_base64(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Encode bytes as URL-safe base64 without padding
+pub fn encode_base64url(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256_rfc4231_case_2() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        let expected =
+            decode_hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843").unwrap();
+        assert_eq!(mac, expected);
+    }
+
+    #[test]
+    fn test_verify_hmac_sha256() {
+        let mac = hmac_sha256(b"secret", b"payload");
+        assert!(verify_hmac_sha256(b"secret", b"payload", &mac));
+        assert!(!verify_hmac_sha256(b"secret", b"tampered", &mac));
+        assert!(!verify_hmac_sha256(b"other", b"payload", &mac));
+        assert!(!verify_hmac_sha256(b"secret", b"payload", &mac[..16]));
+    }
+
+    #[test]
+    fn test_decode_signature_hex_and_base64() {
+        let mac = hmac_sha256(b"k", b"m");
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(decode_signature(&hex), Some(mac.clone()));
+        assert_eq!(decode_signature(&encode_base64url(&mac)), Some(mac.clone()));
+        assert_eq!(decode_signature(&STANDARD.encode(&mac)), Some(mac));
+        assert_eq!(decode_signature("not base64!"), None);
+    }
+}
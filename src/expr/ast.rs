@@ -3,6 +3,7 @@
 
 // Abstract Syntax Tree (AST) for the expression language
 
+use crate::json::JsonPath;
 use regex::Regex;
 use std::fmt;
 
@@ -40,7 +41,10 @@ pub enum Expr {
     /// String literal
     StringLiteral(String),
 
-    /// Built-in identifier (method, path, host)
+    /// Integer literal
+    IntLiteral(i64),
+
+    /// Built-in identifier (method, path, host, ...)
     Ident(Ident),
 
     /// Function call
@@ -59,6 +63,10 @@ pub enum Expr {
         regex: CompiledRegex,
     },
 
+    /// Pre-parsed JSON path (produced by the compiler from the path
+    /// argument of `headerJson*` and `bodyJson*`)
+    JsonPathLiteral { source: String, path: JsonPath },
+
     /// NOT expression
     Not(Box<Expr>),
 
@@ -67,6 +75,15 @@ pub enum Expr {
 
     /// OR expression
     Or(Box<Expr>, Box<Expr>),
+
+    /// Labeled clause, `@name(expr)` or `deny("reason") unless expr`.
+    /// Evaluates like `expr`; when it makes the program false, the
+    /// decision carries its name and reason.
+    Labeled {
+        name: Option<String>,
+        reason: Option<String>,
+        expr: Box<Expr>,
+    },
 }
 
 impl fmt::Display for Expr {
@@ -74,6 +91,7 @@ impl fmt::Display for Expr {
         match self {
             Expr::BoolLiteral(b) => write!(f, "{}", b),
             Expr::StringLiteral(s) => write!(f, "\"{}\"", s),
+            Expr::IntLiteral(n) => write!(f, "{}", n),
             Expr::Ident(id) => write!(f, "{}", id),
             Expr::FuncCall { name, args } => {
                 write!(f, "{}(", name)?;
@@ -91,9 +109,23 @@ impl fmt::Display for Expr {
             Expr::RegexMatch { expr, regex } => {
                 write!(f, "({} matches \"{}\")", expr, regex.regex.as_str())
             }
+            Expr::JsonPathLiteral { source, .. } => write!(f, "\"{}\"", source),
             Expr::Not(expr) => write!(f, "(NOT {})", expr),
             Expr::And(left, right) => write!(f, "({} AND {})", left, right),
             Expr::Or(left, right) => write!(f, "({} OR {})", left, right),
+            Expr::Labeled { name, reason, expr } => {
+                if let Some(name) = name {
+                    write!(f, "@{}(", name)?;
+                }
+                match reason {
+                    Some(reason) => write!(f, "(deny(\"{}\") unless {})", reason, expr)?,
+                    None => write!(f, "{}", expr)?,
+                }
+                if name.is_some() {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -104,6 +136,48 @@ pub enum Ident {
     Method,
     Path,
     Host,
+    /// Request time in Unix seconds
+    Now,
+    /// Request body size in bytes
+    BodySize,
+    /// Response status code, in response expressions
+    Status,
+    /// GraphQL operation type (query, mutation or subscription)
+    GraphqlOperationType,
+    /// GraphQL operation name
+    GraphqlOperationName,
+    /// Fully qualified gRPC service name
+    GrpcService,
+    /// gRPC method name
+    GrpcMethod,
+    /// Client certificate attribute (`clientCert.<attr>`)
+    ClientCert(CertAttr),
+}
+
+impl Ident {
+    /// Look up a built-in identifier by name
+    pub fn from_name(name: &str) -> Option<Ident> {
+        let ident = match name {
+            "method" => Ident::Method,
+            "path" => Ident::Path,
+            "host" => Ident::Host,
+            "now" => Ident::Now,
+            "bodySize" => Ident::BodySize,
+            "status" => Ident::Status,
+            "graphqlOperationType" => Ident::GraphqlOperationType,
+            "graphqlOperationName" => Ident::GraphqlOperationName,
+            "grpcService" => Ident::GrpcService,
+            "grpcMethod" => Ident::GrpcMethod,
+            "clientCert.subjectCN" => Ident::ClientCert(CertAttr::SubjectCN),
+            "clientCert.issuerCN" => Ident::ClientCert(CertAttr::IssuerCN),
+            "clientCert.serial" => Ident::ClientCert(CertAttr::Serial),
+            "clientCert.sans" => Ident::ClientCert(CertAttr::Sans),
+            "clientCert.notBefore" => Ident::ClientCert(CertAttr::NotBefore),
+            "clientCert.notAfter" => Ident::ClientCert(CertAttr::NotAfter),
+            _ => return None,
+        };
+        Some(ident)
+    }
 }
 
 impl fmt::Display for Ident {
@@ -112,6 +186,38 @@ impl fmt::Display for Ident {
             Ident::Method => write!(f, "method"),
             Ident::Path => write!(f, "path"),
             Ident::Host => write!(f, "host"),
+            Ident::Now => write!(f, "now"),
+            Ident::BodySize => write!(f, "bodySize"),
+            Ident::Status => write!(f, "status"),
+            Ident::GraphqlOperationType => write!(f, "graphqlOperationType"),
+            Ident::GraphqlOperationName => write!(f, "graphqlOperationName"),
+            Ident::GrpcService => write!(f, "grpcService"),
+            Ident::GrpcMethod => write!(f, "grpcMethod"),
+            Ident::ClientCert(attr) => write!(f, "clientCert.{}", attr),
+        }
+    }
+}
+
+/// Client certificate attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertAttr {
+    SubjectCN,
+    IssuerCN,
+    Serial,
+    Sans,
+    NotBefore,
+    NotAfter,
+}
+
+impl fmt::Display for CertAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertAttr::SubjectCN => write!(f, "subjectCN"),
+            CertAttr::IssuerCN => write!(f, "issuerCN"),
+            CertAttr::Serial => write!(f, "serial"),
+            CertAttr::Sans => write!(f, "sans"),
+            CertAttr::NotBefore => write!(f, "notBefore"),
+            CertAttr::NotAfter => write!(f, "notAfter"),
         }
     }
 }
@@ -136,6 +242,18 @@ pub enum BinOp {
 
     /// Regex match (matches)
     Matches,
+
+    /// Integer less than (<)
+    Lt,
+
+    /// Integer less than or equal (<=)
+    Le,
+
+    /// Integer greater than (>)
+    Gt,
+
+    /// Integer greater than or equal (>=)
+    Ge,
 }
 
 impl fmt::Display for BinOp {
@@ -147,6 +265,10 @@ impl fmt::Display for BinOp {
             BinOp::EndsWith => write!(f, "endsWith"),
             BinOp::Contains => write!(f, "contains"),
             BinOp::Matches => write!(f, "matches"),
+            BinOp::Lt => write!(f, "<"),
+            BinOp::Le => write!(f, "<="),
+            BinOp::Gt => write!(f, ">"),
+            BinOp::Ge => write!(f, ">="),
         }
     }
 }
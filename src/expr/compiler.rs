@@ -3,9 +3,18 @@
 
 // Type checker and compiler for the expression language
 
-use super::ast::{BinOp, CompiledRegex, Expr, Ident};
+use super::ast::{BinOp, CertAttr, CompiledRegex, Expr, Ident};
 use super::parser;
+use crate::body::MAX_BODY_SIZE;
+use crate::config::{DataTable, PresignedUrls, Secret, SignedHeaders};
+use crate::context::MAX_HEADER_LIST_ITEMS;
+use crate::json::JsonPath;
+use crate::openapi;
+use crate::rbac;
+use crate::relations::RelationGraph;
+use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
 /// Type in the expression language
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,6 +25,8 @@ pub enum Type {
     StrList,
     /// Boolean type
     Bool,
+    /// Integer type
+    Int,
 }
 
 impl fmt::Display for Type {
@@ -24,6 +35,7 @@ impl fmt::Display for Type {
             Type::Str => write!(f, "string"),
             Type::StrList => write!(f, "[]string"),
             Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
         }
     }
 }
@@ -50,20 +62,99 @@ impl From<parser::ParseError> for CompileError {
     }
 }
 
+/// Settings from the plugin configuration that functions depend on.
+/// Used at compile time to validate function usage and carried by the
+/// compiled `Program` for evaluation.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Key used by signedCookie() to verify cookie signatures
+    pub cookie_secret: Option<Secret>,
+
+    /// Settings used by signatureValid() to verify signed identity headers
+    pub signed_headers: Option<SignedHeaders>,
+
+    /// Settings used by presignedValid() to verify presigned URLs
+    pub presigned_urls: Option<PresignedUrls>,
+
+    /// Maximum number of items returned by headerList() and sfList()
+    pub max_header_list_items: usize,
+
+    /// Maximum request body size in bytes buffered for bodyJson() and
+    /// formValue()
+    pub max_body_size: usize,
+
+    /// Named data tables used by lookup(), lookupList() and inList()
+    pub data: HashMap<String, DataTable>,
+
+    /// Role graph and role source used by hasRole() and hasPermission()
+    pub rbac: Option<rbac::Policy>,
+
+    /// Expanded relationship tuples used by related()
+    pub relations: Option<RelationGraph>,
+
+    /// Route table and scope source used by openapiAllowed()
+    pub openapi: Option<openapi::Policy>,
+
+    /// Whether response attributes (`status`, responseHeader() and
+    /// responseHeaderExists()) are available, as in `responseExpression`
+    pub response_phase: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            cookie_secret: None,
+            signed_headers: None,
+            presigned_urls: None,
+            max_header_list_items: MAX_HEADER_LIST_ITEMS,
+            max_body_size: MAX_BODY_SIZE,
+            data: HashMap::new(),
+            rbac: None,
+            relations: None,
+            openapi: None,
+            response_phase: false,
+        }
+    }
+}
+
+/// Functions that read the request body (GraphQL documents may also be in
+/// the query string, but POST requests carry them in the body)
+const BODY_FUNCTIONS: &[&str] = &[
+    "bodyJson",
+    "bodyJsonList",
+    "bodyJsonInt",
+    "bodyJsonBool",
+    "formValue",
+    "formValues",
+    "graphqlRootFields",
+];
+
 /// Compiled program ready for evaluation
 #[derive(Debug, Clone)]
 pub struct Program {
     pub(crate) root: Expr,
+    pub(crate) env: Rc<Environment>,
+    uses_body: bool,
 }
 
 impl Program {
     /// Compile an expression from a string
     pub fn compile(input: &str) -> Result<Self, CompileError> {
+        Self::compile_with(input, Environment::default())
+    }
+
+    /// Compile an expression from a string using the given environment.
+    /// Programs compiled from the same policy can share one environment.
+    pub fn compile_with(
+        input: &str,
+        env: impl Into<Rc<Environment>>,
+    ) -> Result<Self, CompileError> {
+        let env = env.into();
         // Parse the expression
         let parsed = parser::parse(input)?;
 
         // Type check and transform the expression (e.g., pre-compile regex patterns)
-        let (expr_type, root) = type_check(&parsed)?;
+        let (expr_type, root) = type_check(&parsed, &env)?;
 
         // Ensure top-level expression is boolean
         if expr_type != Type::Bool {
@@ -72,30 +163,155 @@ impl Program {
             });
         }
 
-        Ok(Program { root })
+        let uses_body = references_body(&root, &env);
+        Ok(Program {
+            root,
+            env,
+            uses_body,
+        })
+    }
+
+    /// Returns true if the program references the request body, in which
+    /// case the plugin must buffer it before evaluation
+    pub fn uses_body(&self) -> bool {
+        self.uses_body
+    }
+}
+
+/// Compiled expression that yields a string, such as an `onAllow` header
+/// value. `int` expressions are allowed and formatted in decimal.
+#[derive(Debug, Clone)]
+pub struct StringProgram {
+    pub(crate) root: Expr,
+    pub(crate) env: Rc<Environment>,
+    uses_body: bool,
+}
+
+impl StringProgram {
+    /// Compile a string (or int) expression using the given environment
+    pub fn compile_with(
+        input: &str,
+        env: impl Into<Rc<Environment>>,
+    ) -> Result<Self, CompileError> {
+        let env = env.into();
+        let parsed = parser::parse(input)?;
+        let (expr_type, root) = type_check(&parsed, &env)?;
+        if expr_type != Type::Str && expr_type != Type::Int {
+            return Err(CompileError {
+                message: format!("Expression must be string or int, got {}", expr_type),
+            });
+        }
+        let uses_body = references_body(&root, &env);
+        Ok(StringProgram {
+            root,
+            env,
+            uses_body,
+        })
+    }
+
+    /// Returns true if the expression references the request body
+    pub fn uses_body(&self) -> bool {
+        self.uses_body
+    }
+}
+
+/// Compile an expression that must evaluate to `[]string`, such as the
+/// RBAC `userRoles` source
+pub fn compile_list(input: &str, env: &Environment) -> Result<Expr, CompileError> {
+    let parsed = parser::parse(input)?;
+    let (expr_type, expr) = type_check(&parsed, env)?;
+    if expr_type != Type::StrList {
+        return Err(CompileError {
+            message: format!("Expression must be []string, got {}", expr_type),
+        });
+    }
+    Ok(expr)
+}
+
+/// Returns true if the expression uses a body identifier or function,
+/// including through the RBAC `userRoles` or OpenAPI `scopes` expressions
+fn references_body(expr: &Expr, env: &Environment) -> bool {
+    match expr {
+        Expr::BoolLiteral(_)
+        | Expr::StringLiteral(_)
+        | Expr::IntLiteral(_)
+        | Expr::JsonPathLiteral { .. } => false,
+        Expr::Ident(ident) => matches!(
+            ident,
+            Ident::BodySize | Ident::GraphqlOperationType | Ident::GraphqlOperationName
+        ),
+        Expr::FuncCall { name, args } => {
+            let rbac_body = matches!(name.as_str(), "hasRole" | "hasPermission")
+                && env
+                    .rbac
+                    .as_ref()
+                    .is_some_and(|policy| references_body(&policy.user_roles, env));
+            let openapi_body = name == "openapiAllowed"
+                && env
+                    .openapi
+                    .as_ref()
+                    .is_some_and(|policy| references_body(&policy.scopes, env));
+            BODY_FUNCTIONS.contains(&name.as_str())
+                || rbac_body
+                || openapi_body
+                || args.iter().any(|arg| references_body(arg, env))
+        }
+        Expr::BinaryOp { left, right, .. } | Expr::And(left, right) | Expr::Or(left, right) => {
+            references_body(left, env) || references_body(right, env)
+        }
+        Expr::RegexMatch { expr, .. } | Expr::Not(expr) | Expr::Labeled { expr, .. } => {
+            references_body(expr, env)
+        }
     }
 }
 
 /// Type check an expression recursively, returning the type and a
 /// potentially-transformed expression (e.g., `matches` is replaced with
 /// `RegexMatch` containing a pre-compiled regex).
-fn type_check(expr: &Expr) -> Result<(Type, Expr), CompileError> {
+fn type_check(expr: &Expr, env: &Environment) -> Result<(Type, Expr), CompileError> {
     match expr {
         Expr::BoolLiteral(b) => Ok((Type::Bool, Expr::BoolLiteral(*b))),
 
         Expr::StringLiteral(s) => Ok((Type::Str, Expr::StringLiteral(s.clone()))),
 
-        Expr::Ident(ident) => match ident {
-            Ident::Method | Ident::Path | Ident::Host => {
-                Ok((Type::Str, Expr::Ident(ident.clone())))
-            }
-        },
+        Expr::IntLiteral(n) => Ok((Type::Int, Expr::IntLiteral(*n))),
+
+        Expr::Ident(ident) => {
+            let typ = match ident {
+                Ident::Method
+                | Ident::Path
+                | Ident::Host
+                | Ident::GraphqlOperationType
+                | Ident::GraphqlOperationName
+                | Ident::GrpcService
+                | Ident::GrpcMethod => Type::Str,
+                Ident::Now | Ident::BodySize => Type::Int,
+                Ident::Status => {
+                    require_response_phase("Identifier 'status'", env)?;
+                    Type::Int
+                }
+                Ident::ClientCert(attr) => match attr {
+                    CertAttr::SubjectCN | CertAttr::IssuerCN | CertAttr::Serial => Type::Str,
+                    CertAttr::Sans => Type::StrList,
+                    CertAttr::NotBefore | CertAttr::NotAfter => Type::Int,
+                },
+            };
+            Ok((typ, Expr::Ident(ident.clone())))
+        }
 
         Expr::BinaryOp { op, left, right } => {
-            let (left_type, left_compiled) = type_check(left)?;
-            let (right_type, right_compiled) = type_check(right)?;
+            let (left_type, left_compiled) = type_check(left, env)?;
+            let (right_type, right_compiled) = type_check(right, env)?;
 
             match op {
+                // Integer comparison: relational operators, and ==/!= on ints
+                BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                    int_comparison(op, left_type, left_compiled, right_type, right_compiled)
+                }
+                BinOp::Eq | BinOp::Neq if left_type == Type::Int => {
+                    int_comparison(op, left_type, left_compiled, right_type, right_compiled)
+                }
+
                 BinOp::Eq | BinOp::Neq | BinOp::StartsWith | BinOp::EndsWith => {
                     if left_type != Type::Str {
                         return Err(CompileError {
@@ -195,9 +411,14 @@ fn type_check(expr: &Expr) -> Result<(Type, Expr), CompileError> {
             Ok((Type::Bool, expr.clone()))
         }
 
+        Expr::JsonPathLiteral { .. } => {
+            // Like RegexMatch, only produced by the compiler
+            Ok((Type::Str, expr.clone()))
+        }
+
         Expr::And(left, right) => {
-            let (left_type, left_compiled) = type_check(left)?;
-            let (right_type, right_compiled) = type_check(right)?;
+            let (left_type, left_compiled) = type_check(left, env)?;
+            let (right_type, right_compiled) = type_check(right, env)?;
 
             if left_type != Type::Bool {
                 return Err(CompileError {
@@ -223,8 +444,8 @@ fn type_check(expr: &Expr) -> Result<(Type, Expr), CompileError> {
         }
 
         Expr::Or(left, right) => {
-            let (left_type, left_compiled) = type_check(left)?;
-            let (right_type, right_compiled) = type_check(right)?;
+            let (left_type, left_compiled) = type_check(left, env)?;
+            let (right_type, right_compiled) = type_check(right, env)?;
 
             if left_type != Type::Bool {
                 return Err(CompileError {
@@ -250,7 +471,7 @@ fn type_check(expr: &Expr) -> Result<(Type, Expr), CompileError> {
         }
 
         Expr::Not(inner) => {
-            let (inner_type, inner_compiled) = type_check(inner)?;
+            let (inner_type, inner_compiled) = type_check(inner, env)?;
             if inner_type != Type::Bool {
                 return Err(CompileError {
                     message: format!("NOT operator requires bool operand, got {}", inner_type),
@@ -259,12 +480,80 @@ fn type_check(expr: &Expr) -> Result<(Type, Expr), CompileError> {
             Ok((Type::Bool, Expr::Not(Box::new(inner_compiled))))
         }
 
-        Expr::FuncCall { name, args } => type_check_function(name, args),
+        Expr::Labeled { name, reason, expr } => {
+            let (inner_type, inner_compiled) = type_check(expr, env)?;
+            let label = match name {
+                Some(name) => format!("Label '@{}'", name),
+                None => "deny() unless".to_string(),
+            };
+            if inner_type != Type::Bool {
+                return Err(CompileError {
+                    message: format!("{} requires a bool expression, got {}", label, inner_type),
+                });
+            }
+            // Reasons may be sent in a response header
+            if reason
+                .as_ref()
+                .is_some_and(|r| r.chars().any(char::is_control))
+            {
+                return Err(CompileError {
+                    message: "deny() reason must not contain control characters".to_string(),
+                });
+            }
+            Ok((
+                Type::Bool,
+                Expr::Labeled {
+                    name: name.clone(),
+                    reason: reason.clone(),
+                    expr: Box::new(inner_compiled),
+                },
+            ))
+        }
+
+        Expr::FuncCall { name, args } => type_check_function(name, args, env),
     }
 }
 
+/// Type check an integer comparison, returning the reconstructed expression
+fn int_comparison(
+    op: &BinOp,
+    left_type: Type,
+    left: Expr,
+    right_type: Type,
+    right: Expr,
+) -> Result<(Type, Expr), CompileError> {
+    if left_type != Type::Int {
+        return Err(CompileError {
+            message: format!(
+                "Operator {} requires int operands, got {} on left",
+                op, left_type
+            ),
+        });
+    }
+    if right_type != Type::Int {
+        return Err(CompileError {
+            message: format!(
+                "Operator {} requires int operands, got {} on right",
+                op, right_type
+            ),
+        });
+    }
+    Ok((
+        Type::Bool,
+        Expr::BinaryOp {
+            op: op.clone(),
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    ))
+}
+
 /// Type check a function call, returning the type and the reconstructed expression
-fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), CompileError> {
+fn type_check_function(
+    name: &str,
+    args: &[Expr],
+    env: &Environment,
+) -> Result<(Type, Expr), CompileError> {
     // Helper to build the reconstructed FuncCall expression
     let build_func =
         |name: &str, compiled_args: Vec<Expr>, typ: Type| -> Result<(Type, Expr), CompileError> {
@@ -285,7 +574,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
                     message: format!("Function 'header' expects 1 argument, got {}", args.len()),
                 });
             }
-            let (arg_type, arg_compiled) = type_check(&args[0])?;
+            let (arg_type, arg_compiled) = type_check(&args[0], env)?;
             if arg_type != Type::Str {
                 return Err(CompileError {
                     message: format!(
@@ -307,7 +596,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
                     ),
                 });
             }
-            let (arg_type, arg_compiled) = type_check(&args[0])?;
+            let (arg_type, arg_compiled) = type_check(&args[0], env)?;
             if arg_type != Type::Str {
                 return Err(CompileError {
                     message: format!(
@@ -319,26 +608,59 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
             build_func(name, vec![arg_compiled], Type::StrList)
         }
 
-        // headerList(name: string) -> []string
-        "headerList" => {
+        // headerExists(name: string) -> bool
+        "headerExists" => {
             if args.len() != 1 {
                 return Err(CompileError {
                     message: format!(
-                        "Function 'headerList' expects 1 argument, got {}",
+                        "Function 'headerExists' expects 1 argument, got {}",
                         args.len()
                     ),
                 });
             }
-            let (arg_type, arg_compiled) = type_check(&args[0])?;
+            let (arg_type, arg_compiled) = type_check(&args[0], env)?;
             if arg_type != Type::Str {
                 return Err(CompileError {
                     message: format!(
-                        "Function 'headerList' expects string argument, got {}",
+                        "Function 'headerExists' expects string argument, got {}",
                         arg_type
                     ),
                 });
             }
-            build_func(name, vec![arg_compiled], Type::StrList)
+            build_func(name, vec![arg_compiled], Type::Bool)
+        }
+
+        // responseHeader(name: string) -> string
+        "responseHeader" => {
+            require_response_phase("Function 'responseHeader'", env)?;
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // responseHeaderExists(name: string) -> bool
+        "responseHeaderExists" => {
+            require_response_phase("Function 'responseHeaderExists'", env)?;
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // headerList(name: string) -> []string
+        // headerList(name: string, separator: string) -> []string
+        "headerList" => {
+            let params: &[Type] = match args.len() {
+                1 => &[Type::Str],
+                2 => &[Type::Str, Type::Str],
+                n => {
+                    return Err(CompileError {
+                        message: format!(
+                            "Function 'headerList' expects 1 or 2 arguments, got {}",
+                            n
+                        ),
+                    })
+                }
+            };
+            let compiled_args = check_args(name, args, params, env)?;
+            build_func(name, compiled_args, Type::StrList)
         }
 
         // contains(list: []string, item: string) -> bool
@@ -352,8 +674,8 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
                     ),
                 });
             }
-            let (list_type, list_compiled) = type_check(&args[0])?;
-            let (item_type, item_compiled) = type_check(&args[1])?;
+            let (list_type, list_compiled) = type_check(&args[0], env)?;
+            let (item_type, item_compiled) = type_check(&args[1], env)?;
 
             if list_type != Type::StrList {
                 return Err(CompileError {
@@ -388,7 +710,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
             let mut compiled_args = Vec::with_capacity(args.len());
 
             // First argument must be []string
-            let (list_type, list_compiled) = type_check(&args[0])?;
+            let (list_type, list_compiled) = type_check(&args[0], env)?;
             if list_type != Type::StrList {
                 return Err(CompileError {
                     message: format!(
@@ -401,7 +723,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
 
             // Remaining arguments must be strings
             for (i, arg) in args.iter().skip(1).enumerate() {
-                let (arg_type, arg_compiled) = type_check(arg)?;
+                let (arg_type, arg_compiled) = type_check(arg, env)?;
                 if arg_type != Type::Str {
                     return Err(CompileError {
                         message: format!(
@@ -431,7 +753,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
             let mut compiled_args = Vec::with_capacity(args.len());
 
             // First argument must be []string
-            let (list_type, list_compiled) = type_check(&args[0])?;
+            let (list_type, list_compiled) = type_check(&args[0], env)?;
             if list_type != Type::StrList {
                 return Err(CompileError {
                     message: format!(
@@ -444,7 +766,7 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
 
             // Remaining arguments must be strings
             for (i, arg) in args.iter().skip(1).enumerate() {
-                let (arg_type, arg_compiled) = type_check(arg)?;
+                let (arg_type, arg_compiled) = type_check(arg, env)?;
                 if arg_type != Type::Str {
                     return Err(CompileError {
                         message: format!(
@@ -460,12 +782,388 @@ fn type_check_function(name: &str, args: &[Expr]) -> Result<(Type, Expr), Compil
             build_func(name, compiled_args, Type::Bool)
         }
 
+        // cookie(name: string) -> string
+        "cookie" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // cookieNames() -> []string
+        "cookieNames" => {
+            let compiled_args = check_args(name, args, &[], env)?;
+            build_func(name, compiled_args, Type::StrList)
+        }
+
+        // signedCookie(name: string) -> string
+        "signedCookie" => {
+            if env.cookie_secret.is_none() {
+                return Err(CompileError {
+                    message: "Function 'signedCookie' requires cookieSecret to be configured"
+                        .to_string(),
+                });
+            }
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // signatureValid() -> bool
+        "signatureValid" => {
+            if env.signed_headers.is_none() {
+                return Err(CompileError {
+                    message: "Function 'signatureValid' requires signedHeaders to be configured"
+                        .to_string(),
+                });
+            }
+            let compiled_args = check_args(name, args, &[], env)?;
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // presignedValid() -> bool
+        "presignedValid" => {
+            if env.presigned_urls.is_none() {
+                return Err(CompileError {
+                    message: "Function 'presignedValid' requires presignedUrls to be configured"
+                        .to_string(),
+                });
+            }
+            let compiled_args = check_args(name, args, &[], env)?;
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // hasRole(role: string) -> bool
+        // hasPermission(permission: string) -> bool
+        "hasRole" | "hasPermission" => {
+            let Some(policy) = &env.rbac else {
+                return Err(CompileError {
+                    message: format!("Function '{}' requires rbac to be configured", name),
+                });
+            };
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            let Expr::StringLiteral(value) = &compiled_args[0] else {
+                return Err(CompileError {
+                    message: format!("Function '{}' requires a string literal argument", name),
+                });
+            };
+            if name == "hasRole" && !policy.graph.has_role(value) {
+                return Err(CompileError {
+                    message: format!("Function 'hasRole': unknown role '{}'", value),
+                });
+            }
+            if name == "hasPermission" && !policy.graph.has_permission(value) {
+                return Err(CompileError {
+                    message: format!(
+                        "Function 'hasPermission': no role grants permission '{}'",
+                        value
+                    ),
+                });
+            }
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // openapiAllowed() -> bool
+        "openapiAllowed" => {
+            if env.openapi.is_none() {
+                return Err(CompileError {
+                    message: "Function 'openapiAllowed' requires openapi to be configured"
+                        .to_string(),
+                });
+            }
+            let compiled_args = check_args(name, args, &[], env)?;
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // related(subject: string, relation: string, object: string) -> bool
+        "related" => {
+            let Some(graph) = &env.relations else {
+                return Err(CompileError {
+                    message: "Function 'related' requires relations to be configured".to_string(),
+                });
+            };
+            let compiled_args = check_args(name, args, &[Type::Str, Type::Str, Type::Str], env)?;
+            if let Expr::StringLiteral(relation) = &compiled_args[1] {
+                if !graph.has_relation(relation) {
+                    return Err(CompileError {
+                        message: format!(
+                            "Function 'related': no tuple uses relation '{}'",
+                            relation
+                        ),
+                    });
+                }
+            }
+            build_func(name, compiled_args, Type::Bool)
+        }
+
+        // capture(value: string, pattern: string, group: string | int) -> string
+        "capture" => {
+            if args.len() != 3 {
+                return Err(CompileError {
+                    message: format!("Function 'capture' expects 3 arguments, got {}", args.len()),
+                });
+            }
+            let value = check_args(name, &args[..1], &[Type::Str], env)?.remove(0);
+            // Like `matches`, the pattern must be a literal and is compiled once
+            let Expr::StringLiteral(pattern) = &args[1] else {
+                return Err(CompileError {
+                    message: "Function 'capture' requires a string literal as the pattern"
+                        .to_string(),
+                });
+            };
+            let compiled = CompiledRegex::new(pattern).map_err(|e| CompileError {
+                message: format!("Invalid regex pattern '{}': {}", pattern, e),
+            })?;
+            let group_exists = match &args[2] {
+                Expr::StringLiteral(group) => {
+                    compiled.regex.capture_names().flatten().any(|n| n == group)
+                }
+                Expr::IntLiteral(index) => {
+                    usize::try_from(*index).is_ok_and(|i| i < compiled.regex.captures_len())
+                }
+                _ => {
+                    return Err(CompileError {
+                        message: "Function 'capture' requires a group name or index literal"
+                            .to_string(),
+                    })
+                }
+            };
+            if !group_exists {
+                return Err(CompileError {
+                    message: format!(
+                        "Function 'capture': pattern '{}' has no group {}",
+                        pattern, args[2]
+                    ),
+                });
+            }
+            // The value and compiled pattern are carried as a RegexMatch node
+            let matcher = Expr::RegexMatch {
+                expr: Box::new(value),
+                regex: compiled,
+            };
+            build_func(name, vec![matcher, args[2].clone()], Type::Str)
+        }
+
+        // concat(values: ...string) -> string
+        "concat" => {
+            if args.len() < 2 {
+                return Err(CompileError {
+                    message: format!(
+                        "Function 'concat' expects at least 2 arguments, got {}",
+                        args.len()
+                    ),
+                });
+            }
+            let compiled_args = check_args(name, args, &vec![Type::Str; args.len()], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // headerJson(name: string, path: string) -> string
+        // headerJsonList(name: string, path: string) -> []string
+        // headerJsonInt(name: string, path: string) -> int
+        // headerJsonBool(name: string, path: string) -> bool
+        "headerJson" | "headerJsonList" | "headerJsonInt" | "headerJsonBool" => {
+            let mut compiled_args = check_args(name, args, &[Type::Str, Type::Str], env)?;
+            compiled_args[1] = compile_json_path(name, &compiled_args[1])?;
+            let typ = match name {
+                "headerJson" => Type::Str,
+                "headerJsonList" => Type::StrList,
+                "headerJsonInt" => Type::Int,
+                _ => Type::Bool,
+            };
+            build_func(name, compiled_args, typ)
+        }
+
+        // bodyJson(path: string) -> string
+        // bodyJsonList(path: string) -> []string
+        // bodyJsonInt(path: string) -> int
+        // bodyJsonBool(path: string) -> bool
+        "bodyJson" | "bodyJsonList" | "bodyJsonInt" | "bodyJsonBool" => {
+            let mut compiled_args = check_args(name, args, &[Type::Str], env)?;
+            compiled_args[0] = compile_json_path(name, &compiled_args[0])?;
+            let typ = match name {
+                "bodyJson" => Type::Str,
+                "bodyJsonList" => Type::StrList,
+                "bodyJsonInt" => Type::Int,
+                _ => Type::Bool,
+            };
+            build_func(name, compiled_args, typ)
+        }
+
+        // formValue(name: string) -> string
+        "formValue" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // formValues(name: string) -> []string
+        "formValues" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::StrList)
+        }
+
+        // graphqlRootFields() -> []string
+        "graphqlRootFields" => {
+            let compiled_args = check_args(name, args, &[], env)?;
+            build_func(name, compiled_args, Type::StrList)
+        }
+
+        // sfList(name: string) -> []string
+        "sfList" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::StrList)
+        }
+
+        // sfItem(name: string) -> string
+        "sfItem" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // sfDict(name: string, key: string) -> string
+        "sfDict" => {
+            let compiled_args = check_args(name, args, &[Type::Str, Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // sfParam(name: string, param: string) -> string               (Item header)
+        // sfParam(name: string, key: string, param: string) -> string  (Dictionary member)
+        "sfParam" => {
+            let params: &[Type] = if args.len() == 3 {
+                &[Type::Str, Type::Str, Type::Str]
+            } else {
+                &[Type::Str, Type::Str]
+            };
+            let compiled_args = check_args(name, args, params, env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
+        // lookup(table: string, key: string) -> string
+        // lookupList(table: string, key: string) -> []string
+        // inList(table: string, value: string) -> bool
+        "lookup" | "lookupList" | "inList" => {
+            let compiled_args = check_args(name, args, &[Type::Str, Type::Str], env)?;
+            let (kind, typ) = match name {
+                "lookup" => ("map", Type::Str),
+                "lookupList" => ("map of lists", Type::StrList),
+                _ => ("list", Type::Bool),
+            };
+            check_data_table(name, &compiled_args[0], kind, env)?;
+            build_func(name, compiled_args, typ)
+        }
+
+        // query(name: string) -> string
+        "query" => {
+            let compiled_args = check_args(name, args, &[Type::Str], env)?;
+            build_func(name, compiled_args, Type::Str)
+        }
+
         _ => Err(CompileError {
             message: format!("Unknown function '{}'", name),
         }),
     }
 }
 
+/// Reject response attributes outside of response expressions, where
+/// there is no response yet
+fn require_response_phase(what: &str, env: &Environment) -> Result<(), CompileError> {
+    if env.response_phase {
+        return Ok(());
+    }
+    Err(CompileError {
+        message: format!("{} is only available in responseExpression", what),
+    })
+}
+
+/// Type check the arguments of a function with a fixed signature,
+/// returning the compiled arguments
+fn check_args(
+    name: &str,
+    args: &[Expr],
+    params: &[Type],
+    env: &Environment,
+) -> Result<Vec<Expr>, CompileError> {
+    if args.len() != params.len() {
+        return Err(CompileError {
+            message: format!(
+                "Function '{}' expects {} argument{}, got {}",
+                name,
+                params.len(),
+                if params.len() == 1 { "" } else { "s" },
+                args.len()
+            ),
+        });
+    }
+
+    args.iter()
+        .zip(params)
+        .map(|(arg, param)| {
+            let (arg_type, arg_compiled) = type_check(arg, env)?;
+            if arg_type != *param {
+                return Err(CompileError {
+                    message: format!(
+                        "Function '{}' expects {} argument, got {}",
+                        name, param, arg_type
+                    ),
+                });
+            }
+            Ok(arg_compiled)
+        })
+        .collect()
+}
+
+/// Parse a JSON path argument. Like regex patterns, paths must be string
+/// literals so that they can be parsed once at compile time.
+fn compile_json_path(name: &str, arg: &Expr) -> Result<Expr, CompileError> {
+    let Expr::StringLiteral(path) = arg else {
+        return Err(CompileError {
+            message: format!(
+                "Function '{}' requires a string literal as the JSON path",
+                name
+            ),
+        });
+    };
+    let parsed = JsonPath::parse(path).map_err(|e| CompileError {
+        message: format!("Function '{}': {}", name, e),
+    })?;
+    Ok(Expr::JsonPathLiteral {
+        source: path.clone(),
+        path: parsed,
+    })
+}
+
+/// Validate a data table argument: a string literal naming a table of the
+/// kind the function expects
+fn check_data_table(
+    name: &str,
+    arg: &Expr,
+    kind: &str,
+    env: &Environment,
+) -> Result<(), CompileError> {
+    let Expr::StringLiteral(table) = arg else {
+        return Err(CompileError {
+            message: format!(
+                "Function '{}' requires a string literal as the table name",
+                name
+            ),
+        });
+    };
+    let Some(data) = env.data.get(table) else {
+        return Err(CompileError {
+            message: format!("Function '{}': unknown data table '{}'", name, table),
+        });
+    };
+    if data.kind() != kind {
+        return Err(CompileError {
+            message: format!(
+                "Function '{}' requires a {} table, but '{}' is a {}",
+                name,
+                kind,
+                table,
+                data.kind()
+            ),
+        });
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -581,6 +1279,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_string_program_compile() {
+        let env = Rc::new(Environment::default());
+        assert!(StringProgram::compile_with(r#"header("X-Team")"#, Rc::clone(&env)).is_ok());
+        assert!(StringProgram::compile_with("bodySize", Rc::clone(&env)).is_ok());
+        let err = StringProgram::compile_with(r#"method == "GET""#, env).unwrap_err();
+        assert!(err.message.contains("must be string or int, got bool"));
+    }
+
+    #[test]
+    fn test_cookie_functions_compile() {
+        assert!(Program::compile(r#"cookie("tenant") == "acme""#).is_ok());
+        assert!(Program::compile(r#"contains(cookieNames(), "session")"#).is_ok());
+
+        let err = Program::compile(r#"cookieNames("x") == "y""#).unwrap_err();
+        assert!(err.message.contains("expects 0 arguments"));
+    }
+
+    #[test]
+    fn test_signed_cookie_requires_secret() {
+        let err = Program::compile(r#"signedCookie("session") == "alice""#).unwrap_err();
+        assert!(
+            err.message.contains("cookieSecret"),
+            "Expected cookieSecret error, got: {}",
+            err.message
+        );
+
+        let env = Environment {
+            cookie_secret: Some(Secret::from("s3cret")),
+            ..Default::default()
+        };
+        assert!(Program::compile_with(r#"signedCookie("session") == "alice""#, env).is_ok());
+    }
+
+    #[test]
+    fn test_presigned_valid_requires_presigned_urls() {
+        let err = Program::compile(r#"presignedValid()"#).unwrap_err();
+        assert!(err.message.contains("presignedUrls"));
+    }
+
+    #[test]
+    fn test_response_attributes_require_response_phase() {
+        let err = Program::compile(r#"responseHeader("Server") == "nginx""#).unwrap_err();
+        assert!(err
+            .message
+            .contains("Function 'responseHeader' is only available in responseExpression"));
+        assert!(Program::compile("status == 200").is_err());
+
+        let env = Rc::new(Environment {
+            response_phase: true,
+            ..Default::default()
+        });
+        assert!(Program::compile_with("status >= 500", Rc::clone(&env)).is_ok());
+        assert!(Program::compile_with(
+            r#"responseHeaderExists("X-Internal-Only")"#,
+            Rc::clone(&env)
+        )
+        .is_ok());
+        let err = Program::compile_with(r#"responseHeader(1) == "x""#, env).unwrap_err();
+        assert!(err.message.contains("expects string argument, got int"));
+    }
+
+    #[test]
+    fn test_signature_valid_requires_signed_headers() {
+        let err = Program::compile(r#"signatureValid()"#).unwrap_err();
+        assert!(
+            err.message.contains("signedHeaders"),
+            "Expected signedHeaders error, got: {}",
+            err.message
+        );
+    }
+
     #[test]
     fn test_matches_infix_dynamic_rejected() {
         // Infix syntax with dynamic pattern must also be rejected
@@ -593,4 +1363,169 @@ mod tests {
             err.message
         );
     }
+
+    #[test]
+    fn test_int_comparison_types() {
+        assert!(Program::compile(r#"clientCert.notAfter > now"#).is_ok());
+        assert!(Program::compile(r#"clientCert.notBefore == 0"#).is_ok());
+        assert!(Program::compile(r#"clientCert.subjectCN endsWith ".local""#).is_ok());
+        assert!(Program::compile(r#"contains(clientCert.sans, "billing")"#).is_ok());
+
+        let err = Program::compile(r#"path < 5"#).unwrap_err();
+        assert!(err
+            .message
+            .contains("requires int operands, got string on left"));
+
+        let err = Program::compile(r#"now == "1""#).unwrap_err();
+        assert!(err
+            .message
+            .contains("requires int operands, got string on right"));
+
+        let err = Program::compile(r#"method == 1"#).unwrap_err();
+        assert!(err
+            .message
+            .contains("requires string operands, got int on right"));
+
+        let err = Program::compile(r#"now"#).unwrap_err();
+        assert!(err.message.contains("must be boolean, got int"));
+    }
+
+    #[test]
+    fn test_header_json_functions() {
+        assert!(Program::compile(r#"headerJson("X-Userinfo", "sub") == "jdoe""#).is_ok());
+        assert!(
+            Program::compile(r#"contains(headerJsonList("X-Userinfo", "$.groups"), "sre")"#)
+                .is_ok()
+        );
+        assert!(Program::compile(r#"headerJsonInt("X-Userinfo", "age") >= 18"#).is_ok());
+        assert!(Program::compile(r#"headerJsonBool("X-Userinfo", "email_verified")"#).is_ok());
+
+        // The path is parsed once and carried in the compiled call
+        let program = Program::compile(r#"headerJsonBool("X-Userinfo", "a.b[0]")"#).unwrap();
+        assert!(
+            matches!(&program.root, Expr::FuncCall { args, .. }
+                if matches!(&args[1], Expr::JsonPathLiteral { source, .. } if source == "a.b[0]")),
+            "Expected JsonPathLiteral, got: {:?}",
+            program.root
+        );
+
+        let err = Program::compile(r#"headerJson("X-Userinfo", "a..b") == """#).unwrap_err();
+        assert!(err.message.contains("Invalid JSON path"), "{}", err.message);
+
+        let err =
+            Program::compile(r#"headerJson("X-Userinfo", header("X-Path")) == """#).unwrap_err();
+        assert!(err.message.contains("string literal"), "{}", err.message);
+
+        let err = Program::compile(r#"headerJsonInt("X-Userinfo", "age") == "18""#).unwrap_err();
+        assert!(err.message.contains("int operands"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_structured_field_functions() {
+        assert!(Program::compile(r#"contains(sfList("Example-List"), "tea")"#).is_ok());
+        assert!(Program::compile(r#"sfDict("Priority", "u") == "1""#).is_ok());
+        assert!(Program::compile(r#"sfItem("Example-Item") == "token""#).is_ok());
+        assert!(Program::compile(r#"sfParam("Example-Item", "a") == "?0""#).is_ok());
+        assert!(Program::compile(r#"sfParam("Priority", "u", "x") == """#).is_ok());
+
+        let err = Program::compile(r#"sfParam("Priority") == """#).unwrap_err();
+        assert!(
+            err.message.contains("expects 2 arguments, got 1"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_header_list_separator() {
+        assert!(Program::compile(r#"contains(headerList("X-Scopes", " "), "read")"#).is_ok());
+
+        let err =
+            Program::compile(r#"contains(headerList("X-Scopes", " ", ","), "read")"#).unwrap_err();
+        assert!(
+            err.message.contains("expects 1 or 2 arguments, got 3"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_body_functions_and_uses_body() {
+        let program = Program::compile(r#"bodyJson("$.account.id") == "42""#).unwrap();
+        assert!(program.uses_body());
+        let program = Program::compile(r#"method == "GET" OR NOT (bodySize > 1024)"#).unwrap();
+        assert!(program.uses_body());
+        let program =
+            Program::compile(r#"contains(bodyJsonList("roles"), header("X-Role"))"#).unwrap();
+        assert!(program.uses_body());
+        let program = Program::compile(r#"headerJson("X-Userinfo", "sub") == "jdoe""#).unwrap();
+        assert!(!program.uses_body());
+
+        let err = Program::compile(r#"bodyJson("X-Body", "id") == """#).unwrap_err();
+        assert!(
+            err.message.contains("expects 1 argument, got 2"),
+            "{}",
+            err.message
+        );
+        let err = Program::compile(r#"bodyJsonBool(path)"#).unwrap_err();
+        assert!(err.message.contains("string literal"), "{}", err.message);
+
+        let program = Program::compile(r#"formValue("action") != "delete""#).unwrap();
+        assert!(program.uses_body());
+        let program = Program::compile(r#"contains(formValues("ids"), "1")"#).unwrap();
+        assert!(program.uses_body());
+        let program = Program::compile(r#"graphqlOperationType != "mutation""#).unwrap();
+        assert!(program.uses_body());
+        let program = Program::compile(r#"contains(graphqlRootFields(), "me")"#).unwrap();
+        assert!(program.uses_body());
+
+        let err = Program::compile(r#"formValue() == """#).unwrap_err();
+        assert!(
+            err.message.contains("expects 1 argument"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_data_table_functions() {
+        let env = Environment {
+            data: HashMap::from([
+                (
+                    "adminTeams".to_string(),
+                    DataTable::List(vec!["sre".to_string()]),
+                ),
+                ("costCenters".to_string(), DataTable::Map(HashMap::new())),
+                ("owners".to_string(), DataTable::MapOfLists(HashMap::new())),
+            ]),
+            ..Default::default()
+        };
+        let compile = |input: &str| Program::compile_with(input, env.clone());
+
+        assert!(compile(r#"inList("adminTeams", header("X-Team"))"#).is_ok());
+        assert!(compile(r#"lookup("costCenters", header("X-Team")) == "CC-100""#).is_ok());
+        assert!(compile(r#"contains(lookupList("owners", path), "alice")"#).is_ok());
+
+        let err = compile(r#"inList("missing", "x")"#).unwrap_err();
+        assert!(
+            err.message.contains("unknown data table 'missing'"),
+            "{}",
+            err.message
+        );
+        let err = compile(r#"lookup("adminTeams", "x") == """#).unwrap_err();
+        assert!(
+            err.message
+                .contains("requires a map table, but 'adminTeams' is a list"),
+            "{}",
+            err.message
+        );
+        let err = compile(r#"inList(header("X-Table"), "x")"#).unwrap_err();
+        assert!(err.message.contains("string literal"), "{}", err.message);
+        let err = Program::compile(r#"inList("adminTeams", "x")"#).unwrap_err();
+        assert!(
+            err.message.contains("unknown data table"),
+            "{}",
+            err.message
+        );
+    }
 }
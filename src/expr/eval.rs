@@ -3,9 +3,17 @@
 
 // Expression evaluator - runtime evaluation against RequestContext
 
-use super::ast::{BinOp, Expr, Ident};
-use super::compiler::Program;
+use super::ast::{BinOp, CertAttr, Expr, Ident};
+use super::compiler::{Environment, Program, StringProgram};
+use crate::body;
+use crate::cert::ClientCert;
+use crate::config::DataTable;
 use crate::context::RequestContext;
+use crate::graphql;
+use crate::json;
+use crate::sfv;
+use crate::signature;
+use std::collections::HashSet;
 use std::fmt;
 
 /// Value types during evaluation
@@ -14,6 +22,7 @@ pub enum Value {
     Str(String),
     StrList(Vec<String>),
     Bool(bool),
+    Int(i64),
 }
 
 impl fmt::Display for Value {
@@ -31,6 +40,7 @@ impl fmt::Display for Value {
                 write!(f, "]")
             }
             Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
         }
     }
 }
@@ -49,39 +59,144 @@ impl fmt::Display for EvalError {
 
 impl std::error::Error for EvalError {}
 
+/// Result of evaluating a program. When the program is false, `rule` and
+/// `reason` come from the innermost labeled clause that made it false.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Decision<'a> {
+    /// Whether the program is true
+    pub allowed: bool,
+
+    /// Name of the failing clause, from `@name(...)`
+    pub rule: Option<&'a str>,
+
+    /// Reason of the failing clause, from `deny("reason") unless ...`
+    pub reason: Option<&'a str>,
+}
+
+impl<'a> Decision<'a> {
+    /// Why the program is false: the failing clause's reason, or its name
+    pub fn deny_reason(&self) -> Option<&'a str> {
+        self.reason.or(self.rule)
+    }
+}
+
 impl Program {
     /// Evaluate the program against a request context
-    pub fn eval(&self, ctx: &RequestContext) -> Result<bool, EvalError> {
-        match eval_expr(&self.root, ctx)? {
-            Value::Bool(b) => Ok(b),
+    pub fn eval(&self, ctx: &RequestContext) -> Result<Decision<'_>, EvalError> {
+        decide(&self.root, ctx, &self.env)
+    }
+}
+
+impl StringProgram {
+    /// Evaluate the expression against a request context
+    pub fn eval(&self, ctx: &RequestContext) -> Result<String, EvalError> {
+        match eval_expr(&self.root, ctx, &self.env)? {
+            Value::Str(value) => Ok(value),
+            Value::Int(value) => Ok(value.to_string()),
             _ => Err(EvalError {
-                message: "Expression did not evaluate to boolean".to_string(),
+                message: "Expression did not evaluate to string".to_string(),
             }),
         }
     }
 }
 
+/// Evaluate a boolean expression, following AND, OR, NOT and labels to
+/// find the labeled clause that makes it false. An AND is explained by its
+/// first false operand, an OR by its first explained operand, and a label
+/// adds its name and reason to the explanation of its expression. NOT
+/// explains nothing: the clauses under it were true.
+fn decide<'a>(
+    expr: &'a Expr,
+    ctx: &RequestContext,
+    env: &Environment,
+) -> Result<Decision<'a>, EvalError> {
+    match expr {
+        Expr::And(left, right) => {
+            let left = decide(left, ctx, env)?;
+            if !left.allowed {
+                return Ok(left);
+            }
+            decide(right, ctx, env)
+        }
+        Expr::Or(left, right) => {
+            let left = decide(left, ctx, env)?;
+            if left.allowed {
+                return Ok(left);
+            }
+            let right = decide(right, ctx, env)?;
+            if right.allowed || left.deny_reason().is_none() {
+                return Ok(right);
+            }
+            Ok(left)
+        }
+        Expr::Not(inner) => Ok(Decision {
+            allowed: !decide(inner, ctx, env)?.allowed,
+            ..Decision::default()
+        }),
+        Expr::Labeled { name, reason, expr } => {
+            let mut decision = decide(expr, ctx, env)?;
+            if !decision.allowed {
+                decision.rule = decision.rule.or(name.as_deref());
+                decision.reason = decision.reason.or(reason.as_deref());
+            }
+            Ok(decision)
+        }
+        _ => match eval_expr(expr, ctx, env)? {
+            Value::Bool(allowed) => Ok(Decision {
+                allowed,
+                ..Decision::default()
+            }),
+            _ => Err(EvalError {
+                message: "Expression did not evaluate to boolean".to_string(),
+            }),
+        },
+    }
+}
+
 /// Evaluate an expression recursively
-fn eval_expr(expr: &Expr, ctx: &RequestContext) -> Result<Value, EvalError> {
+fn eval_expr(expr: &Expr, ctx: &RequestContext, env: &Environment) -> Result<Value, EvalError> {
     match expr {
         Expr::BoolLiteral(b) => Ok(Value::Bool(*b)),
 
         Expr::StringLiteral(s) => Ok(Value::Str(s.clone())),
 
+        Expr::JsonPathLiteral { source, .. } => Ok(Value::Str(source.clone())),
+
+        Expr::IntLiteral(n) => Ok(Value::Int(*n)),
+
         Expr::Ident(ident) => match ident {
             Ident::Method => Ok(Value::Str(ctx.method.clone())),
             Ident::Path => Ok(Value::Str(ctx.path.clone())),
             Ident::Host => Ok(Value::Str(ctx.host.clone())),
+            Ident::Now => Ok(Value::Int(ctx.now.try_into().unwrap_or(i64::MAX))),
+            Ident::BodySize => Ok(Value::Int(ctx.body_size().try_into().unwrap_or(i64::MAX))),
+            Ident::Status => Ok(Value::Int(ctx.response_status().into())),
+            Ident::GraphqlOperationType => Ok(Value::Str(
+                graphql_operation(ctx, env)
+                    .map_or_else(String::new, |op| op.operation_type.clone()),
+            )),
+            Ident::GraphqlOperationName => Ok(Value::Str(
+                graphql_operation(ctx, env).map_or_else(String::new, |op| op.name.clone()),
+            )),
+            Ident::GrpcService => Ok(Value::Str(
+                ctx.grpc_call()
+                    .map_or("", |(service, _)| service)
+                    .to_string(),
+            )),
+            Ident::GrpcMethod => Ok(Value::Str(
+                ctx.grpc_call().map_or("", |(_, method)| method).to_string(),
+            )),
+            Ident::ClientCert(attr) => Ok(eval_client_cert(attr, ctx)),
         },
 
         Expr::BinaryOp { op, left, right } => {
-            let left_val = eval_expr(left, ctx)?;
-            let right_val = eval_expr(right, ctx)?;
+            let left_val = eval_expr(left, ctx, env)?;
+            let right_val = eval_expr(right, ctx, env)?;
             eval_binop(op, left_val, right_val)
         }
 
         Expr::RegexMatch { expr, regex } => {
-            let val = eval_expr(expr, ctx)?;
+            let val = eval_expr(expr, ctx, env)?;
             match val {
                 Value::Str(text) => Ok(Value::Bool(regex.regex.is_match(&text))),
                 _ => Err(EvalError {
@@ -91,11 +206,11 @@ fn eval_expr(expr: &Expr, ctx: &RequestContext) -> Result<Value, EvalError> {
         }
 
         Expr::And(left, right) => {
-            let left_val = eval_expr(left, ctx)?;
+            let left_val = eval_expr(left, ctx, env)?;
             match left_val {
                 Value::Bool(false) => Ok(Value::Bool(false)), // Short-circuit
                 Value::Bool(true) => {
-                    let right_val = eval_expr(right, ctx)?;
+                    let right_val = eval_expr(right, ctx, env)?;
                     match right_val {
                         Value::Bool(b) => Ok(Value::Bool(b)),
                         _ => Err(EvalError {
@@ -110,11 +225,11 @@ fn eval_expr(expr: &Expr, ctx: &RequestContext) -> Result<Value, EvalError> {
         }
 
         Expr::Or(left, right) => {
-            let left_val = eval_expr(left, ctx)?;
+            let left_val = eval_expr(left, ctx, env)?;
             match left_val {
                 Value::Bool(true) => Ok(Value::Bool(true)), // Short-circuit
                 Value::Bool(false) => {
-                    let right_val = eval_expr(right, ctx)?;
+                    let right_val = eval_expr(right, ctx, env)?;
                     match right_val {
                         Value::Bool(b) => Ok(Value::Bool(b)),
                         _ => Err(EvalError {
@@ -129,7 +244,7 @@ fn eval_expr(expr: &Expr, ctx: &RequestContext) -> Result<Value, EvalError> {
         }
 
         Expr::Not(inner) => {
-            let val = eval_expr(inner, ctx)?;
+            let val = eval_expr(inner, ctx, env)?;
             match val {
                 Value::Bool(b) => Ok(Value::Bool(!b)),
                 _ => Err(EvalError {
@@ -138,7 +253,9 @@ fn eval_expr(expr: &Expr, ctx: &RequestContext) -> Result<Value, EvalError> {
             }
         }
 
-        Expr::FuncCall { name, args } => eval_function(name, args, ctx),
+        Expr::Labeled { expr, .. } => eval_expr(expr, ctx, env),
+
+        Expr::FuncCall { name, args } => eval_function(name, args, ctx, env),
     }
 }
 
@@ -150,6 +267,13 @@ fn eval_binop(op: &BinOp, left: Value, right: Value) -> Result<Value, EvalError>
         (BinOp::StartsWith, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l.starts_with(&r))),
         (BinOp::EndsWith, Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l.ends_with(&r))),
 
+        (BinOp::Eq, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l == r)),
+        (BinOp::Neq, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l != r)),
+        (BinOp::Lt, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l < r)),
+        (BinOp::Le, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l <= r)),
+        (BinOp::Gt, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l > r)),
+        (BinOp::Ge, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l >= r)),
+
         (BinOp::Contains, Value::StrList(list), Value::Str(item)) => {
             Ok(Value::Bool(list.contains(&item)))
         }
@@ -168,12 +292,85 @@ fn eval_binop(op: &BinOp, left: Value, right: Value) -> Result<Value, EvalError>
     }
 }
 
+/// Evaluate a client certificate attribute. Without a certificate the
+/// attributes are absent: empty strings, an empty list and 0.
+fn eval_client_cert(attr: &CertAttr, ctx: &RequestContext) -> Value {
+    let cert = ctx.client_cert();
+    let text =
+        |get: fn(&ClientCert) -> &String| Value::Str(cert.map(get).cloned().unwrap_or_default());
+    match attr {
+        CertAttr::SubjectCN => text(|c| &c.subject_cn),
+        CertAttr::IssuerCN => text(|c| &c.issuer_cn),
+        CertAttr::Serial => text(|c| &c.serial),
+        CertAttr::Sans => Value::StrList(cert.map(|c| c.sans.clone()).unwrap_or_default()),
+        CertAttr::NotBefore => Value::Int(cert.map_or(0, |c| c.not_before)),
+        CertAttr::NotAfter => Value::Int(cert.map_or(0, |c| c.not_after)),
+    }
+}
+
+/// Evaluate a structured field function. Malformed or missing fields,
+/// members and parameters are absent (empty string or empty list).
+fn eval_structured_field(
+    name: &str,
+    args: &[String],
+    ctx: &RequestContext,
+    env: &Environment,
+) -> Value {
+    let text = |item: Option<&sfv::BareItem>| item.map(|v| v.to_string()).unwrap_or_default();
+    match (name, args) {
+        // sfList(name): the values of the List's items; inner lists are skipped
+        ("sfList", [header]) => Value::StrList(
+            ctx.sf_list(header)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|member| match member {
+                    sfv::Member::Item(item) => Some(item.value.to_string()),
+                    sfv::Member::InnerList(..) => None,
+                })
+                .take(env.max_header_list_items)
+                .collect(),
+        ),
+
+        // sfItem(name): the value of an Item header
+        ("sfItem", [header]) => Value::Str(text(ctx.sf_item(header).as_ref().map(|i| &i.value))),
+
+        // sfDict(name, key): the value of a Dictionary member that is an item
+        ("sfDict", [header, key]) => {
+            let dict = ctx.sf_dict(header).unwrap_or_default();
+            Value::Str(text(match sfv::get(&dict, key) {
+                Some(sfv::Member::Item(item)) => Some(&item.value),
+                _ => None,
+            }))
+        }
+
+        // sfParam(name, param): a parameter of an Item header
+        ("sfParam", [header, param]) => {
+            let item = ctx.sf_item(header);
+            Value::Str(text(item.as_ref().and_then(|i| sfv::get(&i.params, param))))
+        }
+
+        // sfParam(name, key, param): a parameter of a Dictionary member
+        ("sfParam", [header, key, param]) => {
+            let dict = ctx.sf_dict(header).unwrap_or_default();
+            let member = sfv::get(&dict, key);
+            Value::Str(text(member.and_then(|m| sfv::get(m.params(), param))))
+        }
+
+        _ => Value::Str(String::new()),
+    }
+}
+
 /// Evaluate a function call
-fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Value, EvalError> {
+fn eval_function(
+    name: &str,
+    args: &[Expr],
+    ctx: &RequestContext,
+    env: &Environment,
+) -> Result<Value, EvalError> {
     match name {
         "header" => {
             // header(name: string) -> string
-            let name_val = eval_expr(&args[0], ctx)?;
+            let name_val = eval_expr(&args[0], ctx, env)?;
             match name_val {
                 Value::Str(name) => {
                     let value = ctx.header(&name);
@@ -187,7 +384,7 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
 
         "headerValues" => {
             // headerValues(name: string) -> []string
-            let name_val = eval_expr(&args[0], ctx)?;
+            let name_val = eval_expr(&args[0], ctx, env)?;
             match name_val {
                 Value::Str(name) => {
                     let values = ctx.header_values(&name);
@@ -199,24 +396,61 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
             }
         }
 
-        "headerList" => {
-            // headerList(name: string) -> []string
-            let name_val = eval_expr(&args[0], ctx)?;
+        "headerExists" => {
+            // headerExists(name: string) -> bool
+            let name_val = eval_expr(&args[0], ctx, env)?;
             match name_val {
-                Value::Str(name) => {
-                    let list = ctx.header_list(&name);
+                Value::Str(name) => Ok(Value::Bool(ctx.has_header(&name))),
+                _ => Err(EvalError {
+                    message: "headerExists() expects string argument".to_string(),
+                }),
+            }
+        }
+
+        "responseHeader" => {
+            // responseHeader(name: string) -> string
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            match name_val {
+                Value::Str(name) => Ok(Value::Str(ctx.response_header(&name).to_string())),
+                _ => Err(EvalError {
+                    message: "responseHeader() expects string argument".to_string(),
+                }),
+            }
+        }
+
+        "responseHeaderExists" => {
+            // responseHeaderExists(name: string) -> bool
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            match name_val {
+                Value::Str(name) => Ok(Value::Bool(ctx.has_response_header(&name))),
+                _ => Err(EvalError {
+                    message: "responseHeaderExists() expects string argument".to_string(),
+                }),
+            }
+        }
+
+        "headerList" => {
+            // headerList(name: string, separator: string = ",") -> []string
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            let separator = match args.get(1) {
+                Some(arg) => eval_expr(arg, ctx, env)?,
+                None => Value::Str(",".to_string()),
+            };
+            match (name_val, separator) {
+                (Value::Str(name), Value::Str(separator)) => {
+                    let list = ctx.header_list_with(&name, &separator, env.max_header_list_items);
                     Ok(Value::StrList(list))
                 }
                 _ => Err(EvalError {
-                    message: "headerList() expects string argument".to_string(),
+                    message: "headerList() expects string arguments".to_string(),
                 }),
             }
         }
 
         "contains" => {
             // contains(list: []string, item: string) -> bool
-            let list_val = eval_expr(&args[0], ctx)?;
-            let item_val = eval_expr(&args[1], ctx)?;
+            let list_val = eval_expr(&args[0], ctx, env)?;
+            let item_val = eval_expr(&args[1], ctx, env)?;
 
             match (list_val, item_val) {
                 (Value::StrList(list), Value::Str(item)) => Ok(Value::Bool(list.contains(&item))),
@@ -228,7 +462,7 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
 
         "anyOf" => {
             // anyOf(list: []string, items: ...string) -> bool
-            let list_val = eval_expr(&args[0], ctx)?;
+            let list_val = eval_expr(&args[0], ctx, env)?;
             let list = match list_val {
                 Value::StrList(l) => l,
                 _ => {
@@ -240,7 +474,7 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
 
             // Check if any of the items are in the list
             for arg in args.iter().skip(1) {
-                let item_val = eval_expr(arg, ctx)?;
+                let item_val = eval_expr(arg, ctx, env)?;
                 match item_val {
                     Value::Str(item) => {
                         if list.contains(&item) {
@@ -260,7 +494,7 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
 
         "allOf" => {
             // allOf(list: []string, items: ...string) -> bool
-            let list_val = eval_expr(&args[0], ctx)?;
+            let list_val = eval_expr(&args[0], ctx, env)?;
             let list = match list_val {
                 Value::StrList(l) => l,
                 _ => {
@@ -272,7 +506,7 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
 
             // Check if all of the items are in the list
             for arg in args.iter().skip(1) {
-                let item_val = eval_expr(arg, ctx)?;
+                let item_val = eval_expr(arg, ctx, env)?;
                 match item_val {
                     Value::Str(item) => {
                         if !list.contains(&item) {
@@ -290,17 +524,324 @@ fn eval_function(name: &str, args: &[Expr], ctx: &RequestContext) -> Result<Valu
             Ok(Value::Bool(true))
         }
 
+        "cookie" => {
+            // cookie(name: string) -> string
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            match name_val {
+                Value::Str(name) => Ok(Value::Str(ctx.cookie(&name).to_string())),
+                _ => Err(EvalError {
+                    message: "cookie() expects string argument".to_string(),
+                }),
+            }
+        }
+
+        "cookieNames" => {
+            // cookieNames() -> []string
+            Ok(Value::StrList(ctx.cookie_names()))
+        }
+
+        "signedCookie" => {
+            // signedCookie(name: string) -> string
+            let secret = env.cookie_secret.as_ref().ok_or_else(|| EvalError {
+                message: "signedCookie() requires cookieSecret".to_string(),
+            })?;
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            match name_val {
+                Value::Str(name) => Ok(Value::Str(
+                    ctx.signed_cookie(&name, secret.as_bytes()).to_string(),
+                )),
+                _ => Err(EvalError {
+                    message: "signedCookie() expects string argument".to_string(),
+                }),
+            }
+        }
+
+        "signatureValid" => {
+            // signatureValid() -> bool
+            let cfg = env.signed_headers.as_ref().ok_or_else(|| EvalError {
+                message: "signatureValid() requires signedHeaders".to_string(),
+            })?;
+            Ok(Value::Bool(signature::verify_headers(cfg, ctx)))
+        }
+
+        "presignedValid" => {
+            // presignedValid() -> bool
+            let cfg = env.presigned_urls.as_ref().ok_or_else(|| EvalError {
+                message: "presignedValid() requires presignedUrls".to_string(),
+            })?;
+            Ok(Value::Bool(signature::verify_presigned(cfg, ctx)))
+        }
+
+        "headerJson" | "headerJsonList" | "headerJsonInt" | "headerJsonBool" => {
+            // headerJson*(name: string, path: string) -> string/[]string/int/bool
+            let (Value::Str(header), Expr::JsonPathLiteral { path, .. }) =
+                (eval_expr(&args[0], ctx, env)?, &args[1])
+            else {
+                return Err(EvalError {
+                    message: format!("{}() expects string arguments", name),
+                });
+            };
+            let doc = ctx.header_json(&header);
+            let node = doc.as_deref().and_then(|doc| path.lookup(doc));
+            Ok(match name {
+                "headerJson" => Value::Str(json::to_string(node)),
+                "headerJsonList" => Value::StrList(json::to_list(node)),
+                "headerJsonInt" => Value::Int(json::to_int(node)),
+                _ => Value::Bool(json::to_bool(node)),
+            })
+        }
+
+        "bodyJson" | "bodyJsonList" | "bodyJsonInt" | "bodyJsonBool" => {
+            // bodyJson*(path: string) -> string/[]string/int/bool
+            let Expr::JsonPathLiteral { path, .. } = &args[0] else {
+                return Err(EvalError {
+                    message: format!("{}() expects a compiled JSON path", name),
+                });
+            };
+            let doc = if body_within_limit(ctx, env) {
+                ctx.body_json()
+            } else {
+                None
+            };
+            let node = doc.and_then(|doc| path.lookup(doc));
+            Ok(match name {
+                "bodyJson" => Value::Str(json::to_string(node)),
+                "bodyJsonList" => Value::StrList(json::to_list(node)),
+                "bodyJsonInt" => Value::Int(json::to_int(node)),
+                _ => Value::Bool(json::to_bool(node)),
+            })
+        }
+
+        "formValue" | "formValues" => {
+            // formValue(name: string) -> string
+            // formValues(name: string) -> []string
+            let Value::Str(field) = eval_expr(&args[0], ctx, env)? else {
+                return Err(EvalError {
+                    message: format!("{}() expects string argument", name),
+                });
+            };
+            // Fail closed: a field in an unread body, or past the field
+            // limit, could hide from the policy
+            if !body_within_limit(ctx, env) {
+                return Err(EvalError {
+                    message: format!(
+                        "{}(): body is larger than maxBodySize ({} bytes)",
+                        name, env.max_body_size
+                    ),
+                });
+            }
+            let too_many_fields = || EvalError {
+                message: format!(
+                    "{}(): form body has more than {} fields",
+                    name,
+                    body::MAX_FORM_FIELDS
+                ),
+            };
+            Ok(match name {
+                "formValue" => Value::Str(
+                    ctx.form_value(&field)
+                        .ok_or_else(too_many_fields)?
+                        .to_string(),
+                ),
+                _ => Value::StrList(ctx.form_values(&field).ok_or_else(too_many_fields)?),
+            })
+        }
+
+        "graphqlRootFields" => {
+            // graphqlRootFields() -> []string
+            Ok(Value::StrList(
+                graphql_operation(ctx, env).map_or_else(Vec::new, |op| op.root_fields.clone()),
+            ))
+        }
+
+        "sfList" | "sfItem" | "sfDict" | "sfParam" => {
+            // Structured field functions take only string arguments
+            let mut strs = Vec::with_capacity(args.len());
+            for arg in args {
+                match eval_expr(arg, ctx, env)? {
+                    Value::Str(s) => strs.push(s),
+                    _ => {
+                        return Err(EvalError {
+                            message: format!("{}() expects string arguments", name),
+                        })
+                    }
+                }
+            }
+            Ok(eval_structured_field(name, &strs, ctx, env))
+        }
+
+        "lookup" | "lookupList" | "inList" => {
+            // lookup(table, key) -> string, lookupList(table, key) -> []string,
+            // inList(table, value) -> bool
+            let (Expr::StringLiteral(table), Value::Str(key)) =
+                (&args[0], eval_expr(&args[1], ctx, env)?)
+            else {
+                return Err(EvalError {
+                    message: format!("{}() expects string arguments", name),
+                });
+            };
+            let data = env.data.get(table).ok_or_else(|| EvalError {
+                message: format!("Unknown data table '{}'", table),
+            })?;
+            match (name, data) {
+                ("lookup", DataTable::Map(map)) => {
+                    Ok(Value::Str(map.get(&key).cloned().unwrap_or_default()))
+                }
+                ("lookupList", DataTable::MapOfLists(map)) => {
+                    Ok(Value::StrList(map.get(&key).cloned().unwrap_or_default()))
+                }
+                ("inList", DataTable::List(items)) => Ok(Value::Bool(items.contains(&key))),
+                _ => Err(EvalError {
+                    message: format!("{}() cannot use {} table '{}'", name, data.kind(), table),
+                }),
+            }
+        }
+
+        "hasRole" | "hasPermission" => {
+            // hasRole(role: string) -> bool, hasPermission(permission: string) -> bool
+            let Some(policy) = &env.rbac else {
+                return Err(EvalError {
+                    message: format!("{}() requires rbac to be configured", name),
+                });
+            };
+            let Expr::StringLiteral(value) = &args[0] else {
+                return Err(EvalError {
+                    message: format!("{}() expects a string literal argument", name),
+                });
+            };
+            let subject = ctx.rbac_subject(|| match eval_expr(&policy.user_roles, ctx, env)? {
+                Value::StrList(roles) => Ok(policy.graph.subject(&roles)),
+                _ => Err(EvalError {
+                    message: "rbac userRoles must evaluate to []string".to_string(),
+                }),
+            })?;
+            Ok(Value::Bool(if name == "hasRole" {
+                subject.has_role(value)
+            } else {
+                subject.has_permission(value)
+            }))
+        }
+
+        "openapiAllowed" => {
+            // openapiAllowed() -> bool
+            let Some(policy) = &env.openapi else {
+                return Err(EvalError {
+                    message: "openapiAllowed() requires openapi to be configured".to_string(),
+                });
+            };
+            let Some(route) = policy.routes.find(&ctx.method, &ctx.normalized_path) else {
+                return Ok(Value::Bool(policy.allow_unknown));
+            };
+            let Value::StrList(scopes) = eval_expr(&policy.scopes, ctx, env)? else {
+                return Err(EvalError {
+                    message: "openapi scopes must evaluate to []string".to_string(),
+                });
+            };
+            let scopes: HashSet<&str> = scopes.iter().map(String::as_str).collect();
+            Ok(Value::Bool(route.allows(&scopes)))
+        }
+
+        "related" => {
+            // related(subject: string, relation: string, object: string) -> bool
+            let Some(graph) = &env.relations else {
+                return Err(EvalError {
+                    message: "related() requires relations to be configured".to_string(),
+                });
+            };
+            let mut values = Vec::with_capacity(3);
+            for arg in args {
+                match eval_expr(arg, ctx, env)? {
+                    Value::Str(s) => values.push(s),
+                    _ => {
+                        return Err(EvalError {
+                            message: "related() expects string arguments".to_string(),
+                        })
+                    }
+                }
+            }
+            Ok(Value::Bool(
+                graph.related(&values[0], &values[1], &values[2]),
+            ))
+        }
+
+        "capture" => {
+            // capture(value: string, pattern: string, group: string | int) -> string
+            // The compiler stores the value and compiled pattern as a RegexMatch
+            let Expr::RegexMatch { expr, regex } = &args[0] else {
+                return Err(EvalError {
+                    message: "capture() requires a compiled pattern".to_string(),
+                });
+            };
+            let Value::Str(text) = eval_expr(expr, ctx, env)? else {
+                return Err(EvalError {
+                    message: "capture() expects a string value".to_string(),
+                });
+            };
+            let captured = regex.regex.captures(&text).and_then(|caps| match &args[1] {
+                Expr::StringLiteral(group) => caps.name(group),
+                Expr::IntLiteral(index) => caps.get(*index as usize),
+                _ => None,
+            });
+            Ok(Value::Str(captured.map_or("", |m| m.as_str()).to_string()))
+        }
+
+        "concat" => {
+            // concat(values: ...string) -> string
+            let mut result = String::new();
+            for arg in args {
+                match eval_expr(arg, ctx, env)? {
+                    Value::Str(s) => result.push_str(&s),
+                    _ => {
+                        return Err(EvalError {
+                            message: "concat() expects string arguments".to_string(),
+                        })
+                    }
+                }
+            }
+            Ok(Value::Str(result))
+        }
+
+        "query" => {
+            // query(name: string) -> string
+            let name_val = eval_expr(&args[0], ctx, env)?;
+            match name_val {
+                Value::Str(name) => Ok(Value::Str(ctx.query(&name).to_string())),
+                _ => Err(EvalError {
+                    message: "query() expects string argument".to_string(),
+                }),
+            }
+        }
+
         _ => Err(EvalError {
             message: format!("Unknown function '{}'", name),
         }),
     }
 }
 
+/// A body over `maxBodySize` is absent, even if it was buffered (startup
+/// test requests are not limited when they are built)
+fn body_within_limit(ctx: &RequestContext, env: &Environment) -> bool {
+    ctx.body_size() <= env.max_body_size as u64
+}
+
+/// The GraphQL operation of the request; a document in a body over the
+/// limit is absent
+fn graphql_operation<'a>(
+    ctx: &'a RequestContext,
+    env: &Environment,
+) -> Option<&'a graphql::Operation> {
+    if !ctx.method.eq_ignore_ascii_case("GET") && !body_within_limit(ctx, env) {
+        return None;
+    }
+    ctx.graphql_operation()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::TestRequest;
     use std::collections::HashMap;
+    use std::rc::Rc;
 
     fn make_context(method: &str, path: &str, host: &str) -> RequestContext {
         let req = TestRequest {
@@ -308,6 +849,7 @@ mod tests {
             path: path.to_string(),
             host: host.to_string(),
             headers: HashMap::new(),
+            ..Default::default()
         };
         RequestContext::from_test(&req)
     }
@@ -323,6 +865,7 @@ mod tests {
             path: path.to_string(),
             host: host.to_string(),
             headers,
+            ..Default::default()
         };
         RequestContext::from_test(&req)
     }
@@ -331,20 +874,20 @@ mod tests {
     fn test_eval_simple_comparison() {
         let program = Program::compile(r#"method == "GET""#).unwrap();
         let ctx = make_context("GET", "/api", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("POST", "/api", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
     fn test_eval_starts_with() {
         let program = Program::compile(r#"path startsWith "/api""#).unwrap();
         let ctx = make_context("GET", "/api/users", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("GET", "/public", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -352,13 +895,13 @@ mod tests {
         let program = Program::compile(r#"method == "GET" AND path startsWith "/api""#).unwrap();
 
         let ctx = make_context("GET", "/api/users", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("POST", "/api/users", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("GET", "/public", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -366,13 +909,13 @@ mod tests {
         let program = Program::compile(r#"method == "GET" OR method == "HEAD""#).unwrap();
 
         let ctx = make_context("GET", "/", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("HEAD", "/", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("POST", "/", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -380,10 +923,10 @@ mod tests {
         let program = Program::compile(r#"NOT method == "DELETE""#).unwrap();
 
         let ctx = make_context("GET", "/", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("DELETE", "/", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -393,7 +936,58 @@ mod tests {
 
         let program = Program::compile(r#"header("X-Test") == "value123""#).unwrap();
         let ctx = make_context_with_headers("GET", "/", "example.com", headers);
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_string_program() {
+        let ctx = make_context("GET", "/tenants/acme/projects", "example.com");
+        let program = StringProgram::compile_with(
+            r#"capture(path, "^/tenants/([^/]+)/", 1)"#,
+            Environment::default(),
+        )
+        .unwrap();
+        assert_eq!(program.eval(&ctx).unwrap(), "acme");
+
+        let program = StringProgram::compile_with("bodySize", Environment::default()).unwrap();
+        assert_eq!(program.eval(&ctx).unwrap(), "0");
+    }
+
+    #[test]
+    fn test_eval_header_exists() {
+        let mut headers = HashMap::new();
+        headers.insert("X-Auth-User".to_string(), "".to_string());
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+
+        let program = Program::compile(r#"headerExists("x-auth-user")"#).unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+        let program = Program::compile(r#"headerExists("X-Auth-Teams")"#).unwrap();
+        assert!(!program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_response_attributes() {
+        let env = Rc::new(Environment {
+            response_phase: true,
+            ..Default::default()
+        });
+        let mut ctx = make_context_with_headers("GET", "/", "example.com", HashMap::new());
+        ctx.set_response(200, [("X-Internal-Only", "1"), ("server", "nginx")]);
+
+        let program = Program::compile_with(
+            r#"status == 200 AND responseHeaderExists("x-internal-only") AND responseHeader("Server") == "nginx""#,
+            Rc::clone(&env),
+        )
+        .unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        ctx.set_response(503, []);
+        let program = Program::compile_with(
+            r#"status >= 500 AND NOT responseHeaderExists("Server")"#,
+            env,
+        )
+        .unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -404,7 +998,7 @@ mod tests {
         let program =
             Program::compile(r#"contains(headerList("X-Teams"), "platform-eng")"#).unwrap();
         let ctx = make_context_with_headers("GET", "/", "example.com", headers);
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -415,7 +1009,7 @@ mod tests {
         let program =
             Program::compile(r#"anyOf(headerList("X-Teams"), "platform-eng", "sre")"#).unwrap();
         let ctx = make_context_with_headers("GET", "/", "example.com", headers);
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -426,12 +1020,12 @@ mod tests {
         let program =
             Program::compile(r#"allOf(headerList("X-Teams"), "platform-eng", "devops")"#).unwrap();
         let ctx = make_context_with_headers("GET", "/", "example.com", headers.clone());
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         // Missing one team
         headers.insert("X-Teams".to_string(), "platform-eng".to_string());
         let ctx = make_context_with_headers("GET", "/", "example.com", headers);
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -439,10 +1033,10 @@ mod tests {
         let program = Program::compile(r#"matches(path, "^/api/v[0-9]+/.*")"#).unwrap();
 
         let ctx = make_context("GET", "/api/v1/users", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context("GET", "/api/users", "example.com");
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
     }
 
     #[test]
@@ -464,9 +1058,702 @@ mod tests {
         .unwrap();
 
         let ctx = make_context_with_headers("GET", "/api", "example.com", headers.clone());
-        assert_eq!(program.eval(&ctx).unwrap(), true);
+        assert!(program.eval(&ctx).unwrap().allowed);
 
         let ctx = make_context_with_headers("POST", "/api", "example.com", headers);
-        assert_eq!(program.eval(&ctx).unwrap(), false);
+        assert!(!program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_cookie_functions() {
+        let mut headers = HashMap::new();
+        headers.insert("Cookie".to_string(), "session=abc; tenant=acme".to_string());
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+
+        let program = Program::compile(r#"cookie("tenant") == "acme""#).unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        let program = Program::compile(r#"contains(cookieNames(), "session")"#).unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        let program = Program::compile(r#"cookie("missing") == """#).unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_signed_cookie() {
+        use crate::config::Secret;
+        use crate::crypto;
+
+        let env = Environment {
+            cookie_secret: Some(Secret::from("s3cret")),
+            ..Default::default()
+        };
+        let program = Program::compile_with(r#"signedCookie("tenant") == "acme""#, env).unwrap();
+
+        let signature = crypto::encode_base64url(&crypto::hmac_sha256(b"s3cret", b"tenant=acme"));
+        let mut headers = HashMap::new();
+        headers.insert("Cookie".to_string(), format!("tenant=acme.{}", signature));
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        // Forged signature
+        let mut headers = HashMap::new();
+        headers.insert("Cookie".to_string(), format!("tenant=evil.{}", signature));
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+        assert!(!program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_query() {
+        let program = Program::compile(r#"query("view") == "full page""#).unwrap();
+        let ctx = make_context("GET", "/docs?view=full+page", "example.com");
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        let ctx = make_context("GET", "/docs", "example.com");
+        assert!(!program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_client_cert() {
+        let info = "Subject%3D%22O%3DExample%2CCN%3Dbilling.payments.svc.cluster.local%22%3B\
+                    Issuer%3D%22CN%3DExample+Issuing+CA%22%3BNB%3D%221735689600%22%3B\
+                    NA%3D%221767225600%22%3BSAN%3D%22billing%2C10.0.0.7%22";
+        let headers = HashMap::from([(
+            "X-Forwarded-Tls-Client-Cert-Info".to_string(),
+            info.to_string(),
+        )]);
+        let req = TestRequest {
+            headers,
+            time: Some(1_750_000_000),
+            ..Default::default()
+        };
+        let ctx = RequestContext::from_test(&req);
+
+        let program = Program::compile(
+            r#"clientCert.subjectCN endsWith ".svc.cluster.local"
+                AND clientCert.issuerCN == "Example Issuing CA"
+                AND contains(clientCert.sans, "10.0.0.7")
+                AND clientCert.notBefore <= now AND now < clientCert.notAfter"#,
+        )
+        .unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        let expired = RequestContext::from_test(&TestRequest {
+            time: Some(1_800_000_000),
+            ..req.clone()
+        });
+        assert!(!program.eval(&expired).unwrap().allowed);
+
+        // Without a certificate the attributes are absent
+        let ctx = make_context("GET", "/", "example.com");
+        assert!(!program.eval(&ctx).unwrap().allowed);
+        let program =
+            Program::compile(r#"clientCert.serial == "" AND clientCert.notAfter == 0"#).unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_header_json() {
+        let userinfo = r#"{"sub":"jdoe","groups":["sre","admins"],"age":42,"email_verified":true}"#;
+        let mut headers = HashMap::new();
+        headers.insert("X-Userinfo".to_string(), userinfo.to_string());
+        headers.insert(
+            "X-Userinfo-B64".to_string(),
+            crate::crypto::encode_base64url(userinfo.as_bytes()),
+        );
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+
+        for header in ["X-Userinfo", "x-userinfo-b64"] {
+            let expr = format!(
+                r#"headerJson("{0}", "sub") == "jdoe"
+                    AND contains(headerJsonList("{0}", "groups"), "admins")
+                    AND headerJsonInt("{0}", "$.age") > 21
+                    AND headerJsonBool("{0}", "email_verified")"#,
+                header
+            );
+            let program = Program::compile(&expr).unwrap();
+            assert!(program.eval(&ctx).unwrap().allowed, "{}", header);
+        }
+
+        // Missing header or node is absent
+        let program = Program::compile(
+            r#"headerJson("X-Other", "sub") == "" AND headerJsonInt("X-Userinfo", "groups[5]") == 0"#,
+        )
+        .unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_structured_fields() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Example-List".to_string(),
+            r#"sugar, "tea";q=0.5, (a b)"#.to_string(),
+        );
+        headers.insert("Priority".to_string(), "u=1, i, x;reason=bot".to_string());
+        headers.insert("Example-Item".to_string(), "token;a=?0;b=2.50".to_string());
+        headers.insert("Example-Bad".to_string(), "a,,b".to_string());
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+
+        let check = |expr: &str, expected: bool| {
+            let program = Program::compile(expr).unwrap();
+            assert_eq!(program.eval(&ctx).unwrap().allowed, expected, "{}", expr);
+        };
+
+        check(r#"contains(sfList("Example-List"), "tea")"#, true);
+        check(r#"contains(sfList("Example-List"), "a")"#, false);
+        check(r#"sfDict("Priority", "u") == "1""#, true);
+        check(r#"sfDict("Priority", "i") == "?1""#, true);
+        check(r#"sfParam("Priority", "x", "reason") == "bot""#, true);
+        check(r#"sfItem("Example-Item") == "token""#, true);
+        check(r#"sfParam("Example-Item", "a") == "?0""#, true);
+        check(r#"sfParam("Example-Item", "b") == "2.5""#, true);
+
+        // Malformed and missing fields are absent
+        check(r#"contains(sfList("Example-Bad"), "a")"#, false);
+        check(r#"sfDict("Example-Bad", "a") == """#, true);
+        check(
+            r#"sfItem("Missing") == "" AND sfParam("Priority", "u", "x") == """#,
+            true,
+        );
+    }
+
+    #[test]
+    fn test_eval_header_list_separator_and_cap() {
+        let mut headers = HashMap::new();
+        headers.insert("X-Scopes".to_string(), "read write admin".to_string());
+        headers.insert("X-Teams".to_string(), r#""eng, platform", sre"#.to_string());
+        let ctx = make_context_with_headers("GET", "/", "example.com", headers);
+
+        let program = Program::compile(
+            r#"contains(headerList("X-Scopes", " "), "admin")
+                AND contains(headerList("X-Teams"), "eng, platform")"#,
+        )
+        .unwrap();
+        assert!(program.eval(&ctx).unwrap().allowed);
+
+        let env = Environment {
+            max_header_list_items: 2,
+            ..Default::default()
+        };
+        let program =
+            Program::compile_with(r#"contains(headerList("X-Scopes", " "), "admin")"#, env)
+                .unwrap();
+        assert!(!program.eval(&ctx).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_body_json() {
+        let make = |content_type: &str, body: &str| {
+            RequestContext::from_test(&TestRequest {
+                method: "POST".to_string(),
+                headers: HashMap::from([("Content-Type".to_string(), content_type.to_string())]),
+                body: Some(body.to_string()),
+                ..Default::default()
+            })
+        };
+        let body =
+            r#"{"account":{"id":"acct-42","owners":["alice","bob"]},"amount":250,"dryRun":true}"#;
+        let expr = r#"bodyJson("$.account.id") == "acct-42"
+            AND contains(bodyJsonList("account.owners"), "bob")
+            AND bodyJsonInt("amount") <= 1000
+            AND bodyJsonBool("dryRun")
+            AND bodySize > 0"#;
+        let program = Program::compile(expr).unwrap();
+
+        assert!(
+            program
+                .eval(&make("application/json", body))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            program
+                .eval(&make("application/vnd.api+json; charset=utf-8", body))
+                .unwrap()
+                .allowed
+        );
+        // Content-Type must be JSON
+        assert!(!program.eval(&make("text/plain", body)).unwrap().allowed);
+        assert!(
+            !program
+                .eval(&make("application/json", "{"))
+                .unwrap()
+                .allowed
+        );
+
+        // A body over maxBodySize is absent, but bodySize still reports it
+        let env = Environment {
+            max_body_size: 16,
+            ..Default::default()
+        };
+        let program =
+            Program::compile_with(r#"bodyJson("account.id") == "" AND bodySize > 16"#, env)
+                .unwrap();
+        assert!(
+            program
+                .eval(&make("application/json", body))
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn test_eval_form_values() {
+        let make = |content_type: &str, body: &str| {
+            RequestContext::from_test(&TestRequest {
+                method: "POST".to_string(),
+                headers: HashMap::from([("Content-Type".to_string(), content_type.to_string())]),
+                body: Some(body.to_string()),
+                ..Default::default()
+            })
+        };
+        let program = Program::compile(
+            r#"formValue("action") != "delete" AND NOT contains(formValues("scope"), "all")"#,
+        )
+        .unwrap();
+
+        let urlencoded = "application/x-www-form-urlencoded";
+        assert!(
+            program
+                .eval(&make(urlencoded, "action=view&scope=one"))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !program
+                .eval(&make(urlencoded, "action=delete"))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !program
+                .eval(&make(urlencoded, "action=view&scope=one&scope=all"))
+                .unwrap()
+                .allowed
+        );
+
+        let multipart =
+            "--x\r\nContent-Disposition: form-data; name=\"action\"\r\n\r\ndelete\r\n--x--\r\n";
+        assert!(
+            !program
+                .eval(&make("multipart/form-data; boundary=x", multipart))
+                .unwrap()
+                .allowed
+        );
+
+        // Padding fields cannot push a field past the parse limit unseen
+        let padded = format!(
+            "{}&action=delete",
+            vec!["pad=1"; body::MAX_FORM_FIELDS].join("&")
+        );
+        assert!(program.eval(&make(urlencoded, &padded)).is_err());
+
+        // Over maxBodySize the form fails closed rather than being absent
+        let env = Environment {
+            max_body_size: 8,
+            ..Default::default()
+        };
+        let program = Program::compile_with(r#"formValue("action") == """#, env).unwrap();
+        let err = program
+            .eval(&make(urlencoded, "action=delete"))
+            .unwrap_err();
+        assert!(err.message.contains("maxBodySize"), "{}", err.message);
+        assert!(program.eval(&make(urlencoded, "a=1")).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_graphql() {
+        let make = |body: &str| {
+            RequestContext::from_test(&TestRequest {
+                method: "POST".to_string(),
+                path: "/graphql".to_string(),
+                headers: HashMap::from([(
+                    "Content-Type".to_string(),
+                    "application/json".to_string(),
+                )]),
+                body: Some(body.to_string()),
+                ..Default::default()
+            })
+        };
+        let program = Program::compile(
+            r#"graphqlOperationType == "query"
+                OR (graphqlOperationType == "mutation"
+                    AND graphqlOperationName == "AddComment"
+                    AND NOT anyOf(graphqlRootFields(), "deleteUser", "setRole"))"#,
+        )
+        .unwrap();
+
+        let query = r#"{"query":"{ me { id } }"}"#;
+        let mutation = r#"{"query":"mutation AddComment { addComment(text: \"hi\") { id } }"}"#;
+        let destructive =
+            r#"{"query":"mutation AddComment { addComment { id } deleteUser(id: 1) }"}"#;
+        let ambiguous = r#"{"query":"query A { a } mutation B { b }"}"#;
+        let named = r#"{"query":"query A { a } mutation B { b }","operationName":"A"}"#;
+
+        assert!(program.eval(&make(query)).unwrap().allowed);
+        assert!(program.eval(&make(mutation)).unwrap().allowed);
+        assert!(!program.eval(&make(destructive)).unwrap().allowed);
+        assert!(!program.eval(&make(ambiguous)).unwrap().allowed);
+        assert!(program.eval(&make(named)).unwrap().allowed);
+        assert!(!program.eval(&make(r#"{"query":"{"}"#)).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_eval_grpc() {
+        let make = |path: &str, content_type: &str| {
+            RequestContext::from_test(&TestRequest {
+                method: "POST".to_string(),
+                path: path.to_string(),
+                headers: HashMap::from([("Content-Type".to_string(), content_type.to_string())]),
+                ..Default::default()
+            })
+        };
+        let program = Program::compile(
+            r#"grpcService == "billing.v1.Invoices" AND grpcMethod startsWith "Get""#,
+        )
+        .unwrap();
+        assert!(
+            program
+                .eval(&make("/billing.v1.Invoices/GetInvoice", "application/grpc"))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !program
+                .eval(&make(
+                    "/billing.v1.Invoices/DeleteInvoice",
+                    "application/grpc"
+                ))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !program
+                .eval(&make("/billing.v1.Invoices/GetInvoice", "application/json"))
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn test_eval_data_tables() {
+        let env = Environment {
+            data: HashMap::from([
+                (
+                    "adminTeams".to_string(),
+                    DataTable::List(vec!["platform-eng".to_string(), "sre".to_string()]),
+                ),
+                (
+                    "costCenters".to_string(),
+                    DataTable::Map(HashMap::from([(
+                        "payments".to_string(),
+                        "CC-100".to_string(),
+                    )])),
+                ),
+                (
+                    "owners".to_string(),
+                    DataTable::MapOfLists(HashMap::from([(
+                        "/api/payments".to_string(),
+                        vec!["payments".to_string(), "sre".to_string()],
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let program = Program::compile_with(
+            r#"inList("adminTeams", header("X-Team"))
+                OR (lookup("costCenters", header("X-Team")) == "CC-100"
+                    AND contains(lookupList("owners", path), header("X-Team")))"#,
+            env,
+        )
+        .unwrap();
+
+        let check = |path: &str, team: &str, expected: bool| {
+            let ctx = make_context_with_headers(
+                "GET",
+                path,
+                "example.com",
+                HashMap::from([("X-Team".to_string(), team.to_string())]),
+            );
+            assert_eq!(
+                program.eval(&ctx).unwrap().allowed,
+                expected,
+                "{} {}",
+                path,
+                team
+            );
+        };
+        check("/anything", "sre", true);
+        check("/api/payments", "payments", true);
+        check("/api/search", "payments", false);
+        check("/api/payments", "search", false);
+        check("/api/payments", "", false);
+    }
+
+    #[test]
+    fn test_eval_rbac() {
+        let config: crate::config::Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "rbac": {
+                    "userRoles": "headerList(\"X-Auth-User-Roles\")",
+                    "roles": {
+                        "viewer": {"permissions": ["orders:read"]},
+                        "editor": {"inherits": ["viewer"], "permissions": ["orders:write"]},
+                        "admin": {"inherits": ["editor"], "permissions": ["users:manage"]}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let env = config.environment().unwrap();
+        let compile = |input: &str| Program::compile_with(input, env.clone());
+        let program = compile(r#"hasPermission("orders:write") AND NOT hasRole("admin")"#).unwrap();
+
+        let check = |roles: &str, expected: bool| {
+            let ctx = make_context_with_headers(
+                "POST",
+                "/orders",
+                "example.com",
+                HashMap::from([("X-Auth-User-Roles".to_string(), roles.to_string())]),
+            );
+            assert_eq!(program.eval(&ctx).unwrap().allowed, expected, "{}", roles);
+        };
+        check("editor", true);
+        check("viewer, unknown", false);
+        check("viewer, editor", true);
+        check("admin", false);
+        check("", false);
+
+        let err = compile(r#"hasRole("owner")"#).unwrap_err();
+        assert!(
+            err.message.contains("unknown role 'owner'"),
+            "{}",
+            err.message
+        );
+        let err = compile(r#"hasPermission("orders:delete")"#).unwrap_err();
+        assert!(err.message.contains("no role grants"), "{}", err.message);
+        let err = compile(r#"hasRole(header("X-Role"))"#).unwrap_err();
+        assert!(err.message.contains("string literal"), "{}", err.message);
+        let err = Program::compile(r#"hasRole("admin")"#).unwrap_err();
+        assert!(err.message.contains("requires rbac"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_eval_related() {
+        let config: crate::config::Config = serde_json::from_str(
+            r#"{
+                "expression": "true",
+                "relations": [
+                    "project:42#owner@org:acme#member",
+                    "org:acme#member@team:sre#member",
+                    "team:sre#member@user:alice",
+                    "project:7#owner@user:bob"
+                ]
+            }"#,
+        )
+        .unwrap();
+        let env = config.environment().unwrap();
+        let program = Program::compile_with(
+            r#"related(
+                concat("user:", header("X-Auth-User")),
+                "owner",
+                concat("project:", capture(path, "^/projects/(?P<id>[0-9]+)(/|$)", "id")))"#,
+            env.clone(),
+        )
+        .unwrap();
+
+        let check = |path: &str, user: &str, expected: bool| {
+            let ctx = make_context_with_headers(
+                "GET",
+                path,
+                "example.com",
+                HashMap::from([("X-Auth-User".to_string(), user.to_string())]),
+            );
+            assert_eq!(
+                program.eval(&ctx).unwrap().allowed,
+                expected,
+                "{} {}",
+                path,
+                user
+            );
+        };
+        check("/projects/42/settings", "alice", true);
+        check("/projects/7", "bob", true);
+        check("/projects/42", "bob", false);
+        check("/projects/421", "alice", false);
+        check("/other", "alice", false);
+
+        let compile = |input: &str| Program::compile_with(input, env.clone());
+        let err = compile(r#"related("user:alice", "editor", "project:42")"#).unwrap_err();
+        assert!(
+            err.message.contains("no tuple uses relation 'editor'"),
+            "{}",
+            err.message
+        );
+        let err = Program::compile(r#"related("user:alice", "owner", "project:42")"#).unwrap_err();
+        assert!(
+            err.message.contains("requires relations"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_eval_capture_and_concat() {
+        let program = Program::compile(
+            r#"concat(capture(path, "^/v([0-9]+)/([a-z]+)", 2), "@", capture(path, "^/v([0-9]+)", 1)) == "users@2""#,
+        )
+        .unwrap();
+        assert!(
+            program
+                .eval(&make_context("GET", "/v2/users", "example.com"))
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !program
+                .eval(&make_context("GET", "/users", "example.com"))
+                .unwrap()
+                .allowed
+        );
+
+        for (input, message) in [
+            (
+                r#"capture(path, header("X-Re"), 1) == """#,
+                "string literal",
+            ),
+            (r#"capture(path, "^/(a)", 2) == """#, "has no group 2"),
+            (
+                r#"capture(path, "^/(?P<id>a)", "name") == """#,
+                "has no group \"name\"",
+            ),
+            (r#"capture(path, "(", 0) == """#, "Invalid regex"),
+            (r#"concat("a") == "a""#, "at least 2"),
+        ] {
+            let err = Program::compile(input).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", input, err.message);
+        }
+    }
+
+    #[test]
+    fn test_eval_openapi() {
+        let config = |unknown: &str| {
+            let json = format!(
+                r#"{{
+                    "expression": "true",
+                    "openapi": {{
+                        "document": {{
+                            "openapi": "3.0.3",
+                            "security": [{{"oauth": ["api"]}}],
+                            "paths": {{
+                                "/projects/{{id}}": {{
+                                    "get": {{"security": [{{"oauth": ["projects:read"]}}]}},
+                                    "delete": {{"security": [{{"oauth": ["projects:admin"]}}]}}
+                                }},
+                                "/status": {{"get": {{}}}}
+                            }},
+                            "components": {{"securitySchemes": {{"oauth": {{"type": "oauth2"}}}}}}
+                        }},
+                        "scopes": "headerList(\"X-Auth-Scopes\", \" \")",
+                        "unknownOperation": "{}"
+                    }}
+                }}"#,
+                unknown
+            );
+            serde_json::from_str::<crate::config::Config>(&json).unwrap()
+        };
+        let program = Program::compile_with(
+            r#"openapiAllowed() OR header("X-Break-Glass") == "1""#,
+            config("deny").environment().unwrap(),
+        )
+        .unwrap();
+
+        let check = |program: &Program, method: &str, path: &str, scopes: &str, expected: bool| {
+            let ctx = make_context_with_headers(
+                method,
+                path,
+                "example.com",
+                HashMap::from([("X-Auth-Scopes".to_string(), scopes.to_string())]),
+            );
+            assert_eq!(
+                program.eval(&ctx).unwrap().allowed,
+                expected,
+                "{} {} {}",
+                method,
+                path,
+                scopes
+            );
+        };
+        check(&program, "GET", "/projects/42", "api projects:read", true);
+        check(&program, "GET", "/projects/42", "api", false);
+        check(&program, "DELETE", "/projects/42", "projects:read", false);
+        check(&program, "DELETE", "/projects/42", "projects:admin", true);
+        check(&program, "GET", "/status", "api", true);
+        check(&program, "GET", "/status", "", false);
+        check(&program, "POST", "/projects/42", "projects:admin", false);
+        check(&program, "GET", "/unknown", "api", false);
+
+        let program =
+            Program::compile_with("openapiAllowed()", config("allow").environment().unwrap())
+                .unwrap();
+        check(&program, "GET", "/unknown", "", true);
+        check(&program, "GET", "/projects/42", "", false);
+        // Encoded and dot-segment paths resolve to the same operation
+        // rather than to an unknown one
+        check(&program, "GET", "/%70rojects/42", "", false);
+        check(&program, "GET", "/status/..//projects/42", "", false);
+        check(&program, "GET", "/%70rojects/42", "projects:read", true);
+
+        let err = Program::compile("openapiAllowed()").unwrap_err();
+        assert!(err.message.contains("requires openapi"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_eval_labels() {
+        let program = Program::compile(
+            r#"@admin_paths(NOT path startsWith "/admin" OR header("X-Role") == "admin")
+               AND deny("writes need the editor role") unless (method == "GET" OR @editor(header("X-Role") == "editor"))"#,
+        )
+        .unwrap();
+        let decide = |method: &str, path: &str, role: &str| {
+            let headers = HashMap::from([("X-Role".to_string(), role.to_string())]);
+            let ctx = make_context_with_headers(method, path, "example.com", headers);
+            let decision = program.eval(&ctx).unwrap();
+            (decision.allowed, decision.rule, decision.reason)
+        };
+
+        assert_eq!(decide("GET", "/api", ""), (true, None, None));
+        assert_eq!(
+            decide("GET", "/admin/users", "editor"),
+            (false, Some("admin_paths"), None)
+        );
+        // The innermost label names the clause, the outer one gives the reason
+        assert_eq!(
+            decide("POST", "/api", "viewer"),
+            (false, Some("editor"), Some("writes need the editor role"))
+        );
+        assert_eq!(decide("POST", "/api", "editor"), (true, None, None));
+
+        // NOT explains nothing: the clauses under it were true
+        let program = Program::compile(r#"NOT @get(method == "GET")"#).unwrap();
+        let decision = program.eval(&make_context("GET", "/", "a")).unwrap();
+        assert_eq!((decision.allowed, decision.deny_reason()), (false, None));
+
+        let err = Program::compile(r#"@name(header("X"))"#).unwrap_err();
+        assert!(
+            err.message.contains("requires a bool expression"),
+            "{}",
+            err.message
+        );
+        let err = Program::compile("deny(\"a\\r\\nX-Injected: 1\") unless method == \"GET\"")
+            .unwrap_err();
+        assert!(
+            err.message.contains("control characters"),
+            "{}",
+            err.message
+        );
     }
 }
@@ -10,12 +10,14 @@ use std::fmt;
 pub enum Token {
     // Literals
     String(String),
+    Int(i64),
     Ident(String),
 
     // Punctuation
     LParen, // (
     RParen, // )
     Comma,  // ,
+    At,     // @ (clause label)
 
     // Comparison operators
     OpEq,         // ==
@@ -24,12 +26,19 @@ pub enum Token {
     OpEndsWith,   // endsWith
     OpContains,   // contains
     OpMatches,    // matches
+    OpLt,         // <
+    OpLe,         // <=
+    OpGt,         // >
+    OpGe,         // >=
 
     // Boolean operators (keywords)
     KwAnd, // AND
     KwOr,  // OR
     KwNot, // NOT
 
+    // Deny reason: deny("...") unless <expr>
+    KwUnless, // unless
+
     // End of input
     Eof,
 }
@@ -38,19 +47,26 @@ impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Int(n) => write!(f, "{}", n),
             Token::Ident(s) => write!(f, "{}", s),
             Token::LParen => write!(f, "("),
             Token::RParen => write!(f, ")"),
             Token::Comma => write!(f, ","),
+            Token::At => write!(f, "@"),
             Token::OpEq => write!(f, "=="),
             Token::OpNeq => write!(f, "!="),
             Token::OpStartsWith => write!(f, "startsWith"),
             Token::OpEndsWith => write!(f, "endsWith"),
             Token::OpContains => write!(f, "contains"),
             Token::OpMatches => write!(f, "matches"),
+            Token::OpLt => write!(f, "<"),
+            Token::OpLe => write!(f, "<="),
+            Token::OpGt => write!(f, ">"),
+            Token::OpGe => write!(f, ">="),
             Token::KwAnd => write!(f, "AND"),
             Token::KwOr => write!(f, "OR"),
             Token::KwNot => write!(f, "NOT"),
+            Token::KwUnless => write!(f, "unless"),
             Token::Eof => write!(f, "EOF"),
         }
     }
@@ -168,12 +184,33 @@ impl Lexer {
         })
     }
 
-    /// Read an identifier or keyword
+    /// Read an integer literal
+    fn read_int(&mut self) -> Result<i64, LexError> {
+        let start_pos = self.pos;
+        let mut result = String::new();
+
+        while let Some(ch) = self.current_char {
+            if ch.is_ascii_digit() {
+                result.push(ch);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        result.parse().map_err(|_| LexError {
+            pos: start_pos,
+            message: format!("Integer literal out of range: {}", result),
+        })
+    }
+
+    /// Read an identifier or keyword. Dotted names such as
+    /// `clientCert.subjectCN` are read as a single identifier.
     fn read_ident_or_keyword(&mut self) -> String {
         let mut result = String::new();
 
         while let Some(ch) = self.current_char {
-            if ch.is_alphanumeric() || ch == '_' || ch == '-' {
+            if ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == '.' {
                 result.push(ch);
                 self.advance();
             } else {
@@ -206,6 +243,11 @@ impl Lexer {
                 Ok(Token::Comma)
             }
 
+            Some('@') => {
+                self.advance();
+                Ok(Token::At)
+            }
+
             Some('"') => {
                 let s = self.read_string()?;
                 Ok(Token::String(s))
@@ -237,6 +279,31 @@ impl Lexer {
                 }
             }
 
+            Some('<') => {
+                self.advance();
+                if self.current_char == Some('=') {
+                    self.advance();
+                    Ok(Token::OpLe)
+                } else {
+                    Ok(Token::OpLt)
+                }
+            }
+
+            Some('>') => {
+                self.advance();
+                if self.current_char == Some('=') {
+                    self.advance();
+                    Ok(Token::OpGe)
+                } else {
+                    Ok(Token::OpGt)
+                }
+            }
+
+            Some(ch) if ch.is_ascii_digit() => {
+                let n = self.read_int()?;
+                Ok(Token::Int(n))
+            }
+
             Some(ch) if ch.is_alphabetic() || ch == '_' => {
                 let ident = self.read_ident_or_keyword();
 
@@ -245,6 +312,7 @@ impl Lexer {
                     "AND" => Ok(Token::KwAnd),
                     "OR" => Ok(Token::KwOr),
                     "NOT" => Ok(Token::KwNot),
+                    "unless" => Ok(Token::KwUnless),
                     "startsWith" => Ok(Token::OpStartsWith),
                     "endsWith" => Ok(Token::OpEndsWith),
                     "contains" => Ok(Token::OpContains),
@@ -316,6 +384,26 @@ mod tests {
         assert_eq!(tokens[5], Token::OpMatches);
     }
 
+    #[test]
+    fn test_relational_operators_and_ints() {
+        let mut lexer = Lexer::new("clientCert.notAfter >= 1700000000 < <= >");
+        let tokens = lexer.tokenize().unwrap();
+
+        assert_eq!(tokens[0], Token::Ident("clientCert.notAfter".to_string()));
+        assert_eq!(tokens[1], Token::OpGe);
+        assert_eq!(tokens[2], Token::Int(1_700_000_000));
+        assert_eq!(tokens[3], Token::OpLt);
+        assert_eq!(tokens[4], Token::OpLe);
+        assert_eq!(tokens[5], Token::OpGt);
+    }
+
+    #[test]
+    fn test_error_int_out_of_range() {
+        let mut lexer = Lexer::new("99999999999999999999");
+        let err = lexer.tokenize().unwrap_err();
+        assert!(err.message.contains("out of range"));
+    }
+
     #[test]
     fn test_all_keywords() {
         let mut lexer = Lexer::new("AND OR NOT");
@@ -326,6 +414,17 @@ mod tests {
         assert_eq!(tokens[2], Token::KwNot);
     }
 
+    #[test]
+    fn test_clause_labels() {
+        let mut lexer = Lexer::new(r#"@admin_paths(x) deny("why") unless"#);
+        let tokens = lexer.tokenize().unwrap();
+
+        assert_eq!(tokens[0], Token::At);
+        assert_eq!(tokens[1], Token::Ident("admin_paths".to_string()));
+        assert_eq!(tokens[5], Token::Ident("deny".to_string()));
+        assert_eq!(tokens[9], Token::KwUnless);
+    }
+
     #[test]
     fn test_function_call() {
         let mut lexer = Lexer::new(r#"header("X-Test")"#);
@@ -366,7 +465,7 @@ mod tests {
 
     #[test]
     fn test_error_unexpected_char() {
-        let mut lexer = Lexer::new("@invalid");
+        let mut lexer = Lexer::new("#invalid");
         let result = lexer.tokenize();
 
         assert!(result.is_err());
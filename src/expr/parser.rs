@@ -120,17 +120,47 @@ impl Parser {
     }
 
     /// Parse NOT expression
-    /// not_expr ::= "NOT" not_expr | comparison
+    /// not_expr ::= "NOT" not_expr | deny_unless | comparison
     fn parse_not_expr(&mut self) -> Result<Expr, ParseError> {
         if self.current_token == Token::KwNot {
             self.advance()?;
             let expr = self.parse_not_expr()?;
             Ok(Expr::Not(Box::new(expr)))
+        } else if self.current_token == Token::Ident("deny".to_string())
+            && self.peek_token == Token::LParen
+        {
+            self.parse_deny_unless()
         } else {
             self.parse_comparison()
         }
     }
 
+    /// Parse a deny reason. The condition extends as far right as
+    /// possible, like the right side of OR.
+    /// deny_unless ::= "deny" "(" string ")" "unless" or_expr
+    fn parse_deny_unless(&mut self) -> Result<Expr, ParseError> {
+        self.advance()?; // consume deny
+        self.expect(Token::LParen)?;
+        let Token::String(reason) = self.current_token.clone() else {
+            return Err(ParseError {
+                pos: self.pos,
+                message: format!(
+                    "deny() expects a string literal reason, got {:?}",
+                    self.current_token
+                ),
+            });
+        };
+        self.advance()?;
+        self.expect(Token::RParen)?;
+        self.expect(Token::KwUnless)?;
+        let expr = self.parse_or_expr()?;
+        Ok(Expr::Labeled {
+            name: None,
+            reason: Some(reason),
+            expr: Box::new(expr),
+        })
+    }
+
     /// Parse comparison expression
     /// comparison ::= value (comp_op value)? | comp_op "(" value "," value ")"
     fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
@@ -173,6 +203,10 @@ impl Parser {
             Token::OpEndsWith => Some(BinOp::EndsWith),
             Token::OpContains => Some(BinOp::Contains),
             Token::OpMatches => Some(BinOp::Matches),
+            Token::OpLt => Some(BinOp::Lt),
+            Token::OpLe => Some(BinOp::Le),
+            Token::OpGt => Some(BinOp::Gt),
+            Token::OpGe => Some(BinOp::Ge),
             _ => None,
         };
 
@@ -191,15 +225,43 @@ impl Parser {
     }
 
     /// Parse value expression
-    /// value ::= string | func_call | ident | "(" expr ")"
+    /// value ::= string | int | func_call | ident | "(" expr ")" | "@" ident "(" expr ")"
     fn parse_value(&mut self) -> Result<Expr, ParseError> {
         match &self.current_token {
+            Token::At => {
+                self.advance()?; // consume @
+                let Token::Ident(name) = self.current_token.clone() else {
+                    return Err(ParseError {
+                        pos: self.pos,
+                        message: format!(
+                            "Expected a label name after '@', got {:?}",
+                            self.current_token
+                        ),
+                    });
+                };
+                self.advance()?;
+                self.expect(Token::LParen)?;
+                let expr = self.parse_or_expr()?;
+                self.expect(Token::RParen)?;
+                Ok(Expr::Labeled {
+                    name: Some(name),
+                    reason: None,
+                    expr: Box::new(expr),
+                })
+            }
+
             Token::String(s) => {
                 let expr = Expr::StringLiteral(s.clone());
                 self.advance()?;
                 Ok(expr)
             }
 
+            Token::Int(n) => {
+                let expr = Expr::IntLiteral(*n);
+                self.advance()?;
+                Ok(expr)
+            }
+
             Token::Ident(name) => {
                 let name = name.clone();
 
@@ -210,16 +272,14 @@ impl Parser {
                     self.parse_func_call(name)
                 } else {
                     // Check if it's a built-in identifier
-                    let ident = match name.as_str() {
-                        "method" => Ident::Method,
-                        "path" => Ident::Path,
-                        "host" => Ident::Host,
-                        _ => {
+                    let ident = match Ident::from_name(&name) {
+                        Some(ident) => ident,
+                        None => {
                             // Unknown identifier - could be a function name used incorrectly
                             return Err(ParseError {
                                 pos: self.pos,
                                 message: format!(
-                                    "Unknown identifier '{}'. Expected: method, path, host, or function call",
+                                    "Unknown identifier '{}'. Expected: method, path, host, now, bodySize, status, graphqlOperationType, graphqlOperationName, grpcService, grpcMethod, clientCert.*, or function call",
                                     name
                                 ),
                             });
@@ -293,6 +353,7 @@ pub fn parse(input: &str) -> Result<Expr, ParseError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::expr::ast::CertAttr;
 
     #[test]
     fn test_parse_simple_comparison() {
@@ -308,6 +369,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_relational_comparison() {
+        let expr = parse(r#"clientCert.notAfter > now"#).unwrap();
+
+        match expr {
+            Expr::BinaryOp { op, left, right } => {
+                assert_eq!(op, BinOp::Gt);
+                assert_eq!(*left, Expr::Ident(Ident::ClientCert(CertAttr::NotAfter)));
+                assert_eq!(*right, Expr::Ident(Ident::Now));
+            }
+            _ => panic!("Expected BinaryOp"),
+        }
+
+        let expr = parse(r#"now <= 1767225600"#).unwrap();
+        assert_eq!(expr.to_string(), "(now <= 1767225600)");
+    }
+
     #[test]
     fn test_parse_function_call() {
         let expr = parse(r#"contains(headerList("X-Auth-User-Teams"), "platform-eng")"#).unwrap();
@@ -394,6 +472,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_labels() {
+        let expr = parse(r#"@admin_paths(path startsWith "/admin") AND method == "GET""#).unwrap();
+        match expr {
+            Expr::And(left, _) => match *left {
+                Expr::Labeled { name, reason, .. } => {
+                    assert_eq!(name.as_deref(), Some("admin_paths"));
+                    assert_eq!(reason, None);
+                }
+                other => panic!("Expected Labeled, got {:?}", other),
+            },
+            other => panic!("Expected And, got {:?}", other),
+        }
+
+        // The condition of a deny reason extends to the end
+        let expr =
+            parse(r#"method == "GET" AND deny("needs admin") unless host == "a" OR host == "b""#)
+                .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            r#"((method == "GET") AND (deny("needs admin") unless ((host == "a") OR (host == "b"))))"#
+        );
+
+        assert!(parse(r#"deny(reason) unless method == "GET""#)
+            .unwrap_err()
+            .message
+            .contains("string literal reason"));
+        assert!(parse(r#"deny("x") method == "GET""#).is_err());
+        assert!(parse(r#"@("x")"#)
+            .unwrap_err()
+            .message
+            .contains("label name"));
+    }
+
     #[test]
     fn test_parse_parentheses() {
         let expr = parse(r#"(method == "GET")"#).unwrap();
@@ -418,6 +530,9 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.message.contains("Unknown identifier"));
+
+        let result = parse(r#"clientCert.subject == "value""#);
+        assert!(result.unwrap_err().message.contains("Unknown identifier"));
     }
 
     #[test]
@@ -0,0 +1,468 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Lightweight GraphQL executable document parser
+//
+// Only as much of the GraphQL grammar (October 2021 spec) as is needed to
+// find the operation being executed is parsed: operation type and name and
+// the fields of the top-level selection set. Arguments, variables and
+// directives are validated for balanced brackets and skipped. Documents
+// that are too large, nested too deeply or malformed fail as a whole.
+
+use std::collections::HashMap;
+
+/// Maximum size of a GraphQL document, to bound parsing work on
+/// attacker-controlled input
+pub const MAX_DOCUMENT_LEN: usize = 32 * 1024;
+
+/// Maximum nesting of selection sets, lists, objects and arguments
+const MAX_DEPTH: usize = 32;
+
+/// Maximum number of root fields returned for an operation
+const MAX_ROOT_FIELDS: usize = 128;
+
+/// The operation selected from a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    /// `query`, `mutation` or `subscription`
+    pub operation_type: String,
+
+    /// Operation name, empty for anonymous operations
+    pub name: String,
+
+    /// Names (not aliases) of the fields in the top-level selection set,
+    /// including fields selected through fragments, in order and deduplicated
+    pub root_fields: Vec<String>,
+}
+
+/// A parsed executable document
+#[derive(Debug, Default)]
+struct Document {
+    operations: Vec<OperationDefinition>,
+    fragments: HashMap<String, Vec<Selection>>,
+}
+
+#[derive(Debug)]
+struct OperationDefinition {
+    operation_type: String,
+    name: String,
+    selections: Vec<Selection>,
+}
+
+/// A top-level selection. Nested selection sets are parsed but not kept.
+#[derive(Debug)]
+enum Selection {
+    Field(String),
+    FragmentSpread(String),
+    InlineFragment(Vec<Selection>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Punctuator: `! $ & ( ) : = @ [ ] { | }`
+    Punct(char),
+    /// `...`
+    Spread,
+    Name(String),
+    /// Int, float or string value (contents are not needed)
+    Value,
+}
+
+/// Parse a document and select the operation to execute: the one named
+/// `operation_name`, or the only operation if no name is given.
+/// Returns None if the document is invalid or the operation is ambiguous
+/// or missing.
+pub fn parse_operation(document: &str, operation_name: Option<&str>) -> Option<Operation> {
+    if document.len() > MAX_DOCUMENT_LEN {
+        return None;
+    }
+    let tokens = tokenize(document)?;
+    let doc = Parser { tokens, pos: 0 }.document()?;
+
+    let operation = match operation_name.filter(|name| !name.is_empty()) {
+        Some(name) => doc.operations.iter().find(|op| op.name == name)?,
+        None if doc.operations.len() == 1 => &doc.operations[0],
+        None => return None,
+    };
+
+    let mut root_fields = Vec::new();
+    collect_fields(&operation.selections, &doc.fragments, &mut root_fields, 0);
+
+    Some(Operation {
+        operation_type: operation.operation_type.clone(),
+        name: operation.name.clone(),
+        root_fields,
+    })
+}
+
+/// Collect field names from a selection set, expanding fragments. The depth
+/// limit also stops cyclic fragment spreads.
+fn collect_fields(
+    selections: &[Selection],
+    fragments: &HashMap<String, Vec<Selection>>,
+    out: &mut Vec<String>,
+    depth: usize,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    for selection in selections {
+        match selection {
+            Selection::Field(name) => {
+                if out.len() < MAX_ROOT_FIELDS && !out.contains(name) {
+                    out.push(name.clone());
+                }
+            }
+            Selection::FragmentSpread(name) => {
+                if let Some(fragment) = fragments.get(name) {
+                    collect_fields(fragment, fragments, out, depth + 1);
+                }
+            }
+            Selection::InlineFragment(inner) => collect_fields(inner, fragments, out, depth + 1),
+        }
+    }
+}
+
+/// Split a document into tokens, skipping whitespace, commas and comments
+fn tokenize(input: &str) -> Option<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' && chars[i] != '\r' {
+                    i += 1;
+                }
+            }
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punct(ch));
+                i += 1;
+            }
+            '.' => {
+                if chars.get(i..i + 3) != Some(&['.', '.', '.']) {
+                    return None;
+                }
+                tokens.push(Token::Spread);
+                i += 3;
+            }
+            '"' => {
+                i = skip_string(&chars, i)?;
+                tokens.push(Token::Value);
+            }
+            '-' | '0'..='9' => {
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '.' | '+' | '-'))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Value);
+            }
+            _ if ch == '_' || ch.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            _ => return None,
+        }
+    }
+
+    Some(tokens)
+}
+
+/// Skip a string or block string starting at `start`, returning the index
+/// after its closing quote
+fn skip_string(chars: &[char], start: usize) -> Option<usize> {
+    let block = chars.get(start..start + 3) == Some(&['"', '"', '"']);
+    let mut i = if block { start + 3 } else { start + 1 };
+
+    while i < chars.len() {
+        match chars[i] {
+            '"' if block => {
+                if chars.get(i..i + 3) == Some(&['"', '"', '"']) {
+                    return Some(i + 3);
+                }
+                i += 1;
+            }
+            '"' => return Some(i + 1),
+            // Block strings only escape `\"""`
+            '\\' if block => {
+                i += if chars.get(i + 1..i + 4) == Some(&['"', '"', '"']) {
+                    4
+                } else {
+                    1
+                };
+            }
+            '\\' => i += 2,
+            '\n' | '\r' if !block => return None,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Option<()> {
+        self.eat(punct).then_some(())
+    }
+
+    fn name(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn peek_name(&self) -> Option<&str> {
+        match self.peek()? {
+            Token::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn document(mut self) -> Option<Document> {
+        let mut doc = Document::default();
+        while self.peek().is_some() {
+            if self.eat('{') {
+                // Query shorthand: an anonymous query
+                let selections = self.selections(1)?;
+                doc.operations.push(OperationDefinition {
+                    operation_type: "query".to_string(),
+                    name: String::new(),
+                    selections,
+                });
+                continue;
+            }
+
+            match self.name()?.as_str() {
+                kind @ ("query" | "mutation" | "subscription") => {
+                    let name = self.peek_name().map(str::to_string);
+                    if name.is_some() {
+                        self.pos += 1;
+                    }
+                    if self.eat('(') {
+                        self.skip_balanced(')', 1)?;
+                    }
+                    self.directives()?;
+                    self.expect('{')?;
+                    let selections = self.selections(1)?;
+                    doc.operations.push(OperationDefinition {
+                        operation_type: kind.to_string(),
+                        name: name.unwrap_or_default(),
+                        selections,
+                    });
+                }
+                "fragment" => {
+                    let name = self.name()?;
+                    if name == "on" || self.name()? != "on" {
+                        return None;
+                    }
+                    self.name()?;
+                    self.directives()?;
+                    self.expect('{')?;
+                    let selections = self.selections(1)?;
+                    doc.fragments.insert(name, selections);
+                }
+                // Type system definitions are not executable
+                _ => return None,
+            }
+        }
+
+        if doc.operations.is_empty() {
+            return None;
+        }
+        Some(doc)
+    }
+
+    /// Parse the selections of a selection set after its opening brace,
+    /// up to and including the closing brace
+    fn selections(&mut self, depth: usize) -> Option<Vec<Selection>> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let mut selections = Vec::new();
+        loop {
+            match self.next()? {
+                Token::Punct('}') if !selections.is_empty() => return Some(selections),
+                Token::Spread => match self.peek_name() {
+                    Some(name) if name != "on" => {
+                        let name = name.to_string();
+                        self.pos += 1;
+                        self.directives()?;
+                        selections.push(Selection::FragmentSpread(name));
+                    }
+                    _ => {
+                        if self.peek_name() == Some("on") {
+                            self.pos += 1;
+                            self.name()?;
+                        }
+                        self.directives()?;
+                        self.expect('{')?;
+                        let inner = self.selections(depth + 1)?;
+                        selections.push(Selection::InlineFragment(inner));
+                    }
+                },
+                Token::Name(alias_or_name) => {
+                    let name = if self.eat(':') {
+                        self.name()?
+                    } else {
+                        alias_or_name
+                    };
+                    if self.eat('(') {
+                        self.skip_balanced(')', depth + 1)?;
+                    }
+                    self.directives()?;
+                    if self.eat('{') {
+                        self.selections(depth + 1)?;
+                    }
+                    selections.push(Selection::Field(name));
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Skip directives (`@name` with optional arguments)
+    fn directives(&mut self) -> Option<()> {
+        while self.eat('@') {
+            self.name()?;
+            if self.eat('(') {
+                self.skip_balanced(')', 1)?;
+            }
+        }
+        Some(())
+    }
+
+    /// Skip tokens up to and including `close`, checking that nested
+    /// brackets are balanced
+    fn skip_balanced(&mut self, close: char, depth: usize) -> Option<()> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        loop {
+            match self.next()? {
+                Token::Punct(ch) if ch == close => return Some(()),
+                Token::Punct('(') => self.skip_balanced(')', depth + 1)?,
+                Token::Punct('[') => self.skip_balanced(']', depth + 1)?,
+                Token::Punct('{') => self.skip_balanced('}', depth + 1)?,
+                Token::Punct(')' | ']' | '}') => return None,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(document: &str, name: Option<&str>) -> Option<(String, String, Vec<String>)> {
+        parse_operation(document, name).map(|op| (op.operation_type, op.name, op.root_fields))
+    }
+
+    #[test]
+    fn test_query_shorthand() {
+        let (kind, name, fields) = op("{ me { id } viewer: user(id: 1) { name } }", None).unwrap();
+        assert_eq!(kind, "query");
+        assert_eq!(name, "");
+        assert_eq!(fields, vec!["me", "user"]);
+    }
+
+    #[test]
+    fn test_named_operations() {
+        let doc = r#"
+            # Two operations: operationName is required
+            query GetUser($id: ID! = "1") @cached(ttl: 60) {
+              user(id: $id, filter: {roles: ["a", "b"], note: """block "quoted" text"""}) { id }
+            }
+            mutation DeleteUser($id: ID!) {
+              deleteUser(id: $id) { ok }
+              ...AuditFields
+              ... on Mutation @include(if: true) { logEvent(kind: DELETE) }
+            }
+            fragment AuditFields on Mutation { audit { id } }
+        "#;
+        assert_eq!(op(doc, None), None, "ambiguous without operationName");
+        assert_eq!(op(doc, Some("Missing")), None);
+
+        let (kind, name, fields) = op(doc, Some("GetUser")).unwrap();
+        assert_eq!((kind.as_str(), name.as_str()), ("query", "GetUser"));
+        assert_eq!(fields, vec!["user"]);
+
+        let (kind, name, fields) = op(doc, Some("DeleteUser")).unwrap();
+        assert_eq!((kind.as_str(), name.as_str()), ("mutation", "DeleteUser"));
+        assert_eq!(fields, vec!["deleteUser", "audit", "logEvent"]);
+    }
+
+    #[test]
+    fn test_subscription_and_cyclic_fragments() {
+        let doc = "subscription { ...A } fragment A on S { a ...B } fragment B on S { b ...A }";
+        let (kind, _, fields) = op(doc, None).unwrap();
+        assert_eq!(kind, "subscription");
+        assert_eq!(fields, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_invalid_documents() {
+        for doc in [
+            "",
+            "{",
+            "{ }",
+            "{ a(b: 1 }",
+            "{ a(b: [1) }",
+            "query { a } }",
+            "type Query { a: String }",
+            "fragment F on T { a }",
+            "{ a } ..",
+            "{ a(s: \"unterminated) }",
+            "query Q { a } ?",
+        ] {
+            assert_eq!(op(doc, None), None, "expected failure for {:?}", doc);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let deep = format!(
+            "{}a{}",
+            "{ a ".repeat(MAX_DEPTH + 1),
+            " }".repeat(MAX_DEPTH + 1)
+        );
+        assert_eq!(op(&deep, None), None);
+        let shallow = format!("{}a{}", "{ a ".repeat(4), " }".repeat(4));
+        assert!(op(&shallow, None).is_some());
+
+        let huge = format!("{{ a(s: \"{}\") }}", "x".repeat(MAX_DOCUMENT_LEN));
+        assert_eq!(op(&huge, None), None);
+    }
+}
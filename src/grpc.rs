@@ -0,0 +1,146 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// gRPC request detection and deny responses
+//
+// gRPC calls are HTTP/2 POSTs to `/<package>.<Service>/<Method>` with a
+// `application/grpc` (or gRPC-Web) content type. Clients expect every
+// response, including errors, to be HTTP 200 with the outcome in the
+// `grpc-status` and `grpc-message` fields.
+
+use crate::body;
+
+/// gRPC status code 3, INVALID_ARGUMENT
+pub const INVALID_ARGUMENT: u16 = 3;
+
+/// gRPC status code 7, PERMISSION_DENIED
+pub const PERMISSION_DENIED: u16 = 7;
+
+/// gRPC status code 13, INTERNAL
+pub const INTERNAL: u16 = 13;
+
+/// gRPC status code 16, UNAUTHENTICATED
+pub const UNAUTHENTICATED: u16 = 16;
+
+/// Returns the media type of a gRPC or gRPC-Web request Content-Type
+/// (`application/grpc`, `application/grpc+proto`, `application/grpc-web`,
+/// `application/grpc-web-text+proto`, ...), or None for other requests
+pub fn media_type(content_type: &str) -> Option<String> {
+    let media_type = body::media_type(content_type);
+    let subtype = media_type.strip_prefix("application/grpc")?;
+    let subtype = subtype
+        .strip_prefix("-web-text")
+        .or_else(|| subtype.strip_prefix("-web"))
+        .unwrap_or(subtype);
+    (subtype.is_empty() || subtype.starts_with('+')).then_some(media_type)
+}
+
+/// Split a gRPC request path `/<service>/<method>` into the fully
+/// qualified service name and the method name
+pub fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service, method))
+}
+
+/// A Trailers-Only response rejecting a gRPC call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    /// HTTP status code, always 200
+    pub status: u16,
+
+    /// The request's gRPC content type, `grpc-status` and `grpc-message`
+    pub headers: Vec<(&'static str, String)>,
+
+    /// Response body, always empty. It is written nonetheless, so that it
+    /// replaces the body of an upstream response denied in the response
+    /// phase.
+    pub body: Vec<u8>,
+}
+
+/// Render the response rejecting a gRPC call with a status code and a
+/// message (the deny body)
+pub fn reject(content_type: String, code: u16, message: &str) -> Rejection {
+    Rejection {
+        status: 200,
+        headers: vec![
+            ("content-type", content_type),
+            ("grpc-status", code.to_string()),
+            ("grpc-message", encode_message(message)),
+        ],
+        body: Vec::new(),
+    }
+}
+
+/// Percent-encode a `grpc-message` value: bytes outside printable ASCII
+/// and `%` are encoded, as the gRPC HTTP/2 protocol requires
+pub fn encode_message(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_type() {
+        let grpc = |ct: &str| media_type(ct);
+        assert_eq!(
+            grpc("application/grpc").as_deref(),
+            Some("application/grpc")
+        );
+        assert_eq!(
+            grpc("application/grpc+proto; charset=utf-8").as_deref(),
+            Some("application/grpc+proto")
+        );
+        assert!(grpc("application/grpc-web").is_some());
+        assert!(grpc("application/grpc-web-text+proto").is_some());
+        assert_eq!(grpc("application/grpcx"), None);
+        assert_eq!(grpc("application/json"), None);
+        assert_eq!(grpc(""), None);
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("/billing.v1.Invoices/GetInvoice"),
+            Some(("billing.v1.Invoices", "GetInvoice"))
+        );
+        assert_eq!(parse_path("/Service/"), None);
+        assert_eq!(parse_path("//Method"), None);
+        assert_eq!(parse_path("/a/b/c"), None);
+        assert_eq!(parse_path("no/slash"), None);
+    }
+
+    #[test]
+    fn test_encode_message() {
+        assert_eq!(encode_message("Forbidden"), "Forbidden");
+        assert_eq!(encode_message("100% denied\n"), "100%25 denied%0A");
+        assert_eq!(encode_message("café"), "caf%C3%A9");
+    }
+
+    #[test]
+    fn test_reject() {
+        let rejection = reject("application/grpc+proto".to_string(), 7, "No access");
+        assert_eq!(rejection.status, 200);
+        assert_eq!(
+            rejection.headers,
+            vec![
+                ("content-type", "application/grpc+proto".to_string()),
+                ("grpc-status", "7".to_string()),
+                ("grpc-message", "No access".to_string()),
+            ]
+        );
+        // An explicit empty body, so a denied upstream body is not forwarded
+        assert!(rejection.body.is_empty());
+    }
+}
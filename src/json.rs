@@ -0,0 +1,275 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// JSON values and path navigation
+//
+// Paths use a small JSONPath-like syntax (`$.realm_access.roles[0]`) that
+// is parsed and validated when the expression is compiled. Conversions to
+// expression values are lenient in the sense that a missing node or a node
+// of the wrong shape is simply absent (empty string, empty list, 0 or
+// false), matching how missing headers behave.
+
+use crate::crypto;
+use serde_json::Value as JsonValue;
+use std::fmt;
+
+/// Maximum size of a JSON document parsed from a header, to bound the
+/// parsing work done on attacker-controlled input.
+pub const MAX_JSON_HEADER_LEN: usize = 16 * 1024;
+
+/// Maximum number of items returned when converting a JSON array to a list
+const MAX_JSON_LIST_ITEMS: usize = 128;
+
+/// A single step in a JSON path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Object member (`.name` or `["name"]`)
+    Key(String),
+    /// Array element (`[0]`)
+    Index(usize),
+}
+
+/// A parsed JSON path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+/// JSON path syntax error
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathError {
+    pub message: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid JSON path: {}", self.message)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl JsonPath {
+    /// Parse a path such as `groups`, `a.b[0]`, `$.account.id` or
+    /// `claims["https://example.com/roles"]`. An empty path (or `$`)
+    /// selects the whole document.
+    pub fn parse(input: &str) -> Result<JsonPath, PathError> {
+        let error = |message: String| Err(PathError { message });
+
+        let chars: Vec<char> = input.chars().collect();
+        let mut segments = Vec::new();
+        let mut i = 0;
+
+        if chars.first() == Some(&'$') {
+            i += 1;
+        }
+
+        while i < chars.len() {
+            match chars[i] {
+                '.' => {
+                    i += 1;
+                    let key = read_key(&chars, &mut i);
+                    if key.is_empty() {
+                        return error(format!("empty key at position {}", i));
+                    }
+                    segments.push(Segment::Key(key));
+                }
+                '[' => {
+                    i += 1;
+                    if chars.get(i) == Some(&'"') {
+                        // Quoted key: ["name"]
+                        i += 1;
+                        let start = i;
+                        while i < chars.len() && chars[i] != '"' {
+                            i += 1;
+                        }
+                        if i >= chars.len() {
+                            return error("unterminated quoted key".to_string());
+                        }
+                        let key: String = chars[start..i].iter().collect();
+                        segments.push(Segment::Key(key));
+                        i += 1;
+                    } else {
+                        let start = i;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                        let digits: String = chars[start..i].iter().collect();
+                        let index = digits.parse::<usize>().map_err(|_| PathError {
+                            message: format!("expected array index at position {}", start),
+                        })?;
+                        segments.push(Segment::Index(index));
+                    }
+                    if chars.get(i) != Some(&']') {
+                        return error(format!("expected ']' at position {}", i));
+                    }
+                    i += 1;
+                }
+                _ if i == 0 => {
+                    // A leading key does not need a dot: `groups`, `a.b`
+                    let key = read_key(&chars, &mut i);
+                    if key.is_empty() {
+                        return error(format!("unexpected '{}' at position {}", chars[i], i));
+                    }
+                    segments.push(Segment::Key(key));
+                }
+                ch => return error(format!("unexpected '{}' at position {}", ch, i)),
+            }
+        }
+
+        Ok(JsonPath { segments })
+    }
+
+    /// Navigate to the node selected by the path
+    pub fn lookup<'a>(&self, root: &'a JsonValue) -> Option<&'a JsonValue> {
+        self.segments
+            .iter()
+            .try_fold(root, |node, segment| match segment {
+                Segment::Key(key) => node.as_object()?.get(key),
+                Segment::Index(index) => node.as_array()?.get(*index),
+            })
+    }
+}
+
+/// Read an unquoted key up to the next `.` or `[`
+fn read_key(chars: &[char], i: &mut usize) -> String {
+    let start = *i;
+    while *i < chars.len() && !matches!(chars[*i], '.' | '[' | ']') {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect()
+}
+
+/// Parse a header value holding JSON, or base64/base64url-encoded JSON.
+/// Returns None if the value is too large or is not valid JSON.
+pub fn parse_header_value(value: &str) -> Option<JsonValue> {
+    let value = value.trim();
+    if value.is_empty() || value.len() > MAX_JSON_HEADER_LEN {
+        return None;
+    }
+    if value.starts_with('{') || value.starts_with('[') {
+        return serde_json::from_str(value).ok();
+    }
+    let decoded = crypto::decode_base64(value)?;
+    serde_json::from_slice(&decoded).ok()
+}
+
+/// Convert a node to a string. Strings are returned as-is, numbers and
+/// booleans are formatted; anything else is absent.
+pub fn to_string(node: Option<&JsonValue>) -> String {
+    match node {
+        Some(JsonValue::String(s)) => s.clone(),
+        Some(JsonValue::Number(n)) => n.to_string(),
+        Some(JsonValue::Bool(b)) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Convert a node to a list of strings. Arrays yield their scalar elements
+/// and a single scalar yields a one-element list.
+pub fn to_list(node: Option<&JsonValue>) -> Vec<String> {
+    match node {
+        Some(JsonValue::Array(items)) => items
+            .iter()
+            .map(|item| to_string(Some(item)))
+            .filter(|s| !s.is_empty())
+            .take(MAX_JSON_LIST_ITEMS)
+            .collect(),
+        Some(JsonValue::Object(_)) | Some(JsonValue::Null) | None => Vec::new(),
+        scalar => vec![to_string(scalar)],
+    }
+}
+
+/// Convert a node to an integer. Only integral numbers are accepted.
+pub fn to_int(node: Option<&JsonValue>) -> i64 {
+    node.and_then(JsonValue::as_i64).unwrap_or(0)
+}
+
+/// Convert a node to a boolean. Only `true` is true.
+pub fn to_bool(node: Option<&JsonValue>) -> bool {
+    node.and_then(JsonValue::as_bool).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_path() {
+        let key = |k: &str| Segment::Key(k.to_string());
+        assert_eq!(JsonPath::parse("").unwrap().segments, vec![]);
+        assert_eq!(JsonPath::parse("$").unwrap().segments, vec![]);
+        assert_eq!(
+            JsonPath::parse("groups").unwrap().segments,
+            vec![key("groups")]
+        );
+        assert_eq!(
+            JsonPath::parse("$.account.id").unwrap().segments,
+            vec![key("account"), key("id")]
+        );
+        assert_eq!(
+            JsonPath::parse(r#"a.b[0]["x.y"]"#).unwrap().segments,
+            vec![key("a"), key("b"), Segment::Index(0), key("x.y")]
+        );
+    }
+
+    #[test]
+    fn test_parse_path_errors() {
+        for path in [
+            "a..b", "a[", "a[x]", "a[1", r#"a["x"#, "a]", "$x", "a.", "[-1]", "]", "]a", "$]",
+        ] {
+            assert!(
+                JsonPath::parse(path).is_err(),
+                "expected error for {}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_and_conversions() {
+        let doc = json!({
+            "sub": "jdoe",
+            "groups": ["admins", "sre", 7, {"nested": true}],
+            "realm_access": {"roles": ["reader"]},
+            "age": 42,
+            "ratio": 0.5,
+            "verified": true
+        });
+        let get = |path: &str| JsonPath::parse(path).unwrap().lookup(&doc).cloned();
+
+        assert_eq!(to_string(get("sub").as_ref()), "jdoe");
+        assert_eq!(to_string(get("groups[1]").as_ref()), "sre");
+        assert_eq!(to_string(get("age").as_ref()), "42");
+        assert_eq!(to_string(get("groups").as_ref()), "");
+        assert_eq!(to_string(get("missing.deep").as_ref()), "");
+
+        assert_eq!(to_list(get("groups").as_ref()), vec!["admins", "sre", "7"]);
+        assert_eq!(to_list(get("realm_access.roles").as_ref()), vec!["reader"]);
+        assert_eq!(to_list(get("sub").as_ref()), vec!["jdoe"]);
+        assert!(to_list(get("realm_access").as_ref()).is_empty());
+
+        assert_eq!(to_int(get("age").as_ref()), 42);
+        assert_eq!(to_int(get("ratio").as_ref()), 0);
+        assert_eq!(to_int(get("sub").as_ref()), 0);
+
+        assert!(to_bool(get("verified").as_ref()));
+        assert!(!to_bool(get("sub").as_ref()));
+    }
+
+    #[test]
+    fn test_parse_header_value() {
+        let plain = r#"{"sub":"jdoe"}"#;
+        assert_eq!(parse_header_value(plain), Some(json!({"sub": "jdoe"})));
+
+        let encoded = crypto::encode_base64url(plain.as_bytes());
+        assert_eq!(parse_header_value(&encoded), Some(json!({"sub": "jdoe"})));
+
+        assert_eq!(parse_header_value("not json"), None);
+        assert_eq!(parse_header_value("{broken"), None);
+
+        let huge = format!(r#"{{"pad":"{}"}}"#, "x".repeat(MAX_JSON_HEADER_LEN));
+        assert_eq!(parse_header_value(&huge), None);
+    }
+}
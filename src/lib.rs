@@ -6,51 +6,265 @@
 // This plugin performs attribute-based authorization on HTTP requests
 // by evaluating expressions against request attributes.
 
+pub mod body;
+pub mod cert;
 pub mod config;
 pub mod context;
+pub mod crypto;
 pub mod expr;
+pub mod graphql;
+pub mod grpc;
+pub mod json;
+pub mod openapi;
+pub mod policy;
+pub mod policy_set;
+pub mod problem;
+pub mod rbac;
+pub mod relations;
+pub mod sfv;
+pub mod signature;
+pub mod strip;
+pub mod template;
+pub mod url;
 
 #[cfg(feature = "playground")]
 pub mod playground;
 
 #[cfg(all(target_arch = "wasm32", feature = "traefik-plugin"))]
 mod plugin {
-    use crate::config::Config;
+    use crate::config::{Config, DenyReason, TestResult};
     use crate::context::RequestContext;
-    use crate::expr::compiler::Program;
+    use crate::expr::eval::EvalError;
+    use crate::grpc;
+    use crate::policy::{HeaderMutation, Policy, ResponseAction};
+    use crate::signature;
+    use crate::strip;
+    use http_wasm_guest::host::feature::{self, BufferRequest, BufferResponse};
     use http_wasm_guest::{host, Guest, Request, Response};
+    use std::cell::RefCell;
 
     /// Authorization plugin implementation
     pub struct AuthzPlugin {
-        program: Program,
+        policy: Policy,
         config: Config,
+        /// Context of the allowed request whose response is pending, kept
+        /// when responses are checked. The host runs a request and its
+        /// response on the same instance, one request at a time.
+        pending: RefCell<Option<RequestContext>>,
     }
 
     impl Guest for AuthzPlugin {
         fn handle_request(&self, request: Request, response: Response) -> (bool, i32) {
             // Build RequestContext from http-wasm Request
-            let ctx = RequestContext::from_request(&request);
+            let mut ctx = RequestContext::from_request(&request);
+            self.strip_before_evaluation(&mut ctx);
+            if self.policy.uses_body() {
+                ctx.read_body(self.policy.env().max_body_size);
+            }
+
+            // Reject ambiguous or unsigned identity headers
+            if let Some((status, body, reason)) = self.precheck(&ctx) {
+                self.deny(&ctx, &response, status, None, body, reason);
+                return (false, 0);
+            }
 
-            // Evaluate expression
-            match self.program.eval(&ctx) {
+            // Evaluate the expression, rules or policy tree
+            match self.policy.evaluate(&ctx) {
                 Err(e) => {
                     // Fail closed: return 500 on eval error
-                    log_error(&format!("Expression evaluation error: {}", e));
-                    response.set_status(500);
-                    response.body().write(b"Internal Server Error");
+                    self.fail(&ctx, &response, &e);
                     (false, 0)
                 }
-                Ok(false) => {
-                    // Deny: return configured status and body
-                    response.set_status(self.config.deny_status_code as i32);
-                    response.body().write(self.config.deny_body.as_bytes());
+                Ok(outcome) if !outcome.allowed => {
+                    // Deny: render the deciding rule's (or configured) status, body
+                    // and headers, or a 401 challenge if the request is not
+                    // authenticated
+                    let reason = if outcome.unauthenticated {
+                        DenyReason::Unauthenticated
+                    } else {
+                        DenyReason::Denied
+                    };
+                    let deny = self.policy.deny_response(&outcome, &ctx);
+                    for (name, value) in &deny.headers {
+                        response.header().set(name.as_bytes(), value.as_bytes());
+                    }
+                    self.deny(
+                        &ctx,
+                        &response,
+                        deny.status,
+                        deny.content_type,
+                        &deny.body,
+                        reason,
+                    );
                     (false, 0)
                 }
-                Ok(true) => {
-                    // Allow: pass to next middleware
-                    (true, 0)
+                Ok(outcome) => match self.policy.allow_mutations(&outcome, &ctx) {
+                    // Allow: strip headers, apply onAllow header changes and
+                    // pass to next middleware
+                    Ok(mutations) => {
+                        if let Some(strip) = &self.config.strip_headers {
+                            for name in request.header().names() {
+                                if strip.strips(name.to_str().unwrap_or("")) {
+                                    request.header().remove(&name);
+                                }
+                            }
+                        }
+                        for mutation in mutations {
+                            match mutation {
+                                HeaderMutation::Set(name, value) => {
+                                    request.header().set(name.as_bytes(), value.as_bytes())
+                                }
+                                HeaderMutation::Remove(name) => {
+                                    request.header().remove(name.as_bytes())
+                                }
+                            }
+                        }
+                        if self.policy.checks_responses() {
+                            self.pending.replace(Some(ctx));
+                        }
+                        (true, 0)
+                    }
+                    Err(e) => {
+                        // Fail closed, like an evaluation error of the policy
+                        self.fail(&ctx, &response, &e);
+                        (false, 0)
+                    }
+                },
+            }
+        }
+
+        fn handle_response(&self, _request: Request, response: Response) {
+            // Only allowed requests reach here, and their context is only
+            // kept when responses are checked
+            let Some(mut ctx) = self.pending.take() else {
+                return;
+            };
+            ctx.read_response(&response);
+            match self.policy.evaluate_response(&ctx) {
+                Err(e) => {
+                    // Fail closed, without the headers from upstream
+                    clear_headers(&response);
+                    self.fail(&ctx, &response, &e);
+                }
+                Ok(ResponseAction::Deny(deny)) => {
+                    clear_headers(&response);
+                    for (name, value) in &deny.headers {
+                        response.header().set(name.as_bytes(), value.as_bytes());
+                    }
+                    self.deny(
+                        &ctx,
+                        &response,
+                        deny.status,
+                        deny.content_type,
+                        &deny.body,
+                        DenyReason::Denied,
+                    );
+                }
+                Ok(ResponseAction::Strip(patterns)) => {
+                    for name in response.header().names() {
+                        let name_str = name.to_str().unwrap_or("");
+                        if patterns
+                            .iter()
+                            .any(|pattern| strip::matches(pattern, name_str))
+                        {
+                            response.header().remove(&name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove all headers of an upstream response that is replaced
+    fn clear_headers(response: &Response) {
+        for name in response.header().names() {
+            response.header().remove(&name);
+        }
+    }
+
+    impl AuthzPlugin {
+        /// Checks that run before the expression is evaluated. Returns the
+        /// deny status code, body and reason if the request must be
+        /// rejected: when a singular header is repeated (strictHeaders), or
+        /// when signed headers are enforced and the signature is invalid.
+        fn precheck(&self, ctx: &RequestContext) -> Option<(u16, &'static str, DenyReason)> {
+            if let Some(strict) = &self.config.strict_headers {
+                if strict.repeated_header(ctx).is_some() {
+                    return Some((
+                        strict.deny_status_code,
+                        "Bad Request",
+                        DenyReason::RepeatedHeader,
+                    ));
+                }
+            }
+            match &self.config.signed_headers {
+                Some(signed) if signed.enforce && !signature::verify_headers(signed, ctx) => {
+                    Some((
+                        signed.deny_status_code,
+                        "Unauthorized",
+                        DenyReason::InvalidSignature,
+                    ))
+                }
+                _ => None,
+            }
+        }
+
+        /// Remove `stripHeaders` from the context of requests that do not
+        /// come from a trusted source, when configured to strip them before
+        /// evaluation
+        fn strip_before_evaluation(&self, ctx: &mut RequestContext) {
+            if let Some(strip) = &self.config.strip_headers {
+                if strip.strips_before_evaluation(ctx) {
+                    ctx.remove_headers(|name| strip.strips(name));
+                }
+            }
+        }
+
+        /// Log an evaluation error and respond with 500
+        fn fail(&self, ctx: &RequestContext, response: &Response, error: &EvalError) {
+            log_error(&format!("Expression evaluation error: {}", error));
+            self.deny(
+                ctx,
+                response,
+                500,
+                None,
+                "Internal Server Error",
+                DenyReason::Error,
+            );
+        }
+
+        /// Write a deny response. gRPC requests get HTTP 200 with the
+        /// reason's gRPC status code and the body text as the message
+        /// (a Trailers-Only response); other requests get the HTTP status,
+        /// content type and body.
+        fn deny(
+            &self,
+            ctx: &RequestContext,
+            response: &Response,
+            status: u16,
+            content_type: Option<&str>,
+            body: &str,
+            reason: DenyReason,
+        ) {
+            if let Some(content_type) = grpc::media_type(ctx.header("content-type")) {
+                let code = self.config.grpc_status_codes.code(reason);
+                let rejection = grpc::reject(content_type, code, body);
+                response.set_status(rejection.status as i32);
+                for (name, value) in &rejection.headers {
+                    response.header().set(name.as_bytes(), value.as_bytes());
                 }
+                // Written even though it is empty: in the response phase it
+                // replaces the upstream body
+                response.body().write(&rejection.body);
+                return;
+            }
+            response.set_status(status as i32);
+            if let Some(content_type) = content_type {
+                response
+                    .header()
+                    .set(b"content-type", content_type.as_bytes());
             }
+            response.body().write(body.as_bytes());
         }
     }
 
@@ -64,42 +278,118 @@ mod plugin {
             std::process::abort();
         });
 
-        // 2. Compile expression
-        let program = Program::compile(&config.expression).unwrap_or_else(|e| {
-            log_error(&format!("Invalid expression: {}", e));
+        let env = config.environment().unwrap_or_else(|e| {
+            log_error(&format!("Invalid config: {}", e));
             std::process::abort();
         });
 
-        log_info(&format!(
-            "Expression compiled successfully: {}",
-            config.expression
-        ));
+        // 2. Compile the expression, rules or policy tree
+        let policy = Policy::compile(&config, env).unwrap_or_else(|e| {
+            log_error(&format!("Invalid policy: {}", e));
+            std::process::abort();
+        });
 
-        // 3. Run test cases
-        for tc in &config.tests {
-            let ctx = RequestContext::from_test(&tc.request);
-            match program.eval(&ctx) {
-                Err(e) => {
-                    log_error(&format!("Test '{}' evaluation error: {}", tc.name, e));
-                    std::process::abort();
-                }
-                Ok(result) if result != tc.expect => {
-                    log_error(&format!(
-                        "Test '{}' failed: got {}, expected {}",
-                        tc.name, result, tc.expect
-                    ));
-                    std::process::abort();
-                }
-                Ok(_) => {
-                    log_info(&format!("Test '{}' passed", tc.name));
-                }
+        if config.policy.is_some() {
+            log_info("Policy tree compiled successfully");
+        } else if config.rules.is_empty() {
+            log_info(&format!(
+                "Expression compiled successfully: {}",
+                config.expression
+            ));
+        } else {
+            log_info(&format!(
+                "{} rule(s) compiled successfully",
+                config.rules.len()
+            ));
+        }
+
+        // Only buffer request bodies when an expression reads them
+        if policy.uses_body() {
+            feature::enable(BufferRequest);
+        }
+
+        // Responses are only buffered when they are checked, so they can be
+        // replaced or have headers removed before they are sent
+        if policy.checks_responses() {
+            feature::enable(BufferResponse);
+        }
+
+        // 3. Run test cases, reporting the deciding rule when rules or a
+        // policy tree are used
+        let plugin = AuthzPlugin {
+            policy,
+            config,
+            pending: RefCell::new(None),
+        };
+        let decided_by = |by: &str| {
+            if plugin.config.rules.is_empty() && plugin.config.policy.is_none() {
+                String::new()
+            } else {
+                format!(" (decided by {})", by)
+            }
+        };
+        for tc in &plugin.config.tests {
+            let mut ctx = RequestContext::from_test(&tc.request);
+            plugin.strip_before_evaluation(&mut ctx);
+            if let Some(response) = &tc.response {
+                // Request expressions cannot read the response, so it can
+                // be added before evaluation
+                let headers = response.headers.iter();
+                ctx.set_response(
+                    response.status,
+                    headers.map(|(name, value)| (name.as_str(), value.as_str())),
+                );
+            }
+            let result = match plugin.precheck(&ctx) {
+                Some(_) => Ok(TestResult {
+                    allowed: false,
+                    decided_by: "header checks".to_string(),
+                    ..TestResult::default()
+                }),
+                None => plugin.policy.evaluate(&ctx).and_then(|outcome| {
+                    let location = (!outcome.allowed)
+                        .then(|| plugin.policy.deny_response(&outcome, &ctx))
+                        .and_then(|deny| deny.header("location").map(str::to_string));
+                    let response = match tc.response.as_ref().filter(|_| outcome.allowed) {
+                        Some(_) => Some(matches!(
+                            plugin.policy.evaluate_response(&ctx)?,
+                            ResponseAction::Strip(_)
+                        )),
+                        None => None,
+                    };
+                    Ok(TestResult {
+                        allowed: outcome.allowed,
+                        decided_by: outcome.decided_by().to_string(),
+                        reason: outcome.reason.map(str::to_string),
+                        location,
+                        response,
+                    })
+                }),
+            };
+            let result = result.unwrap_or_else(|e| {
+                log_error(&format!("Test '{}' evaluation error: {}", tc.name, e));
+                std::process::abort();
+            });
+            if let Some(failure) = tc.failure(&result) {
+                log_error(&format!(
+                    "Test '{}' failed: {}{}",
+                    tc.name,
+                    failure,
+                    decided_by(&result.decided_by)
+                ));
+                std::process::abort();
             }
+            log_info(&format!(
+                "Test '{}' passed{}",
+                tc.name,
+                decided_by(&result.decided_by)
+            ));
         }
 
-        log_info(&format!("All {} test(s) passed", config.tests.len()));
+        log_info(&format!("All {} test(s) passed", plugin.config.tests.len()));
 
         // 4. Register plugin
-        http_wasm_guest::register(AuthzPlugin { program, config });
+        http_wasm_guest::register(plugin);
     }
 
     fn log_error(msg: &str) {
@@ -0,0 +1,427 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// OpenAPI security requirements
+//
+// An OpenAPI 3 document already declares, per operation, which OAuth2 or
+// OpenID Connect scopes a caller needs. The document is compiled at
+// startup into a route table of path templates and security requirements,
+// so policies can enforce it instead of duplicating route lists.
+
+use crate::config::ConfigError;
+use crate::expr::ast::Expr;
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+
+/// HTTP methods that can have an operation in an OpenAPI path item
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Compiled OpenAPI settings carried by the `Environment`
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Route table built from the document
+    pub routes: RouteTable,
+
+    /// Compiled `scopes` expression, of type `[]string`
+    pub scopes: Expr,
+
+    /// Whether requests that match no operation in the document are allowed
+    pub allow_unknown: bool,
+}
+
+/// Operations of an OpenAPI document, in matching order
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<Route>,
+}
+
+/// A single operation: method, path template and security requirements
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Lowercase HTTP method
+    method: String,
+
+    /// Path template as written in the document, e.g. `/projects/{id}`
+    pub template: String,
+
+    /// Template under a server base path, compiled to an anchored regex
+    pattern: Regex,
+
+    /// Number of template parameters; concrete paths match first
+    params: usize,
+
+    /// Alternative security requirements; each lists the scopes that are
+    /// all required. Empty means the operation requires no security.
+    requirements: Vec<Vec<String>>,
+}
+
+impl Route {
+    /// Returns true if the caller's scopes satisfy at least one of the
+    /// operation's security requirements
+    pub fn allows(&self, scopes: &HashSet<&str>) -> bool {
+        self.requirements.is_empty()
+            || self
+                .requirements
+                .iter()
+                .any(|required| required.iter().all(|s| scopes.contains(s.as_str())))
+    }
+}
+
+impl RouteTable {
+    /// Build the route table from an OpenAPI 3 document
+    pub fn from_document(document: &JsonValue) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            message: format!("openapi: {}", message),
+        };
+
+        let version = document.get("openapi").and_then(JsonValue::as_str);
+        if !version.is_some_and(|v| v.starts_with("3.")) {
+            return Err(error(
+                "document must be OpenAPI 3 (an 'openapi' field of '3.x')".to_string(),
+            ));
+        }
+        let Some(paths) = document.get("paths").and_then(JsonValue::as_object) else {
+            return Err(error("document has no 'paths' object".to_string()));
+        };
+        let schemes = document
+            .pointer("/components/securitySchemes")
+            .and_then(JsonValue::as_object);
+
+        let security_of = |value: &JsonValue, location: &str| {
+            parse_security(value, |name| schemes.is_some_and(|s| s.contains_key(name)))
+                .map_err(|message| error(format!("{}: {}", location, message)))
+        };
+        let global = match document.get("security") {
+            Some(security) => security_of(security, "security")?,
+            None => Vec::new(),
+        };
+        let base_paths = server_base_paths(document).map_err(error)?;
+
+        let mut routes = Vec::new();
+        for (template, item) in paths {
+            if compile_template(template).is_none() {
+                return Err(error(format!("invalid path template '{}'", template)));
+            }
+            if item.get("servers").is_some() {
+                return Err(error(format!(
+                    "{}: servers of path items and operations are not supported",
+                    template
+                )));
+            }
+            for method in METHODS {
+                let Some(operation) = item.get(*method) else {
+                    continue;
+                };
+                if operation.get("servers").is_some() {
+                    return Err(error(format!(
+                        "{} {}: servers of path items and operations are not supported",
+                        method, template
+                    )));
+                }
+                let requirements = match operation.get("security") {
+                    Some(security) => security_of(security, &format!("{} {}", method, template))?,
+                    None => global.clone(),
+                };
+                for base_path in &base_paths {
+                    let (pattern, params) = compile_template(&format!("{}{}", base_path, template))
+                        .ok_or_else(|| error(format!("invalid path template '{}'", template)))?;
+                    routes.push(Route {
+                        method: method.to_string(),
+                        template: template.clone(),
+                        pattern,
+                        params,
+                        requirements: requirements.clone(),
+                    });
+                }
+            }
+        }
+
+        // Concrete paths match before templated ones; the sort is stable,
+        // so templates with as many parameters keep their (sorted) order
+        routes.sort_by_key(|route| route.params);
+        Ok(RouteTable { routes })
+    }
+
+    /// Find the operation for a request method and a path normalized with
+    /// `url::normalize_path`
+    pub fn find(&self, method: &str, path: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| route.method.eq_ignore_ascii_case(method) && route.pattern.is_match(path))
+    }
+}
+
+/// Base paths of the document's `servers` (OpenAPI 3 section 4.8.5), which
+/// operation paths are relative to: the path of each server URL, with
+/// variables replaced by their defaults and without a trailing slash.
+/// Without `servers`, the base path is empty (the URL `/`).
+fn server_base_paths(document: &JsonValue) -> Result<Vec<String>, String> {
+    let Some(servers) = document.get("servers") else {
+        return Ok(vec![String::new()]);
+    };
+    let servers = servers
+        .as_array()
+        .ok_or("servers must be an array of server objects")?;
+    let mut base_paths = Vec::new();
+    for server in servers {
+        let url = server
+            .get("url")
+            .and_then(JsonValue::as_str)
+            .ok_or("servers must have a 'url' string")?;
+        let url = substitute_server_variables(url, server.get("variables"))
+            .map_err(|message| format!("server '{}': {}", url, message))?;
+        // The path follows the authority of an absolute URL
+        let path = match url.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |start| &rest[start..]),
+            None if url.starts_with('/') => url.as_str(),
+            None => {
+                return Err(format!(
+                    "server '{}': url must be absolute or start with '/'",
+                    url
+                ))
+            }
+        };
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let base_path = path.trim_end_matches('/').to_string();
+        if !base_paths.contains(&base_path) {
+            base_paths.push(base_path);
+        }
+    }
+    if base_paths.is_empty() {
+        base_paths.push(String::new());
+    }
+    Ok(base_paths)
+}
+
+/// Replace each `{name}` in a server URL with the default of its variable
+fn substitute_server_variables(url: &str, variables: Option<&JsonValue>) -> Result<String, String> {
+    let mut out = String::with_capacity(url.len());
+    let mut rest = url;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').ok_or("unbalanced braces")?;
+        let name = &rest[start + 1..end];
+        let value = variables
+            .and_then(|variables| variables.pointer(&format!("/{}/default", name)))
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| format!("variable '{}' has no default", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Compile a path template into an anchored regex in which each `{name}`
+/// matches one non-empty path segment. Returns the regex and the number of
+/// parameters, or None for a template that does not start with `/` or has
+/// unbalanced braces.
+fn compile_template(template: &str) -> Option<(Regex, usize)> {
+    if !template.starts_with('/') {
+        return None;
+    }
+    let mut pattern = String::from("^");
+    let mut params = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        let name = &rest[start + 1..end];
+        if name.is_empty() || name.contains(['{', '/']) {
+            return None;
+        }
+        pattern.push_str(&regex::escape(&rest[..start]));
+        pattern.push_str("[^/]+");
+        params += 1;
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}') {
+        return None;
+    }
+    pattern.push_str(&regex::escape(rest));
+    pattern.push('$');
+    Some((Regex::new(&pattern).ok()?, params))
+}
+
+/// Parse a security requirement array into alternatives, each the union of
+/// the scopes of its schemes. An empty requirement object (`{}`) makes
+/// security optional, so the result is empty. Schemes are only checked for
+/// their scopes: authenticating the caller is left to the middleware in
+/// front of this plugin, so a scheme without scopes is always satisfied.
+fn parse_security(
+    security: &JsonValue,
+    scheme_exists: impl Fn(&str) -> bool,
+) -> Result<Vec<Vec<String>>, String> {
+    let requirements = security
+        .as_array()
+        .ok_or("security must be an array of requirement objects")?;
+    let mut alternatives = Vec::with_capacity(requirements.len());
+    for requirement in requirements {
+        let schemes = requirement
+            .as_object()
+            .ok_or("security requirements must be objects")?;
+        if schemes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut scopes = Vec::new();
+        for (scheme, scheme_scopes) in schemes {
+            if !scheme_exists(scheme) {
+                return Err(format!("unknown security scheme '{}'", scheme));
+            }
+            let scheme_scopes = scheme_scopes
+                .as_array()
+                .ok_or("security scopes must be an array of strings")?;
+            for scope in scheme_scopes {
+                let scope = scope
+                    .as_str()
+                    .ok_or("security scopes must be an array of strings")?;
+                scopes.push(scope.to_string());
+            }
+        }
+        alternatives.push(scopes);
+    }
+    Ok(alternatives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> JsonValue {
+        json!({
+            "openapi": "3.0.3",
+            "security": [{"oauth": ["api"]}],
+            "paths": {
+                "/projects/{id}": {
+                    "get": {"security": [{"oauth": ["projects:read"]}, {"apiKey": []}]},
+                    "delete": {"security": [{"oauth": ["projects:write", "projects:admin"]}]}
+                },
+                "/projects/search": {
+                    "get": {}
+                },
+                "/health": {
+                    "get": {"security": [{}]}
+                },
+                "/files/{name}.json": {
+                    "get": {"security": []}
+                }
+            },
+            "components": {"securitySchemes": {"oauth": {}, "apiKey": {}}}
+        })
+    }
+
+    fn allows(table: &RouteTable, method: &str, path: &str, scopes: &[&str]) -> Option<bool> {
+        let scopes: HashSet<&str> = scopes.iter().copied().collect();
+        table.find(method, path).map(|route| route.allows(&scopes))
+    }
+
+    #[test]
+    fn test_route_table() {
+        let table = RouteTable::from_document(&document()).unwrap();
+
+        assert_eq!(
+            allows(&table, "GET", "/projects/42", &["projects:read"]),
+            Some(true)
+        );
+        // {"apiKey": []} requires the scheme but no scopes
+        assert_eq!(allows(&table, "GET", "/projects/42", &[]), Some(true));
+        assert_eq!(
+            allows(&table, "DELETE", "/projects/42", &["projects:write"]),
+            Some(false)
+        );
+        assert_eq!(
+            allows(
+                &table,
+                "delete",
+                "/projects/42",
+                &["projects:write", "projects:admin"]
+            ),
+            Some(true)
+        );
+
+        // The concrete path wins over the template, and inherits the
+        // document-level security
+        let route = table.find("GET", "/projects/search").unwrap();
+        assert_eq!(route.template, "/projects/search");
+        assert_eq!(
+            allows(&table, "GET", "/projects/search", &["api"]),
+            Some(true)
+        );
+        assert_eq!(allows(&table, "GET", "/projects/search", &[]), Some(false));
+
+        assert_eq!(allows(&table, "GET", "/health", &[]), Some(true));
+        assert_eq!(allows(&table, "GET", "/files/report.json", &[]), Some(true));
+
+        assert_eq!(allows(&table, "POST", "/projects/42", &[]), None);
+        assert_eq!(allows(&table, "GET", "/projects/42/members", &[]), None);
+        assert_eq!(allows(&table, "GET", "/projects/", &[]), None);
+    }
+
+    #[test]
+    fn test_route_table_server_base_paths() {
+        let mut document = document();
+        document["servers"] = json!([
+            {"url": "https://api.example.com/api/{version}/", "variables": {"version": {"default": "v1"}}},
+            {"url": "/internal"}
+        ]);
+        let table = RouteTable::from_document(&document).unwrap();
+
+        assert_eq!(
+            allows(&table, "GET", "/api/v1/projects/42", &["projects:read"]),
+            Some(true)
+        );
+        assert_eq!(
+            allows(
+                &table,
+                "DELETE",
+                "/internal/projects/42",
+                &["projects:write"]
+            ),
+            Some(false)
+        );
+        assert_eq!(allows(&table, "GET", "/api/v1/health", &[]), Some(true));
+        // Paths without a base path are not operations of the document
+        assert_eq!(allows(&table, "GET", "/projects/42", &[]), None);
+        assert_eq!(allows(&table, "GET", "/api/v2/projects/42", &[]), None);
+
+        // A server at the root, or no servers, leaves paths unchanged
+        document["servers"] = json!([{"url": "https://api.example.com"}]);
+        let table = RouteTable::from_document(&document).unwrap();
+        assert_eq!(allows(&table, "GET", "/health", &[]), Some(true));
+    }
+
+    #[test]
+    fn test_route_table_errors() {
+        let error = |document: JsonValue| RouteTable::from_document(&document).unwrap_err().message;
+
+        assert!(error(json!({"swagger": "2.0", "paths": {}})).contains("OpenAPI 3"));
+        assert!(error(json!({"openapi": "3.1.0"})).contains("no 'paths'"));
+        assert!(error(json!({"openapi": "3.1.0", "paths": {"/a/{id": {}}}))
+            .contains("invalid path template '/a/{id'"));
+        assert!(error(json!({
+            "openapi": "3.1.0",
+            "paths": {"/a": {"get": {"security": [{"missing": []}]}}}
+        }))
+        .contains("get /a: unknown security scheme 'missing'"));
+        assert!(error(json!({
+            "openapi": "3.1.0",
+            "servers": [{"url": "/api/{version}"}],
+            "paths": {}
+        }))
+        .contains("server '/api/{version}': variable 'version' has no default"));
+        assert!(error(json!({
+            "openapi": "3.1.0",
+            "servers": [{"url": "api/v1"}],
+            "paths": {}
+        }))
+        .contains("url must be absolute or start with '/'"));
+        assert!(error(json!({
+            "openapi": "3.1.0",
+            "paths": {"/a": {"get": {"servers": [{"url": "/b"}]}}}
+        }))
+        .contains("get /a: servers of path items and operations are not supported"));
+    }
+}
@@ -26,8 +26,9 @@ pub fn playground_compile(expression: &str) -> String {
 }
 
 /// Evaluate an expression against a mock request.
-/// Input JSON: {"expression": "...", "request": {"method": "GET", "path": "/...", "host": "...", "headers": {...}}}
-/// Returns {"result": true/false} or {"error": "..."}.
+/// Input JSON: {"expression": "...", "request": {"method": "GET", "path": "/...", "host": "...", "headers": {...}, "cookies": {...}, "body": "..."}}
+/// Returns {"result": true/false}, with a "reason" when a labeled clause
+/// made the expression false, or {"error": "..."}.
 #[wasm_bindgen]
 pub fn playground_eval(input_json: &str) -> String {
     let input: EvalInput = match serde_json::from_str(input_json) {
@@ -50,12 +51,23 @@ pub fn playground_eval(input_json: &str) -> String {
         path: input.request.path,
         host: input.request.host,
         headers: input.request.headers.unwrap_or_default(),
+        cookies: input.request.cookies.unwrap_or_default(),
+        time: input.request.time,
+        body: input.request.body,
+        source_addr: String::new(),
     };
 
     let ctx = RequestContext::from_test(&test_req);
 
     match program.eval(&ctx) {
-        Ok(result) => format!(r#"{{"result":{}}}"#, result),
+        Ok(decision) => match decision.deny_reason() {
+            Some(reason) => format!(
+                r#"{{"result":{},"reason":"{}"}}"#,
+                decision.allowed,
+                escape(reason)
+            ),
+            None => format!(r#"{{"result":{}}}"#, decision.allowed),
+        },
         Err(e) => format!(r#"{{"error":"{}"}}"#, escape(&e.message)),
     }
 }
@@ -80,4 +92,10 @@ struct EvalRequest {
     host: String,
     #[serde(default)]
     headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    cookies: Option<HashMap<String, String>>,
+    #[serde(default)]
+    time: Option<u64>,
+    #[serde(default)]
+    body: Option<String>,
 }
@@ -0,0 +1,1475 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Compiled authorization policy
+//
+// A policy is the single `expression` of the configuration, an ordered
+// list of `rules`, or a `policy` tree. A single expression behaves like one
+// rule that allows when the expression is true, with a default action of
+// deny, so the first two forms are evaluated the same way: the first
+// matching rule decides, otherwise the default action applies. A policy
+// tree is evaluated by policy_set; when it is not applicable the default
+// action applies, and when it is indeterminate evaluation fails.
+//
+// When an allow rule's expression is false because of a labeled clause
+// (`@name(...)` or `deny("reason") unless ...`) and the request ends up
+// denied, the outcome carries the clause's reason.
+//
+// Deny bodies and headers are templates, compiled with the policy and
+// rendered with deny_response(). With denyFormats, the body format is
+// negotiated on the request's Accept header, and with denyRedirect a denied
+// request for which its `when` expression holds is redirected instead.
+//
+// With an `authenticated` expression, a denied request for which it is
+// false is answered with 401 and a WWW-Authenticate challenge, so 403 is
+// left for authenticated callers that the policy denies.
+//
+// Allowed requests can have their headers changed for the upstream
+// (onAllow); allow_mutations() evaluates the header value expressions.
+//
+// The response of an allowed request can be checked too: the
+// `responseExpression` and the `when` conditions of `responseStripHeaders`
+// are compiled with the response attributes available, and
+// evaluate_response() runs them against the request context of the
+// request phase, with the response added to it.
+
+use crate::config::{Action, Config, DenyRedirect, OnAllow, Rule, RuleMatch, WwwAuthenticate};
+use crate::context::RequestContext;
+use crate::expr::compiler::{CompileError, Environment, Program, StringProgram};
+use crate::expr::eval::{Decision, EvalError};
+use crate::grpc;
+use crate::policy_set::{Node, Verdict};
+use crate::problem::{self, Format, Formats};
+use crate::template::{Escape, Template, Vars};
+use std::rc::Rc;
+
+/// Content-Type of templated deny bodies without denyContentType. A type
+/// is always sent, so that clients do not sniff interpolated request
+/// values as markup.
+const DEFAULT_DENY_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Compiled policy with its shared environment
+#[derive(Debug)]
+pub struct Policy {
+    rules: Vec<CompiledRule>,
+    tree: Option<Node>,
+    default_action: Action,
+    deny_status_code: u16,
+    deny_body: Template,
+    /// `{{reason}}`, used as the body when denyBodyReason is set
+    reason_body: Option<Template>,
+    deny_content_type: String,
+    deny_headers: Vec<(String, Template)>,
+    deny_reason_header: Option<String>,
+    escape: Escape,
+    formats: Option<Formats>,
+    redirect: Option<Redirect>,
+    authenticated: Option<Program>,
+    challenge: Option<String>,
+    unauthenticated_body: Template,
+    on_allow: AllowMutations,
+    response: Option<ResponseChecks>,
+    env: Rc<Environment>,
+}
+
+/// Compiled `responseExpression` and `responseStripHeaders`
+#[derive(Debug)]
+struct ResponseChecks {
+    condition: Option<Program>,
+    deny_status_code: u16,
+    deny_body: Template,
+    strip: Vec<(Vec<String>, Option<Program>)>,
+}
+
+/// What to do with the response of an allowed request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseAction<'a> {
+    /// Replace the response with a denial
+    Deny(DenyResponse<'a>),
+    /// Forward the response without the headers matching these patterns
+    Strip(Vec<&'a str>),
+}
+
+/// Compiled `onAllow`; `set` is sorted by header name
+#[derive(Debug, Default)]
+struct AllowMutations {
+    set: Vec<(String, StringProgram)>,
+    remove: Vec<String>,
+    rule_header: Option<String>,
+}
+
+/// A change to the headers of an allowed request
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderMutation<'a> {
+    /// Set a header, replacing all its values
+    Set(&'a str, String),
+    /// Remove a header
+    Remove(&'a str),
+}
+
+/// Compiled `denyRedirect`
+#[derive(Debug)]
+struct Redirect {
+    url: Template,
+    when: Option<Program>,
+    status_code: u16,
+}
+
+/// A rule with its expression compiled
+#[derive(Debug)]
+struct CompiledRule {
+    label: String,
+    matcher: RuleMatch,
+    condition: Option<Program>,
+    action: Action,
+    deny_status_code: Option<u16>,
+    deny_body: Option<Template>,
+}
+
+/// Result of evaluating a policy against a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome<'a> {
+    /// Whether the request is allowed
+    pub allowed: bool,
+
+    /// Label of the rule that decided, or None if the default action did
+    pub rule: Option<&'a str>,
+
+    /// HTTP status code for a denied request
+    pub deny_status_code: u16,
+
+    /// Response body template for a denied request
+    pub deny_body: &'a Template,
+
+    /// Reason of the labeled clause that failed, for a denied request
+    pub reason: Option<&'a str>,
+
+    /// Whether a denied request failed the `authenticated` expression, so
+    /// the response is a 401
+    pub unauthenticated: bool,
+}
+
+impl Outcome<'_> {
+    /// What decided the request, for logs: a rule label or "default action"
+    pub fn decided_by(&self) -> &str {
+        self.rule.unwrap_or("default action")
+    }
+}
+
+/// A rendered deny response
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenyResponse<'a> {
+    /// HTTP status code
+    pub status: u16,
+
+    /// Content-Type header; None only for redirects, which have no body
+    pub content_type: Option<&'a str>,
+
+    /// Extra response headers
+    pub headers: Vec<(&'a str, String)>,
+
+    /// Response body
+    pub body: String,
+}
+
+impl DenyResponse<'_> {
+    /// Value of a response header (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parse a `denyBody` template
+pub(crate) fn compile_deny_body(body: &str) -> Result<Template, CompileError> {
+    Template::parse(body).map_err(|message| CompileError {
+        message: format!("denyBody: {}", message),
+    })
+}
+
+impl Policy {
+    /// Compile the policy of a configuration using its environment
+    pub fn compile(config: &Config, env: Environment) -> Result<Self, CompileError> {
+        let env = Rc::new(env);
+        let tree = match &config.policy {
+            Some(root) => Some(Node::compile(root, "1", &env)?),
+            None => None,
+        };
+        let (rules, default_action) = if tree.is_some() {
+            (Vec::new(), config.default_action)
+        } else if config.rules.is_empty() {
+            let rule = Rule {
+                name: String::new(),
+                matcher: RuleMatch::default(),
+                expression: Some(config.expression.clone()),
+                action: Action::Allow,
+                deny_status_code: None,
+                deny_body: None,
+            };
+            (
+                vec![compile_rule(&rule, "expression".to_string(), &env)?],
+                Action::Deny,
+            )
+        } else {
+            let rules = config
+                .rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| {
+                    compile_rule(rule, rule.label(i), &env).map_err(|e| CompileError {
+                        message: format!("{}: {}", rule.label(i), e.message),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            (rules, config.default_action)
+        };
+
+        let mut deny_headers = config
+            .deny_headers
+            .iter()
+            .map(|(name, value)| {
+                Template::parse_header_value(value)
+                    .map(|template| (name.clone(), template))
+                    .map_err(|message| CompileError {
+                        message: format!("denyHeaders: {}: {}", name, message),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        deny_headers.sort_by(|a, b| a.0.cmp(&b.0));
+        let deny_content_type = config
+            .deny_content_type
+            .clone()
+            .unwrap_or_else(|| DEFAULT_DENY_CONTENT_TYPE.to_string());
+
+        Ok(Policy {
+            rules,
+            tree,
+            default_action,
+            deny_status_code: config.deny_status_code,
+            deny_body: compile_deny_body(&config.deny_body)?,
+            reason_body: config.deny_body_reason.then(Template::reason),
+            escape: Escape::for_content_type(&deny_content_type),
+            deny_content_type,
+            deny_headers,
+            deny_reason_header: config.deny_reason_header.clone(),
+            formats: match &config.deny_formats {
+                Some(formats) => {
+                    Some(Formats::compile(formats).map_err(|message| CompileError {
+                        message: format!("denyFormats: {}", message),
+                    })?)
+                }
+                None => None,
+            },
+            redirect: match &config.deny_redirect {
+                Some(redirect) => Some(compile_redirect(redirect, &env)?),
+                None => None,
+            },
+            authenticated: match &config.authenticated {
+                Some(authenticated) => Some(
+                    Program::compile_with(authenticated, Rc::clone(&env)).map_err(|e| {
+                        CompileError {
+                            message: format!("authenticated: {}", e.message),
+                        }
+                    })?,
+                ),
+                None => None,
+            },
+            challenge: config
+                .www_authenticate
+                .as_ref()
+                .map(WwwAuthenticate::header_value),
+            unauthenticated_body: Template::parse(&config.unauthenticated_body).map_err(
+                |message| CompileError {
+                    message: format!("unauthenticatedBody: {}", message),
+                },
+            )?,
+            on_allow: match &config.on_allow {
+                Some(on_allow) => compile_on_allow(on_allow, &env)?,
+                None => AllowMutations::default(),
+            },
+            response: compile_response_checks(config, &env)?,
+            env,
+        })
+    }
+
+    /// Render the response for a denied request: the status and body of
+    /// the outcome, the configured content type and headers, and the
+    /// reason header when there is a reason. With denyFormats, the format
+    /// is negotiated. A redirect replaces the status and body when its
+    /// `when` expression is true; if the expression fails to evaluate, the
+    /// request is not redirected. gRPC requests get only the text body, as
+    /// their message: redirects, challenges and headers do not apply to a
+    /// Trailers-Only response.
+    pub fn deny_response(&self, outcome: &Outcome, ctx: &RequestContext) -> DenyResponse<'_> {
+        let vars = Vars {
+            method: &ctx.method,
+            path: &ctx.path,
+            uri: &ctx.uri,
+            host: &ctx.host,
+            request_id: ctx.header("x-request-id"),
+            rule: outcome.decided_by(),
+            reason: outcome.reason.unwrap_or_default(),
+            status: outcome.deny_status_code,
+        };
+        if grpc::media_type(ctx.header("content-type")).is_some() {
+            return DenyResponse {
+                status: outcome.deny_status_code,
+                content_type: None,
+                headers: Vec::new(),
+                body: outcome.deny_body.render(&vars, self.escape),
+            };
+        }
+        let mut headers = self.deny_headers(ctx, &vars, outcome.reason);
+        if let Some(redirect) = &self.redirect {
+            let applies = redirect
+                .when
+                .as_ref()
+                .is_none_or(|when| when.eval(ctx).is_ok_and(|decision| decision.allowed));
+            if applies {
+                headers.push(("Location", redirect.url.render(&vars, Escape::Url)));
+                return DenyResponse {
+                    status: redirect.status_code,
+                    content_type: None,
+                    headers,
+                    body: String::new(),
+                };
+            }
+        }
+        if let Some(challenge) = self.challenge.as_ref().filter(|_| outcome.unauthenticated) {
+            headers.push(("WWW-Authenticate", challenge.clone()));
+        }
+        let (content_type, body) = match &self.formats {
+            Some(formats) => {
+                let format = problem::negotiate(&ctx.header_values("accept").join(","));
+                headers.push(("Vary", "Accept".to_string()));
+                let body = match format {
+                    Format::Problem => formats.problem(&vars),
+                    Format::Html => formats.html(&vars),
+                    Format::Text => outcome.deny_body.render(&vars, Escape::None),
+                };
+                (Some(format.content_type()), body)
+            }
+            _ => (
+                Some(self.deny_content_type.as_str()),
+                outcome.deny_body.render(&vars, self.escape),
+            ),
+        };
+        DenyResponse {
+            status: outcome.deny_status_code,
+            content_type,
+            headers,
+            body,
+        }
+    }
+
+    /// Headers of a templated deny response: `X-Content-Type-Options:
+    /// nosniff`, denyHeaders in name order, then the reason header when
+    /// there is a reason. gRPC requests get none.
+    fn deny_headers(
+        &self,
+        ctx: &RequestContext,
+        vars: &Vars,
+        reason: Option<&str>,
+    ) -> Vec<(&str, String)> {
+        if grpc::media_type(ctx.header("content-type")).is_some() {
+            return Vec::new();
+        }
+        let mut headers = vec![("X-Content-Type-Options", "nosniff".to_string())];
+        headers.extend(
+            self.deny_headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.render(vars, Escape::Header))),
+        );
+        if let (Some(header), Some(reason)) = (&self.deny_reason_header, reason) {
+            headers.push((header, reason.to_string()));
+        }
+        headers
+    }
+
+    /// Header changes for an allowed request: removals, the rule header,
+    /// then the set headers in name order. Set values have control
+    /// characters removed, and empty values remove the header.
+    pub fn allow_mutations(
+        &self,
+        outcome: &Outcome,
+        ctx: &RequestContext,
+    ) -> Result<Vec<HeaderMutation<'_>>, EvalError> {
+        let on_allow = &self.on_allow;
+        let mut mutations: Vec<HeaderMutation> = on_allow
+            .remove
+            .iter()
+            .map(|name| HeaderMutation::Remove(name))
+            .collect();
+        if let Some(header) = &on_allow.rule_header {
+            mutations.push(HeaderMutation::Set(
+                header,
+                outcome.decided_by().to_string(),
+            ));
+        }
+        for (name, program) in &on_allow.set {
+            let value = program.eval(ctx).map_err(|e| EvalError {
+                message: format!("onAllow: {}: {}", name, e.message),
+            })?;
+            let value: String = value.chars().filter(|c| !c.is_control()).collect();
+            mutations.push(if value.is_empty() {
+                HeaderMutation::Remove(name)
+            } else {
+                HeaderMutation::Set(name, value)
+            });
+        }
+        Ok(mutations)
+    }
+
+    /// Returns true if responses of allowed requests are checked, so the
+    /// plugin must keep the request context for the response phase
+    pub fn checks_responses(&self) -> bool {
+        self.response.is_some()
+    }
+
+    /// Check the response of an allowed request, whose context carries the
+    /// response. When `responseExpression` is false, the response is
+    /// denied with `responseDenyStatusCode` and `responseDenyBody`, using
+    /// the content type and headers of request denials. Otherwise the
+    /// patterns of the `responseStripHeaders` whose `when` holds are
+    /// returned.
+    pub fn evaluate_response(&self, ctx: &RequestContext) -> Result<ResponseAction<'_>, EvalError> {
+        let Some(checks) = &self.response else {
+            return Ok(ResponseAction::Strip(Vec::new()));
+        };
+        if let Some(condition) = &checks.condition {
+            let decision = condition.eval(ctx).map_err(|e| EvalError {
+                message: format!("responseExpression: {}", e.message),
+            })?;
+            if !decision.allowed {
+                let reason = decision.deny_reason();
+                let vars = Vars {
+                    method: &ctx.method,
+                    path: &ctx.path,
+                    uri: &ctx.uri,
+                    host: &ctx.host,
+                    request_id: ctx.header("x-request-id"),
+                    rule: "responseExpression",
+                    reason: reason.unwrap_or_default(),
+                    status: checks.deny_status_code,
+                };
+                return Ok(ResponseAction::Deny(DenyResponse {
+                    status: checks.deny_status_code,
+                    content_type: Some(self.deny_content_type.as_str()),
+                    headers: self.deny_headers(ctx, &vars, reason),
+                    body: checks.deny_body.render(&vars, self.escape),
+                }));
+            }
+        }
+        let mut patterns = Vec::new();
+        for (i, (headers, when)) in checks.strip.iter().enumerate() {
+            if let Some(when) = when {
+                let decision = when.eval(ctx).map_err(|e| EvalError {
+                    message: format!("responseStripHeaders #{}: when: {}", i + 1, e.message),
+                })?;
+                if !decision.allowed {
+                    continue;
+                }
+            }
+            patterns.extend(headers.iter().map(String::as_str));
+        }
+        Ok(ResponseAction::Strip(patterns))
+    }
+
+    /// Settings shared by all expressions of the policy
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    /// Returns true if any rule, the redirect condition, the authenticated
+    /// expression, an onAllow header value or a response check references
+    /// the request body
+    pub fn uses_body(&self) -> bool {
+        self.tree.as_ref().is_some_and(Node::uses_body)
+            || self.response.as_ref().is_some_and(|checks| {
+                checks.condition.as_ref().is_some_and(Program::uses_body)
+                    || checks
+                        .strip
+                        .iter()
+                        .any(|(_, when)| when.as_ref().is_some_and(Program::uses_body))
+            })
+            || self
+                .on_allow
+                .set
+                .iter()
+                .any(|(_, program)| program.uses_body())
+            || self.authenticated.as_ref().is_some_and(Program::uses_body)
+            || self
+                .redirect
+                .as_ref()
+                .and_then(|redirect| redirect.when.as_ref())
+                .is_some_and(Program::uses_body)
+            || self
+                .rules
+                .iter()
+                .any(|rule| rule.condition.as_ref().is_some_and(Program::uses_body))
+    }
+
+    /// Evaluate the policy. A denied request that fails the authenticated
+    /// expression gets a 401 with the unauthenticated body.
+    pub fn evaluate(&self, ctx: &RequestContext) -> Result<Outcome<'_>, EvalError> {
+        let outcome = self.evaluate_policy(ctx)?;
+        let Some(authenticated) = self.authenticated.as_ref().filter(|_| !outcome.allowed) else {
+            return Ok(outcome);
+        };
+        let decision = authenticated.eval(ctx).map_err(|e| EvalError {
+            message: format!("authenticated: {}", e.message),
+        })?;
+        if decision.allowed {
+            return Ok(outcome);
+        }
+        Ok(Outcome {
+            deny_status_code: 401,
+            deny_body: &self.unauthenticated_body,
+            unauthenticated: true,
+            ..outcome
+        })
+    }
+
+    /// Evaluate the rules in order and return the outcome of the first one
+    /// that matches, or of the default action. An Indeterminate policy tree
+    /// is an error.
+    fn evaluate_policy(&self, ctx: &RequestContext) -> Result<Outcome<'_>, EvalError> {
+        if let Some(tree) = &self.tree {
+            let (allowed, rule) = match tree.evaluate(ctx) {
+                Verdict::Permit(rule) => (true, rule),
+                Verdict::Deny(rule) => (false, rule),
+                Verdict::NotApplicable(reason) => return Ok(self.default_outcome(reason)),
+                Verdict::Indeterminate(_, message) => {
+                    return Err(EvalError {
+                        message: format!("policy is indeterminate: {}", message),
+                    })
+                }
+            };
+            return Ok(Outcome {
+                allowed,
+                rule: Some(&rule.label),
+                deny_status_code: rule.deny_status_code.unwrap_or(self.deny_status_code),
+                deny_body: rule.deny_body.as_ref().unwrap_or(&self.deny_body),
+                reason: None,
+                unauthenticated: false,
+            });
+        }
+
+        // Reason of the first allow rule that applied but whose expression
+        // was false
+        let mut reason = None;
+        for rule in &self.rules {
+            if !matches_request(&rule.matcher, ctx) {
+                continue;
+            }
+            let decision = match &rule.condition {
+                Some(condition) => condition.eval(ctx).map_err(|e| EvalError {
+                    message: format!("{}: {}", rule.label, e.message),
+                })?,
+                None => Decision {
+                    allowed: true,
+                    ..Decision::default()
+                },
+            };
+            if decision.allowed {
+                return Ok(Outcome {
+                    allowed: rule.action == Action::Allow,
+                    rule: Some(&rule.label),
+                    deny_status_code: rule.deny_status_code.unwrap_or(self.deny_status_code),
+                    deny_body: rule.deny_body.as_ref().unwrap_or(&self.deny_body),
+                    reason: None,
+                    unauthenticated: false,
+                });
+            }
+            if rule.action == Action::Allow {
+                reason = reason.or(decision.deny_reason());
+            }
+        }
+        Ok(self.default_outcome(reason))
+    }
+
+    fn default_outcome<'a>(&'a self, reason: Option<&'a str>) -> Outcome<'a> {
+        let allowed = self.default_action == Action::Allow;
+        let reason = reason.filter(|_| !allowed);
+        Outcome {
+            allowed,
+            rule: None,
+            deny_status_code: self.deny_status_code,
+            deny_body: match (&self.reason_body, reason) {
+                (Some(reason_body), Some(_)) => reason_body,
+                _ => &self.deny_body,
+            },
+            reason,
+            unauthenticated: false,
+        }
+    }
+}
+
+fn compile_on_allow(
+    on_allow: &OnAllow,
+    env: &Rc<Environment>,
+) -> Result<AllowMutations, CompileError> {
+    let mut set = on_allow
+        .set
+        .iter()
+        .map(|(name, expression)| {
+            StringProgram::compile_with(expression, Rc::clone(env))
+                .map(|program| (name.clone(), program))
+                .map_err(|e| CompileError {
+                    message: format!("onAllow: {}: {}", name, e.message),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    set.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(AllowMutations {
+        set,
+        remove: on_allow.remove.clone(),
+        rule_header: on_allow.rule_header.clone(),
+    })
+}
+
+/// Compile the response checks, if any, in an environment with the
+/// response attributes available
+fn compile_response_checks(
+    config: &Config,
+    env: &Rc<Environment>,
+) -> Result<Option<ResponseChecks>, CompileError> {
+    if config.response_expression.is_none() && config.response_strip_headers.is_empty() {
+        return Ok(None);
+    }
+    let env = Rc::new(Environment {
+        response_phase: true,
+        ..Environment::clone(env)
+    });
+    let compile = |expression: &str, field: String| {
+        Program::compile_with(expression, Rc::clone(&env)).map_err(|e| CompileError {
+            message: format!("{}: {}", field, e.message),
+        })
+    };
+    let strip = config
+        .response_strip_headers
+        .iter()
+        .enumerate()
+        .map(|(i, strip)| {
+            let when = match &strip.when {
+                Some(when) => Some(compile(
+                    when,
+                    format!("responseStripHeaders #{}: when", i + 1),
+                )?),
+                None => None,
+            };
+            Ok((strip.headers.clone(), when))
+        })
+        .collect::<Result<_, CompileError>>()?;
+    Ok(Some(ResponseChecks {
+        condition: match &config.response_expression {
+            Some(expression) => Some(compile(expression, "responseExpression".to_string())?),
+            None => None,
+        },
+        deny_status_code: config.response_deny_status_code,
+        deny_body: Template::parse(&config.response_deny_body).map_err(|message| CompileError {
+            message: format!("responseDenyBody: {}", message),
+        })?,
+        strip,
+    }))
+}
+
+fn compile_redirect(
+    redirect: &DenyRedirect,
+    env: &Rc<Environment>,
+) -> Result<Redirect, CompileError> {
+    let error = |message: String| CompileError {
+        message: format!("denyRedirect: {}", message),
+    };
+    Ok(Redirect {
+        url: Template::parse_header_value(&redirect.url)
+            .map_err(|message| error(format!("url: {}", message)))?,
+        when: match &redirect.when {
+            Some(when) => Some(
+                Program::compile_with(when, Rc::clone(env))
+                    .map_err(|e| error(format!("when: {}", e.message)))?,
+            ),
+            None => None,
+        },
+        status_code: redirect.status_code,
+    })
+}
+
+fn compile_rule(
+    rule: &Rule,
+    label: String,
+    env: &Rc<Environment>,
+) -> Result<CompiledRule, CompileError> {
+    Ok(CompiledRule {
+        condition: match &rule.expression {
+            Some(expression) => Some(Program::compile_with(expression, Rc::clone(env))?),
+            None => None,
+        },
+        label,
+        matcher: normalize(&rule.matcher),
+        action: rule.action,
+        deny_status_code: rule.deny_status_code,
+        deny_body: rule
+            .deny_body
+            .as_deref()
+            .map(compile_deny_body)
+            .transpose()?,
+    })
+}
+
+/// Lowercase host patterns and uppercase methods once, at startup
+fn normalize(matcher: &RuleMatch) -> RuleMatch {
+    RuleMatch {
+        hosts: matcher
+            .hosts
+            .iter()
+            .map(|h| h.to_ascii_lowercase())
+            .collect(),
+        paths: matcher.paths.clone(),
+        methods: matcher
+            .methods
+            .iter()
+            .map(|m| m.to_ascii_uppercase())
+            .collect(),
+    }
+}
+
+/// Returns true if every non-empty list of the matcher matches the request.
+/// Paths are matched against the normalized request path, so `/%61dmin`
+/// or `/x/../admin` cannot slip past a rule for `/admin/*`.
+fn matches_request(matcher: &RuleMatch, ctx: &RequestContext) -> bool {
+    let path = &ctx.normalized_path;
+    let host = strip_port(&ctx.host).to_ascii_lowercase();
+    (matcher.hosts.is_empty() || matcher.hosts.iter().any(|p| host_matches(p, &host)))
+        && (matcher.paths.is_empty() || matcher.paths.iter().any(|p| path_matches(p, path)))
+        && (matcher.methods.is_empty()
+            || matcher
+                .methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(&ctx.method)))
+}
+
+/// `*.example.com` matches subdomains of example.com but not example.com
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// `/api/*` matches `/api` and paths below it; `/api*` matches any path
+/// starting with `/api`; other patterns match exactly
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => match prefix.strip_suffix('/') {
+            Some(dir) => path == dir || path.starts_with(prefix),
+            None => path.starts_with(prefix),
+        },
+        None => pattern == path,
+    }
+}
+
+/// Remove a `:port` suffix from a Host value (`[::1]:8080` -> `[::1]`)
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port))
+            if port.bytes().all(|b| b.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TestRequest;
+    use std::collections::HashMap;
+
+    fn policy(json: &str) -> Policy {
+        let config: Config = serde_json::from_str(json).unwrap();
+        let env = config.environment().unwrap();
+        Policy::compile(&config, env).unwrap()
+    }
+
+    fn request(method: &str, host: &str, path: &str, headers: &[(&str, &str)]) -> RequestContext {
+        RequestContext::from_test(&TestRequest {
+            method: method.to_string(),
+            host: host.to_string(),
+            path: path.to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_single_expression() {
+        let policy = policy(r#"{"expression": "method == \"GET\"", "denyBody": "No"}"#);
+        let outcome = policy.evaluate(&request("GET", "a", "/", &[])).unwrap();
+        assert!(outcome.allowed);
+        assert_eq!(outcome.rule, Some("expression"));
+
+        let ctx = request("POST", "a", "/", &[]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        assert!(!outcome.allowed);
+        assert_eq!(outcome.decided_by(), "default action");
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!((response.status, response.body.as_str()), (403, "No"));
+    }
+
+    #[test]
+    fn test_rules_first_match() {
+        let policy = policy(
+            r#"{
+                "rules": [
+                    {"name": "health", "match": {"paths": ["/health"], "methods": ["get"]}, "action": "allow"},
+                    {
+                        "name": "admin",
+                        "match": {"paths": ["/admin/*"]},
+                        "expression": "header(\"X-Role\") != \"admin\"",
+                        "action": "deny",
+                        "denyStatusCode": 404,
+                        "denyBody": "Not Found"
+                    },
+                    {"match": {"hosts": ["*.internal.example.com"]}, "action": "deny"}
+                ],
+                "defaultAction": "allow",
+                "denyStatusCode": 401
+            }"#,
+        );
+        let check = |method: &str, host: &str, path: &str, role: &str| {
+            let ctx = request(method, host, path, &[("X-Role", role)]);
+            let outcome = policy.evaluate(&ctx).unwrap();
+            (
+                outcome.allowed,
+                outcome.decided_by().to_string(),
+                outcome.deny_status_code,
+            )
+        };
+
+        assert_eq!(
+            check("GET", "x.internal.example.com", "/health", ""),
+            (true, "rule 'health'".to_string(), 401)
+        );
+        assert_eq!(
+            check("POST", "example.com", "/health", ""),
+            (true, "default action".to_string(), 401)
+        );
+        assert_eq!(
+            check("GET", "example.com", "/admin", "user"),
+            (false, "rule 'admin'".to_string(), 404)
+        );
+        assert_eq!(
+            check("GET", "example.com", "/admin/users", "user"),
+            (false, "rule 'admin'".to_string(), 404)
+        );
+        assert_eq!(
+            check("GET", "example.com", "/admin/users", "admin"),
+            (true, "default action".to_string(), 401)
+        );
+        assert_eq!(
+            check("GET", "example.com", "/administrator", "user"),
+            (true, "default action".to_string(), 401)
+        );
+        // Encoded, doubled-slash and dot-segment paths match like the
+        // normalized path the backend sees
+        for path in [
+            "/%61dmin/users",
+            "//admin/users",
+            "/public/../admin/users",
+            "/public/%2E%2E/admin/users",
+            "/./admin",
+        ] {
+            assert_eq!(
+                check("GET", "example.com", path, "user"),
+                (false, "rule 'admin'".to_string(), 404),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            check("GET", "a.internal.example.com:8443", "/", ""),
+            (false, "rule #3".to_string(), 401)
+        );
+        assert_eq!(
+            check("GET", "internal.example.com", "/", ""),
+            (true, "default action".to_string(), 401)
+        );
+    }
+
+    #[test]
+    fn test_rule_errors_name_the_rule() {
+        let config: Config = serde_json::from_str(
+            r#"{"rules": [{"action": "allow"}, {"name": "bad", "expression": "nope(", "action": "deny"}]}"#,
+        )
+        .unwrap();
+        let err = Policy::compile(&config, config.environment().unwrap()).unwrap_err();
+        assert!(err.message.starts_with("rule 'bad': "), "{}", err.message);
+    }
+
+    #[test]
+    fn test_policy_tree() {
+        let policy = policy(
+            r#"{
+                "policy": {
+                    "combine": "deny-overrides",
+                    "children": [
+                        {
+                            "name": "suspended",
+                            "condition": "header(\"X-Status\") == \"suspended\"",
+                            "effect": "deny",
+                            "denyStatusCode": 423
+                        },
+                        {
+                            "target": "path startsWith \"/api/\"",
+                            "combine": "first-applicable",
+                            "children": [{"name": "reader", "condition": "method == \"GET\"", "effect": "permit"}]
+                        }
+                    ]
+                },
+                "defaultAction": "deny"
+            }"#,
+        );
+        let check = |method: &str, path: &str, status: &str| {
+            let ctx = request(method, "a", path, &[("X-Status", status)]);
+            let outcome = policy.evaluate(&ctx).unwrap();
+            (
+                outcome.allowed,
+                outcome.decided_by().to_string(),
+                outcome.deny_status_code,
+            )
+        };
+        assert_eq!(
+            check("GET", "/api/x", "active"),
+            (true, "rule 'reader'".to_string(), 403)
+        );
+        assert_eq!(
+            check("GET", "/api/x", "suspended"),
+            (false, "rule 'suspended'".to_string(), 423)
+        );
+        assert_eq!(
+            check("POST", "/api/x", "active"),
+            (false, "default action".to_string(), 403)
+        );
+    }
+
+    #[test]
+    fn test_deny_reasons() {
+        let rules = policy(
+            r#"{
+                "rules": [
+                    {"name": "public", "match": {"paths": ["/public/*"]}, "action": "allow"},
+                    {
+                        "name": "blocked",
+                        "expression": "header(\"X-Blocked\") == \"1\"",
+                        "action": "deny"
+                    },
+                    {
+                        "name": "api",
+                        "match": {"paths": ["/api/*"]},
+                        "expression": "deny(\"needs the api team\") unless header(\"X-Team\") == \"api\"",
+                        "action": "allow"
+                    }
+                ],
+                "denyBodyReason": true
+            }"#,
+        );
+        let check = |path: &str, headers: &[(&str, &str)]| {
+            let ctx = request("GET", "a", path, headers);
+            let outcome = rules.evaluate(&ctx).unwrap();
+            (
+                outcome.allowed,
+                outcome.reason,
+                rules.deny_response(&outcome, &ctx).body,
+            )
+        };
+        assert_eq!(
+            check("/api/x", &[]),
+            (
+                false,
+                Some("needs the api team"),
+                "needs the api team".to_string()
+            )
+        );
+        assert_eq!(
+            check("/api/x", &[("X-Team", "api")]),
+            (true, None, "Forbidden".to_string())
+        );
+        assert_eq!(
+            check("/api/x", &[("X-Blocked", "1")]),
+            (false, None, "Forbidden".to_string())
+        );
+        assert_eq!(check("/other", &[]), (false, None, "Forbidden".to_string()));
+
+        // In a policy tree, the first permit rule whose condition was false
+        // explains a denial by the default action
+        let tree = policy(
+            r#"{
+                "policy": {
+                    "combine": "first-applicable",
+                    "children": [
+                        {"name": "blocked", "condition": "header(\"X-Blocked\") == \"1\"", "effect": "deny"},
+                        {
+                            "target": "path startsWith \"/api/\"",
+                            "condition": "deny(\"needs the api team\") unless header(\"X-Team\") == \"api\"",
+                            "effect": "permit"
+                        },
+                        {"condition": "@get_only(method == \"GET\")", "effect": "permit"}
+                    ]
+                },
+                "denyBodyReason": true
+            }"#,
+        );
+        let check = |method: &str, path: &str, headers: &[(&str, &str)]| {
+            let ctx = request(method, "a", path, headers);
+            let outcome = tree.evaluate(&ctx).unwrap();
+            (outcome.allowed, outcome.reason)
+        };
+        assert_eq!(
+            check("POST", "/api/x", &[]),
+            (false, Some("needs the api team"))
+        );
+        assert_eq!(check("POST", "/other", &[]), (false, Some("get_only")));
+        assert_eq!(
+            check("POST", "/api/x", &[("X-Blocked", "1")]),
+            (false, None)
+        );
+        assert_eq!(check("GET", "/api/x", &[]), (true, None));
+
+        let single = policy(r#"{"expression": "@get_only(method == \"GET\")"}"#);
+        let ctx = request("POST", "a", "/", &[]);
+        let outcome = single.evaluate(&ctx).unwrap();
+        assert_eq!(
+            (outcome.reason, single.deny_response(&outcome, &ctx).body),
+            (Some("get_only"), "Forbidden".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deny_response_templates() {
+        let policy = policy(
+            r#"{
+                "rules": [
+                    {
+                        "name": "html",
+                        "match": {"paths": ["/html/*"]},
+                        "action": "deny",
+                        "denyBody": "<p>{{method}} {{path}} is not allowed</p>"
+                    },
+                    {"expression": "deny(\"needs a team\") unless header(\"X-Team\") != \"\"", "action": "allow"}
+                ],
+                "denyBody": "{\"error\": \"{{reason}}\", \"rule\": \"{{rule}}\", \"path\": \"{{path}}\", \"status\": {{status}}}",
+                "denyContentType": "application/json",
+                "denyHeaders": {"X-Request-Id": "{{ requestId }}", "Cache-Control": "no-store"},
+                "denyReasonHeader": "X-Authz-Reason"
+            }"#,
+        );
+        let ctx = request(
+            "GET",
+            "a",
+            "/api/\"x\"",
+            &[("X-Request-Id", "abc\r\nSet-Cookie: a=b")],
+        );
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(response.status, 403);
+        assert_eq!(response.content_type, Some("application/json"));
+        assert_eq!(
+            response.body,
+            r#"{"error": "needs a team", "rule": "default action", "path": "/api/\"x\"", "status": 403}"#
+        );
+        assert_eq!(
+            response.headers,
+            vec![
+                ("X-Content-Type-Options", "nosniff".to_string()),
+                ("Cache-Control", "no-store".to_string()),
+                ("X-Request-Id", "abcSet-Cookie: a=b".to_string()),
+                ("X-Authz-Reason", "needs a team".to_string()),
+            ]
+        );
+
+        // Rule bodies are escaped for the same content type
+        let ctx = request("POST", "a", "/html/\"x\"", &[]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        assert_eq!(
+            policy.deny_response(&outcome, &ctx).body,
+            r#"<p>POST /html/\"x\" is not allowed</p>"#
+        );
+    }
+
+    #[test]
+    fn test_deny_response_default_content_type() {
+        // Without denyContentType, markup in request attributes is sent as
+        // plain text that clients must not sniff as HTML
+        let policy =
+            policy(r#"{"expression": "method == \"GET\"", "denyBody": "{{path}} is forbidden"}"#);
+        let ctx = request("POST", "a", "/<script>alert(1)</script>", &[]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(response.content_type, Some("text/plain; charset=utf-8"));
+        assert_eq!(response.body, "/<script>alert(1)</script> is forbidden");
+        assert_eq!(
+            response.headers,
+            vec![("X-Content-Type-Options", "nosniff".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_deny_template_errors() {
+        let error = |json: &str| {
+            let config: Config = serde_json::from_str(json).unwrap();
+            Policy::compile(&config, config.environment().unwrap())
+                .unwrap_err()
+                .message
+        };
+        assert!(
+            error(r#"{"expression": "method == \"GET\"", "denyBody": "{{user}}"}"#)
+                .starts_with("denyBody: unknown template variable 'user'")
+        );
+        assert!(
+            error(r#"{"rules": [{"name": "r", "action": "deny", "denyBody": "{{path"}]}"#)
+                .starts_with("rule 'r': denyBody: unterminated")
+        );
+        assert!(error(
+            r#"{"expression": "method == \"GET\"", "denyHeaders": {"X-A": "{{rule}}\n"}}"#
+        )
+        .contains("denyHeaders: X-A: header values must not contain control characters"));
+    }
+
+    #[test]
+    fn test_deny_formats() {
+        let policy = policy(
+            r#"{
+                "rules": [
+                    {
+                        "name": "locked",
+                        "match": {"paths": ["/locked/*"]},
+                        "action": "deny",
+                        "denyStatusCode": 423,
+                        "denyBody": "{{path}} is locked"
+                    },
+                    {"expression": "deny(\"needs <admin>\") unless header(\"X-Admin\") == \"1\"", "action": "allow"}
+                ],
+                "denyFormats": {
+                    "problem": {"type": "https://errors.example.com/{{status}}", "instance": "urn:request:{{requestId}}"}
+                }
+            }"#,
+        );
+        let response = |path: &str, headers: &[(&str, &str)]| {
+            let ctx = request("GET", "a", path, headers);
+            let outcome = policy.evaluate(&ctx).unwrap();
+            let response = policy.deny_response(&outcome, &ctx);
+            (response.content_type.unwrap().to_string(), response.body)
+        };
+
+        // The rule's status maps into the problem document, and the reason
+        // of the failing clause into its detail
+        assert_eq!(
+            response("/locked/a", &[("X-Request-Id", "r1")]),
+            (
+                "application/problem+json".to_string(),
+                r#"{"type":"https://errors.example.com/423","title":"Locked","status":423,"instance":"urn:request:r1"}"#.to_string()
+            )
+        );
+        assert_eq!(
+            response(
+                "/a",
+                &[("Accept", "application/json"), ("X-Request-Id", "r2")]
+            )
+            .1,
+            r#"{"type":"https://errors.example.com/403","title":"Forbidden","status":403,"detail":"needs <admin>","instance":"urn:request:r2"}"#
+        );
+
+        let (content_type, body) = response("/a", &[("Accept", "text/html,*/*;q=0.8")]);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.contains("<h1>Forbidden</h1><p>needs &lt;admin&gt;</p>"));
+
+        // Plain text is denyBody, or the rule's denyBody
+        assert_eq!(
+            response("/locked/a", &[("Accept", "text/plain")]),
+            (
+                "text/plain; charset=utf-8".to_string(),
+                "/locked/a is locked".to_string()
+            )
+        );
+        assert_eq!(response("/a", &[("Accept", "text/plain")]).1, "Forbidden");
+
+        // gRPC requests get the text body as their message
+        let ctx = request("GET", "a", "/a", &[("Content-Type", "application/grpc")]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let grpc_response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(grpc_response.body, "Forbidden");
+        assert!(grpc_response.headers.is_empty());
+    }
+
+    #[test]
+    fn test_deny_redirect() {
+        let policy = policy(
+            r#"{
+                "expression": "header(\"X-Auth-User\") != \"\"",
+                "denyRedirect": {
+                    "url": "https://login.example.com/?return_to={{uri}}&reason={{reason}}",
+                    "when": "header(\"Accept\") matches \"text/html\" AND header(\"X-Auth-User\") == \"\""
+                },
+                "denyHeaders": {"Cache-Control": "no-store"}
+            }"#,
+        );
+        let ctx = request(
+            "GET",
+            "a",
+            "/reports?id=1&q=a b",
+            &[("Accept", "text/html")],
+        );
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(response.status, 302);
+        assert_eq!(
+            response.header("location"),
+            Some("https://login.example.com/?return_to=%2Freports%3Fid%3D1%26q%3Da%20b&reason=")
+        );
+        assert_eq!(response.header("Cache-Control"), Some("no-store"));
+        assert_eq!(response.body, "");
+
+        // API clients get the deny response
+        let ctx = request("GET", "a", "/reports", &[("Accept", "application/json")]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(
+            (response.status, response.body.as_str()),
+            (403, "Forbidden")
+        );
+        assert_eq!(response.header("Location"), None);
+
+        // gRPC requests are never redirected and get no HTTP headers
+        let ctx = request(
+            "GET",
+            "a",
+            "/reports.v1.Reports/Get",
+            &[
+                ("Accept", "text/html"),
+                ("Content-Type", "application/grpc"),
+            ],
+        );
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let response = policy.deny_response(&outcome, &ctx);
+        assert_eq!(
+            (response.status, response.body.as_str()),
+            (403, "Forbidden")
+        );
+        assert!(response.headers.is_empty());
+
+        let error = |redirect: &str| {
+            let config: Config = serde_json::from_str(&format!(
+                r#"{{"expression": "method == \"GET\"", "denyRedirect": {}}}"#,
+                redirect
+            ))
+            .unwrap();
+            Policy::compile(&config, config.environment().unwrap())
+                .unwrap_err()
+                .message
+        };
+        assert!(error(r#"{"url": "/login?next={{next}}"}"#)
+            .starts_with("denyRedirect: url: unknown template variable 'next'"));
+        assert!(error(r#"{"url": "/login", "when": "header(\"Accept\")"}"#)
+            .starts_with("denyRedirect: when: "));
+    }
+
+    #[test]
+    fn test_unauthenticated() {
+        let policy = policy(
+            r#"{
+                "rules": [
+                    {"name": "health", "match": {"paths": ["/health"]}, "action": "allow"},
+                    {"name": "admins", "expression": "header(\"X-Auth-Role\") == \"admin\"", "action": "allow"}
+                ],
+                "authenticated": "headerExists(\"X-Auth-User\")",
+                "wwwAuthenticate": {"scheme": "Bearer", "realm": "api", "scope": "admin"},
+                "denyFormats": {}
+            }"#,
+        );
+        let evaluate = |path: &str, headers: &[(&str, &str)]| {
+            let ctx = request("GET", "a", path, headers);
+            let outcome = policy.evaluate(&ctx).unwrap();
+            let response = policy.deny_response(&outcome, &ctx);
+            (
+                outcome.allowed,
+                response.status,
+                response.header("WWW-Authenticate").map(str::to_string),
+                response.body,
+            )
+        };
+
+        // Public paths need no authentication
+        assert!(evaluate("/health", &[]).0);
+
+        let (allowed, status, challenge, body) = evaluate("/a", &[]);
+        assert!(!allowed);
+        assert_eq!(status, 401);
+        assert_eq!(
+            challenge.as_deref(),
+            Some(r#"Bearer realm="api", scope="admin""#)
+        );
+        assert!(body.contains(r#""title":"Unauthorized","status":401"#));
+        let (_, _, _, body) = evaluate("/a", &[("Accept", "text/plain")]);
+        assert_eq!(body, "Unauthorized");
+
+        // gRPC requests carry the outcome in grpc-status, not a challenge
+        let (_, status, challenge, body) =
+            evaluate("/a", &[("Content-Type", "application/grpc+proto")]);
+        assert_eq!((status, challenge), (401, None));
+        assert_eq!(body, "Unauthorized");
+
+        // Authenticated callers that the policy denies get the 403
+        let (allowed, status, challenge, _) = evaluate("/a", &[("X-Auth-User", "alice")]);
+        assert_eq!((allowed, status, challenge), (false, 403, None));
+        assert!(evaluate("/a", &[("X-Auth-User", "alice"), ("X-Auth-Role", "admin")]).0);
+    }
+
+    #[test]
+    fn test_allow_mutations() {
+        let policy = policy(
+            r#"{
+                "rules": [
+                    {"name": "tenants", "match": {"paths": ["/tenants/*"]}, "action": "allow"}
+                ],
+                "onAllow": {
+                    "set": {
+                        "X-Tenant": "capture(path, \"^/tenants/([^/]+)\", 1)",
+                        "X-Authz-Policy-Version": "\"2024-06\"",
+                        "X-User": "headerJson(\"X-Userinfo\", \"sub\")"
+                    },
+                    "remove": ["X-Auth-Debug"],
+                    "ruleHeader": "X-Authz-Rule"
+                }
+            }"#,
+        );
+        let ctx = request(
+            "GET",
+            "a",
+            "/tenants/acme",
+            &[("X-Userinfo", r#"{"sub": "alice\r\nX-Admin: 1"}"#)],
+        );
+        let outcome = policy.evaluate(&ctx).unwrap();
+        assert_eq!(
+            policy.allow_mutations(&outcome, &ctx).unwrap(),
+            vec![
+                HeaderMutation::Remove("X-Auth-Debug"),
+                HeaderMutation::Set("X-Authz-Rule", "rule 'tenants'".to_string()),
+                HeaderMutation::Set("X-Authz-Policy-Version", "2024-06".to_string()),
+                HeaderMutation::Set("X-Tenant", "acme".to_string()),
+                HeaderMutation::Set("X-User", "aliceX-Admin: 1".to_string()),
+            ]
+        );
+
+        // Empty values remove the header, so a client's value is not forwarded
+        let ctx = request("GET", "a", "/tenants/", &[("X-Tenant", "other")]);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        let mutations = policy.allow_mutations(&outcome, &ctx).unwrap();
+        assert!(mutations.contains(&HeaderMutation::Remove("X-Tenant")));
+        assert!(mutations.contains(&HeaderMutation::Remove("X-User")));
+
+        let config: Config = serde_json::from_str(
+            r#"{"expression": "method == \"GET\"", "onAllow": {"set": {"X-A": "method == \"GET\""}}}"#,
+        )
+        .unwrap();
+        let error = Policy::compile(&config, config.environment().unwrap()).unwrap_err();
+        assert!(error
+            .message
+            .starts_with("onAllow: X-A: Expression must be string or int"));
+    }
+
+    #[test]
+    fn test_response_checks() {
+        let checked = policy(
+            r#"{
+                "expression": "header(\"X-Auth-User-Teams\") != \"\"",
+                "responseExpression": "deny(\"internal response\") unless status != 200 OR NOT responseHeaderExists(\"X-Internal-Only\") OR contains(headerList(\"X-Auth-User-Teams\"), \"internal\")",
+                "responseDenyStatusCode": 502,
+                "responseDenyBody": "{{status}}: {{reason}}",
+                "denyReasonHeader": "X-Authz-Reason",
+                "responseStripHeaders": [
+                    {"headers": ["x-debug-*"], "when": "NOT contains(headerList(\"X-Auth-User-Teams\"), \"admins\")"},
+                    {"headers": ["Server"]}
+                ]
+            }"#,
+        );
+        assert!(checked.checks_responses());
+        let response = |teams: &str, status: u16, headers: &[(&str, &str)]| {
+            let mut ctx = request("GET", "a", "/", &[("X-Auth-User-Teams", teams)]);
+            assert!(checked.evaluate(&ctx).unwrap().allowed);
+            ctx.set_response(status, headers.iter().copied());
+            checked.evaluate_response(&ctx).unwrap()
+        };
+
+        // Request values of the request phase are available
+        let leak = [("x-INTERNAL-only", "1")];
+        assert_eq!(
+            response("external", 200, &leak),
+            ResponseAction::Deny(DenyResponse {
+                status: 502,
+                content_type: Some("text/plain; charset=utf-8"),
+                headers: vec![
+                    ("X-Content-Type-Options", "nosniff".to_string()),
+                    ("X-Authz-Reason", "internal response".to_string()),
+                ],
+                body: "502: internal response".to_string(),
+            })
+        );
+        assert_eq!(
+            response("internal", 200, &leak),
+            ResponseAction::Strip(vec!["x-debug-*", "Server"])
+        );
+        assert_eq!(
+            response("admins", 404, &leak),
+            ResponseAction::Strip(vec!["Server"])
+        );
+
+        // Response attributes are only available in response expressions
+        let config: Config = serde_json::from_str(r#"{"expression": "status == 200"}"#).unwrap();
+        let error = Policy::compile(&config, config.environment().unwrap()).unwrap_err();
+        assert!(error
+            .message
+            .contains("Identifier 'status' is only available in responseExpression"));
+
+        let config: Config = serde_json::from_str(
+            r#"{"expression": "method == \"GET\"", "responseStripHeaders": [{"headers": ["X-A"], "when": "responseHeader(\"X-A\")"}]}"#,
+        )
+        .unwrap();
+        let error = Policy::compile(&config, config.environment().unwrap()).unwrap_err();
+        assert!(error
+            .message
+            .starts_with("responseStripHeaders #1: when: Top-level expression must be boolean"));
+
+        let unchecked = policy(r#"{"expression": "method == \"GET\""}"#);
+        assert!(!unchecked.checks_responses());
+        let ctx = request("GET", "a", "/", &[]);
+        assert_eq!(
+            unchecked.evaluate_response(&ctx).unwrap(),
+            ResponseAction::Strip(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_matchers() {
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com"));
+        assert!(path_matches("/api/*", "/api"));
+        assert!(path_matches("/api/*", "/api/v1"));
+        assert!(!path_matches("/api/*", "/apiv1"));
+        assert!(path_matches("/api*", "/apiv1"));
+        assert!(!path_matches("/api", "/api/"));
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+}
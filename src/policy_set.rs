@@ -0,0 +1,526 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Hierarchical policy sets (XACML 3.0 style)
+//
+// A policy tree is made of policy sets, which combine the decisions of
+// their children with a combining algorithm, and rules, which have an
+// effect. Every node may have a target; a node whose target is false is
+// NotApplicable. Evaluation errors do not abort the tree: they make the
+// node Indeterminate, and the combining algorithms decide whether that
+// matters. Indeterminate carries the extended {D}, {P} or {DP} flag of
+// XACML 3.0, the effects the node could have had without the error, so
+// that e.g. deny-overrides can ignore an error in a permit-only subtree
+// once another child denies.
+
+use crate::config::{Action, CombiningAlgorithm, PolicyNode};
+use crate::context::RequestContext;
+use crate::expr::compiler::{CompileError, Environment, Program};
+use crate::expr::eval::Decision;
+use crate::policy::compile_deny_body;
+use crate::template::Template;
+use std::rc::Rc;
+
+/// A compiled node of a policy tree
+#[derive(Debug)]
+pub struct Node {
+    label: String,
+    target: Option<Program>,
+    kind: NodeKind,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Set {
+        combine: CombiningAlgorithm,
+        children: Vec<Node>,
+    },
+    Rule(Rule),
+}
+
+/// A compiled rule: the leaf of a policy tree that decides a request
+#[derive(Debug)]
+pub struct Rule {
+    /// Name of the rule for logs
+    pub label: String,
+    condition: Option<Program>,
+    effect: Action,
+    /// HTTP status code when the rule denies
+    pub deny_status_code: Option<u16>,
+    /// Response body template when the rule denies
+    pub deny_body: Option<Template>,
+}
+
+/// Effects a node could have had if evaluation had not failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indeterminate {
+    Deny,
+    Permit,
+    DenyPermit,
+}
+
+/// Decision of a node for a request
+#[derive(Debug)]
+pub enum Verdict<'a> {
+    /// Allowed by the given rule
+    Permit(&'a Rule),
+    /// Denied by the given rule
+    Deny(&'a Rule),
+    /// No rule applies; carries the deny reason of the first permit rule
+    /// whose target matched but whose condition was false
+    NotApplicable(Option<&'a str>),
+    /// Evaluation failed; carries the first error message
+    Indeterminate(Indeterminate, String),
+}
+
+impl Node {
+    /// Compile a node and its children. `position` names unnamed nodes by
+    /// their place in the tree (`1.2` is the second child of the root).
+    pub fn compile(
+        config: &PolicyNode,
+        position: &str,
+        env: &Rc<Environment>,
+    ) -> Result<Self, CompileError> {
+        let label = config.label(position);
+        let compile = |field: &str, expression: &Option<String>| match expression {
+            Some(expression) => Program::compile_with(expression, Rc::clone(env))
+                .map(Some)
+                .map_err(|e| CompileError {
+                    message: format!("{}: {}: {}", label, field, e.message),
+                }),
+            None => Ok(None),
+        };
+        let target = compile("target", &config.target)?;
+
+        let kind = match (config.combine, config.effect) {
+            (Some(combine), _) => NodeKind::Set {
+                combine,
+                children: config
+                    .children
+                    .iter()
+                    .enumerate()
+                    .map(|(i, child)| Node::compile(child, &format!("{}.{}", position, i + 1), env))
+                    .collect::<Result<_, _>>()?,
+            },
+            (None, effect) => NodeKind::Rule(Rule {
+                condition: compile("condition", &config.condition)?,
+                label: label.clone(),
+                effect: effect.unwrap_or_default(),
+                deny_status_code: config.deny_status_code,
+                deny_body: config
+                    .deny_body
+                    .as_deref()
+                    .map(compile_deny_body)
+                    .transpose()
+                    .map_err(|e| CompileError {
+                        message: format!("{}: {}", label, e.message),
+                    })?,
+            }),
+        };
+        Ok(Node {
+            label,
+            target,
+            kind,
+        })
+    }
+
+    /// Returns true if any target or condition references the request body
+    pub fn uses_body(&self) -> bool {
+        self.target.as_ref().is_some_and(Program::uses_body)
+            || match &self.kind {
+                NodeKind::Set { children, .. } => children.iter().any(Node::uses_body),
+                NodeKind::Rule(rule) => rule.condition.as_ref().is_some_and(Program::uses_body),
+            }
+    }
+
+    /// Evaluate the node for a request
+    pub fn evaluate(&self, ctx: &RequestContext) -> Verdict<'_> {
+        let target = self.target_matches(ctx);
+        if let Ok(false) = target {
+            return Verdict::NotApplicable(None);
+        }
+        match &self.kind {
+            NodeKind::Rule(rule) => match target {
+                Err(error) => Verdict::Indeterminate(rule.indeterminate(), error),
+                Ok(_) => rule.evaluate(ctx),
+            },
+            NodeKind::Set { combine, children } => {
+                let verdict = combine_children(*combine, &self.label, children, ctx);
+                // An Indeterminate target only matters if the set would
+                // have decided
+                match (target, verdict) {
+                    (Ok(_), verdict) | (Err(_), verdict @ Verdict::NotApplicable(_)) => verdict,
+                    (Err(error), Verdict::Permit(_)) => {
+                        Verdict::Indeterminate(Indeterminate::Permit, error)
+                    }
+                    (Err(error), Verdict::Deny(_)) => {
+                        Verdict::Indeterminate(Indeterminate::Deny, error)
+                    }
+                    (Err(error), Verdict::Indeterminate(kind, _)) => {
+                        Verdict::Indeterminate(kind, error)
+                    }
+                }
+            }
+        }
+    }
+
+    fn target_matches(&self, ctx: &RequestContext) -> Result<bool, String> {
+        match &self.target {
+            Some(target) => target
+                .eval(ctx)
+                .map(|decision| decision.allowed)
+                .map_err(|e| format!("{}: target: {}", self.label, e.message)),
+            None => Ok(true),
+        }
+    }
+}
+
+impl Rule {
+    fn evaluate(&self, ctx: &RequestContext) -> Verdict<'_> {
+        let decision = match &self.condition {
+            Some(condition) => condition.eval(ctx),
+            None => Ok(Decision {
+                allowed: true,
+                ..Decision::default()
+            }),
+        };
+        match decision {
+            Ok(decision) if decision.allowed => match self.effect {
+                Action::Allow => Verdict::Permit(self),
+                Action::Deny => Verdict::Deny(self),
+            },
+            // Like the reasons of allow rules in a rule list, only a permit
+            // rule's condition explains why a request was not allowed
+            Ok(decision) => Verdict::NotApplicable(match self.effect {
+                Action::Allow => decision.deny_reason(),
+                Action::Deny => None,
+            }),
+            Err(e) => Verdict::Indeterminate(
+                self.indeterminate(),
+                format!("{}: condition: {}", self.label, e.message),
+            ),
+        }
+    }
+
+    fn indeterminate(&self) -> Indeterminate {
+        match self.effect {
+            Action::Allow => Indeterminate::Permit,
+            Action::Deny => Indeterminate::Deny,
+        }
+    }
+}
+
+fn combine_children<'a>(
+    combine: CombiningAlgorithm,
+    label: &str,
+    children: &'a [Node],
+    ctx: &RequestContext,
+) -> Verdict<'a> {
+    match combine {
+        CombiningAlgorithm::DenyOverrides => overrides(children, ctx, Action::Deny),
+        CombiningAlgorithm::PermitOverrides => overrides(children, ctx, Action::Allow),
+        CombiningAlgorithm::FirstApplicable => {
+            let mut reason = None;
+            for child in children {
+                match child.evaluate(ctx) {
+                    Verdict::NotApplicable(child_reason) => reason = reason.or(child_reason),
+                    verdict => return verdict,
+                }
+            }
+            Verdict::NotApplicable(reason)
+        }
+        CombiningAlgorithm::OnlyOneApplicable => {
+            let mut selected = None;
+            for child in children {
+                match child.target_matches(ctx) {
+                    Err(error) => return Verdict::Indeterminate(Indeterminate::DenyPermit, error),
+                    Ok(false) => {}
+                    Ok(true) if selected.is_some() => {
+                        return Verdict::Indeterminate(
+                            Indeterminate::DenyPermit,
+                            format!("{}: more than one child is applicable", label),
+                        )
+                    }
+                    Ok(true) => selected = Some(child),
+                }
+            }
+            match selected {
+                Some(child) => child.evaluate(ctx),
+                None => Verdict::NotApplicable(None),
+            }
+        }
+    }
+}
+
+/// deny-overrides (`winner` is Deny) and permit-overrides (`winner` is
+/// Allow), following XACML 3.0 C.2 and C.4. The first child with the
+/// winning effect decides. Otherwise an error that could have produced the
+/// winning effect makes the result Indeterminate, even if another child
+/// had the losing effect.
+fn overrides<'a>(children: &'a [Node], ctx: &RequestContext, winner: Action) -> Verdict<'a> {
+    let (win_kind, lose_kind) = match winner {
+        Action::Deny => (Indeterminate::Deny, Indeterminate::Permit),
+        Action::Allow => (Indeterminate::Permit, Indeterminate::Deny),
+    };
+
+    let mut loser = None;
+    let mut reason = None;
+    let mut error_win = None;
+    let mut error_lose = None;
+    let mut error_both = None;
+    for child in children {
+        let verdict = child.evaluate(ctx);
+        match verdict {
+            Verdict::Permit(_) | Verdict::Deny(_) => {
+                if matches!(verdict, Verdict::Deny(_)) == (winner == Action::Deny) {
+                    return verdict;
+                }
+                loser.get_or_insert(verdict);
+            }
+            Verdict::NotApplicable(child_reason) => reason = reason.or(child_reason),
+            Verdict::Indeterminate(kind, error) => {
+                let slot = if kind == Indeterminate::DenyPermit {
+                    &mut error_both
+                } else if kind == win_kind {
+                    &mut error_win
+                } else {
+                    &mut error_lose
+                };
+                slot.get_or_insert(error);
+            }
+        }
+    }
+
+    if let Some(error) = error_both {
+        return Verdict::Indeterminate(Indeterminate::DenyPermit, error);
+    }
+    if let Some(error) = error_win {
+        let kind = if error_lose.is_some() || loser.is_some() {
+            Indeterminate::DenyPermit
+        } else {
+            win_kind
+        };
+        return Verdict::Indeterminate(kind, error);
+    }
+    if let Some(verdict) = loser {
+        return verdict;
+    }
+    match error_lose {
+        Some(error) => Verdict::Indeterminate(lose_kind, error),
+        None => Verdict::NotApplicable(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, TestRequest};
+    use crate::expr::ast::{BinOp, Expr};
+    use std::collections::HashMap;
+
+    fn tree(json: &str) -> Node {
+        let config: Config = serde_json::from_str(&format!(r#"{{"policy": {}}}"#, json)).unwrap();
+        let env = Rc::new(config.environment().unwrap());
+        let mut node = Node::compile(config.policy.as_ref().unwrap(), "1", &env).unwrap();
+        inject_failures(&mut node);
+        node
+    }
+
+    /// Well-typed expressions cannot fail, so make every
+    /// `header("X-Fail") == "1"` in the tree fail to evaluate when true
+    fn inject_failures(node: &mut Node) {
+        fn rewrite(expr: &mut Expr) {
+            match expr {
+                Expr::And(left, right) | Expr::Or(left, right) => {
+                    rewrite(left);
+                    rewrite(right);
+                }
+                Expr::Not(inner) => rewrite(inner),
+                _ if expr.to_string().contains("X-Fail") => {
+                    let mismatch = Expr::BinaryOp {
+                        op: BinOp::Eq,
+                        left: Box::new(Expr::StringLiteral("a".to_string())),
+                        right: Box::new(Expr::IntLiteral(1)),
+                    };
+                    let original = std::mem::replace(expr, Expr::BoolLiteral(false));
+                    *expr = Expr::And(Box::new(original), Box::new(mismatch));
+                }
+                _ => {}
+            }
+        }
+        if let Some(target) = &mut node.target {
+            rewrite(&mut target.root);
+        }
+        match &mut node.kind {
+            NodeKind::Set { children, .. } => children.iter_mut().for_each(inject_failures),
+            NodeKind::Rule(rule) => {
+                if let Some(condition) = &mut rule.condition {
+                    rewrite(&mut condition.root);
+                }
+            }
+        }
+    }
+
+    fn request(path: &str, headers: &[(&str, &str)]) -> RequestContext {
+        RequestContext::from_test(&TestRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        })
+    }
+
+    /// Short form of a verdict: the deciding rule's label for Permit and
+    /// Deny, and the extended flag for Indeterminate
+    fn verdict(node: &Node, headers: &[(&str, &str)]) -> String {
+        verdict_at(node, "/", headers)
+    }
+
+    fn verdict_at(node: &Node, path: &str, headers: &[(&str, &str)]) -> String {
+        match node.evaluate(&request(path, headers)) {
+            Verdict::Permit(rule) => format!("permit {}", rule.label),
+            Verdict::Deny(rule) => format!("deny {}", rule.label),
+            Verdict::NotApplicable(_) => "not applicable".to_string(),
+            Verdict::Indeterminate(kind, _) => format!("indeterminate {:?}", kind),
+        }
+    }
+
+    const PERMIT: &str =
+        r#"{"name": "p", "condition": "header(\"X-P\") == \"1\"", "effect": "permit"}"#;
+    const DENY: &str =
+        r#"{"name": "d", "condition": "header(\"X-D\") == \"1\"", "effect": "deny"}"#;
+    const FAILING_PERMIT: &str =
+        r#"{"name": "fp", "condition": "header(\"X-Fail\") == \"1\"", "effect": "permit"}"#;
+    const FAILING_DENY: &str =
+        r#"{"name": "fd", "condition": "header(\"X-Fail\") == \"1\"", "effect": "deny"}"#;
+    const FAIL: (&str, &str) = ("X-Fail", "1");
+
+    fn set(combine: &str, children: &[&str]) -> Node {
+        tree(&format!(
+            r#"{{"combine": "{}", "children": [{}]}}"#,
+            combine,
+            children.join(",")
+        ))
+    }
+
+    #[test]
+    fn test_deny_overrides() {
+        let node = set("deny-overrides", &[PERMIT, DENY]);
+        assert_eq!(
+            verdict(&node, &[("X-P", "1"), ("X-D", "1")]),
+            "deny rule 'd'"
+        );
+        assert_eq!(verdict(&node, &[("X-P", "1")]), "permit rule 'p'");
+        assert_eq!(verdict(&node, &[]), "not applicable");
+
+        // An error in a deny rule blocks a permit, but not a deny
+        let node = set("deny-overrides", &[PERMIT, FAILING_DENY, DENY]);
+        assert_eq!(
+            verdict(&node, &[("X-P", "1"), FAIL]),
+            "indeterminate DenyPermit"
+        );
+        assert_eq!(verdict(&node, &[FAIL]), "indeterminate Deny");
+        assert_eq!(verdict(&node, &[("X-D", "1"), FAIL]), "deny rule 'd'");
+
+        // An error in a permit rule does not matter once another permits
+        let node = set("deny-overrides", &[FAILING_PERMIT, PERMIT]);
+        assert_eq!(verdict(&node, &[("X-P", "1"), FAIL]), "permit rule 'p'");
+        assert_eq!(verdict(&node, &[FAIL]), "indeterminate Permit");
+    }
+
+    #[test]
+    fn test_permit_overrides() {
+        let node = set("permit-overrides", &[DENY, FAILING_PERMIT, PERMIT]);
+        assert_eq!(
+            verdict(&node, &[("X-P", "1"), ("X-D", "1")]),
+            "permit rule 'p'"
+        );
+        assert_eq!(verdict(&node, &[("X-D", "1")]), "deny rule 'd'");
+        assert_eq!(
+            verdict(&node, &[("X-D", "1"), FAIL]),
+            "indeterminate DenyPermit"
+        );
+        assert_eq!(verdict(&node, &[("X-P", "1"), FAIL]), "permit rule 'p'");
+    }
+
+    #[test]
+    fn test_first_applicable() {
+        let node = set("first-applicable", &[PERMIT, FAILING_DENY, DENY]);
+        assert_eq!(verdict(&node, &[("X-P", "1"), FAIL]), "permit rule 'p'");
+        assert_eq!(verdict(&node, &[("X-D", "1"), FAIL]), "indeterminate Deny");
+        assert_eq!(verdict(&node, &[("X-D", "1")]), "deny rule 'd'");
+    }
+
+    #[test]
+    fn test_only_one_applicable() {
+        let node = tree(
+            r#"{
+                "combine": "only-one-applicable",
+                "children": [
+                    {"target": "path startsWith \"/a\"", "combine": "first-applicable", "children": [{"effect": "permit"}]},
+                    {"target": "path startsWith \"/ab\"", "effect": "deny"},
+                    {"target": "header(\"X-Fail\") == \"1\"", "effect": "deny"}
+                ]
+            }"#,
+        );
+        assert_eq!(verdict_at(&node, "/a", &[]), "permit rule 1.1.1");
+        assert_eq!(verdict_at(&node, "/b", &[]), "not applicable");
+        assert_eq!(verdict_at(&node, "/ab", &[]), "indeterminate DenyPermit");
+        assert_eq!(verdict_at(&node, "/b", &[FAIL]), "indeterminate DenyPermit");
+    }
+
+    #[test]
+    fn test_targets() {
+        let node = tree(
+            r#"{
+                "combine": "deny-overrides",
+                "children": [
+                    {"target": "path startsWith \"/admin\"", "name": "admin", "effect": "deny"},
+                    {"target": "header(\"X-T\") == \"1\" OR header(\"X-Fail\") == \"1\"", "combine": "first-applicable", "children": [
+                        {"condition": "header(\"X-P\") == \"1\"", "effect": "permit"}
+                    ]}
+                ]
+            }"#,
+        );
+        assert_eq!(
+            verdict_at(&node, "/admin", &[("X-T", "1")]),
+            "deny rule 'admin'"
+        );
+        assert_eq!(
+            verdict(&node, &[("X-T", "1"), ("X-P", "1")]),
+            "permit rule 1.2.1"
+        );
+        assert_eq!(verdict(&node, &[("X-P", "1")]), "not applicable");
+
+        // A failing target is Indeterminate only if the set would decide
+        assert_eq!(verdict(&node, &[FAIL]), "not applicable");
+        assert_eq!(
+            verdict(&node, &[FAIL, ("X-P", "1")]),
+            "indeterminate Permit"
+        );
+        match node.evaluate(&request("/", &[FAIL, ("X-P", "1")])) {
+            Verdict::Indeterminate(_, error) => {
+                assert!(error.starts_with("policy set 1.2: target: "), "{}", error)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compile_errors_name_the_node() {
+        let config: Config = serde_json::from_str(
+            r#"{"policy": {"combine": "first-applicable", "children": [{"name": "bad", "condition": "nope(", "effect": "deny"}]}}"#,
+        )
+        .unwrap();
+        let env = Rc::new(config.environment().unwrap());
+        let err = Node::compile(config.policy.as_ref().unwrap(), "1", &env).unwrap_err();
+        assert!(
+            err.message.starts_with("rule 'bad': condition: "),
+            "{}",
+            err.message
+        );
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Content-negotiated deny responses
+//
+// API clients expect errors as RFC 9457 problem details
+// (`application/problem+json`), while browsers should get a readable HTML
+// page. When `denyFormats` is configured, the format of a deny response is
+// chosen from the request's `Accept` header: problem details, HTML or
+// plain text. The problem document is serialized member by member, so
+// template values can never change its structure.
+
+use crate::body;
+use crate::config::DenyFormats;
+use crate::template::{Escape, Template, Vars};
+
+/// A deny response format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Problem,
+    Html,
+    Text,
+}
+
+impl Format {
+    /// Formats in order of preference, for clients that accept several
+    /// equally
+    const PREFERENCE: [Format; 3] = [Format::Problem, Format::Html, Format::Text];
+
+    /// Content-Type of responses in this format
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Problem => "application/problem+json",
+            Format::Html => "text/html; charset=utf-8",
+            Format::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// Media types that select this format
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Problem => &["application/problem+json", "application/json"],
+            Format::Html => &["text/html"],
+            Format::Text => &["text/plain"],
+        }
+    }
+}
+
+/// Choose the format for an `Accept` header value (RFC 9110, Section
+/// 12.5.1). Each format gets the quality of the most specific media range
+/// that matches it; the format with the highest quality wins, ties going to
+/// problem details, then HTML. A missing `Accept` header accepts anything,
+/// and when no format is acceptable problem details are returned anyway.
+pub fn negotiate(accept: &str) -> Format {
+    if accept.trim().is_empty() {
+        return Format::Problem;
+    }
+    let ranges = parse_accept(accept);
+    let mut best = (Format::Problem, 0.0);
+    for format in Format::PREFERENCE {
+        let quality = format
+            .media_types()
+            .iter()
+            .map(|media_type| quality(&ranges, media_type))
+            .fold(0.0, f32::max);
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+    best.0
+}
+
+/// Parse an `Accept` header into lowercase media ranges and their quality.
+/// Ranges with an invalid quality are ignored.
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|element| {
+            let mut params = element.split(';');
+            let range = body::media_type(params.next()?);
+            if range.is_empty() {
+                return None;
+            }
+            let mut quality = 1.0;
+            for param in params {
+                if let Some((name, value)) = param.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        quality = value.trim().parse::<f32>().ok()?;
+                        if !(0.0..=1.0).contains(&quality) {
+                            return None;
+                        }
+                    }
+                }
+            }
+            Some((range, quality))
+        })
+        .collect()
+}
+
+/// Quality of a media type: that of the most specific matching range
+/// (`type/subtype`, then `type/*`, then `*/*`), or 0 if none matches
+fn quality(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let main_type = media_type.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+    for (range, quality) in ranges {
+        let specificity = if range == media_type {
+            2
+        } else if range.strip_suffix("/*") == Some(main_type) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, *quality));
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// Compiled `denyFormats`
+#[derive(Debug, Clone)]
+pub struct Formats {
+    problem_type: Template,
+    title: Template,
+    detail: Template,
+    instance: Template,
+    html: Template,
+}
+
+impl Formats {
+    /// Parse the templates of `denyFormats`; errors name the member
+    pub fn compile(config: &DenyFormats) -> Result<Self, String> {
+        let parse = |name: &str, template: &str| {
+            Template::parse(template).map_err(|message| format!("{}: {}", name, message))
+        };
+        Ok(Formats {
+            problem_type: parse("problem.type", &config.problem.problem_type)?,
+            title: parse("problem.title", &config.problem.title)?,
+            detail: parse("problem.detail", &config.problem.detail)?,
+            instance: parse("problem.instance", &config.problem.instance)?,
+            html: parse("html", &config.html)?,
+        })
+    }
+
+    /// Render a problem details document. Members whose template renders
+    /// empty are left out, except `type` and `status`.
+    pub fn problem(&self, vars: &Vars) -> String {
+        let member = |name: &str, template: &Template| {
+            let value = template.render(vars, Escape::None);
+            (!value.is_empty()).then(|| format!("\"{}\":{}", name, json_string(&value)))
+        };
+        let members: Vec<String> = [
+            Some(format!(
+                "\"type\":{}",
+                json_string(&self.problem_type.render(vars, Escape::None))
+            )),
+            member("title", &self.title),
+            Some(format!("\"status\":{}", vars.status)),
+            member("detail", &self.detail),
+            member("instance", &self.instance),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        format!("{{{}}}", members.join(","))
+    }
+
+    /// Render the HTML page, escaping values as markup
+    pub fn html(&self, vars: &Vars) -> String {
+        self.html.render(vars, Escape::Markup)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(""), Format::Problem);
+        assert_eq!(negotiate("*/*"), Format::Problem);
+        assert_eq!(negotiate("application/json"), Format::Problem);
+        assert_eq!(negotiate("application/problem+json"), Format::Problem);
+        assert_eq!(
+            negotiate("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Format::Html
+        );
+        assert_eq!(negotiate("text/plain"), Format::Text);
+        assert_eq!(negotiate("TEXT/*"), Format::Html);
+        assert_eq!(negotiate("text/*, text/html;q=0"), Format::Text);
+        assert_eq!(
+            negotiate("application/json;q=0.5, text/plain;q=0.9"),
+            Format::Text
+        );
+        assert_eq!(negotiate("text/html;q=2, text/plain"), Format::Text);
+        assert_eq!(negotiate("image/png"), Format::Problem);
+    }
+
+    #[test]
+    fn test_render() {
+        let formats = Formats::compile(&DenyFormats::default()).unwrap();
+        let mut vars = Vars {
+            method: "GET",
+            path: "/a/\"<b>\"",
+            status: 403,
+            reason: "needs the \"admin\" role",
+            ..Vars::default()
+        };
+        assert_eq!(
+            formats.problem(&vars),
+            r#"{"type":"about:blank","title":"Forbidden","status":403,"detail":"needs the \"admin\" role","instance":"/a/\"<b>\""}"#
+        );
+        assert!(formats
+            .html(&vars)
+            .contains("<p>needs the &quot;admin&quot; role</p>"));
+
+        vars.reason = "";
+        vars.status = 499;
+        assert_eq!(
+            formats.problem(&vars),
+            r#"{"type":"about:blank","status":499,"instance":"/a/\"<b>\""}"#
+        );
+
+        let error = Formats::compile(&DenyFormats {
+            html: "{{user}}".to_string(),
+            ..DenyFormats::default()
+        })
+        .unwrap_err();
+        assert!(error.starts_with("html: unknown template variable 'user'"));
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Role-based access control
+//
+// Roles inherit other roles and grant permission strings. The role graph
+// is validated and flattened once at startup, so that each role maps
+// directly to every role and permission it implies and a request only
+// needs set lookups.
+
+use crate::config::{ConfigError, Role};
+use crate::expr::ast::Expr;
+use std::collections::{HashMap, HashSet};
+
+/// Compiled RBAC settings carried by the `Environment`
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Compiled `userRoles` expression, of type `[]string`
+    pub user_roles: Expr,
+
+    /// Flattened role graph
+    pub graph: RoleGraph,
+}
+
+/// Role graph with inheritance resolved
+#[derive(Debug, Clone, Default)]
+pub struct RoleGraph {
+    /// Role -> the role itself and every role it inherits, transitively
+    roles: HashMap<String, HashSet<String>>,
+
+    /// Role -> permissions granted by the role and its inherited roles
+    permissions: HashMap<String, HashSet<String>>,
+}
+
+/// Effective roles and permissions of the user making a request
+#[derive(Debug, Clone, Default)]
+pub struct Subject {
+    roles: HashSet<String>,
+    permissions: HashSet<String>,
+}
+
+impl Subject {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission)
+    }
+}
+
+impl RoleGraph {
+    /// Validate and flatten the configured roles. Fails on empty names,
+    /// inheritance of an unknown role and inheritance cycles.
+    pub fn build(roles: &HashMap<String, Role>) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            message: format!("rbac: {}", message),
+        };
+
+        // Sort for deterministic error messages
+        let mut names: Vec<&String> = roles.keys().collect();
+        names.sort();
+
+        for name in &names {
+            if name.is_empty() {
+                return Err(error("role names must not be empty".to_string()));
+            }
+            let role = &roles[name.as_str()];
+            if let Some(parent) = role.inherits.iter().find(|p| !roles.contains_key(*p)) {
+                return Err(error(format!(
+                    "role '{}' inherits unknown role '{}'",
+                    name, parent
+                )));
+            }
+            if role.permissions.iter().any(|p| p.is_empty()) {
+                return Err(error(format!("role '{}' has an empty permission", name)));
+            }
+        }
+
+        let mut graph = RoleGraph::default();
+        for name in names {
+            graph.flatten(name, roles, &mut Vec::new())?;
+        }
+        Ok(graph)
+    }
+
+    /// Resolve the inherited roles of `name` depth-first, memoizing each
+    /// role. `path` holds the roles being resolved, to detect cycles.
+    fn flatten(
+        &mut self,
+        name: &str,
+        roles: &HashMap<String, Role>,
+        path: &mut Vec<String>,
+    ) -> Result<(), ConfigError> {
+        if self.roles.contains_key(name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|r| r == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(ConfigError {
+                message: format!("rbac: role inheritance cycle: {}", cycle.join(" -> ")),
+            });
+        }
+
+        path.push(name.to_string());
+        let role = &roles[name];
+        let mut effective_roles = HashSet::from([name.to_string()]);
+        let mut permissions: HashSet<String> = role.permissions.iter().cloned().collect();
+        for parent in &role.inherits {
+            self.flatten(parent, roles, path)?;
+            effective_roles.extend(self.roles[parent].iter().cloned());
+            permissions.extend(self.permissions[parent].iter().cloned());
+        }
+        path.pop();
+
+        self.roles.insert(name.to_string(), effective_roles);
+        self.permissions.insert(name.to_string(), permissions);
+        Ok(())
+    }
+
+    /// Returns true if `role` is a configured role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains_key(role)
+    }
+
+    /// Returns true if any configured role grants `permission`
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.values().any(|p| p.contains(permission))
+    }
+
+    /// Effective roles and permissions of a user with the given roles.
+    /// Roles that are not configured are ignored.
+    pub fn subject(&self, user_roles: &[String]) -> Subject {
+        let mut subject = Subject::default();
+        for role in user_roles {
+            if let (Some(roles), Some(permissions)) =
+                (self.roles.get(role), self.permissions.get(role))
+            {
+                subject.roles.extend(roles.iter().cloned());
+                subject.permissions.extend(permissions.iter().cloned());
+            }
+        }
+        subject
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(inherits: &[&str], permissions: &[&str]) -> Role {
+        Role {
+            inherits: inherits.iter().map(|s| s.to_string()).collect(),
+            permissions: permissions.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn roles(entries: &[(&str, Role)]) -> HashMap<String, Role> {
+        entries
+            .iter()
+            .map(|(name, role)| (name.to_string(), role.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten_inheritance() {
+        let graph = RoleGraph::build(&roles(&[
+            ("viewer", role(&[], &["orders:read"])),
+            ("editor", role(&["viewer"], &["orders:write"])),
+            ("billing", role(&["viewer"], &["invoices:read"])),
+            ("admin", role(&["editor", "billing"], &["users:manage"])),
+        ]))
+        .unwrap();
+
+        let admin = graph.subject(&["admin".to_string()]);
+        for r in ["admin", "editor", "billing", "viewer"] {
+            assert!(admin.has_role(r), "{}", r);
+        }
+        for p in [
+            "orders:read",
+            "orders:write",
+            "invoices:read",
+            "users:manage",
+        ] {
+            assert!(admin.has_permission(p), "{}", p);
+        }
+
+        let user = graph.subject(&["editor".to_string(), "unknown".to_string()]);
+        assert!(user.has_role("viewer"));
+        assert!(!user.has_role("billing"));
+        assert!(!user.has_role("unknown"));
+        assert!(user.has_permission("orders:write"));
+        assert!(!user.has_permission("invoices:read"));
+
+        assert!(graph.has_role("billing"));
+        assert!(!graph.has_role("owner"));
+        assert!(graph.has_permission("users:manage"));
+        assert!(!graph.has_permission("users:delete"));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let err = RoleGraph::build(&roles(&[("admin", role(&["root"], &[]))])).unwrap_err();
+        assert_eq!(
+            err.message,
+            "rbac: role 'admin' inherits unknown role 'root'"
+        );
+
+        let err = RoleGraph::build(&roles(&[
+            ("a", role(&["b"], &[])),
+            ("b", role(&["c"], &[])),
+            ("c", role(&["a"], &[])),
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            err.message,
+            "rbac: role inheritance cycle: a -> b -> c -> a"
+        );
+
+        let err = RoleGraph::build(&roles(&[("a", role(&["a"], &[]))])).unwrap_err();
+        assert_eq!(err.message, "rbac: role inheritance cycle: a -> a");
+
+        let err = RoleGraph::build(&roles(&[("a", role(&[], &[""]))])).unwrap_err();
+        assert_eq!(err.message, "rbac: role 'a' has an empty permission");
+
+        let err = RoleGraph::build(&roles(&[("", role(&[], &[]))])).unwrap_err();
+        assert_eq!(err.message, "rbac: role names must not be empty");
+    }
+}
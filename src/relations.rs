@@ -0,0 +1,256 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Relationship tuples (Zanzibar-style)
+//
+// A tuple `object#relation@subject` states that the subject has the
+// relation to the object, e.g. `org:acme#member@user:alice`. A subject may
+// itself be a userset `object#relation`, meaning everyone with that
+// relation: `project:42#owner@org:acme#member` makes every member of
+// org:acme an owner of project:42.
+//
+// Usersets are expanded once at startup, so each (object, relation) pair
+// maps directly to every subject that has it and related() is a set
+// lookup. Expansion is bounded by MAX_DEPTH nested usersets.
+
+use crate::config::ConfigError;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Maximum number of nested usersets followed when expanding a relation
+pub const MAX_DEPTH: usize = 8;
+
+/// Maximum number of configured tuples, which bounds startup time and the
+/// memory used by the expanded relations
+pub const MAX_TUPLES: usize = 10_000;
+
+/// A parsed `object#relation@subject` tuple
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuple {
+    pub object: String,
+    pub relation: String,
+    /// `type:id`, or a userset `type:id#relation`
+    pub subject: String,
+}
+
+/// Relations with usersets expanded: object -> relation -> subjects
+#[derive(Debug, Clone, Default)]
+pub struct RelationGraph {
+    subjects: HashMap<String, HashMap<String, HashSet<String>>>,
+    relations: HashSet<String>,
+}
+
+/// Parse a tuple. Objects are `type:id` with non-empty parts and no `#`
+/// or `@`; relations are non-empty names without `#`, `@` or `:`.
+pub fn parse_tuple(tuple: &str) -> Option<Tuple> {
+    let (object_relation, subject) = tuple.split_once('@')?;
+    let (object, relation) = object_relation.split_once('#')?;
+    if !is_object(object) || !is_relation(relation) {
+        return None;
+    }
+    let valid_subject = match subject.split_once('#') {
+        Some((object, relation)) => is_object(object) && is_relation(relation),
+        None => is_object(subject),
+    };
+    valid_subject.then(|| Tuple {
+        object: object.to_string(),
+        relation: relation.to_string(),
+        subject: subject.to_string(),
+    })
+}
+
+fn is_object(object: &str) -> bool {
+    match object.split_once(':') {
+        Some((kind, id)) => !kind.is_empty() && !id.is_empty() && !object.contains(['#', '@']),
+        None => false,
+    }
+}
+
+fn is_relation(relation: &str) -> bool {
+    !relation.is_empty() && !relation.contains(['#', '@', ':'])
+}
+
+impl RelationGraph {
+    /// Parse the configured tuples and expand their usersets. Fails on
+    /// malformed tuples, too many tuples, or usersets nested deeper than
+    /// MAX_DEPTH.
+    pub fn build(tuples: &[String]) -> Result<Self, ConfigError> {
+        let error = |message: String| ConfigError {
+            message: format!("relations: {}", message),
+        };
+
+        if tuples.len() > MAX_TUPLES {
+            return Err(error(format!(
+                "at most {} tuples are allowed, got {}",
+                MAX_TUPLES,
+                tuples.len()
+            )));
+        }
+
+        // object -> relation -> direct subjects
+        let mut direct: HashMap<&str, HashMap<&str, Vec<&str>>> = HashMap::new();
+        let mut parsed = Vec::with_capacity(tuples.len());
+        for tuple in tuples {
+            let t = parse_tuple(tuple).ok_or_else(|| {
+                error(format!(
+                    "invalid tuple '{}', expected 'type:id#relation@type:id[#relation]'",
+                    tuple
+                ))
+            })?;
+            parsed.push(t);
+        }
+        for t in &parsed {
+            direct
+                .entry(&t.object)
+                .or_default()
+                .entry(&t.relation)
+                .or_default()
+                .push(&t.subject);
+        }
+
+        let mut graph = RelationGraph::default();
+        for (object, relations) in &direct {
+            for relation in relations.keys() {
+                let subjects = expand(&direct, object, relation).map_err(|path| {
+                    error(format!(
+                        "'{}#{}' nests usersets more than {} levels deep: {}",
+                        object,
+                        relation,
+                        MAX_DEPTH,
+                        path.join(" -> ")
+                    ))
+                })?;
+                graph.relations.insert(relation.to_string());
+                graph
+                    .subjects
+                    .entry(object.to_string())
+                    .or_default()
+                    .insert(relation.to_string(), subjects);
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Returns true if `subject` has `relation` to `object`, directly or
+    /// through usersets
+    pub fn related(&self, subject: &str, relation: &str, object: &str) -> bool {
+        self.subjects
+            .get(object)
+            .and_then(|relations| relations.get(relation))
+            .is_some_and(|subjects| subjects.contains(subject))
+    }
+
+    /// Returns true if any tuple uses `relation`
+    pub fn has_relation(&self, relation: &str) -> bool {
+        self.relations.contains(relation)
+    }
+}
+
+/// Collect every subject of `object#relation`, following usersets breadth
+/// first. Each userset is visited once, so cycles terminate. Returns the
+/// chain of usersets that exceeded MAX_DEPTH on failure.
+fn expand(
+    direct: &HashMap<&str, HashMap<&str, Vec<&str>>>,
+    object: &str,
+    relation: &str,
+) -> Result<HashSet<String>, Vec<String>> {
+    let start = format!("{}#{}", object, relation);
+    let mut subjects = HashSet::new();
+    let mut visited = HashSet::from([start.clone()]);
+    let mut queue = VecDeque::from([(start, vec![])]);
+
+    while let Some((userset, path)) = queue.pop_front() {
+        let (object, relation) = userset.split_once('#').unwrap_or((&userset, ""));
+        let Some(direct_subjects) = direct.get(object).and_then(|r| r.get(relation)) else {
+            continue;
+        };
+        for &subject in direct_subjects {
+            subjects.insert(subject.to_string());
+            if subject.contains('#') && visited.insert(subject.to_string()) {
+                let mut path = path.clone();
+                path.push(subject.to_string());
+                if path.len() > MAX_DEPTH {
+                    return Err(path);
+                }
+                queue.push_back((subject.to_string(), path));
+            }
+        }
+    }
+    Ok(subjects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(tuples: &[&str]) -> Result<RelationGraph, ConfigError> {
+        let tuples: Vec<String> = tuples.iter().map(|t| t.to_string()).collect();
+        RelationGraph::build(&tuples)
+    }
+
+    #[test]
+    fn test_parse_tuple() {
+        assert_eq!(
+            parse_tuple("project:42#owner@org:acme#member"),
+            Some(Tuple {
+                object: "project:42".to_string(),
+                relation: "owner".to_string(),
+                subject: "org:acme#member".to_string(),
+            })
+        );
+        assert!(parse_tuple("org:acme#member@user:alice").is_some());
+        for invalid in [
+            "org:acme#member",
+            "org:acme@user:alice",
+            "acme#member@user:alice",
+            "org:#member@user:alice",
+            "org:acme#@user:alice",
+            "org:acme#member@alice",
+            "org:acme#member@user:alice#",
+            "org:acme#member@user:a@b",
+        ] {
+            assert_eq!(parse_tuple(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_related_usersets() {
+        let graph = graph(&[
+            "project:42#owner@org:acme#member",
+            "org:acme#member@team:sre#member",
+            "org:acme#member@user:carol",
+            "team:sre#member@user:alice",
+            "team:sre#member@team:sre#member",
+            "project:7#viewer@user:bob",
+        ])
+        .unwrap();
+
+        assert!(graph.related("user:alice", "owner", "project:42"));
+        assert!(graph.related("user:carol", "owner", "project:42"));
+        assert!(graph.related("user:alice", "member", "org:acme"));
+        assert!(graph.related("team:sre#member", "owner", "project:42"));
+        assert!(!graph.related("user:bob", "owner", "project:42"));
+        assert!(!graph.related("user:alice", "viewer", "project:42"));
+        assert!(!graph.related("user:alice", "owner", "project:7"));
+        assert!(graph.has_relation("viewer"));
+        assert!(!graph.has_relation("editor"));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let err = graph(&["org:acme#member"]).unwrap_err();
+        assert!(err.message.contains("invalid tuple"), "{}", err.message);
+
+        // A chain of usersets one level too deep
+        let chain: Vec<String> = (0..=MAX_DEPTH)
+            .map(|i| format!("group:{}#member@group:{}#member", i, i + 1))
+            .collect();
+        let chain: Vec<&str> = chain.iter().map(String::as_str).collect();
+        let err = graph(&chain).unwrap_err();
+        assert!(err.message.contains("levels deep"), "{}", err.message);
+        assert!(graph(&chain[1..]).is_ok());
+
+        let many = vec!["org:a#member@user:b"; MAX_TUPLES + 1];
+        let err = graph(&many).unwrap_err();
+        assert!(err.message.contains("at most"), "{}", err.message);
+    }
+}
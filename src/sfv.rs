@@ -0,0 +1,497 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Structured Field Values for HTTP (RFC 8941)
+//
+// A complete parser for Lists, Dictionaries and Items, following the
+// parsing algorithms in RFC 8941 section 4.2. Any syntax error fails the
+// whole field, as the RFC requires; callers treat that as an absent value.
+
+use crate::crypto;
+use std::fmt;
+
+/// A bare item value (RFC 8941 section 3.3)
+#[derive(Debug, Clone, PartialEq)]
+pub enum BareItem {
+    Integer(i64),
+    Decimal(f64),
+    String(String),
+    Token(String),
+    ByteSequence(Vec<u8>),
+    Boolean(bool),
+}
+
+impl fmt::Display for BareItem {
+    /// Formats the item as it is serialized in a field, except that strings
+    /// are written without quotes or escapes. This is the form used for
+    /// string values in expressions.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BareItem::Integer(n) => write!(f, "{}", n),
+            BareItem::Decimal(d) => {
+                // At least one and at most three fractional digits
+                let formatted = format!("{:.3}", d);
+                let trimmed = formatted.trim_end_matches('0');
+                if trimmed.ends_with('.') {
+                    write!(f, "{}0", trimmed)
+                } else {
+                    write!(f, "{}", trimmed)
+                }
+            }
+            BareItem::String(s) | BareItem::Token(s) => write!(f, "{}", s),
+            BareItem::ByteSequence(bytes) => write!(f, ":{}:", crypto::encode_base64(bytes)),
+            BareItem::Boolean(b) => write!(f, "?{}", if *b { 1 } else { 0 }),
+        }
+    }
+}
+
+/// Ordered parameters attached to an item or inner list
+pub type Parameters = Vec<(String, BareItem)>;
+
+/// An item with its parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub value: BareItem,
+    pub params: Parameters,
+}
+
+/// A member of a List or Dictionary
+#[derive(Debug, Clone, PartialEq)]
+pub enum Member {
+    Item(Item),
+    InnerList(Vec<Item>, Parameters),
+}
+
+impl Member {
+    /// Parameters of the item or inner list
+    pub fn params(&self) -> &Parameters {
+        match self {
+            Member::Item(item) => &item.params,
+            Member::InnerList(_, params) => params,
+        }
+    }
+}
+
+/// A List (RFC 8941 section 3.1)
+pub type List = Vec<Member>;
+
+/// A Dictionary (RFC 8941 section 3.2), in field order
+pub type Dictionary = Vec<(String, Member)>;
+
+/// Look up a key in parameters or a dictionary
+pub fn get<'a, T>(entries: &'a [(String, T)], key: &str) -> Option<&'a T> {
+    entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Parse a field value as a List
+pub fn parse_list(input: &str) -> Option<List> {
+    parse_field(input, |p| p.parse_list())
+}
+
+/// Parse a field value as a Dictionary
+pub fn parse_dictionary(input: &str) -> Option<Dictionary> {
+    parse_field(input, |p| p.parse_dictionary())
+}
+
+/// Parse a field value as an Item
+pub fn parse_item(input: &str) -> Option<Item> {
+    parse_field(input, |p| p.parse_item())
+}
+
+/// Parse a complete field value (RFC 8941 section 4.2)
+fn parse_field<T>(input: &str, parse: impl FnOnce(&mut Parser) -> Option<T>) -> Option<T> {
+    if !input.is_ascii() {
+        return None;
+    }
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        pos: 0,
+    };
+    parser.skip_sp();
+    let value = parse(&mut parser)?;
+    parser.skip_sp();
+    if parser.peek().is_some() {
+        return None;
+    }
+    Some(value)
+}
+
+/// Insert into an ordered map, overwriting the value of an existing key
+/// in place as the RFC requires
+fn insert<T>(entries: &mut Vec<(String, T)>, key: String, value: T) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn consume(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_sp(&mut self) {
+        while self.consume(b' ') {}
+    }
+
+    fn skip_ows(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Members separated by commas, shared by Lists and Dictionaries
+    fn parse_members(&mut self, mut member: impl FnMut(&mut Self) -> Option<()>) -> Option<()> {
+        while self.peek().is_some() {
+            member(self)?;
+            self.skip_ows();
+            if self.peek().is_none() {
+                return Some(());
+            }
+            if !self.consume(b',') {
+                return None;
+            }
+            self.skip_ows();
+            // A trailing comma is an error
+            self.peek()?;
+        }
+        Some(())
+    }
+
+    fn parse_list(&mut self) -> Option<List> {
+        let mut members = Vec::new();
+        self.parse_members(|p| {
+            members.push(p.parse_item_or_inner_list()?);
+            Some(())
+        })?;
+        Some(members)
+    }
+
+    fn parse_dictionary(&mut self) -> Option<Dictionary> {
+        let mut entries = Vec::new();
+        self.parse_members(|p| {
+            let key = p.parse_key()?;
+            let member = if p.consume(b'=') {
+                p.parse_item_or_inner_list()?
+            } else {
+                Member::Item(Item {
+                    value: BareItem::Boolean(true),
+                    params: p.parse_parameters()?,
+                })
+            };
+            insert(&mut entries, key, member);
+            Some(())
+        })?;
+        Some(entries)
+    }
+
+    fn parse_item_or_inner_list(&mut self) -> Option<Member> {
+        if self.peek() == Some(b'(') {
+            self.parse_inner_list()
+        } else {
+            self.parse_item().map(Member::Item)
+        }
+    }
+
+    fn parse_inner_list(&mut self) -> Option<Member> {
+        if !self.consume(b'(') {
+            return None;
+        }
+        let mut items = Vec::new();
+        while self.peek().is_some() {
+            self.skip_sp();
+            if self.consume(b')') {
+                let params = self.parse_parameters()?;
+                return Some(Member::InnerList(items, params));
+            }
+            items.push(self.parse_item()?);
+            if !matches!(self.peek(), Some(b' ' | b')')) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn parse_item(&mut self) -> Option<Item> {
+        let value = self.parse_bare_item()?;
+        let params = self.parse_parameters()?;
+        Some(Item { value, params })
+    }
+
+    fn parse_bare_item(&mut self) -> Option<BareItem> {
+        match self.peek()? {
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            b'"' => self.parse_string(),
+            b'*' | b'A'..=b'Z' | b'a'..=b'z' => self.parse_token(),
+            b':' => self.parse_byte_sequence(),
+            b'?' => self.parse_boolean(),
+            _ => None,
+        }
+    }
+
+    fn parse_parameters(&mut self) -> Option<Parameters> {
+        let mut params = Vec::new();
+        while self.consume(b';') {
+            self.skip_sp();
+            let key = self.parse_key()?;
+            let value = if self.consume(b'=') {
+                self.parse_bare_item()?
+            } else {
+                BareItem::Boolean(true)
+            };
+            insert(&mut params, key, value);
+        }
+        Some(params)
+    }
+
+    fn parse_key(&mut self) -> Option<String> {
+        let start = self.pos;
+        match self.peek()? {
+            b'a'..=b'z' | b'*' => self.pos += 1,
+            _ => return None,
+        }
+        while matches!(
+            self.peek(),
+            Some(b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'.' | b'*')
+        ) {
+            self.pos += 1;
+        }
+        Some(self.text(start))
+    }
+
+    fn parse_number(&mut self) -> Option<BareItem> {
+        let start = self.pos;
+        self.consume(b'-');
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return None;
+        }
+
+        let mut is_decimal = false;
+        let mut digits = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                b'0'..=b'9' => digits += 1,
+                b'.' if !is_decimal => {
+                    if digits > 12 {
+                        return None;
+                    }
+                    is_decimal = true;
+                }
+                _ => break,
+            }
+            self.pos += 1;
+            // At most 15 digits for integers, and 12 + 3 for decimals
+            if digits > 15 {
+                return None;
+            }
+        }
+
+        let text = self.text(start);
+        if is_decimal {
+            let fraction = text.split_once('.').map_or("", |(_, f)| f);
+            if fraction.is_empty() || fraction.len() > 3 {
+                return None;
+            }
+            text.parse().ok().map(BareItem::Decimal)
+        } else {
+            text.parse().ok().map(BareItem::Integer)
+        }
+    }
+
+    fn parse_string(&mut self) -> Option<BareItem> {
+        if !self.consume(b'"') {
+            return None;
+        }
+        let mut value = String::new();
+        loop {
+            match self.next()? {
+                b'\\' => match self.next()? {
+                    c @ (b'"' | b'\\') => value.push(c as char),
+                    _ => return None,
+                },
+                b'"' => return Some(BareItem::String(value)),
+                c @ 0x20..=0x7e => value.push(c as char),
+                _ => return None,
+            }
+        }
+    }
+
+    fn parse_token(&mut self) -> Option<BareItem> {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            let tchar = c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&c);
+            if !tchar {
+                break;
+            }
+            self.pos += 1;
+        }
+        Some(BareItem::Token(self.text(start)))
+    }
+
+    fn parse_byte_sequence(&mut self) -> Option<BareItem> {
+        if !self.consume(b':') {
+            return None;
+        }
+        let start = self.pos;
+        while self.peek()? != b':' {
+            let c = self.next()?;
+            if !(c.is_ascii_alphanumeric() || matches!(c, b'+' | b'/' | b'=')) {
+                return None;
+            }
+        }
+        let encoded = self.text(start);
+        self.pos += 1;
+        if encoded.is_empty() {
+            return Some(BareItem::ByteSequence(Vec::new()));
+        }
+        crypto::decode_base64(&encoded).map(BareItem::ByteSequence)
+    }
+
+    fn parse_boolean(&mut self) -> Option<BareItem> {
+        if !self.consume(b'?') {
+            return None;
+        }
+        match self.next()? {
+            b'1' => Some(BareItem::Boolean(true)),
+            b'0' => Some(BareItem::Boolean(false)),
+            _ => None,
+        }
+    }
+
+    /// Input from `start` to the current position (input is ASCII)
+    fn text(&self, start: usize) -> String {
+        String::from_utf8_lossy(&self.input[start..self.pos]).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(s: &str) -> BareItem {
+        BareItem::Token(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let list = parse_list(r#"sugar, tea;q=0.5, ("a" b);lvl=5, "rum""#).unwrap();
+        assert_eq!(list.len(), 4);
+        assert_eq!(
+            list[1],
+            Member::Item(Item {
+                value: token("tea"),
+                params: vec![("q".to_string(), BareItem::Decimal(0.5))],
+            })
+        );
+        assert_eq!(
+            list[2],
+            Member::InnerList(
+                vec![
+                    Item {
+                        value: BareItem::String("a".to_string()),
+                        params: vec![],
+                    },
+                    Item {
+                        value: token("b"),
+                        params: vec![],
+                    },
+                ],
+                vec![("lvl".to_string(), BareItem::Integer(5))],
+            )
+        );
+        assert_eq!(parse_list(""), Some(vec![]));
+    }
+
+    #[test]
+    fn test_parse_dictionary() {
+        let dict = parse_dictionary("a=?0, b, c; foo=bar, a=2").unwrap();
+        let keys: Vec<&str> = dict.iter().map(|(k, _)| k.as_str()).collect();
+        // Duplicate keys overwrite the value but keep the original position
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(
+            get(&dict, "a"),
+            Some(&Member::Item(Item {
+                value: BareItem::Integer(2),
+                params: vec![],
+            }))
+        );
+        assert_eq!(get(&dict, "b").unwrap().params(), &vec![]);
+        assert_eq!(
+            get(get(&dict, "c").unwrap().params(), "foo"),
+            Some(&token("bar"))
+        );
+    }
+
+    #[test]
+    fn test_parse_item_bare_items() {
+        let value = |s: &str| parse_item(s).map(|item| item.value);
+        assert_eq!(value("42"), Some(BareItem::Integer(42)));
+        assert_eq!(value("-4.5"), Some(BareItem::Decimal(-4.5)));
+        assert_eq!(
+            value(r#""say \"hi\"""#),
+            Some(BareItem::String(r#"say "hi""#.to_string()))
+        );
+        assert_eq!(value("*foo/bar:baz"), Some(token("*foo/bar:baz")));
+        assert_eq!(
+            value(":aGVsbG8=:"),
+            Some(BareItem::ByteSequence(b"hello".to_vec()))
+        );
+        assert_eq!(value("?1"), Some(BareItem::Boolean(true)));
+    }
+
+    #[test]
+    fn test_malformed_fields() {
+        for input in [
+            "a,",
+            "a,,b",
+            "(a b",
+            "1.2345",
+            "1234567890123456",
+            "1234567890123.0",
+            "1.",
+            r#""unterminated"#,
+            r#""bad \n escape""#,
+            ":not base64!:",
+            "?2",
+            "é",
+            "a b",
+        ] {
+            assert_eq!(parse_list(input), None, "expected failure for {:?}", input);
+        }
+        assert_eq!(parse_dictionary("A=1"), None);
+        assert_eq!(parse_item("a, b"), None);
+    }
+
+    #[test]
+    fn test_display_bare_items() {
+        assert_eq!(BareItem::Decimal(2.0).to_string(), "2.0");
+        assert_eq!(BareItem::Decimal(0.125).to_string(), "0.125");
+        assert_eq!(BareItem::String("x y".to_string()).to_string(), "x y");
+        assert_eq!(BareItem::Boolean(false).to_string(), "?0");
+        assert_eq!(
+            BareItem::ByteSequence(b"hello".to_vec()).to_string(),
+            ":aGVsbG8=:"
+        );
+    }
+}
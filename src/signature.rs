@@ -0,0 +1,326 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Verification of HMAC-signed request attributes
+//
+// Signed identity headers let the plugin prove that `X-Auth-User-*`
+// headers were produced by the upstream auth service rather than forged
+// by a client that bypassed it. Presigned URLs let a service hand out
+// time-limited links that the plugin can check without any shared state.
+
+use crate::config::{PresignedUrls, SignedHeaders};
+use crate::context::RequestContext;
+use crate::crypto;
+use crate::url;
+
+/// Build the canonical string covered by a signed-headers signature
+///
+/// The timestamp comes first, followed by one `name:value` line per
+/// configured header in the configured order. Names are lowercased and
+/// multiple field lines are joined with ",". Missing headers contribute an
+/// empty value, so removing a signed header invalidates the signature.
+pub fn canonical_headers(cfg: &SignedHeaders, ctx: &RequestContext, timestamp: &str) -> String {
+    let mut canonical = String::with_capacity(64);
+    canonical.push_str(timestamp);
+    canonical.push('\n');
+    for name in &cfg.headers {
+        canonical.push_str(&name.to_lowercase());
+        canonical.push(':');
+        canonical.push_str(&ctx.header_values(name).join(","));
+        canonical.push('\n');
+    }
+    canonical
+}
+
+/// Verify the signed identity headers of a request
+///
+/// Returns false if the signature or timestamp header is missing or
+/// malformed, if the timestamp is further than `maxAge` seconds from the
+/// request time (in either direction), or if the signature does not match.
+pub fn verify_headers(cfg: &SignedHeaders, ctx: &RequestContext) -> bool {
+    // Signature and timestamp must each be sent exactly once; a duplicated
+    // header is ambiguous and could be used to smuggle a second value.
+    let (timestamp, signature) = match (
+        ctx.header_values(&cfg.timestamp_header),
+        ctx.header_values(&cfg.signature_header),
+    ) {
+        ([timestamp], [signature]) => (timestamp.trim(), signature),
+        _ => return false,
+    };
+
+    let Ok(signed_at) = timestamp.parse::<u64>() else {
+        return false;
+    };
+    if ctx.now.abs_diff(signed_at) > cfg.max_age {
+        return false;
+    }
+
+    let Some(signature) = crypto::decode_signature(signature) else {
+        return false;
+    };
+
+    let canonical = canonical_headers(cfg, ctx, timestamp);
+    crypto::verify_hmac_sha256(cfg.secret.as_bytes(), canonical.as_bytes(), &signature)
+}
+
+/// Build the canonical string covered by a presigned URL signature
+///
+/// All query parameters except the signature are included (the expiry is
+/// therefore covered by the signature), sorted by name and then value and
+/// re-encoded, so that equivalent encodings of the same URL verify.
+pub fn canonical_request(cfg: &PresignedUrls, ctx: &RequestContext) -> String {
+    let mut params: Vec<(String, String)> = ctx
+        .query_params()
+        .iter()
+        .filter(|(name, _)| *name != cfg.signature_param)
+        .map(|(name, value)| (url::percent_encode(name), url::percent_encode(value)))
+        .collect();
+    params.sort();
+
+    let query: Vec<String> = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+
+    format!("{}\n{}\n{}", ctx.method, ctx.path, query.join("&"))
+}
+
+/// Verify the presigned URL of a request
+///
+/// Returns false if the expiry or signature parameter is missing,
+/// duplicated or malformed, if the URL has expired, if the signature
+/// does not match, or if the query has more parameters than are parsed
+/// (those past `MAX_QUERY_PARAMS` would not be covered by the signature).
+pub fn verify_presigned(cfg: &PresignedUrls, ctx: &RequestContext) -> bool {
+    let raw_query = ctx.uri.split_once('?').map_or("", |(_, query)| query);
+    if url::count_query_params(raw_query) > url::MAX_QUERY_PARAMS {
+        return false;
+    }
+
+    let single = |name: &str| {
+        let mut values = ctx.query_params().iter().filter(|(n, _)| n == name);
+        match (values.next(), values.next()) {
+            (Some((_, value)), None) => Some(value.as_str()),
+            _ => None,
+        }
+    };
+
+    let (Some(expires), Some(signature)) =
+        (single(&cfg.expires_param), single(&cfg.signature_param))
+    else {
+        return false;
+    };
+
+    match expires.parse::<u64>() {
+        Ok(expires) if ctx.now <= expires => {}
+        _ => return false,
+    }
+
+    let Some(signature) = crypto::decode_signature(signature) else {
+        return false;
+    };
+
+    let canonical = canonical_request(cfg, ctx);
+    crypto::verify_hmac_sha256(cfg.secret.as_bytes(), canonical.as_bytes(), &signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Secret, TestRequest};
+    use std::collections::HashMap;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn signed_headers() -> SignedHeaders {
+        SignedHeaders {
+            headers: vec![
+                "X-Auth-User-Login".to_string(),
+                "X-Auth-User-Teams".to_string(),
+            ],
+            signature_header: "X-Auth-Signature".to_string(),
+            timestamp_header: "X-Auth-Timestamp".to_string(),
+            secret: Secret::from("s3cret"),
+            max_age: 300,
+            enforce: false,
+            deny_status_code: 401,
+        }
+    }
+
+    fn make_context(login: &str, teams: &str, timestamp: u64, signature: &str) -> RequestContext {
+        let mut headers = HashMap::new();
+        headers.insert("X-Auth-User-Login".to_string(), login.to_string());
+        headers.insert("X-Auth-User-Teams".to_string(), teams.to_string());
+        headers.insert("X-Auth-Timestamp".to_string(), timestamp.to_string());
+        headers.insert("X-Auth-Signature".to_string(), signature.to_string());
+        RequestContext::from_test(&TestRequest {
+            headers,
+            time: Some(NOW),
+            ..Default::default()
+        })
+    }
+
+    fn sign(login: &str, teams: &str, timestamp: u64) -> String {
+        let canonical = format!(
+            "{}\nx-auth-user-login:{}\nx-auth-user-teams:{}\n",
+            timestamp, login, teams
+        );
+        crypto::encode_base64url(&crypto::hmac_sha256(b"s3cret", canonical.as_bytes()))
+    }
+
+    #[test]
+    fn test_verify_valid_signature() {
+        let sig = sign("alice", "sre", NOW - 10);
+        let ctx = make_context("alice", "sre", NOW - 10, &sig);
+        assert!(verify_headers(&signed_headers(), &ctx));
+    }
+
+    #[test]
+    fn test_verify_hex_signature() {
+        let canonical = format!("{}\nx-auth-user-login:alice\nx-auth-user-teams:sre\n", NOW);
+        let mac = crypto::hmac_sha256(b"s3cret", canonical.as_bytes());
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        let ctx = make_context("alice", "sre", NOW, &hex);
+        assert!(verify_headers(&signed_headers(), &ctx));
+    }
+
+    #[test]
+    fn test_verify_tampered_header() {
+        let sig = sign("alice", "sre", NOW);
+        let ctx = make_context("alice", "sre,admins", NOW, &sig);
+        assert!(!verify_headers(&signed_headers(), &ctx));
+    }
+
+    #[test]
+    fn test_verify_expired_and_future_timestamps() {
+        let old = NOW - 301;
+        let ctx = make_context("alice", "sre", old, &sign("alice", "sre", old));
+        assert!(!verify_headers(&signed_headers(), &ctx));
+
+        let future = NOW + 301;
+        let ctx = make_context("alice", "sre", future, &sign("alice", "sre", future));
+        assert!(!verify_headers(&signed_headers(), &ctx));
+    }
+
+    #[test]
+    fn test_verify_missing_signature() {
+        let ctx = RequestContext::from_test(&TestRequest {
+            time: Some(NOW),
+            ..Default::default()
+        });
+        assert!(!verify_headers(&signed_headers(), &ctx));
+    }
+
+    fn presigned_urls() -> PresignedUrls {
+        PresignedUrls {
+            secret: Secret::from("link-key"),
+            expires_param: "expires".to_string(),
+            signature_param: "sig".to_string(),
+        }
+    }
+
+    fn presign(method: &str, path: &str, canonical_query: &str) -> String {
+        let canonical = format!("{}\n{}\n{}", method, path, canonical_query);
+        crypto::encode_base64url(&crypto::hmac_sha256(b"link-key", canonical.as_bytes()))
+    }
+
+    fn make_request(method: &str, uri: &str) -> RequestContext {
+        RequestContext::from_test(&TestRequest {
+            method: method.to_string(),
+            path: uri.to_string(),
+            time: Some(NOW),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_presigned_valid() {
+        let sig = presign("GET", "/files/a.pdf", "expires=1700000100&user=bob%20smith");
+        // Parameter order and encoding of the request do not matter
+        let uri = format!("/files/a.pdf?user=bob+smith&sig={}&expires=1700000100", sig);
+        assert!(verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+    }
+
+    #[test]
+    fn test_presigned_expired() {
+        let sig = presign("GET", "/files/a.pdf", "expires=1699999999");
+        let uri = format!("/files/a.pdf?expires=1699999999&sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+    }
+
+    #[test]
+    fn test_presigned_tampered() {
+        let sig = presign("GET", "/files/a.pdf", "expires=1700000100");
+
+        // Different path
+        let uri = format!("/files/b.pdf?expires=1700000100&sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+
+        // Different method
+        let uri = format!("/files/a.pdf?expires=1700000100&sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("PUT", &uri)
+        ));
+
+        // Extended expiry
+        let uri = format!("/files/a.pdf?expires=1800000000&sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+
+        // Added parameter
+        let uri = format!("/files/a.pdf?expires=1700000100&admin=1&sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+    }
+
+    #[test]
+    fn test_presigned_missing_or_duplicate_params() {
+        let sig = presign("GET", "/files/a.pdf", "expires=1700000100");
+        let uri = format!("/files/a.pdf?sig={}", sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+
+        let uri = format!("/files/a.pdf?expires=1700000100&sig={}&sig={}", sig, sig);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+    }
+
+    #[test]
+    fn test_presigned_rejects_params_past_cap() {
+        let extra: Vec<String> = (0..url::MAX_QUERY_PARAMS - 2)
+            .map(|i| format!("p{:03}=v", i))
+            .collect();
+        let canonical = format!("expires=1700000100&{}", extra.join("&"));
+        let sig = presign("GET", "/files/a.pdf", &canonical);
+        let uri = format!("/files/a.pdf?{}&sig={}", canonical, sig);
+        assert!(verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+
+        // An unsigned parameter appended past the cap must not be accepted
+        let uri = format!("{}&admin=true", uri);
+        assert!(!verify_presigned(
+            &presigned_urls(),
+            &make_request("GET", &uri)
+        ));
+    }
+}
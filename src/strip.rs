@@ -0,0 +1,198 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Stripping of identity and policy-only headers
+//
+// Headers that only the policy needs, or raw identity headers, should not
+// reach backends. `stripHeaders` names them with exact names or `*` globs,
+// matched case-insensitively like all header names. They are removed from
+// allowed requests after evaluation, and optionally from the request
+// context before evaluation when the client is not a trusted source, so a
+// client cannot supply identity headers that the policy would believe.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Returns true if a header name matches a pattern, ignoring case. `*` in
+/// the pattern matches any run of characters, including none.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    // Position after the last `*`, and the name position it is matched to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p].eq_ignore_ascii_case(&name[n]) {
+            p += 1;
+            n += 1;
+        } else if let Some((after_star, matched)) = star {
+            // Let the last `*` absorb one more character
+            star = Some((after_star, matched + 1));
+            p = after_star;
+            n = matched + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// An IP network in CIDR notation; a bare address is a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parse `10.0.0.0/8`, `2001:db8::/32` or a single address
+    pub fn parse(input: &str) -> Option<Cidr> {
+        let (address, prefix) = match input.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+            None => (input, None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Cidr { network, prefix })
+    }
+
+    /// Returns true if the network contains the address. IPv4-mapped IPv6
+    /// addresses are compared as IPv4.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Networks are parsed when the configuration is loaded, so an invalid one
+/// fails at startup and requests only compare addresses
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let input = String::deserialize(deserializer)?;
+        Cidr::parse(&input).ok_or_else(|| {
+            de::Error::custom(format!("'{}' is not an IP address or CIDR network", input))
+        })
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+fn prefix_matches(network: &[u8], address: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = (usize::from(prefix / 8), prefix % 8);
+    if network[..bytes] != address[..bytes] {
+        return false;
+    }
+    bits == 0 || {
+        let mask = 0xff << (8 - bits);
+        network[bytes] & mask == address[bytes] & mask
+    }
+}
+
+/// The IP address of a source address such as `192.0.2.1:51234`,
+/// `[2001:db8::1]:443` or a bare address
+pub fn source_ip(source_addr: &str) -> Option<IpAddr> {
+    source_addr
+        .parse::<SocketAddr>()
+        .map(|socket| socket.ip())
+        .or_else(|_| source_addr.parse::<IpAddr>())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("X-Auth-User", "x-auth-user"));
+        assert!(matches("x-auth-*", "X-Auth-User-Teams"));
+        assert!(matches("X-Auth-*", "x-auth-"));
+        assert!(!matches("X-Auth-*", "X-Authz-Rule"));
+        assert!(matches("*-Internal", "X-Debug-Internal"));
+        assert!(matches("X-*-Token-*", "x-upstream-token-id"));
+        assert!(matches("X-*a*b", "X-aab"));
+        assert!(!matches("X-*-Token", "X-Token"));
+        assert!(!matches("X-Auth-User", "X-Auth-User-Teams"));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(private.contains(ip("::ffff:10.0.0.1")));
+
+        let subnet = Cidr::parse("192.168.1.128/25").unwrap();
+        assert!(subnet.contains(ip("192.168.1.200")));
+        assert!(!subnet.contains(ip("192.168.1.127")));
+
+        let host = Cidr::parse("2001:db8::1").unwrap();
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+        assert!(Cidr::parse("2001:db8::/32")
+            .unwrap()
+            .contains(ip("2001:db8:ffff::1")));
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains(ip("203.0.113.9")));
+
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+        assert_eq!(Cidr::parse("10.0.0/8"), None);
+        assert_eq!(Cidr::parse("localhost"), None);
+
+        assert_eq!(host.to_string(), "2001:db8::1/128");
+        let parsed: Vec<Cidr> = serde_json::from_str(r#"["10.0.0.0/8", "192.0.2.1"]"#).unwrap();
+        assert_eq!(parsed, vec![private, Cidr::parse("192.0.2.1/32").unwrap()]);
+        let err = serde_json::from_str::<Cidr>(r#""10.0.0.0/40""#).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("not an IP address or CIDR network"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_source_ip() {
+        assert_eq!(
+            source_ip("192.0.2.1:51234"),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            source_ip("[2001:db8::1]:443"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(source_ip("192.0.2.1"), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(source_ip(""), None);
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// Deny response templates
+//
+// Deny bodies, header values and redirect URLs may contain `{{name}}`
+// placeholders for a few request attributes, and `{{{{` for a literal
+// `{{`. Templates are parsed at startup so an unknown variable or an
+// unterminated placeholder fails the configuration rather than a request.
+// Values are escaped for the content type of the response when rendered:
+// a path like `/<script>` must not become markup in an HTML deny page, a
+// quote in a header must not end a JSON string, and the original URL is
+// percent-encoded in a redirect's query string. Header values drop control
+// characters, so a request attribute cannot inject a CRLF and start a new
+// header.
+
+use crate::url;
+use std::fmt::Write;
+
+/// Request attributes available to templates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+    Method,
+    Path,
+    Uri,
+    Host,
+    RequestId,
+    Rule,
+    Reason,
+    Status,
+    StatusText,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Var> {
+        match name {
+            "method" => Some(Var::Method),
+            "path" => Some(Var::Path),
+            "uri" => Some(Var::Uri),
+            "host" => Some(Var::Host),
+            "requestId" => Some(Var::RequestId),
+            "rule" => Some(Var::Rule),
+            "reason" => Some(Var::Reason),
+            "status" => Some(Var::Status),
+            "statusText" => Some(Var::StatusText),
+            _ => None,
+        }
+    }
+}
+
+/// Values of the template variables for one response
+#[derive(Debug, Clone, Default)]
+pub struct Vars<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub uri: &'a str,
+    pub host: &'a str,
+    pub request_id: &'a str,
+    pub rule: &'a str,
+    pub reason: &'a str,
+    pub status: u16,
+}
+
+/// How interpolated values are escaped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Escape {
+    /// Values are inserted as they are (text/plain)
+    #[default]
+    None,
+    /// `&`, `<`, `>`, `"` and `'` become character references
+    Markup,
+    /// Values are escaped for use inside a JSON string
+    Json,
+    /// Control characters are removed (header values)
+    Header,
+    /// Values are percent-encoded for use in a URL component (redirects)
+    Url,
+}
+
+impl Escape {
+    /// Escaping for a body of the given content type: markup for HTML and
+    /// XML, JSON for JSON types, none otherwise
+    pub fn for_content_type(content_type: &str) -> Escape {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if media_type == "application/json" || media_type.ends_with("+json") {
+            Escape::Json
+        } else if media_type == "text/html"
+            || media_type == "text/xml"
+            || media_type == "application/xml"
+            || media_type.ends_with("+xml")
+        {
+            Escape::Markup
+        } else {
+            Escape::None
+        }
+    }
+
+    fn push(self, out: &mut String, value: &str) {
+        match self {
+            Escape::None => out.push_str(value),
+            Escape::Markup => {
+                for c in value.chars() {
+                    match c {
+                        '&' => out.push_str("&amp;"),
+                        '<' => out.push_str("&lt;"),
+                        '>' => out.push_str("&gt;"),
+                        '"' => out.push_str("&quot;"),
+                        '\'' => out.push_str("&#39;"),
+                        c => out.push(c),
+                    }
+                }
+            }
+            Escape::Json => {
+                for c in value.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        c if c.is_control() => {
+                            let _ = write!(out, "\\u{:04x}", c as u32);
+                        }
+                        c => out.push(c),
+                    }
+                }
+            }
+            Escape::Header => out.extend(value.chars().filter(|c| !c.is_control())),
+            Escape::Url => out.push_str(&url::percent_encode(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Var(Var),
+}
+
+/// A parsed template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse a template. `{{name}}` (spaces inside the braces are allowed)
+    /// is replaced by a variable and `{{{{` is a literal `{{`; all other
+    /// text is literal.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = input;
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("{{{{") {
+                literal.push_str("{{");
+                rest = after;
+                continue;
+            }
+            let Some(len) = rest[2..].find("}}") else {
+                return Err(format!("unterminated '{{{{' at '{}'", rest));
+            };
+            let name = rest[2..2 + len].trim();
+            let var = Var::from_name(name).ok_or_else(|| {
+                format!(
+                    "unknown template variable '{}', expected method, path, uri, host, requestId, rule, reason, status or statusText",
+                    name
+                )
+            })?;
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Var(var));
+            rest = &rest[2 + len + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// The template `{{reason}}`, which renders only the deny reason
+    pub fn reason() -> Self {
+        Template {
+            parts: vec![Part::Var(Var::Reason)],
+        }
+    }
+
+    /// Parse a header value template, which must not contain control
+    /// characters itself
+    pub fn parse_header_value(input: &str) -> Result<Self, String> {
+        if input.chars().any(char::is_control) {
+            return Err("header values must not contain control characters".to_string());
+        }
+        Self::parse(input)
+    }
+
+    /// Render the template, escaping variable values
+    pub fn render(&self, vars: &Vars, escape: Escape) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Var(Var::Method) => escape.push(&mut out, vars.method),
+                Part::Var(Var::Path) => escape.push(&mut out, vars.path),
+                Part::Var(Var::Uri) => escape.push(&mut out, vars.uri),
+                Part::Var(Var::Host) => escape.push(&mut out, vars.host),
+                Part::Var(Var::RequestId) => escape.push(&mut out, vars.request_id),
+                Part::Var(Var::Rule) => escape.push(&mut out, vars.rule),
+                Part::Var(Var::Reason) => escape.push(&mut out, vars.reason),
+                Part::Var(Var::Status) => {
+                    let _ = write!(out, "{}", vars.status);
+                }
+                Part::Var(Var::StatusText) => out.push_str(status_text(vars.status)),
+            }
+        }
+        out
+    }
+}
+
+/// Reason phrase of an HTTP status code (RFC 9110), or an empty string for
+/// codes without one
+pub fn status_text(status: u16) -> &'static str {
+    match status {
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        423 => "Locked",
+        424 => "Failed Dependency",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars<'static> {
+        Vars {
+            method: "GET",
+            path: "/a/<b>?q=\"1\"",
+            uri: "/a/<b>?q=\"1\"&r=2",
+            host: "example.com",
+            request_id: "req-1\r\nX-Injected: 1",
+            rule: "rule 'admin'",
+            reason: "needs admin",
+            status: 403,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let template =
+            Template::parse("{{ method }} {{path}} denied by {{rule}} ({{status}} {{statusText}})")
+                .unwrap();
+        assert_eq!(
+            template.render(&vars(), Escape::None),
+            "GET /a/<b>?q=\"1\" denied by rule 'admin' (403 Forbidden)"
+        );
+        assert_eq!(
+            template.render(&vars(), Escape::Markup),
+            "GET /a/&lt;b&gt;?q=&quot;1&quot; denied by rule &#39;admin&#39; (403 Forbidden)"
+        );
+
+        let template = Template::parse(
+            r#"{"error": "{{reason}}", "path": "{{path}}", "id": "{{requestId}}"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&vars(), Escape::Json),
+            r#"{"error": "needs admin", "path": "/a/<b>?q=\"1\"", "id": "req-1\r\nX-Injected: 1"}"#
+        );
+
+        let template = Template::parse_header_value("{{requestId}}").unwrap();
+        assert_eq!(
+            template.render(&vars(), Escape::Header),
+            "req-1X-Injected: 1"
+        );
+
+        let template = Template::parse_header_value(
+            "https://login.example.com/?return_to=https://{{host}}{{uri}}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&vars(), Escape::Url),
+            "https://login.example.com/?return_to=https://example.com%2Fa%2F%3Cb%3E%3Fq%3D%221%22%26r%3D2"
+        );
+
+        assert_eq!(
+            Template::parse("Forbidden")
+                .unwrap()
+                .render(&vars(), Escape::Markup),
+            "Forbidden"
+        );
+        assert_eq!(
+            Template::parse("{ \"a\": 1 }")
+                .unwrap()
+                .render(&vars(), Escape::Json),
+            "{ \"a\": 1 }"
+        );
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        let template = Template::parse("{{{{method}} is {{method}}, {{{{ and }}").unwrap();
+        assert_eq!(
+            template.render(&vars(), Escape::None),
+            "{{method}} is GET, {{ and }}"
+        );
+        assert_eq!(
+            Template::parse("{{{{{{reason}}")
+                .unwrap()
+                .render(&vars(), Escape::None),
+            "{{needs admin"
+        );
+        assert!(Template::parse("{{{{ {{path")
+            .unwrap_err()
+            .contains("unterminated"));
+    }
+
+    #[test]
+    fn test_reason_template() {
+        assert_eq!(Template::reason(), Template::parse("{{reason}}").unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("{{user}}")
+            .unwrap_err()
+            .contains("unknown template variable 'user'"));
+        assert!(Template::parse("a {{path")
+            .unwrap_err()
+            .contains("unterminated"));
+        assert!(Template::parse_header_value("a\r\nb")
+            .unwrap_err()
+            .contains("control characters"));
+    }
+
+    #[test]
+    fn test_escape_for_content_type() {
+        assert_eq!(Escape::for_content_type("text/plain"), Escape::None);
+        assert_eq!(
+            Escape::for_content_type("text/html; charset=utf-8"),
+            Escape::Markup
+        );
+        assert_eq!(
+            Escape::for_content_type("application/problem+json"),
+            Escape::Json
+        );
+        assert_eq!(Escape::for_content_type("Application/JSON"), Escape::Json);
+        assert_eq!(
+            Escape::for_content_type("application/atom+xml"),
+            Escape::Markup
+        );
+    }
+}
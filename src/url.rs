@@ -0,0 +1,203 @@
+// Copyright (c) 2025 Andrew Kroh
+// SPDX-License-Identifier: MIT
+
+// URL query string helpers
+//
+// Percent-encoding and `application/x-www-form-urlencoded` parsing
+// (WHATWG URL Standard section 5), implemented here to avoid pulling in
+// a URL crate for a few small functions.
+
+/// Maximum number of parameters parsed from a query string to prevent
+/// unbounded memory allocation from attacker-controlled URLs.
+pub const MAX_QUERY_PARAMS: usize = 128;
+
+/// Parse a query string (without the leading `?`) into decoded name/value
+/// pairs, in order. `+` is decoded as a space. Parameters without `=` have
+/// an empty value, and empty segments (`a=1&&b=2`) are skipped.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .take(MAX_QUERY_PARAMS)
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_form_component(name), decode_form_component(value))
+        })
+        .collect()
+}
+
+/// Number of parameters in a query string, counted like `parse_query` but
+/// without the `MAX_QUERY_PARAMS` cap
+pub fn count_query_params(query: &str) -> usize {
+    query.split('&').filter(|pair| !pair.is_empty()).count()
+}
+
+/// Decode a form-urlencoded component (`+` means space)
+pub fn decode_form_component(input: &str) -> String {
+    percent_decode(&input.replace('+', " "))
+}
+
+/// Decode `%XX` escapes. Invalid escapes are kept literally and invalid
+/// UTF-8 is replaced with U+FFFD.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                out.push(hi << 4 | lo);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Normalize a request path for matching (RFC 3986 section 6.2.2):
+/// percent-encoded unreserved characters are decoded (`%61` -> `a`),
+/// empty segments are collapsed (`//` -> `/`) and dot-segments are
+/// resolved (`/a/../b` -> `/b`). A trailing slash is kept. Backends that
+/// normalize paths this way then see the same path as the policy.
+pub fn normalize_path(path: &str) -> String {
+    let decoded = decode_unreserved(path);
+    let Some(rest) = decoded.strip_prefix('/') else {
+        return decoded;
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in rest.split('/') {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut out = String::with_capacity(decoded.len());
+    for segment in &segments {
+        out.push('/');
+        out.push_str(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        out.push('/');
+    }
+    out
+}
+
+/// Decode `%XX` escapes of unreserved characters only; other escapes,
+/// such as `%2F`, keep their meaning and are left as they are
+fn decode_unreserved(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = String::with_capacity(input.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                let b = hi << 4 | lo;
+                if is_unreserved(b) {
+                    out.push(b as char);
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+        let ch = input[i..].chars().next().unwrap_or_default();
+        out.push(ch);
+        i += ch.len_utf8();
+    }
+    out
+}
+
+/// RFC 3986 unreserved characters (`A-Z a-z 0-9 - . _ ~`)
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+/// (`A-Z a-z 0-9 - . _ ~`)
+pub fn percent_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        if is_unreserved(b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let params = parse_query("a=1&b=hello+world&c=%2Fpath%3F&flag&&a=2");
+        assert_eq!(
+            params,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "hello world".to_string()),
+                ("c".to_string(), "/path?".to_string()),
+                ("flag".to_string(), "".to_string()),
+                ("a".to_string(), "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_query_capped_at_max() {
+        let query: Vec<String> = (0..200).map(|i| format!("p{}=v", i)).collect();
+        assert_eq!(parse_query(&query.join("&")).len(), MAX_QUERY_PARAMS);
+        assert_eq!(count_query_params(&query.join("&")), 200);
+        assert_eq!(count_query_params("a=1&&b=2&"), 2);
+    }
+
+    #[test]
+    fn test_percent_decode_invalid_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/admin/x"), "/admin/x");
+        assert_eq!(normalize_path("/%61dmin/x"), "/admin/x");
+        assert_eq!(normalize_path("//admin//x"), "/admin/x");
+        assert_eq!(normalize_path("/public/../admin/x"), "/admin/x");
+        assert_eq!(normalize_path("/public/%2e%2E/admin/./x"), "/admin/x");
+        assert_eq!(normalize_path("/../../admin"), "/admin");
+        assert_eq!(normalize_path("/api/"), "/api/");
+        assert_eq!(normalize_path("/api/v1/.."), "/api/");
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path(""), "");
+        // Reserved characters stay encoded
+        assert_eq!(
+            normalize_path("/a%2Fb/%zz/caf%C3%A9"),
+            "/a%2Fb/%zz/caf%C3%A9"
+        );
+        assert_eq!(normalize_path("/café/x"), "/café/x");
+    }
+
+    #[test]
+    fn test_percent_encode_roundtrip() {
+        let input = "a b/c?d=e&f~g.h_i-j";
+        let encoded = percent_encode(input);
+        assert_eq!(encoded, "a%20b%2Fc%3Fd%3De%26f~g.h_i-j");
+        assert_eq!(percent_decode(&encoded), input);
+    }
+}
@@ -3,7 +3,9 @@
 
 use traefik_authz_wasm::config::{Config, TestRequest};
 use traefik_authz_wasm::context::RequestContext;
+use traefik_authz_wasm::crypto;
 use traefik_authz_wasm::expr::compiler::Program;
+use traefik_authz_wasm::policy::Policy;
 
 #[test]
 fn test_full_pipeline_simple() {
@@ -22,8 +24,9 @@ fn test_full_pipeline_simple() {
         path: "/api".to_string(),
         host: "example.com".to_string(),
         headers: std::collections::HashMap::new(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), true);
+    assert!(program.eval(&ctx).unwrap().allowed);
 
     // Test POST request
     let ctx = RequestContext::from_test(&TestRequest {
@@ -31,8 +34,9 @@ fn test_full_pipeline_simple() {
         path: "/api".to_string(),
         host: "example.com".to_string(),
         headers: std::collections::HashMap::new(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), false);
+    assert!(!program.eval(&ctx).unwrap().allowed);
 }
 
 #[test]
@@ -55,8 +59,9 @@ fn test_full_pipeline_with_headers() {
         path: "/api".to_string(),
         host: "example.com".to_string(),
         headers: headers.clone(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), true);
+    assert!(program.eval(&ctx).unwrap().allowed);
 
     // Test with wrong team
     headers.insert("X-Teams".to_string(), "marketing".to_string());
@@ -65,8 +70,9 @@ fn test_full_pipeline_with_headers() {
         path: "/api".to_string(),
         host: "example.com".to_string(),
         headers,
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), false);
+    assert!(!program.eval(&ctx).unwrap().allowed);
 }
 
 #[test]
@@ -109,7 +115,7 @@ fn test_config_with_test_cases() {
     // Validate all test cases
     for test_case in &config.tests {
         let ctx = RequestContext::from_test(&test_case.request);
-        let result = program.eval(&ctx).unwrap();
+        let result = program.eval(&ctx).unwrap().allowed;
         assert_eq!(
             result, test_case.expect,
             "Test '{}' failed: expected {}, got {}",
@@ -118,6 +124,67 @@ fn test_config_with_test_cases() {
     }
 }
 
+#[test]
+fn test_policy_tree_test_cases() {
+    let config_json = r#"{
+        "policy": {
+            "combine": "deny-overrides",
+            "children": [
+                {
+                    "name": "suspended",
+                    "condition": "header(\"X-Status\") == \"suspended\"",
+                    "effect": "deny"
+                },
+                {
+                    "target": "path startsWith \"/api/\"",
+                    "name": "api",
+                    "condition": "deny(\"requires the api team\") unless header(\"X-Team\") == \"api\"",
+                    "effect": "permit"
+                }
+            ]
+        },
+        "tests": [
+            {
+                "name": "api team allowed",
+                "request": {"path": "/api/users", "headers": {"X-Team": "api"}},
+                "expect": true
+            },
+            {
+                "name": "other team denied with reason",
+                "request": {"path": "/api/users", "headers": {"X-Team": "web"}},
+                "expect": false,
+                "expectReason": "requires the api team"
+            },
+            {
+                "name": "suspended denied by rule",
+                "request": {"path": "/api/users", "headers": {"X-Team": "api", "X-Status": "suspended"}},
+                "expect": false
+            }
+        ]
+    }"#;
+
+    let config: Config = serde_json::from_str(config_json).unwrap();
+    let policy = Policy::compile(&config, config.environment().unwrap()).unwrap();
+
+    for test_case in &config.tests {
+        let ctx = RequestContext::from_test(&test_case.request);
+        let outcome = policy.evaluate(&ctx).unwrap();
+        assert_eq!(
+            outcome.allowed, test_case.expect,
+            "Test '{}' failed",
+            test_case.name
+        );
+        if let Some(expected) = &test_case.expect_reason {
+            assert_eq!(
+                outcome.reason,
+                Some(expected.as_str()),
+                "Test '{}' failed",
+                test_case.name
+            );
+        }
+    }
+}
+
 #[test]
 fn test_complex_expression_pipeline() {
     let config_json = r#"{
@@ -151,7 +218,7 @@ fn test_complex_expression_pipeline() {
 
     for test_case in &config.tests {
         let ctx = RequestContext::from_test(&test_case.request);
-        let result = program.eval(&ctx).unwrap();
+        let result = program.eval(&ctx).unwrap().allowed;
         assert_eq!(result, test_case.expect, "Test '{}' failed", test_case.name);
     }
 }
@@ -171,8 +238,9 @@ fn test_regex_in_pipeline() {
         path: "/api/v1/users".to_string(),
         host: "example.com".to_string(),
         headers: std::collections::HashMap::new(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), true);
+    assert!(program.eval(&ctx).unwrap().allowed);
 
     // Test non-matching path
     let ctx = RequestContext::from_test(&TestRequest {
@@ -180,8 +248,9 @@ fn test_regex_in_pipeline() {
         path: "/api/users".to_string(),
         host: "example.com".to_string(),
         headers: std::collections::HashMap::new(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), false);
+    assert!(!program.eval(&ctx).unwrap().allowed);
 }
 
 #[test]
@@ -201,8 +270,9 @@ fn test_variadic_functions_pipeline() {
         path: "/admin".to_string(),
         host: "example.com".to_string(),
         headers: headers.clone(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), true);
+    assert!(program.eval(&ctx).unwrap().allowed);
 
     // Test with moderator role
     headers.insert("X-Roles".to_string(), "moderator,user".to_string());
@@ -211,8 +281,9 @@ fn test_variadic_functions_pipeline() {
         path: "/admin".to_string(),
         host: "example.com".to_string(),
         headers: headers.clone(),
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), true);
+    assert!(program.eval(&ctx).unwrap().allowed);
 
     // Test without required roles
     headers.insert("X-Roles".to_string(), "user".to_string());
@@ -221,6 +292,125 @@ fn test_variadic_functions_pipeline() {
         path: "/admin".to_string(),
         host: "example.com".to_string(),
         headers,
+        ..Default::default()
     });
-    assert_eq!(program.eval(&ctx).unwrap(), false);
+    assert!(!program.eval(&ctx).unwrap().allowed);
+}
+
+#[test]
+fn test_signed_headers_pipeline() {
+    let config_json = r#"{
+        "expression": "signatureValid() AND contains(headerList(\"X-Auth-User-Teams\"), \"sre\")",
+        "signedHeaders": {
+            "headers": ["X-Auth-User-Login", "X-Auth-User-Teams"],
+            "secret": "shared-secret",
+            "maxAge": 60
+        }
+    }"#;
+
+    let config: Config = serde_json::from_str(config_json).unwrap();
+    let program = Program::compile_with(&config.expression, config.environment().unwrap()).unwrap();
+
+    let sign = |login: &str, teams: &str, timestamp: u64| {
+        let canonical = format!(
+            "{}\nx-auth-user-login:{}\nx-auth-user-teams:{}\n",
+            timestamp, login, teams
+        );
+        crypto::encode_base64url(&crypto::hmac_sha256(b"shared-secret", canonical.as_bytes()))
+    };
+    let request = |teams: &str, timestamp: u64, signature: String| {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("X-Auth-User-Login".to_string(), "alice".to_string());
+        headers.insert("X-Auth-User-Teams".to_string(), teams.to_string());
+        headers.insert("X-Auth-Timestamp".to_string(), timestamp.to_string());
+        headers.insert("X-Auth-Signature".to_string(), signature);
+        RequestContext::from_test(&TestRequest {
+            headers,
+            time: Some(1_700_000_000),
+            ..Default::default()
+        })
+    };
+
+    // Valid signature from the auth service
+    let ctx = request("sre", 1_700_000_000, sign("alice", "sre", 1_700_000_000));
+    assert!(program.eval(&ctx).unwrap().allowed);
+
+    // Client appended a team to a signed header
+    let ctx = request(
+        "dev,sre",
+        1_700_000_000,
+        sign("alice", "dev", 1_700_000_000),
+    );
+    assert!(!program.eval(&ctx).unwrap().allowed);
+
+    // Replayed signature older than maxAge
+    let ctx = request("sre", 1_699_999_000, sign("alice", "sre", 1_699_999_000));
+    assert!(!program.eval(&ctx).unwrap().allowed);
+}
+
+#[test]
+fn test_rules_pipeline() {
+    let config_json = r#"{
+        "rules": [
+            {"name": "health", "match": {"paths": ["/health"], "methods": ["GET"]}, "action": "allow"},
+            {
+                "name": "admin",
+                "match": {"paths": ["/admin/*"]},
+                "expression": "NOT contains(headerList(\"X-Auth-User-Teams\"), \"sre\")",
+                "action": "deny",
+                "denyStatusCode": 404,
+                "denyBody": "Not Found"
+            },
+            {
+                "name": "teams",
+                "expression": "anyOf(headerList(\"X-Auth-User-Teams\"), \"sre\", \"dev\")",
+                "action": "allow"
+            }
+        ],
+        "denyBody": "Forbidden"
+    }"#;
+
+    let config: Config = serde_json::from_str(config_json).unwrap();
+    let policy = Policy::compile(&config, config.environment().unwrap()).unwrap();
+
+    let request = |path: &str, teams: &str| {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("X-Auth-User-Teams".to_string(), teams.to_string());
+        RequestContext::from_test(&TestRequest {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers,
+            ..Default::default()
+        })
+    };
+
+    let ctx = request("/health", "");
+    let outcome = policy.evaluate(&ctx).unwrap();
+    assert_eq!(
+        (outcome.allowed, outcome.rule),
+        (true, Some("rule 'health'"))
+    );
+
+    let ctx = request("/admin/users", "dev");
+    let outcome = policy.evaluate(&ctx).unwrap();
+    assert!(!outcome.allowed);
+    assert_eq!(outcome.decided_by(), "rule 'admin'");
+    let response = policy.deny_response(&outcome, &ctx);
+    assert_eq!(
+        (response.status, response.body.as_str()),
+        (404, "Not Found")
+    );
+
+    let ctx = request("/admin/users", "sre");
+    assert_eq!(policy.evaluate(&ctx).unwrap().decided_by(), "rule 'teams'");
+
+    let ctx = request("/api", "ops");
+    let outcome = policy.evaluate(&ctx).unwrap();
+    assert!(!outcome.allowed);
+    assert_eq!(outcome.decided_by(), "default action");
+    let response = policy.deny_response(&outcome, &ctx);
+    assert_eq!(
+        (response.status, response.body.as_str()),
+        (403, "Forbidden")
+    );
 }